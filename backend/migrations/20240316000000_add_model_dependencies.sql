@@ -0,0 +1,12 @@
+-- Track declared licenses and model-to-model dependencies
+ALTER TABLE ai_models ADD COLUMN IF NOT EXISTS license VARCHAR(100);
+
+CREATE TABLE model_dependencies (
+    model_id UUID NOT NULL REFERENCES ai_models(id) ON DELETE CASCADE,
+    dependency_id UUID NOT NULL REFERENCES ai_models(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (model_id, dependency_id),
+    CHECK (model_id <> dependency_id)
+);
+
+CREATE INDEX idx_model_dependencies_dependency ON model_dependencies(dependency_id);
@@ -0,0 +1,4 @@
+-- Soft-deleted models keep their row until the retention window elapses
+ALTER TABLE ai_models ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+
+CREATE INDEX idx_ai_models_deleted_at ON ai_models (deleted_at) WHERE deleted_at IS NOT NULL;
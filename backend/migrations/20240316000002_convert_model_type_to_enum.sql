@@ -0,0 +1,15 @@
+-- Restrict model_type to a known set of values
+CREATE TYPE model_type AS ENUM ('classification', 'regression', 'llm', 'embedding', 'vision', 'other');
+
+ALTER TABLE ai_models
+    ALTER COLUMN model_type TYPE model_type
+    USING (
+        CASE lower(trim(model_type))
+            WHEN 'classification' THEN 'classification'
+            WHEN 'regression' THEN 'regression'
+            WHEN 'llm' THEN 'llm'
+            WHEN 'embedding' THEN 'embedding'
+            WHEN 'vision' THEN 'vision'
+            ELSE 'other'
+        END
+    )::model_type;
@@ -0,0 +1,9 @@
+-- Record individual downloads so unique downloaders can be counted
+CREATE TABLE model_download_events (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    model_id UUID NOT NULL REFERENCES ai_models(id) ON DELETE CASCADE,
+    user_id UUID REFERENCES users(id),
+    downloaded_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_model_download_events_model_user ON model_download_events(model_id, user_id);
@@ -0,0 +1,14 @@
+-- Keep only the most recent default per user before enforcing uniqueness
+UPDATE payment_methods pm
+SET is_default = false
+WHERE is_default = true
+AND EXISTS (
+    SELECT 1 FROM payment_methods other
+    WHERE other.user_id = pm.user_id
+    AND other.is_default = true
+    AND other.created_at > pm.created_at
+);
+
+CREATE UNIQUE INDEX idx_payment_methods_one_default
+    ON payment_methods(user_id)
+    WHERE is_default = true;
@@ -0,0 +1,8 @@
+-- Allow a subscription to run until the end of its paid period after cancelling
+ALTER TABLE user_subscriptions
+    ADD COLUMN cancel_at_period_end BOOLEAN NOT NULL DEFAULT false,
+    ADD COLUMN current_period_end TIMESTAMPTZ;
+
+CREATE INDEX idx_user_subscriptions_pending_cancel
+    ON user_subscriptions(current_period_end)
+    WHERE is_active = true AND cancel_at_period_end = true;
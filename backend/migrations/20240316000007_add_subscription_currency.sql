@@ -0,0 +1,3 @@
+-- Prices on a plan are expressed in the plan's currency
+ALTER TABLE subscriptions
+    ADD COLUMN currency VARCHAR(3) NOT NULL DEFAULT 'USD';
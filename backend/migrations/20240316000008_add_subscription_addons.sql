@@ -0,0 +1,42 @@
+-- Optional extras purchased on top of a base plan
+CREATE TABLE addons (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name VARCHAR(100) NOT NULL UNIQUE,
+    description TEXT,
+    price_monthly DECIMAL(10,2) NOT NULL,
+    currency VARCHAR(3) NOT NULL DEFAULT 'USD',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE user_subscription_addons (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_subscription_id UUID NOT NULL REFERENCES user_subscriptions(id),
+    addon_id UUID NOT NULL REFERENCES addons(id),
+    quantity INTEGER NOT NULL DEFAULT 1 CHECK (quantity > 0),
+    added_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    removed_at TIMESTAMPTZ
+);
+
+CREATE UNIQUE INDEX idx_user_subscription_addons_active
+    ON user_subscription_addons(user_subscription_id, addon_id)
+    WHERE removed_at IS NULL;
+
+-- Credits owed to a user, deducted from their next charge
+CREATE TABLE subscription_credits (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id),
+    amount DECIMAL(10,2) NOT NULL CHECK (amount >= 0),
+    currency VARCHAR(3) NOT NULL,
+    reason VARCHAR(255) NOT NULL,
+    payment_intent_id UUID REFERENCES payment_intents(id),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_subscription_credits_unapplied
+    ON subscription_credits(user_id)
+    WHERE payment_intent_id IS NULL;
+
+INSERT INTO addons (name, description, price_monthly) VALUES
+('Extra seat', 'One additional team member', 10.00),
+('Priority support', 'Guaranteed response within 4 business hours', 49.00);
@@ -0,0 +1,15 @@
+-- Who created and last edited each model
+ALTER TABLE ai_models
+    ADD COLUMN created_by UUID REFERENCES users(id),
+    ADD COLUMN updated_by UUID REFERENCES users(id);
+
+-- Per-user log of actions taken on models
+CREATE TABLE model_activity (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id),
+    model_id UUID NOT NULL REFERENCES ai_models(id) ON DELETE CASCADE,
+    action VARCHAR(50) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_model_activity_user ON model_activity (user_id, created_at DESC);
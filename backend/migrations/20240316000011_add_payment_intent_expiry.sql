@@ -0,0 +1,10 @@
+-- Pending intents lapse after a fixed window and are then marked expired
+ALTER TABLE payment_intents ADD COLUMN expires_at TIMESTAMPTZ;
+
+UPDATE payment_intents
+SET expires_at = created_at + INTERVAL '24 hours'
+WHERE status = 'pending';
+
+CREATE INDEX idx_payment_intents_pending_expiry
+    ON payment_intents (expires_at)
+    WHERE status = 'pending';
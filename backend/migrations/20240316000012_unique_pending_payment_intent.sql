@@ -0,0 +1,15 @@
+-- Keep only the newest pending intent per user and plan before enforcing uniqueness
+UPDATE payment_intents pi
+SET status = 'expired', updated_at = NOW()
+WHERE status = 'pending'
+AND EXISTS (
+    SELECT 1 FROM payment_intents newer
+    WHERE newer.user_id = pi.user_id
+    AND newer.subscription_id = pi.subscription_id
+    AND newer.status = 'pending'
+    AND newer.created_at > pi.created_at
+);
+
+CREATE UNIQUE INDEX idx_payment_intents_one_pending
+    ON payment_intents (user_id, subscription_id)
+    WHERE status = 'pending';
@@ -0,0 +1,25 @@
+-- Promo codes applied at checkout
+CREATE TABLE coupons (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    code VARCHAR(50) NOT NULL UNIQUE,
+    percent_off DECIMAL(5,2) CHECK (percent_off > 0 AND percent_off <= 100),
+    amount_off DECIMAL(10,2) CHECK (amount_off > 0),
+    currency VARCHAR(3),
+    valid_until TIMESTAMPTZ,
+    max_redemptions INTEGER CHECK (max_redemptions > 0),
+    times_redeemed INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CHECK ((percent_off IS NULL) <> (amount_off IS NULL)),
+    CHECK (amount_off IS NULL OR currency IS NOT NULL)
+);
+
+CREATE TABLE coupon_redemptions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    coupon_id UUID NOT NULL REFERENCES coupons(id),
+    user_id UUID NOT NULL REFERENCES users(id),
+    payment_intent_id UUID NOT NULL REFERENCES payment_intents(id),
+    amount_off DECIMAL(10,2) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_coupon_redemptions_coupon ON coupon_redemptions(coupon_id);
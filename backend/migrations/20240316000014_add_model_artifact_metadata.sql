@@ -0,0 +1,4 @@
+-- Size and checksum of the downloadable model artifact
+ALTER TABLE ai_models
+    ADD COLUMN file_size_bytes BIGINT CHECK (file_size_bytes >= 0),
+    ADD COLUMN artifact_sha256 CHAR(64);
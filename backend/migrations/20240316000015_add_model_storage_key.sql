@@ -0,0 +1,2 @@
+-- Object key of the artifact in our own storage bucket
+ALTER TABLE ai_models ADD COLUMN storage_key TEXT;
@@ -0,0 +1,4 @@
+-- Largest page of models each plan may request from the list endpoint
+UPDATE subscriptions SET features = features || '{"max_page_size": 50}' WHERE tier = 'free';
+UPDATE subscriptions SET features = features || '{"max_page_size": 100}' WHERE tier = 'pro';
+UPDATE subscriptions SET features = features || '{"max_page_size": 500}' WHERE tier = 'enterprise';
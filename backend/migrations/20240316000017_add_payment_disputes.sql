@@ -0,0 +1,17 @@
+-- Chargebacks raised against our payments
+CREATE TABLE payment_disputes (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    stripe_dispute_id VARCHAR(255) NOT NULL UNIQUE,
+    stripe_charge_id VARCHAR(255) NOT NULL,
+    payment_intent_id UUID REFERENCES payment_intents(id),
+    user_id UUID REFERENCES users(id),
+    -- Minor units, as reported by Stripe
+    amount BIGINT NOT NULL,
+    currency VARCHAR(3) NOT NULL,
+    reason VARCHAR(50) NOT NULL,
+    status VARCHAR(50) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    closed_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_payment_disputes_user ON payment_disputes(user_id);
@@ -0,0 +1,29 @@
+-- performance_metrics is now read as a typed struct. Known metrics must be
+-- JSON numbers: numeric strings are converted and anything else is dropped.
+-- Other keys are left as they are.
+UPDATE ai_models
+SET performance_metrics = NULL
+WHERE performance_metrics IS NOT NULL
+AND jsonb_typeof(performance_metrics) <> 'object';
+
+UPDATE ai_models
+SET performance_metrics = (
+    SELECT COALESCE(
+        jsonb_object_agg(
+            key,
+            CASE
+                WHEN key IN ('accuracy', 'f1', 'latency_ms', 'throughput')
+                    AND jsonb_typeof(value) = 'string'
+                THEN to_jsonb((value #>> '{}')::float8)
+                ELSE value
+            END
+        ),
+        '{}'::jsonb
+    )
+    FROM jsonb_each(performance_metrics)
+    WHERE key NOT IN ('accuracy', 'f1', 'latency_ms', 'throughput')
+    OR jsonb_typeof(value) = 'number'
+    OR (jsonb_typeof(value) = 'string'
+        AND value #>> '{}' ~ '^\s*-?[0-9]+(\.[0-9]+)?([eE][-+]?[0-9]+)?\s*$')
+)
+WHERE performance_metrics IS NOT NULL;
@@ -0,0 +1,4 @@
+-- Retries of a failed payment point at the intent that started the checkout
+ALTER TABLE payment_intents ADD COLUMN retry_of UUID REFERENCES payment_intents(id);
+
+CREATE INDEX idx_payment_intents_retry_of ON payment_intents(retry_of);
@@ -0,0 +1,3 @@
+-- Role of each user. JWTs carry it as a claim; API keys resolve it here.
+ALTER TABLE users ADD COLUMN role VARCHAR(20) NOT NULL DEFAULT 'user'
+    CHECK (role IN ('user', 'admin'));
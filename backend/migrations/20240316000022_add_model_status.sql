@@ -0,0 +1,7 @@
+-- Review workflow for models. Everything already listed stays published.
+CREATE TYPE model_status AS ENUM ('draft', 'pending_review', 'published', 'rejected');
+
+ALTER TABLE ai_models ADD COLUMN status model_status NOT NULL DEFAULT 'draft';
+UPDATE ai_models SET status = 'published';
+
+CREATE INDEX idx_ai_models_status ON ai_models(status) WHERE deleted_at IS NULL;
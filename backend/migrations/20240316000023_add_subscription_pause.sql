@@ -0,0 +1,8 @@
+-- Paused subscriptions keep their row but grant no access until resumed
+ALTER TABLE user_subscriptions
+    ADD COLUMN paused_at TIMESTAMPTZ,
+    ADD COLUMN resume_at TIMESTAMPTZ,
+    ADD CHECK ((paused_at IS NULL) = (resume_at IS NULL));
+
+CREATE INDEX idx_user_subscriptions_resume_at ON user_subscriptions(resume_at)
+    WHERE paused_at IS NOT NULL;
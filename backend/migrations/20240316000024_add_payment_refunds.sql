@@ -0,0 +1,2 @@
+-- Running total refunded against each intent, in major units
+ALTER TABLE payment_intents ADD COLUMN amount_refunded DECIMAL(10,2) NOT NULL DEFAULT 0;
@@ -0,0 +1,17 @@
+-- A user has at most one active subscription. Keep only the newest active
+-- row for anyone who ended up with several.
+UPDATE user_subscriptions us
+SET is_active = false,
+    ends_at = NOW(),
+    updated_at = NOW()
+WHERE us.is_active = true
+AND EXISTS (
+    SELECT 1 FROM user_subscriptions newer
+    WHERE newer.user_id = us.user_id
+    AND newer.is_active = true
+    AND (newer.created_at, newer.id) > (us.created_at, us.id)
+);
+
+DROP INDEX idx_user_subscriptions_active;
+CREATE UNIQUE INDEX idx_user_subscriptions_active ON user_subscriptions(user_id)
+    WHERE is_active = true;
@@ -0,0 +1,10 @@
+-- Optional free trial when a plan is first subscribed to
+ALTER TABLE subscriptions
+    ADD COLUMN trial_days INT CHECK (trial_days > 0);
+
+ALTER TABLE user_subscriptions
+    ADD COLUMN trial_ends_at TIMESTAMPTZ,
+    ADD COLUMN trial_ending_notified_at TIMESTAMPTZ;
+
+CREATE INDEX idx_user_subscriptions_trialing ON user_subscriptions(trial_ends_at)
+    WHERE payment_status = 'trialing';
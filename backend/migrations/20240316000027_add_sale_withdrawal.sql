@@ -0,0 +1,5 @@
+-- Sellers can stop selling a model, either making it free or archiving it.
+-- Archived models stay downloadable only by users who already downloaded them.
+ALTER TABLE ai_models
+    ADD COLUMN withdrawn_from_sale_at TIMESTAMPTZ,
+    ADD COLUMN archived BOOLEAN NOT NULL DEFAULT false;
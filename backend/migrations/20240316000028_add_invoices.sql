@@ -0,0 +1,18 @@
+-- Invoices for successful payments. Numbers come from a sequence so they're
+-- unique and increasing even when payments succeed concurrently; a number
+-- drawn by a transaction that rolls back is skipped, never reused.
+CREATE SEQUENCE invoice_number_seq;
+
+CREATE TABLE invoices (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    number BIGINT NOT NULL UNIQUE DEFAULT nextval('invoice_number_seq'),
+    user_id UUID NOT NULL REFERENCES users(id),
+    payment_intent_id UUID NOT NULL UNIQUE REFERENCES payment_intents(id),
+    amount DECIMAL(10,2) NOT NULL,
+    currency VARCHAR(3) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+ALTER SEQUENCE invoice_number_seq OWNED BY invoices.number;
+
+CREATE INDEX idx_invoices_user ON invoices(user_id, number DESC);
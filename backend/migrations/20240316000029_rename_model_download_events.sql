@@ -0,0 +1,5 @@
+-- One row per download, now also used for download trends
+ALTER TABLE model_download_events RENAME TO model_downloads;
+ALTER INDEX idx_model_download_events_model_user RENAME TO idx_model_downloads_model_user;
+
+CREATE INDEX idx_model_downloads_model_time ON model_downloads(model_id, downloaded_at);
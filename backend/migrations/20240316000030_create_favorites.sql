@@ -0,0 +1,9 @@
+-- Models a user has saved while browsing
+CREATE TABLE favorites (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    model_id UUID NOT NULL REFERENCES ai_models(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, model_id)
+);
+
+CREATE INDEX idx_favorites_user_recent ON favorites(user_id, created_at DESC);
@@ -0,0 +1,9 @@
+-- Subscriptions are billed monthly or yearly; ends_at marks the end of the
+-- paid period once a payment activates them
+CREATE TYPE billing_interval AS ENUM ('monthly', 'yearly');
+
+ALTER TABLE user_subscriptions
+    ADD COLUMN billing_interval billing_interval NOT NULL DEFAULT 'monthly';
+
+CREATE INDEX idx_user_subscriptions_ends_at ON user_subscriptions(ends_at)
+    WHERE is_active = true;
@@ -0,0 +1,29 @@
+-- Snapshot of a model's descriptive fields for each version it has had.
+-- The latest snapshot mirrors the ai_models row.
+CREATE TABLE model_versions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    model_id UUID NOT NULL REFERENCES ai_models(id) ON DELETE CASCADE,
+    version VARCHAR(50) NOT NULL,
+    snapshot JSONB NOT NULL,
+    created_by UUID,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (model_id, version)
+);
+
+CREATE INDEX idx_model_versions_model_recent ON model_versions(model_id, created_at DESC);
+
+-- Existing models start with their current state as their only version
+INSERT INTO model_versions (model_id, version, snapshot, created_by, created_at, updated_at)
+SELECT id, version,
+       jsonb_build_object(
+           'name', name,
+           'description', description,
+           'model_type', model_type,
+           'framework', framework,
+           'metadata', metadata,
+           'performance_metrics', performance_metrics,
+           'repository_url', repository_url
+       ),
+       updated_by, updated_at, updated_at
+FROM ai_models;
@@ -0,0 +1,14 @@
+-- First response to each idempotent POST, replayed for repeats of the key
+CREATE TABLE idempotency_keys (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    key VARCHAR(255) NOT NULL,
+    route TEXT NOT NULL,
+    request_hash TEXT NOT NULL,
+    -- Both NULL while the first request is still being handled
+    response_status SMALLINT,
+    response_body BYTEA,
+    response_content_type TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (user_id, key, route)
+);
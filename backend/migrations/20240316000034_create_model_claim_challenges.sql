@@ -0,0 +1,9 @@
+-- Tokens users publish at an unowned model's repository_url to claim it
+CREATE TABLE model_claim_challenges (
+    model_id UUID NOT NULL REFERENCES ai_models(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    token TEXT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (model_id, user_id)
+);
@@ -0,0 +1,3 @@
+-- Lineage of models created with POST /api/models/:id/fork
+ALTER TABLE ai_models ADD COLUMN forked_from UUID REFERENCES ai_models(id) ON DELETE SET NULL;
+CREATE INDEX idx_ai_models_forked_from ON ai_models(forked_from) WHERE forked_from IS NOT NULL;
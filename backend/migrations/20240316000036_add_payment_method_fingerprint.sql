@@ -0,0 +1,6 @@
+-- Stripe's card fingerprint identifies the same physical card across
+-- payment method tokens; it is not the card number.
+ALTER TABLE payment_methods ADD COLUMN card_fingerprint VARCHAR(64);
+CREATE UNIQUE INDEX idx_payment_methods_user_fingerprint
+    ON payment_methods(user_id, card_fingerprint)
+    WHERE card_fingerprint IS NOT NULL;
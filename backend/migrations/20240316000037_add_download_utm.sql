@@ -0,0 +1,11 @@
+-- Campaign attribution passed as utm_* query parameters on downloads
+ALTER TABLE model_downloads
+    ADD COLUMN utm_source VARCHAR(100),
+    ADD COLUMN utm_medium VARCHAR(100),
+    ADD COLUMN utm_campaign VARCHAR(100),
+    ADD COLUMN utm_term VARCHAR(100),
+    ADD COLUMN utm_content VARCHAR(100);
+
+CREATE INDEX idx_model_downloads_model_campaign
+    ON model_downloads(model_id, utm_source, utm_campaign)
+    WHERE utm_source IS NOT NULL OR utm_campaign IS NOT NULL;
@@ -0,0 +1,14 @@
+-- One free trial per user across all plans, unless an admin grants another
+ALTER TABLE users
+    ADD COLUMN trial_used_at TIMESTAMPTZ,
+    ADD COLUMN trial_exception BOOLEAN NOT NULL DEFAULT false;
+
+UPDATE users u
+SET trial_used_at = prior.first_trial
+FROM (
+    SELECT user_id, MIN(starts_at) AS first_trial
+    FROM user_subscriptions
+    WHERE trial_ends_at IS NOT NULL
+    GROUP BY user_id
+) prior
+WHERE prior.user_id = u.id;
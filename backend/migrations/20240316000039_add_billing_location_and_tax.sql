@@ -0,0 +1,7 @@
+-- Where a user is billed, used to work out tax on their payments
+ALTER TABLE users
+    ADD COLUMN billing_country CHAR(2),
+    ADD COLUMN billing_region VARCHAR(10);
+
+-- Tax included in `amount`
+ALTER TABLE payment_intents ADD COLUMN tax_amount DECIMAL(10,2) NOT NULL DEFAULT 0;
@@ -0,0 +1,17 @@
+-- An owner's live models must have distinct names, ignoring case. Rename
+-- existing duplicates (all but the oldest) so the index can be built.
+WITH duplicates AS (
+    SELECT id, ROW_NUMBER() OVER (
+        PARTITION BY owner_id, lower(name) ORDER BY created_at, id
+    ) AS position
+    FROM ai_models
+    WHERE owner_id IS NOT NULL AND deleted_at IS NULL
+)
+UPDATE ai_models
+SET name = left(ai_models.name, 244) || ' (' || left(ai_models.id::text, 8) || ')'
+FROM duplicates
+WHERE duplicates.id = ai_models.id AND duplicates.position > 1;
+
+CREATE UNIQUE INDEX idx_ai_models_owner_name
+    ON ai_models (owner_id, lower(name))
+    WHERE deleted_at IS NULL;
@@ -0,0 +1,6 @@
+-- Stripe event ids already handled, so a replayed delivery is ignored
+CREATE TABLE stripe_webhook_events (
+    event_id VARCHAR(255) PRIMARY KEY,
+    event_type VARCHAR(100) NOT NULL,
+    received_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,7 @@
+-- Links a subscription to one managed in Stripe Billing, so Stripe's
+-- subscription webhooks can find it
+ALTER TABLE user_subscriptions ADD COLUMN stripe_subscription_id VARCHAR(255);
+
+CREATE UNIQUE INDEX idx_user_subscriptions_stripe_id
+    ON user_subscriptions(stripe_subscription_id)
+    WHERE stripe_subscription_id IS NOT NULL;
@@ -0,0 +1,6 @@
+-- Marks closed accounts, so closing one again is a no-op rather than an error
+ALTER TABLE users ADD COLUMN deleted_at TIMESTAMPTZ;
+
+UPDATE users
+SET deleted_at = updated_at
+WHERE email LIKE 'deleted-%@deleted.invalid';
@@ -0,0 +1,2 @@
+-- Incremental catalog sync pages through models by last change
+CREATE INDEX idx_ai_models_updated_at ON ai_models(updated_at, id);
@@ -0,0 +1,3 @@
+-- When a model's artifact last moved to a new storage key, revoking the
+-- download URLs signed for the old one
+ALTER TABLE ai_models ADD COLUMN key_rotated_at TIMESTAMPTZ;
@@ -0,0 +1,8 @@
+-- Each user's Stripe customer, recorded when it's created so it's never
+-- looked up (or created twice) through Stripe's metadata search
+CREATE TABLE stripe_customers (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    stripe_customer_id VARCHAR(255) NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,7 @@
+-- Synthetic download history for models that predate per-download events
+ALTER TABLE model_downloads
+    ADD COLUMN backfilled BOOLEAN NOT NULL DEFAULT false;
+
+CREATE INDEX idx_model_downloads_backfilled
+    ON model_downloads(model_id)
+    WHERE backfilled;
@@ -0,0 +1,15 @@
+-- Plan prices and payment amounts in the currency's smallest unit, so they
+-- round-trip through Money without going through floating point
+ALTER TABLE subscriptions
+    ALTER COLUMN price_monthly TYPE BIGINT
+        USING ROUND(price_monthly * CASE WHEN UPPER(currency) = 'JPY' THEN 1 ELSE 100 END)::BIGINT,
+    ALTER COLUMN price_yearly TYPE BIGINT
+        USING ROUND(price_yearly * CASE WHEN UPPER(currency) = 'JPY' THEN 1 ELSE 100 END)::BIGINT;
+
+ALTER TABLE payment_intents
+    ALTER COLUMN amount TYPE BIGINT
+        USING ROUND(amount * CASE WHEN UPPER(currency) = 'JPY' THEN 1 ELSE 100 END)::BIGINT;
+
+ALTER TABLE payment_history
+    ALTER COLUMN amount TYPE BIGINT
+        USING ROUND(amount * CASE WHEN UPPER(currency) = 'JPY' THEN 1 ELSE 100 END)::BIGINT;
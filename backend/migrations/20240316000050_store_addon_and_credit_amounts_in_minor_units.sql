@@ -0,0 +1,8 @@
+-- Add-on prices and credits in the currency's smallest unit
+ALTER TABLE addons
+    ALTER COLUMN price_monthly TYPE BIGINT
+        USING ROUND(price_monthly * CASE WHEN UPPER(currency) = 'JPY' THEN 1 ELSE 100 END)::BIGINT;
+
+ALTER TABLE subscription_credits
+    ALTER COLUMN amount TYPE BIGINT
+        USING ROUND(amount * CASE WHEN UPPER(currency) = 'JPY' THEN 1 ELSE 100 END)::BIGINT;
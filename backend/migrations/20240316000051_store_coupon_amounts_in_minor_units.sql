@@ -0,0 +1,16 @@
+-- Fixed coupon discounts in the currency's smallest unit
+ALTER TABLE coupons
+    ALTER COLUMN amount_off TYPE BIGINT
+        USING ROUND(amount_off * CASE WHEN UPPER(currency) = 'JPY' THEN 1 ELSE 100 END)::BIGINT;
+
+-- Redemptions take their currency from the payment they discounted
+ALTER TABLE coupon_redemptions ADD COLUMN amount_off_minor BIGINT;
+
+UPDATE coupon_redemptions cr
+SET amount_off_minor = ROUND(cr.amount_off * CASE WHEN UPPER(pi.currency) = 'JPY' THEN 1 ELSE 100 END)
+FROM payment_intents pi
+WHERE pi.id = cr.payment_intent_id;
+
+ALTER TABLE coupon_redemptions DROP COLUMN amount_off;
+ALTER TABLE coupon_redemptions RENAME COLUMN amount_off_minor TO amount_off;
+ALTER TABLE coupon_redemptions ALTER COLUMN amount_off SET NOT NULL;
@@ -0,0 +1,4 @@
+-- Refunded totals in the currency's smallest unit, like `amount`
+ALTER TABLE payment_intents
+    ALTER COLUMN amount_refunded TYPE BIGINT
+        USING ROUND(amount_refunded * CASE WHEN UPPER(currency) = 'JPY' THEN 1 ELSE 100 END)::BIGINT;
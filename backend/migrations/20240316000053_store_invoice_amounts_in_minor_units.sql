@@ -0,0 +1,4 @@
+-- Invoice amounts in the currency's smallest unit, like the payments they're for
+ALTER TABLE invoices
+    ALTER COLUMN amount TYPE BIGINT
+        USING ROUND(amount * CASE WHEN UPPER(currency) = 'JPY' THEN 1 ELSE 100 END)::BIGINT;
@@ -0,0 +1,4 @@
+-- Tax in the currency's smallest unit, like the `amount` it's included in
+ALTER TABLE payment_intents
+    ALTER COLUMN tax_amount TYPE BIGINT
+        USING ROUND(tax_amount * CASE WHEN UPPER(currency) = 'JPY' THEN 1 ELSE 100 END)::BIGINT;
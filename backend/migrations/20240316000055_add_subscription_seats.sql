@@ -0,0 +1,13 @@
+-- Seats on a subscription, and the members of the subscriber's team using them
+ALTER TABLE user_subscriptions
+    ADD COLUMN seats INTEGER NOT NULL DEFAULT 1 CHECK (seats >= 1);
+
+CREATE TABLE subscription_seat_assignments (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_subscription_id UUID NOT NULL REFERENCES user_subscriptions(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    assigned_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (user_subscription_id, user_id)
+);
+
+CREATE INDEX idx_seat_assignments_user ON subscription_seat_assignments(user_id);
@@ -0,0 +1,12 @@
+-- Stripe subscriptions and payment methods of closed accounts still to be
+-- canceled or detached; rows are removed once Stripe confirms
+CREATE TABLE pending_stripe_cleanups (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    kind VARCHAR(20) NOT NULL CHECK (kind IN ('subscription', 'payment_method')),
+    stripe_id VARCHAR(255) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (kind, stripe_id)
+);
+
+CREATE INDEX idx_pending_stripe_cleanups_user ON pending_stripe_cleanups(user_id);
@@ -0,0 +1,20 @@
+-- Keep every snapshot instead of overwriting a version's snapshot when a
+-- model is edited without bumping its version. Snapshots are numbered per
+-- model, and several may share a version.
+ALTER TABLE model_versions ADD COLUMN sequence INTEGER;
+
+UPDATE model_versions v
+SET sequence = numbered.sequence
+FROM (
+    SELECT id, row_number() OVER (PARTITION BY model_id ORDER BY created_at, id) AS sequence
+    FROM model_versions
+) numbered
+WHERE v.id = numbered.id;
+
+ALTER TABLE model_versions
+    ALTER COLUMN sequence SET NOT NULL,
+    DROP CONSTRAINT model_versions_model_id_version_key,
+    ADD CONSTRAINT model_versions_model_id_sequence_key UNIQUE (model_id, sequence);
+
+DROP INDEX idx_model_versions_model_recent;
+CREATE INDEX idx_model_versions_model_version ON model_versions(model_id, version, sequence DESC);
@@ -0,0 +1,204 @@
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{header::AUTHORIZATION, request::Parts, HeaderName},
+};
+use jsonwebtoken::{decode, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    error::AppError,
+    models::{account_is_open, ApiKey},
+    AppState,
+};
+
+pub static API_KEY_HEADER: HeaderName = HeaderName::from_static("x-api-key");
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    Admin,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: Uuid,
+    pub exp: usize,
+    #[serde(default)]
+    pub role: Role,
+}
+
+/// The authenticated caller, resolved from a `Bearer` JWT or an
+/// `X-API-Key` header. Closed accounts are rejected either way.
+#[derive(Debug, Clone, Copy)]
+pub struct AuthUser(pub Uuid);
+
+/// The authenticated caller along with their role, for handlers that allow
+/// either the resource's owner or an admin.
+#[derive(Debug, Clone, Copy)]
+pub struct Caller {
+    pub user_id: Uuid,
+    pub role: Role,
+}
+
+impl Caller {
+    /// Tags the request span with the user so later log lines carry it.
+    fn identified(user_id: Uuid, role: Role) -> Self {
+        tracing::Span::current().record("user_id", tracing::field::display(user_id));
+        Caller { user_id, role }
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+
+    /// Succeeds for admins and for the owner of a resource.
+    pub fn ensure_owner_or_admin(&self, owner_id: Option<Uuid>) -> Result<(), AppError> {
+        if self.is_admin() || owner_id == Some(self.user_id) {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden(
+                "Only the owner or an admin can do this".into(),
+            ))
+        }
+    }
+}
+
+/// An authenticated caller with the `admin` role.
+#[derive(Debug, Clone, Copy)]
+pub struct AdminUser(pub Uuid);
+
+fn decode_claims(parts: &Parts, jwt_secret: &str) -> Result<Claims, AppError> {
+    let token = parts
+        .headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| AppError::Unauthorized("Missing bearer token".into()))?;
+
+    let claims = decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(jwt_secret.as_bytes()),
+        &Validation::default(),
+    )
+    .map_err(|_| AppError::Unauthorized("Invalid or expired token".into()))?
+    .claims;
+
+    Ok(claims)
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Caller
+where
+    AppState: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let state = AppState::from_ref(state);
+
+        if let Some(key) = parts.headers.get(&API_KEY_HEADER) {
+            let key = key
+                .to_str()
+                .map_err(|_| AppError::Unauthorized("Invalid API key".into()))?;
+            let (user_id, is_admin) = ApiKey::authenticate(&state.pool, key)
+                .await?
+                .ok_or_else(|| AppError::Unauthorized("Invalid or revoked API key".into()))?;
+            let role = if is_admin { Role::Admin } else { Role::User };
+            return Ok(Caller::identified(user_id, role));
+        }
+
+        let claims = decode_claims(parts, &state.jwt_secret)?;
+        if !account_is_open(&state.pool, claims.sub).await? {
+            return Err(AppError::Unauthorized("This account has been closed".into()));
+        }
+
+        Ok(Caller::identified(claims.sub, claims.role))
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthUser
+where
+    AppState: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let caller = Caller::from_request_parts(parts, state).await?;
+
+        Ok(AuthUser(caller.user_id))
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AdminUser
+where
+    AppState: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let caller = Caller::from_request_parts(parts, state).await?;
+
+        if !caller.is_admin() {
+            return Err(AppError::Forbidden("Admin access required".into()));
+        }
+
+        Ok(AdminUser(caller.user_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{app_state, create_user};
+    use axum::http::Request;
+    use sqlx::PgPool;
+
+    async fn authenticate(state: &AppState, key: &str) -> Result<AuthUser, AppError> {
+        let request = Request::builder().header(&API_KEY_HEADER, key).body(()).unwrap();
+        let (mut parts, ()) = request.into_parts();
+        AuthUser::from_request_parts(&mut parts, state).await
+    }
+
+    #[sqlx::test]
+    async fn an_api_key_authenticates_its_owner_until_revoked(pool: PgPool) {
+        let user_id = create_user(&pool).await;
+        let created = ApiKey::create(&pool, user_id, "CI").await.unwrap();
+        let state = app_state(&pool).await;
+
+        let AuthUser(authenticated) = authenticate(&state, &created.key).await.unwrap();
+        assert_eq!(authenticated, user_id);
+        let listed = ApiKey::list_for_user(&pool, user_id).await.unwrap();
+        assert!(listed[0].last_used_at.is_some());
+
+        ApiKey::revoke(&pool, user_id, created.api_key.id).await.unwrap().unwrap();
+        let revoked = authenticate(&state, &created.key).await;
+        assert!(matches!(revoked, Err(AppError::Unauthorized(_))));
+        let unknown = authenticate(&state, "mk_not-a-key").await;
+        assert!(matches!(unknown, Err(AppError::Unauthorized(_))));
+    }
+
+    #[sqlx::test]
+    async fn only_a_hash_of_the_key_is_stored(pool: PgPool) {
+        let user_id = create_user(&pool).await;
+        let created = ApiKey::create(&pool, user_id, "CI").await.unwrap();
+
+        let rows: Vec<String> =
+            sqlx::query_scalar!(r#"SELECT row_to_json(k)::text AS "row!" FROM api_keys k"#)
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+
+        assert_eq!(rows.len(), 1);
+        assert!(!rows[0].contains(&created.key));
+        assert!(rows[0].contains(&crate::models::hash_api_key(&created.key)));
+    }
+}
@@ -0,0 +1,101 @@
+use sqlx::PgPool;
+
+/// Rows inserted by one run of [`download_stats`].
+#[derive(Debug, Default)]
+pub struct BackfillSummary {
+    pub models: i64,
+    pub downloads: i64,
+}
+
+/// Give models from before `model_downloads` existed a download history.
+/// Each model whose `download_count` exceeds its recorded downloads gets
+/// the difference as anonymous rows flagged `backfilled`, spread evenly
+/// from its creation to its first real download (or now). Models that
+/// were already backfilled are skipped, so running it again inserts nothing.
+pub async fn download_stats(pool: &PgPool) -> Result<BackfillSummary, sqlx::Error> {
+    let summary = sqlx::query!(
+        r#"
+        WITH inserted AS (
+            INSERT INTO model_downloads (model_id, user_id, downloaded_at, backfilled)
+            SELECT m.id, NULL,
+                   m.created_at
+                       + (COALESCE(history.first, NOW()) - m.created_at)
+                       * ((n - 0.5) / (m.download_count - history.recorded))::float8,
+                   true
+            FROM ai_models m
+            CROSS JOIN LATERAL (
+                SELECT COUNT(*) AS recorded, MIN(d.downloaded_at) AS first
+                FROM model_downloads d
+                WHERE d.model_id = m.id
+            ) history
+            CROSS JOIN LATERAL generate_series(1, m.download_count - history.recorded) AS n
+            WHERE m.download_count > history.recorded
+            AND NOT EXISTS (
+                SELECT 1 FROM model_downloads d
+                WHERE d.model_id = m.id AND d.backfilled
+            )
+            RETURNING model_id
+        )
+        SELECT COUNT(DISTINCT model_id) AS "models!", COUNT(*) AS "downloads!"
+        FROM inserted
+        "#
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(BackfillSummary {
+        models: summary.models,
+        downloads: summary.downloads,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    async fn insert_model(pool: &PgPool, download_count: i32) -> Uuid {
+        sqlx::query_scalar!(
+            r#"
+            INSERT INTO ai_models (name, description, model_type, framework, version, download_count, created_at)
+            VALUES ('Model', 'A model', 'classification', 'pytorch', '1.0', $1, NOW() - INTERVAL '30 days')
+            RETURNING id
+            "#,
+            download_count
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn backfills_the_missing_downloads_once(pool: PgPool) {
+        let model_id = insert_model(&pool, 5).await;
+        sqlx::query!("INSERT INTO model_downloads (model_id) VALUES ($1)", model_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        insert_model(&pool, 0).await;
+
+        let summary = download_stats(&pool).await.unwrap();
+        assert_eq!((summary.models, summary.downloads), (1, 4));
+
+        let history = sqlx::query!(
+            r#"
+            SELECT COUNT(*) AS "total!",
+                   COUNT(*) FILTER (WHERE backfilled) AS "backfilled!",
+                   BOOL_AND(downloaded_at <= NOW()) AS "in_the_past!"
+            FROM model_downloads WHERE model_id = $1
+            "#,
+            model_id
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!((history.total, history.backfilled), (5, 4));
+        assert!(history.in_the_past);
+
+        let again = download_stats(&pool).await.unwrap();
+        assert_eq!((again.models, again.downloads), (0, 0));
+    }
+}
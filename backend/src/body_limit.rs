@@ -0,0 +1,147 @@
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http_body_util::{LengthLimitError, Limited};
+use std::env;
+
+use crate::error::AppError;
+
+/// Stripe sends the webhook itself, so it gets its own cap.
+const WEBHOOK_PATH: &str = "/api/payments/webhook";
+
+/// Largest request bodies accepted: `MAX_BODY_BYTES` (default 1 MiB) for
+/// most routes and `MAX_WEBHOOK_BODY_BYTES` (default 5 MiB) for the Stripe
+/// webhook.
+#[derive(Debug, Clone)]
+pub struct BodyLimits {
+    pub default: usize,
+    pub webhook: usize,
+}
+
+impl BodyLimits {
+    pub fn from_env() -> Self {
+        Self {
+            default: bytes_from_env("MAX_BODY_BYTES", 1024 * 1024),
+            webhook: bytes_from_env("MAX_WEBHOOK_BODY_BYTES", 5 * 1024 * 1024),
+        }
+    }
+
+    fn for_path(&self, path: &str) -> usize {
+        if path == WEBHOOK_PATH {
+            self.webhook
+        } else {
+            self.default
+        }
+    }
+}
+
+fn bytes_from_env(name: &str, default: usize) -> usize {
+    env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|bytes| *bytes > 0)
+        .unwrap_or(default)
+}
+
+/// Reject bodies over the limit with a JSON `413`. A declared
+/// `Content-Length` is checked up front; otherwise the body is cut off
+/// once it passes the limit, and whatever was reading it answers `413`.
+pub async fn limit_body(State(limits): State<BodyLimits>, request: Request, next: Next) -> Response {
+    let limit = limits.for_path(request.uri().path());
+
+    let declared = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if declared.is_some_and(|length| length > limit as u64) {
+        return too_large(limit);
+    }
+
+    let request = request.map(|body| Body::new(Limited::new(body, limit)));
+    let response = next.run(request).await;
+
+    // Extractors answer in plain text; keep error bodies uniform.
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if response.status() == StatusCode::PAYLOAD_TOO_LARGE && !is_json {
+        return too_large(limit);
+    }
+    response
+}
+
+fn too_large(limit: usize) -> Response {
+    AppError::PayloadTooLarge(format!("Request body must be at most {} bytes", limit))
+        .into_response()
+}
+
+/// Whether reading a body failed because it passed [`limit_body`]'s cap.
+pub fn is_length_limit_error(error: &axum::Error) -> bool {
+    let mut source = std::error::Error::source(error);
+    while let Some(e) = source {
+        if e.is::<LengthLimitError>() {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::DefaultBodyLimit, middleware, routing::post, Json, Router};
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    async fn post_json(app: &Router, path: &str, body: Body, length: Option<usize>) -> Response {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri(path)
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(length) = length {
+            request = request.header(header::CONTENT_LENGTH, length);
+        }
+        app.clone().oneshot(request.body(body).unwrap()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn an_over_limit_body_is_a_json_413_whether_declared_or_streamed() {
+        let limits = BodyLimits {
+            default: 64,
+            webhook: 256,
+        };
+        let echo = post(|Json(body): Json<Value>| async move { Json(body) });
+        let app = Router::new()
+            .route("/api/models", echo.clone())
+            .route(WEBHOOK_PATH, echo)
+            .layer(DefaultBodyLimit::disable())
+            .layer(middleware::from_fn_with_state(limits, limit_body));
+        let payload = serde_json::to_string(&serde_json::json!({ "description": "x".repeat(100) }))
+            .unwrap();
+        let streamed = || {
+            let chunks = payload.as_bytes().chunks(16).map(|c| Ok::<_, std::io::Error>(c.to_vec()));
+            Body::from_stream(futures::stream::iter(chunks.collect::<Vec<_>>()))
+        };
+
+        let (whole, length) = (|| Body::from(payload.clone()), Some(payload.len()));
+        let declared = post_json(&app, "/api/models", whole(), length);
+        let streamed = post_json(&app, "/api/models", streamed(), None).await;
+        for response in [declared.await, streamed] {
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["error"], "Request body must be at most 64 bytes");
+        }
+
+        let webhook = post_json(&app, WEBHOOK_PATH, whole(), length).await;
+        assert_eq!(webhook.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,164 @@
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tokio::sync::Semaphore;
+
+use crate::{error::AppError, AppState};
+
+/// Expensive routes whose in-flight requests are capped together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteGroup {
+    /// Filtered listings and comparisons.
+    Search,
+    /// Full-catalog exports.
+    Export,
+}
+
+impl RouteGroup {
+    fn of(method: &Method, path: &str) -> Option<Self> {
+        match (method, path) {
+            (&Method::GET, "/api/models") | (&Method::POST, "/api/models/compare") => {
+                Some(RouteGroup::Search)
+            }
+            (&Method::GET, "/api/models/export") => Some(RouteGroup::Export),
+            _ => None,
+        }
+    }
+}
+
+fn semaphore_from_env(var: &str, default: usize) -> Arc<Semaphore> {
+    let limit = env::var(var)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default);
+    Arc::new(Semaphore::new(limit))
+}
+
+/// Caps concurrent requests per [`RouteGroup`] so a burst of expensive
+/// queries can't exhaust the database pool. Requests over the cap are shed
+/// immediately rather than queued.
+///
+/// | Variable | Meaning |
+/// | --- | --- |
+/// | `CONCURRENCY_LIMIT_SEARCH` | In-flight listings and comparisons (default 32). |
+/// | `CONCURRENCY_LIMIT_EXPORT` | In-flight exports (default 4). |
+/// | `CONCURRENCY_RETRY_AFTER_SECS` | `Retry-After` sent with shed requests (default 1). |
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimits {
+    search: Arc<Semaphore>,
+    export: Arc<Semaphore>,
+    retry_after: Duration,
+}
+
+impl ConcurrencyLimits {
+    pub fn from_env() -> Self {
+        Self {
+            search: semaphore_from_env("CONCURRENCY_LIMIT_SEARCH", 32),
+            export: semaphore_from_env("CONCURRENCY_LIMIT_EXPORT", 4),
+            retry_after: Duration::from_secs(
+                env::var("CONCURRENCY_RETRY_AFTER_SECS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(1),
+            ),
+        }
+    }
+
+    fn semaphore(&self, group: RouteGroup) -> &Arc<Semaphore> {
+        match group {
+            RouteGroup::Search => &self.search,
+            RouteGroup::Export => &self.export,
+        }
+    }
+}
+
+/// Answers `503` with `Retry-After` when a request's group is saturated.
+/// Needs `MatchedPath`, so install it with `route_layer`.
+pub async fn shed_load(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let group = request
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|path| RouteGroup::of(request.method(), path.as_str()));
+    let Some(group) = group else {
+        return next.run(request).await;
+    };
+
+    let limits = &state.concurrency_limits;
+    // Held until the response is built; streamed bodies aren't counted.
+    let Ok(_permit) = limits.semaphore(group).clone().try_acquire_owned() else {
+        tracing::warn!("shedding {:?} request: concurrency limit reached", group);
+        return AppError::Overloaded {
+            message: "The server is busy; try again shortly".into(),
+            retry_after: limits.retry_after,
+        }
+        .into_response();
+    };
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::app_state;
+    use axum::{body::Body, http::StatusCode, middleware, routing::get, Router};
+    use sqlx::PgPool;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::ServiceExt;
+
+    #[sqlx::test]
+    async fn the_request_past_the_cap_is_shed_while_cheap_routes_run(pool: PgPool) {
+        let mut state = app_state(&pool).await;
+        state.concurrency_limits = ConcurrencyLimits {
+            search: Arc::new(Semaphore::new(2)),
+            export: Arc::new(Semaphore::new(2)),
+            retry_after: Duration::from_secs(3),
+        };
+        // Listings park here until the test lets them finish.
+        let gate = Arc::new(Semaphore::new(0));
+        let entered = Arc::new(AtomicUsize::new(0));
+        let (parked, in_flight) = (gate.clone(), entered.clone());
+        let app = Router::new()
+            .route(
+                "/api/models",
+                get(move || async move {
+                    in_flight.fetch_add(1, Ordering::SeqCst);
+                    let _ = parked.acquire().await.unwrap();
+                    StatusCode::OK
+                }),
+            )
+            .route("/api/health", get(|| async { StatusCode::OK }))
+            .route_layer(middleware::from_fn_with_state(state.clone(), shed_load))
+            .with_state(state);
+        let get_status = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+                app.oneshot(request).await.unwrap()
+            }
+        };
+
+        let listings: Vec<_> = (0..2).map(|_| tokio::spawn(get_status("/api/models"))).collect();
+        while entered.load(Ordering::SeqCst) < 2 {
+            tokio::task::yield_now().await;
+        }
+
+        let shed = get_status("/api/models").await;
+        assert_eq!(shed.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(shed.headers()["retry-after"], "3");
+        assert_eq!(get_status("/api/health").await.status(), StatusCode::OK);
+
+        gate.add_permits(3);
+        for listing in listings {
+            assert_eq!(listing.await.unwrap().status(), StatusCode::OK);
+        }
+        assert_eq!(get_status("/api/models").await.status(), StatusCode::OK);
+    }
+}
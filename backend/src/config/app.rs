@@ -0,0 +1,228 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::{ConnectRetry, PoolSettings};
+
+/// Application settings read from the environment.
+///
+/// | Variable                        | Default   |
+/// |---------------------------------|-----------|
+/// | `DATABASE_URL`                  | required  |
+/// | `HOST`                          | `0.0.0.0` |
+/// | `PORT`                          | 3000      |
+/// | `JWT_SECRET`                    | required  |
+/// | `STRIPE_SECRET_KEY`             | required  |
+/// | `STRIPE_WEBHOOK_SECRET`         | required  |
+/// | `STRIPE_WEBHOOK_TOLERANCE_SECS` | 300       |
+///
+/// plus the pool variables on [`PoolSettings`] and [`ConnectRetry`].
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub database_url: String,
+    pub addr: SocketAddr,
+    pub jwt_secret: String,
+    pub stripe_secret_key: String,
+    pub stripe_webhook_secret: String,
+    /// How old a webhook's signed timestamp may be, `STRIPE_WEBHOOK_TOLERANCE_SECS`.
+    pub stripe_webhook_tolerance: Duration,
+    pub pool: PoolSettings,
+    pub connect_retry: ConnectRetry,
+}
+
+impl Config {
+    /// Read and check every setting, reporting all the problems at once
+    /// rather than stopping at the first.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::read(EnvReader::new(|variable| std::env::var(variable).ok()))
+    }
+
+    fn read(mut env: EnvReader) -> Result<Self, ConfigError> {
+
+        let database_url = env.required("DATABASE_URL");
+        if !database_url.is_empty()
+            && !database_url.starts_with("postgres://")
+            && !database_url.starts_with("postgresql://")
+        {
+            env.invalid("DATABASE_URL", "must be a postgres:// URL");
+        }
+
+        let host = env.optional("HOST").unwrap_or_else(|| "0.0.0.0".into());
+        let port: u16 = env.parsed("PORT", 3000);
+        if port == 0 {
+            env.invalid("PORT", "must be between 1 and 65535");
+        }
+        let addr = match format!("{}:{}", host, port).parse() {
+            Ok(addr) => addr,
+            Err(_) => {
+                env.invalid("HOST", format!("{:?} is not an IP address", host));
+                SocketAddr::from(([0, 0, 0, 0], port))
+            }
+        };
+
+        let jwt_secret = env.required("JWT_SECRET");
+        let stripe_secret_key = env.required("STRIPE_SECRET_KEY");
+        if !stripe_secret_key.is_empty() && !stripe_secret_key.starts_with("sk_") {
+            env.invalid("STRIPE_SECRET_KEY", "must start with sk_");
+        }
+        let stripe_webhook_secret = env.required("STRIPE_WEBHOOK_SECRET");
+        if !stripe_webhook_secret.is_empty() && !stripe_webhook_secret.starts_with("whsec_") {
+            env.invalid("STRIPE_WEBHOOK_SECRET", "must start with whsec_");
+        }
+        let stripe_webhook_tolerance =
+            Duration::from_secs(env.parsed("STRIPE_WEBHOOK_TOLERANCE_SECS", 300));
+
+        let pool = PoolSettings::read(&mut env);
+        let connect_retry = ConnectRetry::read(&mut env);
+
+        env.finish()?;
+        Ok(Self {
+            database_url,
+            addr,
+            jwt_secret,
+            stripe_secret_key,
+            stripe_webhook_secret,
+            stripe_webhook_tolerance,
+            pool,
+            connect_retry,
+        })
+    }
+}
+
+/// One environment variable that's missing or unusable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigProblem {
+    pub variable: &'static str,
+    pub message: String,
+}
+
+/// Every problem found by [`Config::from_env`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    pub problems: Vec<ConfigProblem>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid configuration:")?;
+        for problem in &self.problems {
+            write!(f, "\n  {}: {}", problem.variable, problem.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Where variables come from: the process environment outside tests.
+type Lookup = Box<dyn Fn(&str) -> Option<String>>;
+
+/// Reads variables while collecting problems instead of failing on the
+/// first. Values that can't be read come back empty or as the default so
+/// reading can carry on.
+pub(super) struct EnvReader {
+    lookup: Lookup,
+    problems: Vec<ConfigProblem>,
+}
+
+impl EnvReader {
+    fn new(lookup: impl Fn(&str) -> Option<String> + 'static) -> Self {
+        Self {
+            lookup: Box::new(lookup),
+            problems: Vec::new(),
+        }
+    }
+
+    pub(super) fn optional(&self, variable: &'static str) -> Option<String> {
+        (self.lookup)(variable)
+    }
+
+    pub(super) fn required(&mut self, variable: &'static str) -> String {
+        match self.optional(variable).filter(|v| !v.trim().is_empty()) {
+            Some(value) => value,
+            None => {
+                self.invalid(variable, "must be set");
+                String::new()
+            }
+        }
+    }
+
+    pub(super) fn parsed<T: FromStr>(&mut self, variable: &'static str, default: T) -> T {
+        match self.optional(variable) {
+            Some(value) => value.trim().parse().unwrap_or_else(|_| {
+                self.invalid(variable, format!("{:?} is not a valid number", value));
+                default
+            }),
+            None => default,
+        }
+    }
+
+    pub(super) fn invalid(&mut self, variable: &'static str, message: impl Into<String>) {
+        self.problems.push(ConfigProblem {
+            variable,
+            message: message.into(),
+        });
+    }
+
+    fn finish(self) -> Result<(), ConfigError> {
+        if self.problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError {
+                problems: self.problems,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn read(vars: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        let vars: HashMap<String, String> =
+            vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        Config::read(EnvReader::new(move |variable| vars.get(variable).cloned()))
+    }
+
+    #[test]
+    fn every_missing_or_invalid_setting_is_reported_together() {
+        let error = read(&[
+            ("STRIPE_SECRET_KEY", "pk_live_oops"),
+            ("PORT", "eighty"),
+            ("DB_MAX_CONNECTIONS", "0"),
+        ])
+        .unwrap_err();
+
+        let variables: Vec<_> = error.problems.iter().map(|p| p.variable).collect();
+        assert_eq!(
+            variables,
+            [
+                "DATABASE_URL",
+                "PORT",
+                "JWT_SECRET",
+                "STRIPE_SECRET_KEY",
+                "STRIPE_WEBHOOK_SECRET",
+                "DB_MAX_CONNECTIONS",
+            ]
+        );
+        assert!(error.to_string().contains("\n  STRIPE_SECRET_KEY: must start with sk_"));
+    }
+
+    #[test]
+    fn a_complete_environment_reads_with_defaults_filled_in() {
+        let config = read(&[
+            ("DATABASE_URL", "postgres://localhost/home"),
+            ("JWT_SECRET", "secret"),
+            ("STRIPE_SECRET_KEY", "sk_test_1"),
+            ("STRIPE_WEBHOOK_SECRET", "whsec_1"),
+        ])
+        .unwrap();
+
+        assert_eq!(config.addr, SocketAddr::from(([0, 0, 0, 0], 3000)));
+        assert_eq!(config.stripe_webhook_tolerance, Duration::from_secs(300));
+        assert_eq!(config.pool, PoolSettings::default());
+    }
+}
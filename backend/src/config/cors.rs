@@ -0,0 +1,202 @@
+use axum::http::{header, HeaderName, HeaderValue, Method};
+use std::time::Duration;
+use tower_http::cors::{AllowCredentials, AllowOrigin, CorsLayer, MaxAge};
+
+/// Cross-origin settings.
+///
+/// | Variable                 | Default           |
+/// |--------------------------|-------------------|
+/// | `CORS_ALLOWED_ORIGINS`   | `PUBLIC_BASE_URL` |
+/// | `CORS_ALLOW_CREDENTIALS` | true              |
+/// | `CORS_MAX_AGE_SECS`      | 600               |
+///
+/// `CORS_ALLOWED_ORIGINS` is a comma-separated list, or `*` for any origin.
+///
+/// Credentials are only ever allowed for explicitly listed origins; with `*`
+/// they're turned off, since browsers reject that combination anyway.
+#[derive(Debug, Clone)]
+pub struct CorsSettings {
+    /// `None` means any origin.
+    pub allowed_origins: Option<Vec<HeaderValue>>,
+    pub allow_credentials: bool,
+    pub max_age: Duration,
+}
+
+impl CorsSettings {
+    pub fn from_env() -> Self {
+        let origins = std::env::var("CORS_ALLOWED_ORIGINS").unwrap_or_else(|_| {
+            std::env::var("PUBLIC_BASE_URL").unwrap_or_else(|_| "http://localhost:5173".into())
+        });
+
+        let allowed_origins = if origins.trim() == "*" {
+            None
+        } else {
+            Some(
+                origins
+                    .split(',')
+                    .map(|origin| origin.trim().trim_end_matches('/'))
+                    .filter(|origin| !origin.is_empty())
+                    .map(|origin| {
+                        HeaderValue::from_str(origin).unwrap_or_else(|_| {
+                            panic!("CORS_ALLOWED_ORIGINS contains an invalid origin: {:?}", origin)
+                        })
+                    })
+                    .collect(),
+            )
+        };
+
+        let mut allow_credentials = std::env::var("CORS_ALLOW_CREDENTIALS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(true);
+        if allow_credentials && allowed_origins.is_none() {
+            tracing::warn!("CORS credentials disabled: not allowed with a wildcard origin");
+            allow_credentials = false;
+        }
+
+        Self {
+            allowed_origins,
+            allow_credentials,
+            max_age: Duration::from_secs(
+                std::env::var("CORS_MAX_AGE_SECS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(600),
+            ),
+        }
+    }
+
+    fn is_trusted(&self, origin: &HeaderValue) -> bool {
+        self.allowed_origins
+            .as_ref()
+            .is_some_and(|origins| origins.contains(origin))
+    }
+
+    pub fn layer(&self) -> CorsLayer {
+        let layer = CorsLayer::new()
+            .allow_methods([
+                Method::GET,
+                Method::HEAD,
+                Method::POST,
+                Method::PUT,
+                Method::DELETE,
+                Method::OPTIONS,
+            ])
+            .allow_headers([
+                header::AUTHORIZATION,
+                header::CONTENT_TYPE,
+                header::IF_NONE_MATCH,
+                HeaderName::from_static("x-api-key"),
+                HeaderName::from_static("x-json-case"),
+                HeaderName::from_static("x-feature-overrides"),
+                HeaderName::from_static("x-request-id"),
+                HeaderName::from_static("idempotency-key"),
+            ])
+            .expose_headers([header::ETAG, HeaderName::from_static("x-request-id")]);
+
+        let Some(origins) = &self.allowed_origins else {
+            return layer
+                .allow_origin(AllowOrigin::any())
+                .max_age(self.max_age);
+        };
+
+        // Untrusted origins get no allow-origin header, and neither
+        // credentials nor a cacheable preflight.
+        let max_age = self.max_age;
+        let trusted = self.clone();
+        let layer = layer
+            .allow_origin(AllowOrigin::list(origins.clone()))
+            .max_age(MaxAge::dynamic(move |origin, _| {
+                if trusted.is_trusted(origin) {
+                    max_age
+                } else {
+                    Duration::ZERO
+                }
+            }));
+
+        if self.allow_credentials {
+            let trusted = self.clone();
+            layer.allow_credentials(AllowCredentials::predicate(move |origin, _| {
+                trusted.is_trusted(origin)
+            }))
+        } else {
+            layer
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn settings(allowed_origins: Option<&[&str]>) -> CorsSettings {
+        CorsSettings {
+            allowed_origins: allowed_origins.map(|origins| {
+                origins.iter().map(|origin| HeaderValue::from_str(origin).unwrap()).collect()
+            }),
+            allow_credentials: allowed_origins.is_some(),
+            max_age: Duration::from_secs(900),
+        }
+    }
+
+    /// The CORS headers of a preflight from `origin`.
+    async fn preflight(settings: &CorsSettings, origin: &str) -> Vec<(String, String)> {
+        let app = Router::new()
+            .route("/api/models", get(|| async { "ok" }))
+            .layer(settings.layer());
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/api/models")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        let mut headers: Vec<_> = [
+            header::ACCESS_CONTROL_ALLOW_ORIGIN,
+            header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            header::ACCESS_CONTROL_MAX_AGE,
+        ]
+        .into_iter()
+        .filter_map(|name| {
+            let value = response.headers().get(&name)?.to_str().unwrap().to_string();
+            Some((name.to_string(), value))
+        })
+        .collect();
+        headers.sort();
+        headers
+    }
+
+    fn pairs(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+        expected.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[tokio::test]
+    async fn only_trusted_origins_get_credentials_and_a_cached_preflight() {
+        let settings = settings(Some(&["https://app.example.com"]));
+
+        assert_eq!(
+            preflight(&settings, "https://app.example.com").await,
+            pairs(&[
+                ("access-control-allow-credentials", "true"),
+                ("access-control-allow-origin", "https://app.example.com"),
+                ("access-control-max-age", "900"),
+            ])
+        );
+        assert_eq!(
+            preflight(&settings, "https://evil.example.net").await,
+            pairs(&[("access-control-max-age", "0")])
+        );
+    }
+
+    #[tokio::test]
+    async fn a_wildcard_origin_never_allows_credentials() {
+        assert_eq!(
+            preflight(&settings(None), "https://anywhere.example.org").await,
+            pairs(&[("access-control-allow-origin", "*"), ("access-control-max-age", "900")])
+        );
+    }
+}
@@ -1,14 +1,276 @@
 use sqlx::postgres::PgPoolOptions;
-use sqlx::PgPool;
+use sqlx::{Executor, PgPool};
 use std::time::Duration;
 
-pub async fn create_pool() -> Result<PgPool, sqlx::Error> {
-    let database_url = std::env::var("DATABASE_URL")
-        .expect("DATABASE_URL must be set");
+use super::{Config, ConfigProblem, EnvReader};
 
-    PgPoolOptions::new()
-        .max_connections(5)
-        .acquire_timeout(Duration::from_secs(3))
-        .connect(&database_url)
+/// Connection pool sizing and timeouts.
+///
+/// | Variable                  | Default |
+/// |---------------------------|---------|
+/// | `DB_MAX_CONNECTIONS`      | 5       |
+/// | `DB_MIN_CONNECTIONS`      | 0       |
+/// | `DB_ACQUIRE_TIMEOUT_SECS` | 3       |
+/// | `DB_IDLE_TIMEOUT_SECS`    | 600     |
+/// | `DB_STATEMENT_TIMEOUT_MS` | off     |
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolSettings {
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub acquire_timeout: Duration,
+    pub idle_timeout: Duration,
+    /// Postgres cancels any statement running longer than this. Off by
+    /// default, since migrations share the pool.
+    pub statement_timeout: Option<Duration>,
+}
+
+impl Default for PoolSettings {
+    fn default() -> Self {
+        Self {
+            max_connections: 5,
+            min_connections: 0,
+            acquire_timeout: Duration::from_secs(3),
+            idle_timeout: Duration::from_secs(600),
+            statement_timeout: None,
+        }
+    }
+}
+
+impl PoolSettings {
+    /// Read settings for [`Config::from_env`], noting any that are invalid.
+    pub(super) fn read(env: &mut EnvReader) -> Self {
+        let defaults = Self::default();
+
+        let settings = Self {
+            max_connections: env.parsed("DB_MAX_CONNECTIONS", defaults.max_connections),
+            min_connections: env.parsed("DB_MIN_CONNECTIONS", defaults.min_connections),
+            acquire_timeout: Duration::from_secs(env.parsed(
+                "DB_ACQUIRE_TIMEOUT_SECS",
+                defaults.acquire_timeout.as_secs(),
+            )),
+            idle_timeout: Duration::from_secs(env.parsed(
+                "DB_IDLE_TIMEOUT_SECS",
+                defaults.idle_timeout.as_secs(),
+            )),
+            statement_timeout: Some(Duration::from_millis(
+                env.parsed("DB_STATEMENT_TIMEOUT_MS", 0),
+            ))
+            .filter(|timeout| !timeout.is_zero()),
+        };
+
+        if let Err(problem) = settings.validate() {
+            env.invalid(problem.variable, problem.message);
+        }
+        settings
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigProblem> {
+        if self.max_connections == 0 {
+            return Err(ConfigProblem {
+                variable: "DB_MAX_CONNECTIONS",
+                message: "must be at least 1".into(),
+            });
+        }
+        if self.min_connections > self.max_connections {
+            return Err(ConfigProblem {
+                variable: "DB_MIN_CONNECTIONS",
+                message: format!(
+                    "{} exceeds DB_MAX_CONNECTIONS ({})",
+                    self.min_connections, self.max_connections
+                ),
+            });
+        }
+        if self.acquire_timeout.is_zero() {
+            return Err(ConfigProblem {
+                variable: "DB_ACQUIRE_TIMEOUT_SECS",
+                message: "must be at least 1".into(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn options(&self) -> PgPoolOptions {
+        let statement_timeout = self.statement_timeout;
+        PgPoolOptions::new()
+            .max_connections(self.max_connections)
+            .min_connections(self.min_connections)
+            .acquire_timeout(self.acquire_timeout)
+            .idle_timeout(self.idle_timeout)
+            .after_connect(move |conn, _meta| {
+                Box::pin(async move {
+                    if let Some(timeout) = statement_timeout {
+                        let set = format!("SET statement_timeout = {}", timeout.as_millis());
+                        conn.execute(set.as_str()).await?;
+                    }
+                    Ok(())
+                })
+            })
+    }
+}
+
+/// How hard startup tries to reach the database, which may come up after
+/// us (docker-compose starts both at once).
+///
+/// | Variable                  | Default |
+/// |---------------------------|---------|
+/// | `DB_CONNECT_RETRIES`      | 5       |
+/// | `DB_CONNECT_BACKOFF_SECS` | 2       |
+///
+/// The wait doubles after each failed attempt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectRetry {
+    /// Attempts after the first.
+    pub retries: u32,
+    pub backoff: Duration,
+}
+
+impl Default for ConnectRetry {
+    fn default() -> Self {
+        Self {
+            retries: 5,
+            backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+impl ConnectRetry {
+    pub(super) fn read(env: &mut EnvReader) -> Self {
+        let defaults = Self::default();
+        Self {
+            retries: env.parsed("DB_CONNECT_RETRIES", defaults.retries),
+            backoff: Duration::from_secs(env.parsed(
+                "DB_CONNECT_BACKOFF_SECS",
+                defaults.backoff.as_secs(),
+            )),
+        }
+    }
+
+    /// Run `connect` until it succeeds or the retries are used up, returning
+    /// the last error in that case.
+    pub async fn run<T, E, F, Fut>(&self, mut connect: F) -> Result<T, E>
+    where
+        E: std::fmt::Display,
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<T, E>>,
+    {
+        let mut delay = self.backoff;
+        let mut attempt = 1;
+        loop {
+            match connect().await {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt <= self.retries => {
+                    tracing::warn!(
+                        "database connection attempt {} of {} failed: {}; retrying in {:?}",
+                        attempt,
+                        self.retries + 1,
+                        e,
+                        delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay = delay.saturating_mul(2);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Connect to the configured database and check it answers a query,
+/// retrying per [`Config::connect_retry`].
+pub async fn create_pool(config: &Config) -> Result<PgPool, sqlx::Error> {
+    config
+        .connect_retry
+        .run(|| async {
+            let pool = config.pool.options().connect(&config.database_url).await?;
+            sqlx::query("SELECT 1").execute(&pool).await?;
+            Ok(pool)
+        })
         .await
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_that_cannot_work_are_rejected() {
+        assert_eq!(PoolSettings::default().validate(), Ok(()));
+
+        let invalid = [
+            PoolSettings {
+                max_connections: 0,
+                ..Default::default()
+            },
+            PoolSettings {
+                min_connections: 6,
+                ..Default::default()
+            },
+            PoolSettings {
+                acquire_timeout: Duration::ZERO,
+                ..Default::default()
+            },
+        ];
+        let variables: Vec<_> = invalid
+            .iter()
+            .map(|settings| settings.validate().unwrap_err().variable)
+            .collect();
+        assert_eq!(
+            variables,
+            ["DB_MAX_CONNECTIONS", "DB_MIN_CONNECTIONS", "DB_ACQUIRE_TIMEOUT_SECS"]
+        );
+    }
+
+    #[sqlx::test]
+    async fn the_pool_is_built_from_the_settings(pool: PgPool) {
+        let settings = PoolSettings {
+            max_connections: 1,
+            acquire_timeout: Duration::from_millis(200),
+            statement_timeout: Some(Duration::from_millis(1500)),
+            ..Default::default()
+        };
+        let configured = settings
+            .options()
+            .connect_with(pool.connect_options().clone())
+            .await
+            .unwrap();
+
+        let mut only = configured.acquire().await.unwrap();
+        let timeout: String = sqlx::query_scalar("SHOW statement_timeout")
+            .fetch_one(&mut only)
+            .await
+            .unwrap();
+        assert_eq!(timeout, "1500ms");
+        assert!(matches!(configured.acquire().await, Err(sqlx::Error::PoolTimedOut)));
+    }
+
+    #[tokio::test]
+    async fn a_connection_that_fails_once_succeeds_on_the_retry() {
+        let retry = ConnectRetry {
+            retries: 2,
+            backoff: Duration::from_millis(5),
+        };
+        let attempts = std::cell::Cell::new(0);
+        let flaky = || {
+            attempts.set(attempts.get() + 1);
+            let attempt = attempts.get();
+            async move {
+                match attempt {
+                    1 => Err("connection refused"),
+                    _ => Ok("connected"),
+                }
+            }
+        };
+        assert_eq!(retry.run(flaky).await, Ok("connected"));
+        assert_eq!(attempts.get(), 2);
+
+        attempts.set(0);
+        let down = || {
+            attempts.set(attempts.get() + 1);
+            let attempt = attempts.get();
+            async move { Err::<(), _>(format!("attempt {} refused", attempt)) }
+        };
+        assert_eq!(retry.run(down).await, Err("attempt 3 refused".to_string()));
+        assert_eq!(attempts.get(), 3);
+    }
+}
@@ -1,3 +1,7 @@
+mod app;
+mod cors;
 mod database;
 
-pub use database::*; 
\ No newline at end of file
+pub use app::*;
+pub use cors::*;
+pub use database::*;
@@ -1,55 +1,934 @@
 use sqlx::PgPool;
 use uuid::Uuid;
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use serde_json::Value as JsonValue;
+use sqlx::types::Json;
 
-use crate::models::{AIModel, CreateAIModel, UpdateAIModel, ListQueryParams};
+use super::DownloadBuffer;
+use crate::error::AppError;
+
+use crate::models::{AIModel, CampaignDownloads, PerformanceMetrics, CatalogRow, ModelAnalytics, UtmParams, ModelVersion, DownloadBucket, StatsBucket, ModelType, CreateAIModel, UpdateAIModel, ListQueryParams, DependencySummary, ModelActivity, ModelStatus, ModelSummary, OpenStaleReport, PriceDistribution, SubscriptionTier, UserDownload};
+
+/// Rows and total for `AIModelRepository::list`, in one pass so the total
+/// can't disagree with the filters. Parameters are bound by
+/// `AIModelRepository::fetch_counted`.
+const LIST_SQL: &str = r#"
+    SELECT id, name, description, model_type, framework, version, status,
+           created_at, updated_at, metadata, performance_metrics, repository_url,
+           tags, download_count, downloads, is_public, price::float8 AS price, required_tier,
+           owner_id, avg_rating::float8 AS avg_rating, license, deleted_at,
+           created_by, updated_by, file_size_bytes, artifact_sha256, storage_key,
+           withdrawn_from_sale_at, archived, forked_from, key_rotated_at,
+           COUNT(*) OVER() AS total
+    FROM ai_models
+    WHERE deleted_at IS NULL
+    AND ($1::model_type IS NULL OR model_type = $1)
+    AND ($2::float8 IS NULL OR (performance_metrics->>'accuracy')::float8 >= $2)
+    AND ($3::subscription_tier IS NULL OR required_tier = $3)
+    AND (CASE WHEN $6::model_status IS NULL
+              THEN (status = 'published' AND is_public) OR owner_id = $7
+              ELSE status = $6 END)
+    AND ($8::float8 IS NULL OR COALESCE(price, 0) >= $8)
+    AND ($9::float8 IS NULL OR COALESCE(price, 0) <= $9)
+    AND (NOT COALESCE($10::bool, false) OR COALESCE(price, 0) = 0)
+    ORDER BY created_at DESC
+    LIMIT $4 OFFSET $5
+"#;
+
+/// Like [`LIST_SQL`], for `AIModelRepository::list_updated_after`; `$11`
+/// is the cut-off.
+const LIST_UPDATED_AFTER_SQL: &str = r#"
+    SELECT id, name, description, model_type, framework, version, status,
+           created_at, updated_at, metadata, performance_metrics, repository_url,
+           tags, download_count, downloads, is_public, price::float8 AS price, required_tier,
+           owner_id, avg_rating::float8 AS avg_rating, license, deleted_at,
+           created_by, updated_by, file_size_bytes, artifact_sha256, storage_key,
+           withdrawn_from_sale_at, archived, forked_from, key_rotated_at,
+           COUNT(*) OVER() AS total
+    FROM ai_models
+    WHERE updated_at > $11
+    AND ($1::model_type IS NULL OR model_type = $1)
+    AND ($2::float8 IS NULL OR (performance_metrics->>'accuracy')::float8 >= $2)
+    AND ($3::subscription_tier IS NULL OR required_tier = $3)
+    AND (CASE WHEN $6::model_status IS NULL
+              THEN (status = 'published' AND is_public) OR owner_id = $7
+              ELSE status = $6 END)
+    AND ($8::float8 IS NULL OR COALESCE(price, 0) >= $8)
+    AND ($9::float8 IS NULL OR COALESCE(price, 0) <= $9)
+    AND (NOT COALESCE($10::bool, false) OR COALESCE(price, 0) = 0)
+    ORDER BY updated_at ASC, id ASC
+    LIMIT $4 OFFSET $5
+"#;
+
+/// A listed model and how many models matched in all.
+#[derive(sqlx::FromRow)]
+struct CountedModel {
+    #[sqlx(flatten)]
+    model: AIModel,
+    total: i64,
+}
 
 #[derive(Clone)]
 pub struct AIModelRepository {
     pool: PgPool,
+    /// Set when download counts are buffered rather than written per download.
+    download_buffer: Option<DownloadBuffer>,
 }
 
 impl AIModelRepository {
     pub fn new(pool: PgPool) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            download_buffer: None,
+        }
+    }
+
+    /// Count downloads in `buffer` instead of updating the model row each
+    /// time; something must flush it.
+    pub fn with_download_buffer(self, buffer: DownloadBuffer) -> Self {
+        Self {
+            download_buffer: Some(buffer),
+            ..self
+        }
+    }
+
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::create", skip_all)]
+    pub async fn create(&self, model: CreateAIModel, user_id: Uuid) -> Result<AIModel, AppError> {
+        insert_model(&self.pool, model, user_id)
+            .await
+            .map_err(duplicate_name)
+    }
+
+    /// Create all the models or, if any insert fails, none of them.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::create_many", skip_all)]
+    pub async fn create_many(
+        &self,
+        models: Vec<CreateAIModel>,
+        user_id: Uuid,
+    ) -> Result<Vec<AIModel>, AppError> {
+        let mut tx = self.pool.begin().await?;
+
+        let mut created = Vec::with_capacity(models.len());
+        for model in models {
+            created.push(
+                insert_model(&mut tx, model, user_id)
+                    .await
+                    .map_err(duplicate_name)?,
+            );
+        }
+
+        tx.commit().await?;
+
+        Ok(created)
     }
 
-    pub async fn create(&self, model: CreateAIModel) -> Result<AIModel, sqlx::Error> {
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::get", skip_all)]
+    pub async fn get(&self, id: Uuid) -> Result<Option<AIModel>, sqlx::Error> {
         let record = sqlx::query_as!(
             AIModel,
             r#"
-            INSERT INTO ai_models (
-                name, description, model_type, framework, version,
-                metadata, repository_url, is_public, price, required_tier,
-                tags, performance_metrics
+            SELECT id, name, description, model_type AS "model_type: ModelType", framework, version,
+                   status AS "status: ModelStatus", created_at, updated_at, metadata,
+                   performance_metrics AS "performance_metrics: Json<PerformanceMetrics>",
+                   repository_url, tags, download_count, downloads, is_public, price::float8 AS price,
+                   required_tier AS "required_tier: SubscriptionTier", owner_id,
+                   avg_rating::float8 AS avg_rating, license, deleted_at, created_by, updated_by,
+                   file_size_bytes, artifact_sha256, storage_key, withdrawn_from_sale_at, archived,
+                   forked_from, key_rotated_at
+            FROM ai_models
+            WHERE id = $1 AND deleted_at IS NULL
+            "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(record)
+    }
+
+    /// List live models matching `params`. Without a status filter this is
+    /// published models plus any of `viewer`'s own.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::list", skip_all)]
+    pub async fn list(
+        &self,
+        params: &ListQueryParams,
+        viewer: Option<Uuid>,
+    ) -> Result<(Vec<AIModel>, i64), sqlx::Error> {
+        self.counted_page(LIST_SQL, params, viewer, None).await
+    }
+
+    /// Models changed after `since`, oldest change first, for clients
+    /// mirroring the catalog. Filtered like `list`, but soft-deleted models
+    /// are included so mirrors learn about deletions.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::list_updated_after", skip_all)]
+    pub async fn list_updated_after(
+        &self,
+        params: &ListQueryParams,
+        viewer: Option<Uuid>,
+        since: DateTime<Utc>,
+    ) -> Result<(Vec<AIModel>, i64), sqlx::Error> {
+        self.counted_page(LIST_UPDATED_AFTER_SQL, params, viewer, Some(since))
+            .await
+    }
+
+    /// Run one of the `COUNT(*) OVER()` list queries for the page `params`
+    /// asks for. The total comes from the same pass as the rows, so it
+    /// always matches the filters. A page past the end has no rows to carry
+    /// the total, so it's read from the first row instead.
+    async fn counted_page(
+        &self,
+        sql: &str,
+        params: &ListQueryParams,
+        viewer: Option<Uuid>,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<(Vec<AIModel>, i64), sqlx::Error> {
+        let page = params.page.unwrap_or(1);
+        let per_page = params.per_page.unwrap_or_else(crate::models::default_page_size);
+        let offset = (page - 1) * per_page;
+
+        let rows = self
+            .fetch_counted(sql, params, viewer, since, per_page, offset)
+            .await?;
+        let total = match rows.first() {
+            Some(row) => row.total,
+            None if offset > 0 => self
+                .fetch_counted(sql, params, viewer, since, 1, 0)
+                .await?
+                .first()
+                .map_or(0, |row| row.total),
+            None => 0,
+        };
+
+        Ok((rows.into_iter().map(|row| row.model).collect(), total))
+    }
+
+    async fn fetch_counted(
+        &self,
+        sql: &str,
+        params: &ListQueryParams,
+        viewer: Option<Uuid>,
+        since: Option<DateTime<Utc>>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<CountedModel>, sqlx::Error> {
+        let mut query = sqlx::query_as::<_, CountedModel>(sql)
+            .bind(params.model_type)
+            .bind(params.min_accuracy)
+            .bind(params.required_tier)
+            .bind(limit)
+            .bind(offset)
+            .bind(params.status)
+            .bind(viewer)
+            .bind(params.min_price)
+            .bind(params.max_price)
+            .bind(params.free_only);
+        if let Some(since) = since {
+            query = query.bind(since);
+        }
+        query.fetch_all(&self.pool).await
+    }
+
+    /// One chunk of the catalog for export, filtered like `list` but paged
+    /// by keyset: pass the `(created_at, id)` of the last row of the
+    /// previous chunk as `after`.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::export_chunk", skip_all)]
+    pub async fn export_chunk(
+        &self,
+        params: &ListQueryParams,
+        viewer: Option<Uuid>,
+        after: Option<(DateTime<Utc>, Uuid)>,
+        limit: i64,
+    ) -> Result<Vec<CatalogRow>, sqlx::Error> {
+        let (after_created_at, after_id) = after.unzip();
+
+        sqlx::query_as!(
+            CatalogRow,
+            r#"
+            SELECT id, name, description,
+                   model_type AS "model_type: ModelType",
+                   framework, version,
+                   status AS "status: ModelStatus",
+                   license, price::float8 AS price,
+                   required_tier AS "required_tier: SubscriptionTier",
+                   download_count, is_public, created_at, updated_at
+            FROM ai_models
+            WHERE deleted_at IS NULL
+            AND ($1::model_type IS NULL OR model_type = $1)
+            AND ($2::float8 IS NULL OR (performance_metrics->>'accuracy')::float8 >= $2)
+            AND ($3::subscription_tier IS NULL OR required_tier = $3)
+            AND (CASE WHEN $4::model_status IS NULL
+                      THEN (status = 'published' AND is_public) OR owner_id = $5
+                      ELSE status = $4 END)
+            AND ($6::timestamptz IS NULL OR (created_at, id) < ($6, $7))
+            AND ($9::float8 IS NULL OR COALESCE(price, 0) >= $9)
+            AND ($10::float8 IS NULL OR COALESCE(price, 0) <= $10)
+            AND (NOT COALESCE($11::bool, false) OR COALESCE(price, 0) = 0)
+            ORDER BY created_at DESC, id DESC
+            LIMIT $8
+            "#,
+            params.model_type as _,
+            params.min_accuracy,
+            params.required_tier as _,
+            params.status as _,
+            viewer,
+            after_created_at,
+            after_id,
+            limit,
+            params.min_price,
+            params.max_price,
+            params.free_only
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::update", skip_all)]
+    pub async fn update(
+        &self,
+        id: Uuid,
+        model: UpdateAIModel,
+        user_id: Uuid,
+    ) -> Result<Option<AIModel>, AppError> {
+        let record = sqlx::query_as!(
+            AIModel,
+            r#"
+            WITH updated AS (
+                UPDATE ai_models
+                SET
+                    name = COALESCE($1, name),
+                    description = COALESCE($2, description),
+                    model_type = COALESCE($3, model_type),
+                    framework = COALESCE($4, framework),
+                    version = COALESCE($5, version),
+                    metadata = CASE WHEN $15 THEN '{}'::jsonb ELSE COALESCE($6, metadata) END,
+                    repository_url = CASE WHEN $16 THEN NULL ELSE COALESCE($7, repository_url) END,
+                    is_public = COALESCE($8, is_public),
+                    price = COALESCE($9::float8, price),
+                    required_tier = COALESCE($10, required_tier),
+                    tags = COALESCE($11, tags),
+                    performance_metrics = CASE WHEN $17 THEN NULL
+                        ELSE COALESCE($12, performance_metrics) END,
+                    license = COALESCE($18, license),
+                    updated_by = $14,
+                    updated_at = NOW()
+                WHERE id = $13 AND deleted_at IS NULL
+                RETURNING *
+            ), activity AS (
+                INSERT INTO model_activity (user_id, model_id, action)
+                SELECT $14, id, 'updated' FROM updated
+            ), versioned AS (
+                -- A new snapshot whenever the versioned fields changed, even
+                -- if the version wasn't bumped.
+                INSERT INTO model_versions (model_id, version, snapshot, created_by, sequence)
+                SELECT u.id, u.version, s.snapshot, $14, COALESCE(latest.sequence, 0) + 1
+                FROM updated u
+                CROSS JOIN LATERAL (
+                    SELECT jsonb_build_object(
+                        'name', u.name,
+                        'description', u.description,
+                        'model_type', u.model_type,
+                        'framework', u.framework,
+                        'metadata', u.metadata,
+                        'performance_metrics', u.performance_metrics,
+                        'repository_url', u.repository_url
+                    ) AS snapshot
+                ) s
+                LEFT JOIN LATERAL (
+                    SELECT sequence, version, snapshot
+                    FROM model_versions
+                    WHERE model_id = u.id
+                    ORDER BY sequence DESC
+                    LIMIT 1
+                ) latest ON true
+                WHERE latest.sequence IS NULL
+                OR latest.version <> u.version
+                OR latest.snapshot <> s.snapshot
             )
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
-            RETURNING *
+            SELECT id, name, description, model_type AS "model_type: ModelType", framework, version,
+                   status AS "status: ModelStatus", created_at, updated_at, metadata,
+                   performance_metrics AS "performance_metrics: Json<PerformanceMetrics>",
+                   repository_url, tags, download_count, downloads, is_public, price::float8 AS price,
+                   required_tier AS "required_tier: SubscriptionTier", owner_id,
+                   avg_rating::float8 AS avg_rating, license, deleted_at, created_by, updated_by,
+                   file_size_bytes, artifact_sha256, storage_key, withdrawn_from_sale_at, archived,
+                   forked_from, key_rotated_at
+            FROM updated
             "#,
             model.name,
             model.description,
-            model.model_type,
+            model.model_type as _,
             model.framework,
             model.version,
-            model.metadata.unwrap_or_else(|| JsonValue::Object(serde_json::Map::new())),
-            model.repository_url,
-            model.is_public.unwrap_or(true),
+            model.metadata.as_set(),
+            model.repository_url.as_set(),
+            model.is_public,
             model.price,
-            model.required_tier.unwrap_or_default() as _,
-            &model.tags.unwrap_or_default(),
-            model.performance_metrics
+            model.required_tier as _,
+            model.tags.as_deref(),
+            model.performance_metrics.as_set().map(Json) as _,
+            id,
+            user_id,
+            model.metadata.is_clear(),
+            model.repository_url.is_clear(),
+            model.performance_metrics.is_clear(),
+            model.license
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(duplicate_name)?;
+
+        Ok(record)
+    }
+
+    /// Count the owner's live models by the tier they require.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::owned_counts_by_tier", skip_all)]
+    pub async fn owned_counts_by_tier(
+        &self,
+        owner_id: Uuid,
+    ) -> Result<Vec<(SubscriptionTier, i64)>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT required_tier AS "required_tier: SubscriptionTier", COUNT(*) AS "models!"
+            FROM ai_models
+            WHERE owner_id = $1 AND deleted_at IS NULL
+            GROUP BY required_tier
+            "#,
+            owner_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|row| (row.required_tier, row.models)).collect())
+    }
+
+    /// The user's most recent actions across all models, newest first.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::activity_for_user", skip_all)]
+    pub async fn activity_for_user(
+        &self,
+        user_id: Uuid,
+        limit: i64,
+    ) -> Result<Vec<ModelActivity>, sqlx::Error> {
+        sqlx::query_as!(
+            ModelActivity,
+            r#"
+            SELECT a.id, a.model_id, m.name AS model_name, a.action, a.created_at
+            FROM model_activity a
+            JOIN ai_models m ON m.id = a.model_id
+            WHERE a.user_id = $1
+            ORDER BY a.created_at DESC
+            LIMIT $2
+            "#,
+            user_id,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::downloads_for_user", skip_all)]
+    pub async fn downloads_for_user(
+        &self,
+        user_id: Uuid,
+        limit: i64,
+    ) -> Result<Vec<UserDownload>, sqlx::Error> {
+        sqlx::query_as!(
+            UserDownload,
+            r#"
+            SELECT e.model_id, m.name AS model_name, e.downloaded_at
+            FROM model_downloads e
+            JOIN ai_models m ON m.id = e.model_id
+            WHERE e.user_id = $1
+            ORDER BY e.downloaded_at DESC
+            LIMIT $2
+            "#,
+            user_id,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Stale reports by the user on models that haven't changed since.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::open_stale_reports_by_user", skip_all)]
+    pub async fn open_stale_reports_by_user(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<OpenStaleReport>, sqlx::Error> {
+        sqlx::query_as!(
+            OpenStaleReport,
+            r#"
+            SELECT r.id, r.model_id, m.name AS model_name, r.reason, r.created_at
+            FROM model_stale_reports r
+            JOIN ai_models m ON m.id = r.model_id
+            WHERE r.user_id = $1
+            AND m.deleted_at IS NULL
+            AND m.updated_at <= r.created_at
+            ORDER BY r.created_at DESC
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Move a model to `to` if it's currently in one of `from`, recording
+    /// the change in the acting user's activity. Returns `None` if the model
+    /// doesn't exist or is in another state.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::transition_status", skip_all)]
+    pub async fn transition_status(
+        &self,
+        id: Uuid,
+        from: &[ModelStatus],
+        to: ModelStatus,
+        user_id: Uuid,
+    ) -> Result<Option<AIModel>, sqlx::Error> {
+        // Compared as text since sqlx can't bind arrays of custom enums here.
+        let from: Vec<String> = from
+            .iter()
+            .map(|status| status.as_str().to_string())
+            .collect();
+
+        sqlx::query_as!(
+            AIModel,
+            r#"
+            WITH changed AS (
+                UPDATE ai_models
+                SET status = $3, updated_by = $4, updated_at = NOW()
+                WHERE id = $1 AND deleted_at IS NULL AND status::text = ANY($2)
+                RETURNING *
+            ), activity AS (
+                INSERT INTO model_activity (user_id, model_id, action)
+                SELECT $4, id, $3::text FROM changed
+            )
+            SELECT id, name, description, model_type AS "model_type: ModelType", framework, version,
+                   status AS "status: ModelStatus", created_at, updated_at, metadata,
+                   performance_metrics AS "performance_metrics: Json<PerformanceMetrics>",
+                   repository_url, tags, download_count, downloads, is_public, price::float8 AS price,
+                   required_tier AS "required_tier: SubscriptionTier", owner_id,
+                   avg_rating::float8 AS avg_rating, license, deleted_at, created_by, updated_by,
+                   file_size_bytes, artifact_sha256, storage_key, withdrawn_from_sale_at, archived,
+                   forked_from, key_rotated_at
+            FROM changed
+            "#,
+            id,
+            &from,
+            to as _,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Stop selling a model: either make it free to everyone, or archive it
+    /// so only past downloaders can still get it. Returns `None` if the model
+    /// doesn't exist or was already withdrawn.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::withdraw_from_sale", skip_all)]
+    pub async fn withdraw_from_sale(
+        &self,
+        id: Uuid,
+        archive: bool,
+        user_id: Uuid,
+    ) -> Result<Option<AIModel>, sqlx::Error> {
+        sqlx::query_as!(
+            AIModel,
+            r#"
+            WITH withdrawn AS (
+                UPDATE ai_models
+                SET withdrawn_from_sale_at = NOW(),
+                    archived = $2,
+                    price = CASE WHEN $2 THEN price ELSE 0 END,
+                    required_tier = CASE WHEN $2 THEN required_tier ELSE 'free' END,
+                    updated_by = $3,
+                    updated_at = NOW()
+                WHERE id = $1 AND deleted_at IS NULL AND withdrawn_from_sale_at IS NULL
+                RETURNING *
+            ), activity AS (
+                INSERT INTO model_activity (user_id, model_id, action)
+                SELECT $3, id, 'withdrawn_from_sale' FROM withdrawn
+            )
+            SELECT id, name, description, model_type AS "model_type: ModelType", framework, version,
+                   status AS "status: ModelStatus", created_at, updated_at, metadata,
+                   performance_metrics AS "performance_metrics: Json<PerformanceMetrics>",
+                   repository_url, tags, download_count, downloads, is_public, price::float8 AS price,
+                   required_tier AS "required_tier: SubscriptionTier", owner_id,
+                   avg_rating::float8 AS avg_rating, license, deleted_at, created_by, updated_by,
+                   file_size_bytes, artifact_sha256, storage_key, withdrawn_from_sale_at, archived,
+                   forked_from, key_rotated_at
+            FROM withdrawn
+            "#,
+            id,
+            archive,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Appends `tags` the model doesn't already have, keeping existing order,
+    /// and returns the new tag list. `None` if the model is gone, nothing
+    /// was new, or the result would hold more than `max_tags` tags.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::add_tags", skip_all)]
+    pub async fn add_tags(
+        &self,
+        id: Uuid,
+        tags: &[String],
+        max_tags: usize,
+        user_id: Uuid,
+    ) -> Result<Option<Vec<String>>, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"
+            WITH added AS (
+                SELECT ARRAY(
+                    SELECT DISTINCT t FROM unnest($2::text[]) AS t
+                    WHERE NOT t = ANY(m.tags)
+                ) AS tags
+                FROM ai_models m
+                WHERE m.id = $1
+            ), updated AS (
+                UPDATE ai_models
+                SET tags = ai_models.tags || added.tags,
+                    updated_by = $4,
+                    updated_at = NOW()
+                FROM added
+                WHERE id = $1
+                    AND deleted_at IS NULL
+                    AND cardinality(added.tags) > 0
+                    AND cardinality(ai_models.tags) + cardinality(added.tags) <= $3
+                RETURNING ai_models.id, ai_models.tags
+            ), activity AS (
+                INSERT INTO model_activity (user_id, model_id, action)
+                SELECT $4, id, 'updated' FROM updated
+            )
+            SELECT tags AS "tags!" FROM updated
+            "#,
+            id,
+            tags,
+            max_tags as i32,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Drops `tag` from the model and returns the remaining tags. A tag the
+    /// model doesn't have leaves it untouched. `None` if the model is gone.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::remove_tag", skip_all)]
+    pub async fn remove_tag(
+        &self,
+        id: Uuid,
+        tag: &str,
+        user_id: Uuid,
+    ) -> Result<Option<Vec<String>>, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"
+            WITH updated AS (
+                UPDATE ai_models
+                SET tags = array_remove(tags, $2),
+                    updated_by = $3,
+                    updated_at = NOW()
+                WHERE id = $1 AND deleted_at IS NULL AND $2 = ANY(tags)
+                RETURNING id, tags
+            ), activity AS (
+                INSERT INTO model_activity (user_id, model_id, action)
+                SELECT $3, id, 'updated' FROM updated
+            )
+            SELECT tags AS "tags!" FROM updated
+            UNION ALL
+            SELECT tags FROM ai_models
+            WHERE id = $1 AND deleted_at IS NULL AND NOT EXISTS (SELECT 1 FROM updated)
+            "#,
+            id,
+            tag,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Point the model at its artifact's new storage key, if it's still at
+    /// `old_key`. `None` if the model is gone or its key changed meanwhile.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::rotate_storage_key", skip_all)]
+    pub async fn rotate_storage_key(
+        &self,
+        id: Uuid,
+        old_key: &str,
+        new_key: &str,
+        user_id: Uuid,
+    ) -> Result<Option<AIModel>, sqlx::Error> {
+        sqlx::query_as!(
+            AIModel,
+            r#"
+            WITH rotated AS (
+                UPDATE ai_models
+                SET storage_key = $3,
+                    key_rotated_at = NOW(),
+                    updated_by = $4,
+                    updated_at = NOW()
+                WHERE id = $1 AND deleted_at IS NULL AND storage_key = $2
+                RETURNING *
+            ), activity AS (
+                INSERT INTO model_activity (user_id, model_id, action)
+                SELECT $4, id, 'key_rotated' FROM rotated
+            )
+            SELECT id, name, description, model_type AS "model_type: ModelType", framework, version,
+                   status AS "status: ModelStatus", created_at, updated_at, metadata,
+                   performance_metrics AS "performance_metrics: Json<PerformanceMetrics>",
+                   repository_url, tags, download_count, downloads, is_public, price::float8 AS price,
+                   required_tier AS "required_tier: SubscriptionTier", owner_id,
+                   avg_rating::float8 AS avg_rating, license, deleted_at, created_by, updated_by,
+                   file_size_bytes, artifact_sha256, storage_key, withdrawn_from_sale_at, archived,
+                   forked_from, key_rotated_at
+            FROM rotated
+            "#,
+            id,
+            old_key,
+            new_key,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Copy a model's descriptive fields into a new private draft owned by
+    /// `user_id`. Sales state (price, artifact, downloads) starts fresh. If
+    /// `user_id` already has a model by that name, such as the source
+    /// itself, the fork's name gets its id's first 8 characters appended.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::fork", skip_all)]
+    pub async fn fork(&self, source_id: Uuid, user_id: Uuid) -> Result<Option<AIModel>, AppError> {
+        sqlx::query_as!(
+            AIModel,
+            r#"
+            WITH forked AS (
+                INSERT INTO ai_models (
+                    id, name, description, model_type, framework, version,
+                    metadata, performance_metrics, repository_url, tags, license,
+                    is_public, forked_from, owner_id, created_by, updated_by
+                )
+                SELECT fork.id,
+                       CASE WHEN EXISTS (
+                           SELECT 1 FROM ai_models taken
+                           WHERE taken.owner_id = $2
+                           AND lower(taken.name) = lower(source.name)
+                           AND taken.deleted_at IS NULL
+                       )
+                       THEN left(source.name, 244) || ' (' || left(fork.id::text, 8) || ')'
+                       ELSE source.name
+                       END,
+                       description, model_type, framework, version,
+                       metadata, performance_metrics, repository_url, tags, license,
+                       FALSE, source.id, $2, $2, $2
+                FROM ai_models source
+                CROSS JOIN LATERAL (SELECT gen_random_uuid() AS id) fork
+                WHERE source.id = $1 AND source.deleted_at IS NULL
+                RETURNING *
+            ), activity AS (
+                INSERT INTO model_activity (user_id, model_id, action)
+                SELECT $2, id, 'forked' FROM forked
+            ), versioned AS (
+                INSERT INTO model_versions (model_id, version, snapshot, created_by, sequence)
+                SELECT id, version,
+                       jsonb_build_object(
+                           'name', name,
+                           'description', description,
+                           'model_type', model_type,
+                           'framework', framework,
+                           'metadata', metadata,
+                           'performance_metrics', performance_metrics,
+                           'repository_url', repository_url
+                       ),
+                       $2, 1
+                FROM forked
+            )
+            SELECT id, name, description, model_type AS "model_type: ModelType", framework, version,
+                   status AS "status: ModelStatus", created_at, updated_at, metadata,
+                   performance_metrics AS "performance_metrics: Json<PerformanceMetrics>",
+                   repository_url, tags, download_count, downloads, is_public, price::float8 AS price,
+                   required_tier AS "required_tier: SubscriptionTier", owner_id,
+                   avg_rating::float8 AS avg_rating, license, deleted_at, created_by, updated_by,
+                   file_size_bytes, artifact_sha256, storage_key, withdrawn_from_sale_at, archived,
+                   forked_from, key_rotated_at
+            FROM forked
+            "#,
+            source_id,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(duplicate_name)
+    }
+
+    /// Issue (or reissue) `user_id`'s challenge for claiming the model.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::create_claim_challenge", skip_all)]
+    pub async fn create_claim_challenge(
+        &self,
+        model_id: Uuid,
+        user_id: Uuid,
+        token: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO model_claim_challenges (model_id, user_id, token, expires_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (model_id, user_id)
+            DO UPDATE SET token = EXCLUDED.token, expires_at = EXCLUDED.expires_at,
+                          created_at = NOW()
+            "#,
+            model_id,
+            user_id,
+            token,
+            expires_at
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// The user's unexpired claim token for the model, if any.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::claim_token", skip_all)]
+    pub async fn claim_token(
+        &self,
+        model_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<String>, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT token FROM model_claim_challenges
+            WHERE model_id = $1 AND user_id = $2 AND expires_at > NOW()
+            "#,
+            model_id,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Make `user_id` the owner of an unowned model and drop its claim
+    /// challenges. Returns `None` if someone owns it by now.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::claim", skip_all)]
+    pub async fn claim(&self, id: Uuid, user_id: Uuid) -> Result<Option<AIModel>, sqlx::Error> {
+        sqlx::query_as!(
+            AIModel,
+            r#"
+            WITH claimed AS (
+                UPDATE ai_models
+                SET owner_id = $2,
+                    updated_by = $2,
+                    updated_at = NOW()
+                WHERE id = $1 AND owner_id IS NULL AND deleted_at IS NULL
+                RETURNING *
+            ), activity AS (
+                INSERT INTO model_activity (user_id, model_id, action)
+                SELECT $2, id, 'claimed' FROM claimed
+            ), challenges AS (
+                DELETE FROM model_claim_challenges
+                WHERE model_id IN (SELECT id FROM claimed)
+            )
+            SELECT id, name, description, model_type AS "model_type: ModelType", framework, version,
+                   status AS "status: ModelStatus", created_at, updated_at, metadata,
+                   performance_metrics AS "performance_metrics: Json<PerformanceMetrics>",
+                   repository_url, tags, download_count, downloads, is_public, price::float8 AS price,
+                   required_tier AS "required_tier: SubscriptionTier", owner_id,
+                   avg_rating::float8 AS avg_rating, license, deleted_at, created_by, updated_by,
+                   file_size_bytes, artifact_sha256, storage_key, withdrawn_from_sale_at, archived,
+                   forked_from, key_rotated_at
+            FROM claimed
+            "#,
+            id,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Every snapshot of a model, newest first.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::list_versions", skip_all)]
+    pub async fn list_versions(&self, model_id: Uuid) -> Result<Vec<ModelVersion>, sqlx::Error> {
+        sqlx::query_as!(
+            ModelVersion,
+            r#"
+            SELECT id, model_id, version, sequence, snapshot, created_by, created_at, updated_at
+            FROM model_versions
+            WHERE model_id = $1
+            ORDER BY sequence DESC
+            "#,
+            model_id
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// The latest snapshot taken at `version`.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::get_version", skip_all)]
+    pub async fn get_version(
+        &self,
+        model_id: Uuid,
+        version: &str,
+    ) -> Result<Option<ModelVersion>, sqlx::Error> {
+        sqlx::query_as!(
+            ModelVersion,
+            r#"
+            SELECT id, model_id, version, sequence, snapshot, created_by, created_at, updated_at
+            FROM model_versions
+            WHERE model_id = $1 AND version = $2
+            ORDER BY sequence DESC
+            LIMIT 1
+            "#,
+            model_id,
+            version
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::has_downloaded", skip_all)]
+    pub async fn has_downloaded(&self, id: Uuid, user_id: Uuid) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM model_downloads
+                WHERE model_id = $1 AND user_id = $2
+            ) AS "downloaded!"
+            "#,
+            id,
+            user_id
         )
         .fetch_one(&self.pool)
+        .await
+    }
+
+    /// Soft-delete a model. The row is kept (so download history and payment
+    /// references survive) until the purge job removes it.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::delete", skip_all)]
+    pub async fn delete(&self, id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "UPDATE ai_models SET deleted_at = NOW() WHERE id = $1 AND deleted_at IS NULL",
+            id
+        )
+        .execute(&self.pool)
         .await?;
 
-        Ok(record)
+        Ok(result.rows_affected() > 0)
     }
 
-    pub async fn get(&self, id: Uuid) -> Result<Option<AIModel>, sqlx::Error> {
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::restore", skip_all)]
+    pub async fn restore(&self, id: Uuid) -> Result<Option<AIModel>, sqlx::Error> {
         let record = sqlx::query_as!(
             AIModel,
-            "SELECT * FROM ai_models WHERE id = $1",
+            r#"
+            UPDATE ai_models
+            SET deleted_at = NULL
+            WHERE id = $1 AND deleted_at IS NOT NULL
+            RETURNING id, name, description, model_type AS "model_type: ModelType", framework, version,
+                      status AS "status: ModelStatus", created_at, updated_at, metadata,
+                      performance_metrics AS "performance_metrics: Json<PerformanceMetrics>",
+                      repository_url, tags, download_count, downloads, is_public, price::float8 AS price,
+                      required_tier AS "required_tier: SubscriptionTier", owner_id,
+                      avg_rating::float8 AS avg_rating, license, deleted_at, created_by, updated_by,
+                      file_size_bytes, artifact_sha256, storage_key, withdrawn_from_sale_at, archived,
+                      forked_from, key_rotated_at
+            "#,
             id
         )
         .fetch_optional(&self.pool)
@@ -58,24 +937,318 @@ impl AIModelRepository {
         Ok(record)
     }
 
-    pub async fn list(&self, params: &ListQueryParams) -> Result<(Vec<AIModel>, i64), sqlx::Error> {
-        let page = params.page.unwrap_or(1);
-        let per_page = params.per_page.unwrap_or(10);
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::required_tier", skip_all)]
+    pub async fn required_tier(&self, id: Uuid) -> Result<Option<SubscriptionTier>, sqlx::Error> {
+        let tier = sqlx::query_scalar!(
+            r#"
+            SELECT required_tier as "required_tier: SubscriptionTier"
+            FROM ai_models
+            WHERE id = $1 AND deleted_at IS NULL
+            "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(tier)
+    }
+
+    /// Returns the new download count, or `None` if the model doesn't exist
+    /// or has been deleted. With a download buffer the count includes
+    /// downloads not flushed yet.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::increment_downloads", skip_all)]
+    pub async fn increment_downloads(
+        &self,
+        id: Uuid,
+        user_id: Option<Uuid>,
+        utm: &UtmParams,
+    ) -> Result<Option<i32>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let stored = if self.download_buffer.is_some() {
+            sqlx::query_scalar!(
+                "SELECT download_count FROM ai_models WHERE id = $1 AND deleted_at IS NULL",
+                id
+            )
+            .fetch_optional(&mut tx)
+            .await?
+        } else {
+            sqlx::query_scalar!(
+                r#"
+                UPDATE ai_models
+                SET download_count = download_count + 1
+                WHERE id = $1 AND deleted_at IS NULL
+                RETURNING download_count
+                "#,
+                id
+            )
+            .fetch_optional(&mut tx)
+            .await?
+        };
+        let Some(stored) = stored else {
+            return Ok(None);
+        };
+
+        sqlx::query!(
+            r#"
+            INSERT INTO model_downloads (
+                model_id, user_id,
+                utm_source, utm_medium, utm_campaign, utm_term, utm_content
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+            id,
+            user_id,
+            utm.utm_source,
+            utm.utm_medium,
+            utm.utm_campaign,
+            utm.utm_term,
+            utm.utm_content
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        let download_count = match &self.download_buffer {
+            Some(buffer) => stored + buffer.add(id),
+            None => stored,
+        };
+        Ok(Some(download_count))
+    }
+
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::summary", skip_all)]
+    pub async fn summary(&self, id: Uuid) -> Result<Option<ModelSummary>, sqlx::Error> {
+        let summary = sqlx::query_as!(
+            ModelSummary,
+            r#"
+            SELECT
+                m.id,
+                m.download_count,
+                COUNT(DISTINCT e.user_id) AS "unique_downloaders!",
+                COUNT(e.id) FILTER (WHERE e.user_id IS NULL AND NOT e.backfilled) AS "anonymous_downloads!"
+            FROM ai_models m
+            LEFT JOIN model_downloads e ON e.model_id = m.id
+            WHERE m.id = $1
+            GROUP BY m.id
+            "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(summary)
+    }
+
+    /// Download totals, recent activity and the most common consumer tier.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::analytics", skip_all)]
+    pub async fn analytics(&self, id: Uuid) -> Result<ModelAnalytics, sqlx::Error> {
+        sqlx::query_as!(
+            ModelAnalytics,
+            r#"
+            WITH downloads AS (
+                SELECT user_id, downloaded_at
+                FROM model_downloads
+                WHERE model_id = $1
+            ), consumers AS (
+                SELECT COALESCE(current.tier, 'free') AS tier, COUNT(*) AS users
+                FROM (SELECT DISTINCT user_id FROM downloads WHERE user_id IS NOT NULL) d
+                LEFT JOIN LATERAL (
+                    SELECT s.tier
+                    FROM user_subscriptions us
+                    JOIN subscriptions s ON s.id = us.subscription_id
+                    WHERE us.user_id = d.user_id AND us.is_active = true
+                    ORDER BY us.starts_at DESC
+                    LIMIT 1
+                ) current ON true
+                GROUP BY 1
+            )
+            SELECT
+                $1::uuid AS "model_id!",
+                (SELECT COUNT(*) FROM downloads) AS "total_downloads!",
+                (SELECT COUNT(DISTINCT user_id) FROM downloads) AS "unique_users!",
+                (SELECT COUNT(*) FROM downloads
+                 WHERE downloaded_at >= NOW() - interval '7 days') AS "downloads_last_7_days!",
+                (SELECT COUNT(*) FROM downloads
+                 WHERE downloaded_at >= NOW() - interval '30 days') AS "downloads_last_30_days!",
+                (SELECT tier FROM consumers
+                 ORDER BY users DESC, tier DESC
+                 LIMIT 1) AS "top_tier: SubscriptionTier"
+            "#,
+            id
+        )
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// Price spread of the model's published peers. Returns `None` if the
+    /// model doesn't exist. Unpriced models count as free.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::price_distribution", skip_all)]
+    pub async fn price_distribution(
+        &self,
+        id: Uuid,
+        exclude_free: bool,
+    ) -> Result<Option<PriceDistribution>, sqlx::Error> {
+        let distribution = sqlx::query_as!(
+            PriceDistribution,
+            r#"
+            WITH target AS (
+                SELECT id, model_type, tags
+                FROM ai_models
+                WHERE id = $1 AND deleted_at IS NULL
+            ), peers AS (
+                SELECT COALESCE(m.price, 0)::float8 AS price
+                FROM target t
+                JOIN ai_models m
+                    ON m.model_type = t.model_type
+                    AND (cardinality(t.tags) = 0 OR m.tags && t.tags)
+                WHERE m.id <> t.id
+                AND m.deleted_at IS NULL
+                AND m.is_public = true
+                AND m.status = 'published'
+                AND (NOT $2 OR m.price > 0)
+            )
+            SELECT
+                t.id AS "model_id!",
+                (SELECT COUNT(*) FROM peers) AS "comparable_models!",
+                (SELECT MIN(price) FROM peers) AS min_price,
+                (SELECT percentile_cont(0.5) WITHIN GROUP (ORDER BY price) FROM peers) AS median_price,
+                (SELECT MAX(price) FROM peers) AS max_price
+            FROM target t
+            "#,
+            id,
+            exclude_free
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(distribution)
+    }
+
+    /// Downloads of the model in `[from, to)`, counted per `bucket`. The
+    /// first and last buckets only count the part inside the range.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::download_stats", skip_all)]
+    pub async fn download_stats(
+        &self,
+        id: Uuid,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        bucket: StatsBucket,
+    ) -> Result<Vec<DownloadBucket>, sqlx::Error> {
+        sqlx::query_as!(
+            DownloadBucket,
+            r#"
+            SELECT b.start AS "bucket_start!", COUNT(d.id) AS "downloads!"
+            FROM generate_series(
+                date_trunc($2, $3::timestamptz),
+                $4::timestamptz - interval '1 microsecond',
+                ('1 ' || $2)::interval
+            ) AS b(start)
+            LEFT JOIN model_downloads d
+                ON d.model_id = $1
+                AND d.downloaded_at >= GREATEST(b.start, $3)
+                AND d.downloaded_at < LEAST(b.start + ('1 ' || $2)::interval, $4)
+            GROUP BY b.start
+            ORDER BY b.start
+            "#,
+            id,
+            bucket.as_str(),
+            from,
+            to
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Downloads in `[from, to)` grouped by UTM source and campaign, most
+    /// downloaded first.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::campaign_downloads", skip_all)]
+    pub async fn campaign_downloads(
+        &self,
+        id: Uuid,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<CampaignDownloads>, sqlx::Error> {
+        sqlx::query_as!(
+            CampaignDownloads,
+            r#"
+            SELECT utm_source, utm_campaign, COUNT(*) AS "downloads!"
+            FROM model_downloads
+            WHERE model_id = $1 AND downloaded_at >= $2 AND downloaded_at < $3
+            GROUP BY utm_source, utm_campaign
+            ORDER BY COUNT(*) DESC, utm_source NULLS LAST, utm_campaign NULLS LAST
+            "#,
+            id,
+            from,
+            to
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Save a model to the user's favorites. Saving one twice is a no-op.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::add_favorite", skip_all)]
+    pub async fn add_favorite(&self, user_id: Uuid, model_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO favorites (user_id, model_id)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id, model_id) DO NOTHING
+            "#,
+            user_id,
+            model_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns `false` if the model wasn't a favorite.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::remove_favorite", skip_all)]
+    pub async fn remove_favorite(&self, user_id: Uuid, model_id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM favorites WHERE user_id = $1 AND model_id = $2",
+            user_id,
+            model_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The user's favorites, most recently saved first. Models that were
+    /// deleted, or unpublished by someone else, are left out.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::list_favorites", skip_all)]
+    pub async fn list_favorites(
+        &self,
+        user_id: Uuid,
+        page: i64,
+        per_page: i64,
+    ) -> Result<(Vec<AIModel>, i64), sqlx::Error> {
         let offset = (page - 1) * per_page;
 
         let records = sqlx::query_as!(
             AIModel,
             r#"
-            SELECT * FROM ai_models
-            WHERE ($1::text IS NULL OR model_type = $1)
-            AND ($2::float8 IS NULL OR performance_metrics->>'accuracy' >= $2::text)
-            AND ($3::subscription_tier IS NULL OR required_tier = $3)
-            ORDER BY created_at DESC
-            LIMIT $4 OFFSET $5
+            SELECT m.id, m.name, m.description, m.model_type AS "model_type: ModelType", m.framework, m.version,
+                   m.status AS "status: ModelStatus", m.created_at, m.updated_at, m.metadata,
+                   m.performance_metrics AS "performance_metrics: Json<PerformanceMetrics>",
+                   m.repository_url, m.tags, m.download_count, m.downloads, m.is_public, m.price::float8 AS price,
+                   m.required_tier AS "required_tier: SubscriptionTier", m.owner_id,
+                   m.avg_rating::float8 AS avg_rating, m.license, m.deleted_at, m.created_by, m.updated_by,
+                   m.file_size_bytes, m.artifact_sha256, m.storage_key, m.withdrawn_from_sale_at, m.archived,
+                   m.forked_from, m.key_rotated_at
+            FROM favorites f
+            JOIN ai_models m ON m.id = f.model_id
+            WHERE f.user_id = $1
+            AND m.deleted_at IS NULL
+            AND ((m.status = 'published' AND m.is_public) OR m.owner_id = $1)
+            ORDER BY f.created_at DESC
+            LIMIT $2 OFFSET $3
             "#,
-            params.model_type,
-            params.min_accuracy,
-            params.required_tier as _,
+            user_id,
             per_page,
             offset
         )
@@ -84,87 +1257,310 @@ impl AIModelRepository {
 
         let total = sqlx::query_scalar!(
             r#"
-            SELECT COUNT(*) FROM ai_models
-            WHERE ($1::text IS NULL OR model_type = $1)
-            AND ($2::float8 IS NULL OR performance_metrics->>'accuracy' >= $2::text)
-            AND ($3::subscription_tier IS NULL OR required_tier = $3)
+            SELECT COUNT(*) AS "count!" FROM favorites f
+            JOIN ai_models m ON m.id = f.model_id
+            WHERE f.user_id = $1
+            AND m.deleted_at IS NULL
+            AND ((m.status = 'published' AND m.is_public) OR m.owner_id = $1)
             "#,
-            params.model_type,
-            params.min_accuracy,
-            params.required_tier as _
+            user_id
         )
         .fetch_one(&self.pool)
-        .await?
-        .unwrap_or(0);
+        .await?;
 
         Ok((records, total))
     }
 
-    pub async fn update(&self, id: Uuid, model: UpdateAIModel) -> Result<Option<AIModel>, sqlx::Error> {
-        let record = sqlx::query_as!(
-            AIModel,
+    /// Record a staleness report. Returns `false` if this user already
+    /// reported the model.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::flag_stale", skip_all)]
+    pub async fn flag_stale(
+        &self,
+        model_id: Uuid,
+        user_id: Uuid,
+        reason: Option<String>,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
             r#"
-            UPDATE ai_models
-            SET
-                name = COALESCE($1, name),
-                description = COALESCE($2, description),
-                model_type = COALESCE($3, model_type),
-                framework = COALESCE($4, framework),
-                version = COALESCE($5, version),
-                metadata = COALESCE($6, metadata),
-                repository_url = COALESCE($7, repository_url),
-                is_public = COALESCE($8, is_public),
-                price = COALESCE($9, price),
-                required_tier = COALESCE($10, required_tier),
-                tags = COALESCE($11, tags),
-                performance_metrics = COALESCE($12, performance_metrics),
-                updated_at = NOW()
-            WHERE id = $13
-            RETURNING *
+            INSERT INTO model_stale_reports (model_id, user_id, reason)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (model_id, user_id) DO NOTHING
             "#,
-            model.name,
-            model.description,
-            model.model_type,
-            model.framework,
-            model.version,
-            model.metadata,
-            model.repository_url,
-            model.is_public,
-            model.price,
-            model.required_tier as _,
-            &model.tags.unwrap_or_default(),
-            model.performance_metrics,
-            id
+            model_id,
+            user_id,
+            reason
         )
-        .fetch_optional(&self.pool)
+        .execute(&self.pool)
         .await?;
 
-        Ok(record)
+        Ok(result.rows_affected() > 0)
     }
 
-    pub async fn delete(&self, id: Uuid) -> Result<bool, sqlx::Error> {
-        let result = sqlx::query!(
-            "DELETE FROM ai_models WHERE id = $1",
-            id
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::stale_report_count", skip_all)]
+    pub async fn stale_report_count(&self, model_id: Uuid) -> Result<i64, sqlx::Error> {
+        let count = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM model_stale_reports WHERE model_id = $1"#,
+            model_id
         )
-        .execute(&self.pool)
+        .fetch_one(&self.pool)
         .await?;
 
-        Ok(result.rows_affected() > 0)
+        Ok(count)
     }
 
-    pub async fn increment_downloads(&self, id: Uuid) -> Result<(), sqlx::Error> {
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::add_dependency", skip_all)]
+    pub async fn add_dependency(&self, model_id: Uuid, dependency_id: Uuid) -> Result<(), sqlx::Error> {
         sqlx::query!(
             r#"
-            UPDATE ai_models
-            SET download_count = download_count + 1
-            WHERE id = $1
+            INSERT INTO model_dependencies (model_id, dependency_id)
+            VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
             "#,
-            id
+            model_id,
+            dependency_id
         )
         .execute(&self.pool)
         .await?;
 
         Ok(())
     }
-} 
\ No newline at end of file
+
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::list_dependencies", skip_all)]
+    pub async fn list_dependencies(
+        &self,
+        model_id: Uuid,
+    ) -> Result<Vec<DependencySummary>, sqlx::Error> {
+        sqlx::query_as!(
+            DependencySummary,
+            r#"
+            SELECT m.id, m.name, m.version, m.license
+            FROM model_dependencies d
+            JOIN ai_models m ON m.id = d.dependency_id
+            WHERE d.model_id = $1 AND m.deleted_at IS NULL
+            ORDER BY m.name
+            "#,
+            model_id
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Hard-delete models that were soft-deleted before `cutoff`, together with
+    /// the rows that reference them. Returns the number of models purged.
+    #[tracing::instrument(target = "repository", name = "AIModelRepository::purge_deleted_before", skip_all)]
+    pub async fn purge_deleted_before(&self, cutoff: DateTime<Utc>) -> Result<u64, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+            DELETE FROM model_usage
+            WHERE model_id IN (SELECT id FROM ai_models WHERE deleted_at < $1)
+            "#,
+            cutoff
+        )
+        .execute(&mut tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            DELETE FROM model_reviews
+            WHERE model_id IN (SELECT id FROM ai_models WHERE deleted_at < $1)
+            "#,
+            cutoff
+        )
+        .execute(&mut tx)
+        .await?;
+
+        let result = sqlx::query!(
+            "DELETE FROM ai_models WHERE deleted_at < $1",
+            cutoff
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+/// Owners can't have two live models with the same name (ignoring case).
+fn duplicate_name(e: sqlx::Error) -> AppError {
+    AppError::conflict_on_unique(e, "a model with this name already exists")
+}
+
+/// Insert a model and its `created` activity, on the pool or inside a
+/// caller's transaction.
+async fn insert_model<'e, E>(
+    executor: E,
+    model: CreateAIModel,
+    user_id: Uuid,
+) -> Result<AIModel, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query_as!(
+        AIModel,
+        r#"
+        WITH inserted AS (
+            INSERT INTO ai_models (
+                name, description, model_type, framework, version,
+                metadata, repository_url, is_public, price, required_tier,
+                tags, performance_metrics, license, owner_id, created_by, updated_by
+            )
+            VALUES (
+                $1, $2, $3, $4, $5, $6, $7, $8, $9::float8, $10, $11, $12, $14,
+                $13, $13, $13
+            )
+            RETURNING *
+        ), activity AS (
+            INSERT INTO model_activity (user_id, model_id, action)
+            SELECT $13, id, 'created' FROM inserted
+        ), versioned AS (
+            INSERT INTO model_versions (model_id, version, snapshot, created_by, sequence)
+            SELECT id, version,
+                   jsonb_build_object(
+                       'name', name,
+                       'description', description,
+                       'model_type', model_type,
+                       'framework', framework,
+                       'metadata', metadata,
+                       'performance_metrics', performance_metrics,
+                       'repository_url', repository_url
+                   ),
+                   $13, 1
+            FROM inserted
+        )
+        SELECT id, name, description, model_type AS "model_type: ModelType", framework, version,
+               status AS "status: ModelStatus", created_at, updated_at, metadata,
+               performance_metrics AS "performance_metrics: Json<PerformanceMetrics>",
+               repository_url, tags, download_count, downloads, is_public, price::float8 AS price,
+               required_tier AS "required_tier: SubscriptionTier", owner_id,
+               avg_rating::float8 AS avg_rating, license, deleted_at, created_by, updated_by,
+               file_size_bytes, artifact_sha256, storage_key, withdrawn_from_sale_at, archived,
+               forked_from, key_rotated_at
+        FROM inserted
+        "#,
+        model.name,
+        model.description,
+        model.model_type as _,
+        model.framework,
+        model.version,
+        model.metadata.unwrap_or_else(|| JsonValue::Object(serde_json::Map::new())),
+        model.repository_url,
+        model.is_public,
+        model.price,
+        model.required_tier.unwrap_or_default() as _,
+        &model.tags.unwrap_or_default(),
+        model.performance_metrics.map(Json) as _,
+        user_id,
+        model.license
+    )
+    .fetch_one(executor)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{create_user, new_model, published};
+
+    #[sqlx::test]
+    async fn purge_removes_only_models_deleted_before_the_cutoff(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let old = published(&pool, owner, new_model("Long gone")).await;
+        let recent = published(&pool, owner, new_model("Just deleted")).await;
+        let kept = published(&pool, owner, new_model("Still here")).await;
+        let repo = AIModelRepository::new(pool.clone());
+        repo.delete(old.id).await.unwrap();
+        repo.delete(recent.id).await.unwrap();
+        sqlx::query!(
+            "UPDATE ai_models SET deleted_at = NOW() - INTERVAL '40 days' WHERE id = $1",
+            old.id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let purged = repo
+            .purge_deleted_before(Utc::now() - chrono::Duration::days(30))
+            .await
+            .unwrap();
+
+        assert_eq!(purged, 1);
+        let remaining: Vec<Uuid> = sqlx::query_scalar!("SELECT id FROM ai_models ORDER BY name")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(remaining, vec![recent.id, kept.id]);
+    }
+
+    #[sqlx::test]
+    async fn the_accuracy_filter_compares_the_stored_metric_numerically(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let scored = |name, metrics: serde_json::Value| CreateAIModel {
+            performance_metrics: Some(serde_json::from_value(metrics).unwrap()),
+            ..new_model(name)
+        };
+        let precise = scored("Precise", serde_json::json!({ "accuracy": 0.95, "auc": 0.9 }));
+        let precise = published(&pool, owner, precise).await;
+        published(&pool, owner, scored("Rough", serde_json::json!({ "accuracy": 0.5 }))).await;
+        published(&pool, owner, scored("Unscored", serde_json::json!({ "f1": 0.99 }))).await;
+        let repo = AIModelRepository::new(pool.clone());
+
+        let stored = repo.get(precise.id).await.unwrap().unwrap().performance_metrics.unwrap();
+        assert_eq!(stored.accuracy, Some(0.95));
+        assert_eq!(stored.extra.get("auc"), Some(&serde_json::json!(0.9)));
+
+        let params = ListQueryParams {
+            min_accuracy: Some(0.9),
+            ..Default::default()
+        };
+        let (models, total) = repo.list(&params, None).await.unwrap();
+        assert_eq!(total, 1);
+        assert_eq!(models.into_iter().map(|m| m.id).collect::<Vec<_>>(), [precise.id]);
+    }
+
+    #[sqlx::test]
+    async fn a_filtered_page_reports_the_full_matching_total(pool: PgPool) {
+        use crate::models::{ListQueryParams, ModelType, PerformanceMetrics};
+        use crate::test_support::draft;
+
+        let owner = create_user(&pool).await;
+        let mut matching = 0;
+        for i in 0..12 {
+            let model_type = if i % 3 == 0 { ModelType::Vision } else { ModelType::Llm };
+            let accuracy = 0.7 + f64::from(i % 4) * 0.1;
+            let model = CreateAIModel {
+                model_type,
+                performance_metrics: Some(PerformanceMetrics {
+                    accuracy: Some(accuracy),
+                    ..Default::default()
+                }),
+                ..new_model(&format!("Model {}", i))
+            };
+            // Would match, but drafts aren't listed.
+            if i == 11 {
+                draft(&pool, owner, model).await;
+                continue;
+            }
+            if model_type == ModelType::Llm && accuracy >= 0.85 {
+                matching += 1;
+            }
+            published(&pool, owner, model).await;
+        }
+        let repo = AIModelRepository::new(pool.clone());
+        let page = |page| ListQueryParams {
+            model_type: Some(ModelType::Llm),
+            min_accuracy: Some(0.85),
+            page: Some(page),
+            per_page: Some(2),
+            ..Default::default()
+        };
+
+        let (first, total) = repo.list(&page(1), None).await.unwrap();
+        assert_eq!((first.len(), total), (2, matching));
+        let (past_the_end, total) = repo.list(&page(10), None).await.unwrap();
+        assert_eq!((past_the_end.len(), total), (0, matching));
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Download counts not yet written to `ai_models`, by model. Popular
+/// models otherwise take a row lock on every download; buffering turns
+/// those into one batched update per flush.
+#[derive(Clone, Default)]
+pub struct DownloadBuffer {
+    pending: Arc<Mutex<HashMap<Uuid, i32>>>,
+}
+
+impl DownloadBuffer {
+    /// How often buffered counts are flushed, `DOWNLOAD_COUNT_FLUSH_SECS`.
+    /// Unset or `0` leaves buffering off and counts are written directly.
+    pub fn flush_interval_from_env() -> Option<Duration> {
+        env::var("DOWNLOAD_COUNT_FLUSH_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+    }
+
+    /// Count a download. Returns how many downloads of the model are now
+    /// waiting to be flushed.
+    pub fn add(&self, model_id: Uuid) -> i32 {
+        let mut pending = self.pending.lock().unwrap();
+        let count = pending.entry(model_id).or_default();
+        *count += 1;
+        *count
+    }
+
+    /// Write all buffered counts in one statement. On failure they're put
+    /// back for the next flush. Returns the number of downloads written.
+    pub async fn flush(&self, pool: &PgPool) -> Result<i64, sqlx::Error> {
+        let taken = std::mem::take(&mut *self.pending.lock().unwrap());
+        if taken.is_empty() {
+            return Ok(0);
+        }
+
+        let (ids, counts): (Vec<Uuid>, Vec<i32>) = taken.iter().map(|(id, n)| (*id, *n)).unzip();
+        let result = sqlx::query!(
+            r#"
+            UPDATE ai_models
+            SET download_count = ai_models.download_count + pending.count
+            FROM UNNEST($1::uuid[], $2::int[]) AS pending(id, count)
+            WHERE ai_models.id = pending.id
+            "#,
+            &ids,
+            &counts
+        )
+        .execute(pool)
+        .await;
+
+        if let Err(e) = result {
+            let mut pending = self.pending.lock().unwrap();
+            for (id, count) in taken {
+                *pending.entry(id).or_default() += count;
+            }
+            return Err(e);
+        }
+
+        Ok(counts.iter().map(|n| i64::from(*n)).sum())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{create_user, new_model, published};
+
+    #[sqlx::test]
+    async fn concurrent_buffered_downloads_flush_to_the_exact_count(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let popular = published(&pool, owner, new_model("Popular")).await;
+        let niche = published(&pool, owner, new_model("Niche")).await;
+        let buffer = DownloadBuffer::default();
+
+        let tasks: Vec<_> = (0..250)
+            .map(|i| {
+                let buffer = buffer.clone();
+                let id = if i % 5 == 0 { niche.id } else { popular.id };
+                tokio::spawn(async move { buffer.add(id) })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(buffer.flush(&pool).await.unwrap(), 250);
+        assert_eq!(buffer.flush(&pool).await.unwrap(), 0);
+        let counts = sqlx::query!(
+            "SELECT id, download_count FROM ai_models WHERE id = ANY($1) ORDER BY name",
+            &[popular.id, niche.id][..]
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        let counts: Vec<_> = counts.into_iter().map(|row| (row.id, row.download_count)).collect();
+        assert_eq!(counts, [(niche.id, 50), (popular.id, 200)]);
+    }
+}
@@ -1,3 +1,9 @@
 mod ai_models;
+mod download_buffer;
+mod reviews;
+mod slow_query;
 
-pub use ai_models::AIModelRepository; 
\ No newline at end of file
+pub use ai_models::AIModelRepository;
+pub use download_buffer::DownloadBuffer;
+pub use reviews::ReviewRepository;
+pub use slow_query::SlowQueryLayer;
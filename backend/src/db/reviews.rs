@@ -0,0 +1,165 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::models::{CreateReview, ModelReview};
+
+#[derive(Clone)]
+pub struct ReviewRepository {
+    pool: PgPool,
+}
+
+impl ReviewRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Insert a review and refresh the model's cached `avg_rating` in the
+    /// same transaction. A second review by the same user violates the
+    /// `(model_id, user_id)` unique constraint.
+    #[tracing::instrument(target = "repository", name = "ReviewRepository::create_review", skip_all)]
+    pub async fn create_review(
+        &self,
+        model_id: Uuid,
+        user_id: Uuid,
+        review: CreateReview,
+    ) -> Result<ModelReview, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let record = sqlx::query_as!(
+            ModelReview,
+            r#"
+            INSERT INTO model_reviews (model_id, user_id, rating, review_text)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, model_id, user_id, rating, review_text AS comment, created_at
+            "#,
+            model_id,
+            user_id,
+            review.rating,
+            review.comment
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE ai_models
+            SET avg_rating = (SELECT AVG(rating) FROM model_reviews WHERE model_id = $1)
+            WHERE id = $1
+            "#,
+            model_id
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(record)
+    }
+
+    #[tracing::instrument(target = "repository", name = "ReviewRepository::find_review_id", skip_all)]
+    pub async fn find_review_id(
+        &self,
+        model_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<Uuid>, sqlx::Error> {
+        let id = sqlx::query_scalar!(
+            "SELECT id FROM model_reviews WHERE model_id = $1 AND user_id = $2",
+            model_id,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(id)
+    }
+
+    #[tracing::instrument(target = "repository", name = "ReviewRepository::list_reviews", skip_all)]
+    pub async fn list_reviews(
+        &self,
+        model_id: Uuid,
+        page: i64,
+        per_page: i64,
+    ) -> Result<Vec<ModelReview>, sqlx::Error> {
+        let offset = (page - 1) * per_page;
+
+        let records = sqlx::query_as!(
+            ModelReview,
+            r#"
+            SELECT id, model_id, user_id, rating, review_text AS comment, created_at
+            FROM model_reviews
+            WHERE model_id = $1
+            ORDER BY created_at DESC
+            LIMIT $2 OFFSET $3
+            "#,
+            model_id,
+            per_page,
+            offset
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(records)
+    }
+
+    #[tracing::instrument(target = "repository", name = "ReviewRepository::average_rating", skip_all)]
+    pub async fn average_rating(&self, model_id: Uuid) -> Result<Option<f64>, sqlx::Error> {
+        let average = sqlx::query_scalar!(
+            "SELECT AVG(rating)::float8 FROM model_reviews WHERE model_id = $1",
+            model_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(average)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::AIModelRepository;
+    use crate::test_support::{create_user, new_model, published};
+
+    fn rating(rating: i32) -> CreateReview {
+        CreateReview {
+            rating,
+            comment: None,
+        }
+    }
+
+    #[sqlx::test]
+    async fn the_average_covers_every_review(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = published(&pool, owner, new_model("Rated")).await;
+        let reviews = ReviewRepository::new(pool.clone());
+        assert_eq!(reviews.average_rating(model.id).await.unwrap(), None);
+
+        for stars in [5, 4, 2] {
+            let reviewer = create_user(&pool).await;
+            reviews.create_review(model.id, reviewer, rating(stars)).await.unwrap();
+        }
+
+        let average = reviews.average_rating(model.id).await.unwrap().unwrap();
+        assert!((average - 11.0 / 3.0).abs() < 1e-9, "{}", average);
+        // The cached column keeps two decimal places.
+        let model = AIModelRepository::new(pool).get(model.id).await.unwrap().unwrap();
+        assert_eq!(model.avg_rating, Some(3.67));
+    }
+
+    #[sqlx::test]
+    async fn a_second_review_by_the_same_user_is_rejected(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let reviewer = create_user(&pool).await;
+        let model = published(&pool, owner, new_model("Rated")).await;
+        let reviews = ReviewRepository::new(pool.clone());
+        let first = reviews.create_review(model.id, reviewer, rating(5)).await.unwrap();
+
+        let second = reviews.create_review(model.id, reviewer, rating(1)).await;
+
+        let error = second.unwrap_err();
+        let code = error.as_database_error().and_then(|e| e.code());
+        assert_eq!(code.as_deref(), Some("23505"));
+        assert_eq!(reviews.find_review_id(model.id, reviewer).await.unwrap(), Some(first.id));
+        assert_eq!(reviews.average_rating(model.id).await.unwrap(), Some(5.0));
+    }
+}
@@ -0,0 +1,56 @@
+use std::time::{Duration, Instant};
+
+use tracing::{span, Subscriber};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+/// Target of the spans repository methods open, which [`SlowQueryLayer`]
+/// times.
+const REPOSITORY_TARGET: &str = "repository";
+
+/// Logs a warning for every repository call that takes longer than
+/// `DB_SLOW_QUERY_MS` (default 500), naming the method and the time taken.
+pub struct SlowQueryLayer {
+    threshold: Duration,
+}
+
+impl SlowQueryLayer {
+    pub fn from_env() -> Self {
+        let ms = std::env::var("DB_SLOW_QUERY_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(500);
+        Self {
+            threshold: Duration::from_millis(ms),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Started(Instant);
+
+impl<S> Layer<S> for SlowQueryLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        if attrs.metadata().target() != REPOSITORY_TARGET {
+            return;
+        }
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(Started(Instant::now()));
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(Started(started)) = span.extensions().get::<Started>().copied() else {
+            return;
+        };
+        let elapsed = started.elapsed();
+        if elapsed >= self.threshold {
+            tracing::warn!("slow repository call {} took {:?}", span.name(), elapsed);
+        }
+    }
+}
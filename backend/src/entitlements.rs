@@ -0,0 +1,197 @@
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{extract::State, http::header, response::IntoResponse, Json};
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use serde::{Deserialize, Serialize};
+use sqlx::types::JsonValue;
+use uuid::Uuid;
+
+use crate::{
+    auth::AuthUser,
+    error::AppError,
+    models::{Subscription, SubscriptionTier, UserSubscription},
+    AppState,
+};
+
+/// Signs entitlement receipts with an Ed25519 key so other services can
+/// check a user's plan offline, with only the public key.
+///
+/// | Variable | Meaning |
+/// | --- | --- |
+/// | `ENTITLEMENT_SIGNING_KEY` | Ed25519 private key, PKCS#8 PEM. Receipts are disabled when unset. |
+/// | `ENTITLEMENT_PUBLIC_KEY` | Matching public key PEM, served to verifiers. |
+/// | `ENTITLEMENT_RECEIPT_TTL_SECS` | How long a receipt is valid (default 3600). |
+#[derive(Clone)]
+pub struct ReceiptSigner {
+    key: Arc<EncodingKey>,
+    public_key_pem: Arc<str>,
+    ttl: Duration,
+}
+
+impl ReceiptSigner {
+    pub fn from_env() -> Option<Self> {
+        let private_pem = env::var("ENTITLEMENT_SIGNING_KEY").ok()?;
+        let key = EncodingKey::from_ed_pem(private_pem.as_bytes())
+            .expect("ENTITLEMENT_SIGNING_KEY must be an Ed25519 PEM private key");
+        let public_key_pem = env::var("ENTITLEMENT_PUBLIC_KEY")
+            .expect("ENTITLEMENT_PUBLIC_KEY must be set with ENTITLEMENT_SIGNING_KEY");
+
+        Some(Self {
+            key: Arc::new(key),
+            public_key_pem: public_key_pem.into(),
+            ttl: Duration::from_secs(
+                env::var("ENTITLEMENT_RECEIPT_TTL_SECS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(3600),
+            ),
+        })
+    }
+
+    fn sign(&self, claims: &EntitlementClaims) -> Result<String, jsonwebtoken::errors::Error> {
+        encode(&Header::new(Algorithm::EdDSA), claims, &self.key)
+    }
+}
+
+/// The payload of a receipt. Verifiers should check the `EdDSA` signature
+/// and `exp` before trusting `tier` or `features`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EntitlementClaims {
+    pub sub: Uuid,
+    pub tier: SubscriptionTier,
+    pub features: JsonValue,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EntitlementReceipt {
+    /// Compact JWS over [`EntitlementClaims`].
+    pub receipt: String,
+    pub tier: SubscriptionTier,
+    pub expires_at: DateTime<Utc>,
+}
+
+fn signer(state: &AppState) -> Result<&ReceiptSigner, AppError> {
+    state
+        .receipt_signer
+        .as_ref()
+        .ok_or_else(|| AppError::NotFound("Entitlement receipts are not enabled".into()))
+}
+
+/// A signed statement of the caller's current tier and plan features.
+#[axum::debug_handler(state = AppState)]
+pub async fn verify_entitlements(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+) -> Result<Json<EntitlementReceipt>, AppError> {
+    let signer = signer(&state)?;
+
+    let tier = UserSubscription::tier_for_user(&state.pool, user_id).await?;
+    // Prefer the user's own plan; fall back to the tier's base plan when
+    // they've been dropped to a lower tier (paused, disputed).
+    let active_plan = match UserSubscription::get_active_for_user(&state.pool, user_id).await? {
+        Some(active) => Subscription::get_by_id(&state.pool, active.subscription_id).await?,
+        None => None,
+    };
+    let plan = match active_plan.filter(|plan| plan.tier == tier) {
+        Some(plan) => Some(plan),
+        None => Subscription::get_by_tier(&state.pool, tier).await?,
+    };
+
+    let now = Utc::now();
+    let expires_at = now + chrono::Duration::seconds(signer.ttl.as_secs() as i64);
+    let claims = EntitlementClaims {
+        sub: user_id,
+        tier,
+        features: plan
+            .map(|plan| plan.features)
+            .unwrap_or_else(|| JsonValue::Object(Default::default())),
+        iat: now.timestamp(),
+        exp: expires_at.timestamp(),
+    };
+    let receipt = signer.sign(&claims).map_err(anyhow::Error::from)?;
+
+    Ok(Json(EntitlementReceipt {
+        receipt,
+        tier,
+        expires_at,
+    }))
+}
+
+/// The PEM public key receipts verify against.
+#[axum::debug_handler(state = AppState)]
+pub async fn entitlement_public_key(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let signer = signer(&state)?;
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-pem-file")],
+        signer.public_key_pem.to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{app_state, create_user, subscribe};
+    use base64::Engine;
+    use jsonwebtoken::{decode, errors::ErrorKind, DecodingKey, Validation};
+    use sqlx::PgPool;
+
+    fn signer_and_public_key() -> (ReceiptSigner, DecodingKey) {
+        let keys = rcgen::KeyPair::generate_for(&rcgen::PKCS_ED25519).unwrap();
+        let signer = ReceiptSigner {
+            key: Arc::new(EncodingKey::from_ed_pem(keys.serialize_pem().as_bytes()).unwrap()),
+            public_key_pem: keys.public_key_pem().into(),
+            ttl: Duration::from_secs(3600),
+        };
+        let public_key = DecodingKey::from_ed_pem(keys.public_key_pem().as_bytes()).unwrap();
+        (signer, public_key)
+    }
+
+    fn verified(receipt: &str, public_key: &DecodingKey) -> Result<EntitlementClaims, ErrorKind> {
+        decode(receipt, public_key, &Validation::new(Algorithm::EdDSA))
+            .map(|data| data.claims)
+            .map_err(|e| e.into_kind())
+    }
+
+    #[sqlx::test]
+    async fn a_receipt_verifies_only_untampered_and_unexpired(pool: PgPool) {
+        let user_id = create_user(&pool).await;
+        subscribe(&pool, user_id, SubscriptionTier::Pro).await;
+        let (signer, public_key) = signer_and_public_key();
+        let state = AppState {
+            receipt_signer: Some(signer.clone()),
+            ..app_state(&pool).await
+        };
+
+        let Json(issued) = verify_entitlements(State(state), AuthUser(user_id)).await.unwrap();
+        let claims = verified(&issued.receipt, &public_key).unwrap();
+        assert_eq!((claims.sub, claims.tier), (user_id, SubscriptionTier::Pro));
+        assert_eq!(claims.exp, issued.expires_at.timestamp());
+
+        // Claim a better tier without re-signing.
+        let parts: Vec<&str> = issued.receipt.split('.').collect();
+        let engine = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        let mut payload: JsonValue =
+            serde_json::from_slice(&engine.decode(parts[1]).unwrap()).unwrap();
+        payload["tier"] = serde_json::to_value(SubscriptionTier::Enterprise).unwrap();
+        let forged = engine.encode(serde_json::to_vec(&payload).unwrap());
+        let tampered = format!("{}.{}.{}", parts[0], forged, parts[2]);
+        assert_eq!(verified(&tampered, &public_key).err(), Some(ErrorKind::InvalidSignature));
+
+        let now = Utc::now().timestamp();
+        let stale = signer
+            .sign(&EntitlementClaims {
+                iat: now - 7200,
+                exp: now - 3600,
+                ..claims
+            })
+            .unwrap();
+        assert_eq!(verified(&stale, &public_key).err(), Some(ErrorKind::ExpiredSignature));
+    }
+}
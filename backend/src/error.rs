@@ -0,0 +1,250 @@
+use std::time::Duration;
+
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{models::ValidationErrors, services::stripe::card_decline};
+
+/// The existing resource a request collided with.
+#[derive(Debug, Serialize)]
+pub struct ConflictingResource {
+    pub id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AppError {
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    Unauthorized(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    PayloadTooLarge(String),
+    #[error("{message}")]
+    Conflict {
+        message: String,
+        existing: Option<ConflictingResource>,
+    },
+    /// The card was declined; `code` is Stripe's decline code.
+    #[error("{message}")]
+    PaymentFailed { code: String, message: String },
+    #[error("{message}")]
+    TooManyRequests { message: String, retry_after: Duration },
+    /// Shed under load; answered with `503` and `Retry-After`.
+    #[error("{message}")]
+    Overloaded { message: String, retry_after: Duration },
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
+}
+
+impl AppError {
+    pub fn conflict(message: impl Into<String>) -> Self {
+        AppError::Conflict {
+            message: message.into(),
+            existing: None,
+        }
+    }
+
+    pub fn conflict_with(message: impl Into<String>, id: Uuid, url: Option<String>) -> Self {
+        AppError::Conflict {
+            message: message.into(),
+            existing: Some(ConflictingResource { id, url }),
+        }
+    }
+
+    /// A `409` with `message` if `e` is a unique-constraint violation,
+    /// otherwise a `500`.
+    pub fn conflict_on_unique(e: sqlx::Error, message: impl Into<String>) -> Self {
+        if is_unique_violation(&e) {
+            AppError::conflict(message)
+        } else {
+            e.into()
+        }
+    }
+}
+
+/// Whether Postgres cancelled a statement for running past
+/// `statement_timeout`.
+pub fn is_statement_timeout(e: &sqlx::Error) -> bool {
+    matches!(e, sqlx::Error::Database(db) if db.code().as_deref() == Some("57014"))
+}
+
+/// Whether Postgres rejected a write for breaking a unique constraint.
+pub fn is_unique_violation(e: &sqlx::Error) -> bool {
+    matches!(e, sqlx::Error::Database(db) if db.code().as_deref() == Some("23505"))
+}
+
+/// Whether Postgres rejected a write for referencing a missing row, or a
+/// delete for removing a row still referenced.
+pub fn is_foreign_key_violation(e: &sqlx::Error) -> bool {
+    matches!(e, sqlx::Error::Database(db) if db.code().as_deref() == Some("23503"))
+}
+
+/// Constraint violations are the request colliding with existing data, so
+/// they answer `409`; anything else is a `500`. Handlers that can say
+/// what collided should use [`AppError::conflict_on_unique`] instead.
+impl From<sqlx::Error> for AppError {
+    fn from(e: sqlx::Error) -> Self {
+        if is_unique_violation(&e) {
+            AppError::conflict("A record with these details already exists")
+        } else if is_foreign_key_violation(&e) {
+            AppError::conflict("This refers to a record that doesn't exist or is still in use")
+        } else {
+            AppError::Internal(e.into())
+        }
+    }
+}
+
+impl From<ValidationErrors> for AppError {
+    fn from(errors: ValidationErrors) -> Self {
+        AppError::BadRequest(errors.to_string())
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        // Repositories and services returning `anyhow::Result` wrap
+        // database and Stripe errors before they get here; give them the
+        // same mapping.
+        let this = match self {
+            AppError::Internal(e) => match e.downcast::<sqlx::Error>() {
+                Ok(e) => AppError::from(e),
+                Err(e) => match e.downcast_ref::<stripe::StripeError>().and_then(card_decline) {
+                    Some(declined) => declined,
+                    None => AppError::Internal(e),
+                },
+            },
+            other => other,
+        };
+        let mut retry_after_secs = None;
+        let (status, body) = match this {
+            AppError::NotFound(message) => (StatusCode::NOT_FOUND, json!({ "error": message })),
+            AppError::BadRequest(message) => (StatusCode::BAD_REQUEST, json!({ "error": message })),
+            AppError::Unauthorized(message) => (StatusCode::UNAUTHORIZED, json!({ "error": message })),
+            AppError::Forbidden(message) => (StatusCode::FORBIDDEN, json!({ "error": message })),
+            AppError::PayloadTooLarge(message) => {
+                (StatusCode::PAYLOAD_TOO_LARGE, json!({ "error": message }))
+            }
+            AppError::Conflict { message, existing } => (
+                StatusCode::CONFLICT,
+                json!({ "error": message, "conflicting_resource": existing }),
+            ),
+            AppError::PaymentFailed { code, message } => (
+                StatusCode::PAYMENT_REQUIRED,
+                json!({ "error": message, "code": code }),
+            ),
+            AppError::TooManyRequests { message, retry_after } => {
+                let secs = whole_secs(retry_after);
+                retry_after_secs = Some(secs);
+                (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    json!({ "error": message, "retry_after_secs": secs }),
+                )
+            }
+            AppError::Overloaded { message, retry_after } => {
+                let secs = whole_secs(retry_after);
+                retry_after_secs = Some(secs);
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    json!({ "error": message, "retry_after_secs": secs }),
+                )
+            }
+            // A query that timed out may well succeed once load drops.
+            AppError::Internal(e)
+                if e.downcast_ref::<sqlx::Error>().is_some_and(is_statement_timeout) =>
+            {
+                tracing::warn!("query timed out: {:#}", e);
+                let secs = whole_secs(STATEMENT_TIMEOUT_RETRY_AFTER);
+                retry_after_secs = Some(secs);
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    json!({ "error": "The request took too long; try again", "retry_after_secs": secs }),
+                )
+            }
+            AppError::Internal(e) => {
+                tracing::error!("internal error: {:#}", e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    json!({ "error": "Internal server error" }),
+                )
+            }
+        };
+
+        let mut response = (status, Json(body)).into_response();
+        if let Some(secs) = retry_after_secs {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
+    }
+}
+
+/// How long clients are asked to wait after a query timed out.
+const STATEMENT_TIMEOUT_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// Round up so clients never retry a moment too early.
+fn whole_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PoolSettings;
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    async fn a_query_past_the_statement_timeout_is_aborted_as_a_503(pool: PgPool) {
+        let settings = PoolSettings {
+            statement_timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let timed = settings
+            .options()
+            .connect_with(pool.connect_options().clone())
+            .await
+            .unwrap();
+
+        let started = std::time::Instant::now();
+        let e = sqlx::query("SELECT pg_sleep(5)").execute(&timed).await.unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(4));
+        assert!(is_statement_timeout(&e));
+        timed.close().await;
+
+        let response = AppError::from(e).into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+    }
+
+    #[sqlx::test]
+    async fn a_foreign_key_violation_is_a_409_not_a_500(pool: PgPool) {
+        let e = sqlx::query!(
+            "INSERT INTO model_reviews (model_id, user_id, rating) VALUES ($1, $2, 5)",
+            Uuid::new_v4(),
+            Uuid::new_v4()
+        )
+        .execute(&pool)
+        .await
+        .unwrap_err();
+        assert!(is_foreign_key_violation(&e));
+
+        let error = AppError::from(e);
+        assert!(matches!(error, AppError::Conflict { .. }));
+        assert_eq!(error.into_response().status(), StatusCode::CONFLICT);
+
+        let other = AppError::from(sqlx::Error::RowNotFound);
+        assert_eq!(other.into_response().status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::models::payment::is_terminal_payment_status;
+
+/// Something that changed on a model, as pushed to live subscribers.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ModelEvent {
+    Downloaded {
+        model_id: Uuid,
+        download_count: i32,
+    },
+    ReviewAdded {
+        model_id: Uuid,
+        review_id: Uuid,
+        rating: i32,
+    },
+}
+
+impl ModelEvent {
+    pub fn model_id(&self) -> Uuid {
+        match self {
+            ModelEvent::Downloaded { model_id, .. } | ModelEvent::ReviewAdded { model_id, .. } => {
+                *model_id
+            }
+        }
+    }
+}
+
+/// In-process fan-out of model events. Subscribers that fall more than
+/// `EVENT_BUS_CAPACITY` events behind miss the oldest ones.
+#[derive(Clone)]
+pub struct EventBus(broadcast::Sender<ModelEvent>);
+
+const EVENT_BUS_CAPACITY: usize = 1024;
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self(broadcast::channel(EVENT_BUS_CAPACITY).0)
+    }
+}
+
+impl EventBus {
+    /// Publish to whoever is listening; with no subscribers it's a no-op.
+    pub fn publish(&self, event: ModelEvent) {
+        let _ = self.0.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ModelEvent> {
+        self.0.subscribe()
+    }
+}
+
+/// A payment intent's status, as pushed to clients waiting on checkout.
+#[derive(Debug, Clone, Serialize)]
+pub struct PaymentStatusEvent {
+    pub payment_intent_id: String,
+    pub status: String,
+}
+
+impl PaymentStatusEvent {
+    /// No further updates follow a terminal status.
+    pub fn is_terminal(&self) -> bool {
+        is_terminal_payment_status(&self.status)
+    }
+}
+
+/// Per-intent fan-out of payment status changes, keyed by Stripe payment
+/// intent id. Channels exist only while someone is listening.
+#[derive(Clone, Default)]
+pub struct PaymentStatusBus(Arc<Mutex<HashMap<String, broadcast::Sender<PaymentStatusEvent>>>>);
+
+/// A checkout sees a handful of transitions at most.
+const PAYMENT_STATUS_CAPACITY: usize = 16;
+
+impl PaymentStatusBus {
+    pub fn subscribe(&self, payment_intent_id: &str) -> broadcast::Receiver<PaymentStatusEvent> {
+        let mut channels = self.0.lock().unwrap();
+        // Drop channels whose listeners have all gone.
+        channels.retain(|_, sender| sender.receiver_count() > 0);
+        channels
+            .entry(payment_intent_id.to_string())
+            .or_insert_with(|| broadcast::channel(PAYMENT_STATUS_CAPACITY).0)
+            .subscribe()
+    }
+
+    /// Tell anyone waiting on the intent; with no listeners it's a no-op.
+    pub fn publish(&self, payment_intent_id: &str, status: &str) {
+        let mut channels = self.0.lock().unwrap();
+        let Some(sender) = channels.get(payment_intent_id) else {
+            return;
+        };
+        let event = PaymentStatusEvent {
+            payment_intent_id: payment_intent_id.to_string(),
+            status: status.to_string(),
+        };
+        let terminal = event.is_terminal();
+        let _ = sender.send(event);
+        if terminal {
+            channels.remove(payment_intent_id);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{request::Parts, HeaderName},
+    Json,
+};
+
+use crate::{auth::Caller, error::AppError, AppState};
+
+/// Admin-only request header that overrides flags for that one request,
+/// e.g. `X-Feature-Overrides: async_webhooks=on, new_search=off`.
+pub static FEATURE_OVERRIDES_HEADER: HeaderName = HeaderName::from_static("x-feature-overrides");
+
+fn parse_state(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "on" | "true" | "1" | "enabled" => Some(true),
+        "off" | "false" | "0" | "disabled" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parse `name=on,other=off`. Malformed entries are skipped.
+fn parse_flags(spec: &str) -> HashMap<String, bool> {
+    spec.split(',')
+        .filter_map(|entry| {
+            let (name, value) = entry.split_once('=')?;
+            let name = name.trim();
+            if name.is_empty() {
+                return None;
+            }
+            Some((name.to_string(), parse_state(value)?))
+        })
+        .collect()
+}
+
+/// Globally configured feature flags, from `FEATURE_FLAGS` in the same
+/// `name=on,other=off` form as the override header. Unknown flags are off.
+#[derive(Debug, Clone, Default)]
+pub struct FeatureFlags(Arc<HashMap<String, bool>>);
+
+impl FeatureFlags {
+    pub fn from_env() -> Self {
+        let spec = std::env::var("FEATURE_FLAGS").unwrap_or_default();
+        Self(Arc::new(parse_flags(&spec)))
+    }
+
+    /// Whether the flag is on globally, for settings fixed at startup that
+    /// per-request overrides can't change.
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.0.get(name).copied().unwrap_or(false)
+    }
+}
+
+/// The flags in effect for the current request: the global flags, with an
+/// admin's `X-Feature-Overrides` layered on top. Everyone else's header is
+/// ignored.
+#[derive(Debug, Clone)]
+pub struct Features {
+    flags: FeatureFlags,
+    overrides: HashMap<String, bool>,
+}
+
+impl Features {
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.overrides
+            .get(name)
+            .or_else(|| self.flags.0.get(name))
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// Every flag that's configured or overridden, resolved.
+    pub fn resolved(&self) -> BTreeMap<String, bool> {
+        self.flags
+            .0
+            .keys()
+            .chain(self.overrides.keys())
+            .map(|name| (name.clone(), self.is_enabled(name)))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Features
+where
+    AppState: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let flags = AppState::from_ref(state).feature_flags;
+
+        let Some(spec) = parts
+            .headers
+            .get(&FEATURE_OVERRIDES_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned)
+        else {
+            return Ok(Features {
+                flags,
+                overrides: HashMap::new(),
+            });
+        };
+
+        let is_admin = Caller::from_request_parts(parts, state)
+            .await
+            .is_ok_and(|caller| caller.is_admin());
+        let overrides = if is_admin {
+            parse_flags(&spec)
+        } else {
+            HashMap::new()
+        };
+
+        Ok(Features { flags, overrides })
+    }
+}
+
+/// The flags as they apply to the caller's request.
+#[axum::debug_handler(state = AppState)]
+pub async fn list_features(features: Features) -> Json<BTreeMap<String, bool>> {
+    Json(features.resolved())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{admin, app_state, bearer, create_user, user};
+    use axum::http::{header::AUTHORIZATION, Request};
+    use sqlx::PgPool;
+
+    async fn features_for(state: &AppState, caller: Option<Caller>, overrides: &str) -> Features {
+        let mut request = Request::builder().header(&FEATURE_OVERRIDES_HEADER, overrides);
+        if let Some(caller) = caller {
+            request = request.header(AUTHORIZATION, bearer(caller));
+        }
+        let (mut parts, ()) = request.body(()).unwrap().into_parts();
+        Features::from_request_parts(&mut parts, state).await.unwrap()
+    }
+
+    #[sqlx::test]
+    async fn only_an_admin_can_override_a_flag_for_their_request(pool: PgPool) {
+        let state = AppState {
+            feature_flags: FeatureFlags(Arc::new(parse_flags("async_webhooks=off"))),
+            ..app_state(&pool).await
+        };
+        let overrides = "async_webhooks=on";
+
+        let as_admin = features_for(&state, Some(admin(create_user(&pool).await)), overrides).await;
+        assert!(as_admin.is_enabled("async_webhooks"));
+
+        let as_user = features_for(&state, Some(user(create_user(&pool).await)), overrides).await;
+        assert!(!as_user.is_enabled("async_webhooks"));
+        assert!(!features_for(&state, None, overrides).await.is_enabled("async_webhooks"));
+        assert!(!state.feature_flags.is_enabled("async_webhooks"));
+    }
+}
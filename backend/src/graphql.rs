@@ -0,0 +1,447 @@
+use async_graphql::{
+    http::GraphiQLSource, Context, EmptyMutation, EmptySubscription, ErrorExtensions, InputObject,
+    Object, Schema, SimpleObject,
+};
+use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
+use axum::{
+    extract::State,
+    response::Html,
+    routing::{get, post},
+    Router,
+};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::{
+    auth::{AuthUser, Caller},
+    error::AppError,
+    models::{
+        default_page_size, stale_after_months, validate_paging, AIModel, BillingInterval,
+        ListQueryParams, ModelReview, ModelStatus, ModelType, Subscription, SubscriptionTier,
+        UserSubscription, Validate, FALLBACK_MAX_PAGE_SIZE,
+    },
+    AppState,
+};
+
+/// Deepest query accepted; `model { reviews }` needs 3.
+const MAX_DEPTH: usize = 6;
+
+/// Reviews returned for a model when the query doesn't ask for a page size.
+const DEFAULT_REVIEWS: i64 = 10;
+
+pub type ApiSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+/// The read-only GraphQL schema. Resolvers take [`AppState`] and the
+/// optional [`Caller`] from the request data set in [`execute`].
+pub fn schema() -> ApiSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .limit_depth(MAX_DEPTH)
+        .finish()
+}
+
+pub fn graphql_routes() -> Router<AppState> {
+    Router::new()
+        .route("/graphql", post(execute))
+        .route("/graphql/playground", get(playground))
+}
+
+async fn execute(
+    State(state): State<AppState>,
+    caller: Option<Caller>,
+    request: GraphQLRequest,
+) -> GraphQLResponse {
+    let mut request = request.into_inner().data(state.clone());
+    if let Some(caller) = caller {
+        request = request.data(caller);
+    }
+    state.graphql.execute(request).await.into()
+}
+
+async fn playground() -> Html<String> {
+    Html(GraphiQLSource::build().endpoint("/graphql").finish())
+}
+
+/// Report an error the way the REST API would: its message and status,
+/// except internal errors, which are logged and kept from the client.
+fn gql_error(e: impl Into<AppError>) -> async_graphql::Error {
+    let (code, message) = match e.into() {
+        AppError::NotFound(message) => ("NOT_FOUND", message),
+        AppError::BadRequest(message) => ("BAD_REQUEST", message),
+        AppError::Unauthorized(message) => ("UNAUTHORIZED", message),
+        AppError::Forbidden(message) => ("FORBIDDEN", message),
+        AppError::Conflict { message, .. } => ("CONFLICT", message),
+        AppError::PaymentFailed { message, .. } => ("PAYMENT_FAILED", message),
+        AppError::Internal(e) => {
+            tracing::error!("internal error: {:#}", e);
+            ("INTERNAL_SERVER_ERROR", "Internal server error".into())
+        }
+        other => ("BAD_REQUEST", other.to_string()),
+    };
+    async_graphql::Error::new(message).extend_with(|_, extensions| extensions.set("code", code))
+}
+
+fn caller(ctx: &Context<'_>) -> Option<Caller> {
+    ctx.data_opt::<Caller>().copied()
+}
+
+fn auth_user(ctx: &Context<'_>) -> async_graphql::Result<AuthUser> {
+    caller(ctx)
+        .map(|caller| AuthUser(caller.user_id))
+        .ok_or_else(|| gql_error(AppError::Unauthorized("Missing bearer token".into())))
+}
+
+#[derive(Debug, Default, InputObject)]
+pub struct ModelFilter {
+    pub model_type: Option<ModelType>,
+    pub min_accuracy: Option<f64>,
+    pub required_tier: Option<SubscriptionTier>,
+    pub min_price: Option<f64>,
+    pub max_price: Option<f64>,
+    pub free_only: Option<bool>,
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Published models plus the caller's own, newest first.
+    async fn models(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<ModelFilter>,
+        page: Option<i64>,
+        per_page: Option<i64>,
+    ) -> async_graphql::Result<ModelPage> {
+        let state = ctx.data::<AppState>()?;
+        let viewer = caller(ctx).map(|caller| caller.user_id);
+        validate_paging(page, per_page).map_err(gql_error)?;
+
+        let max_page_size = match viewer {
+            Some(user_id) => UserSubscription::max_page_size_for_user(&state.pool, user_id)
+                .await
+                .map_err(gql_error)?,
+            None => None,
+        };
+        let filter = filter.unwrap_or_default();
+        let params = ListQueryParams {
+            model_type: filter.model_type,
+            min_accuracy: filter.min_accuracy,
+            required_tier: filter.required_tier,
+            min_price: filter.min_price,
+            max_price: filter.max_price,
+            free_only: filter.free_only,
+            page,
+            per_page,
+            ..Default::default()
+        }
+        .clamp_per_page(max_page_size.unwrap_or(FALLBACK_MAX_PAGE_SIZE));
+        params.validate().map_err(gql_error)?;
+
+        let (models, total) = state.repo.list(&params, viewer).await.map_err(gql_error)?;
+        Ok(ModelPage {
+            models: models.into_iter().map(Model).collect(),
+            total,
+            page: params.page.unwrap_or(1),
+            per_page: params.per_page.unwrap_or_else(default_page_size),
+        })
+    }
+
+    /// A model, if it exists and the caller may see it.
+    async fn model(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<Option<Model>> {
+        let state = ctx.data::<AppState>()?;
+        let caller = caller(ctx);
+        let model = state.repo.get(id).await.map_err(gql_error)?.filter(|model| {
+            model.is_visible_to(
+                caller.map(|caller| caller.user_id),
+                caller.is_some_and(|caller| caller.is_admin()),
+            )
+        });
+        Ok(model.map(Model))
+    }
+
+    /// Every subscription plan.
+    async fn subscriptions(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Plan>> {
+        let state = ctx.data::<AppState>()?;
+        let (_, plans) = state
+            .plan_cache
+            .get_or_load(&state.pool)
+            .await
+            .map_err(gql_error)?;
+        Ok(plans.into_iter().map(Plan).collect())
+    }
+
+    /// The caller's subscriptions, newest first. Requires authentication.
+    async fn my_subscriptions(
+        &self,
+        ctx: &Context<'_>,
+    ) -> async_graphql::Result<Vec<MySubscription>> {
+        let state = ctx.data::<AppState>()?;
+        let AuthUser(user_id) = auth_user(ctx)?;
+        let subscriptions = UserSubscription::list_for_user(&state.pool, user_id)
+            .await
+            .map_err(gql_error)?;
+        Ok(subscriptions.into_iter().map(MySubscription).collect())
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct ModelPage {
+    pub models: Vec<Model>,
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+pub struct Model(AIModel);
+
+#[Object]
+impl Model {
+    async fn id(&self) -> Uuid {
+        self.0.id
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn description(&self) -> &str {
+        &self.0.description
+    }
+
+    async fn model_type(&self) -> ModelType {
+        self.0.model_type
+    }
+
+    async fn framework(&self) -> &str {
+        &self.0.framework
+    }
+
+    async fn version(&self) -> &str {
+        &self.0.version
+    }
+
+    async fn status(&self) -> ModelStatus {
+        self.0.status
+    }
+
+    async fn tags(&self) -> &[String] {
+        &self.0.tags
+    }
+
+    async fn download_count(&self) -> i32 {
+        self.0.download_count
+    }
+
+    async fn price(&self) -> Option<f64> {
+        self.0.price
+    }
+
+    async fn required_tier(&self) -> SubscriptionTier {
+        self.0.required_tier
+    }
+
+    async fn license(&self) -> Option<&str> {
+        self.0.license.as_deref()
+    }
+
+    async fn owner_id(&self) -> Option<Uuid> {
+        self.0.owner_id
+    }
+
+    async fn is_stale(&self) -> bool {
+        self.0.is_stale(stale_after_months())
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+
+    async fn updated_at(&self) -> DateTime<Utc> {
+        self.0.updated_at
+    }
+
+    /// `null` until the model has been reviewed.
+    async fn average_rating(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<f64>> {
+        let state = ctx.data::<AppState>()?;
+        state
+            .reviews
+            .average_rating(self.0.id)
+            .await
+            .map_err(gql_error)
+    }
+
+    /// Newest first.
+    async fn reviews(
+        &self,
+        ctx: &Context<'_>,
+        page: Option<i64>,
+        per_page: Option<i64>,
+    ) -> async_graphql::Result<Vec<Review>> {
+        let state = ctx.data::<AppState>()?;
+        validate_paging(page, per_page).map_err(gql_error)?;
+        let per_page = per_page.unwrap_or(DEFAULT_REVIEWS).min(FALLBACK_MAX_PAGE_SIZE);
+        let reviews = state
+            .reviews
+            .list_reviews(self.0.id, page.unwrap_or(1), per_page)
+            .await
+            .map_err(gql_error)?;
+        Ok(reviews.into_iter().map(Review).collect())
+    }
+}
+
+pub struct Review(ModelReview);
+
+#[Object]
+impl Review {
+    async fn id(&self) -> Uuid {
+        self.0.id
+    }
+
+    async fn user_id(&self) -> Uuid {
+        self.0.user_id
+    }
+
+    async fn rating(&self) -> i32 {
+        self.0.rating
+    }
+
+    async fn comment(&self) -> Option<&str> {
+        self.0.comment.as_deref()
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+}
+
+pub struct Plan(Subscription);
+
+#[Object]
+impl Plan {
+    async fn id(&self) -> Uuid {
+        self.0.id
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn tier(&self) -> SubscriptionTier {
+        self.0.tier
+    }
+
+    async fn price_monthly(&self) -> f64 {
+        self.0.price(BillingInterval::Monthly).to_major()
+    }
+
+    async fn price_yearly(&self) -> f64 {
+        self.0.price(BillingInterval::Yearly).to_major()
+    }
+
+    async fn currency(&self) -> &str {
+        &self.0.currency
+    }
+
+    async fn trial_days(&self) -> Option<i32> {
+        self.0.trial_days
+    }
+}
+
+pub struct MySubscription(UserSubscription);
+
+#[Object]
+impl MySubscription {
+    async fn id(&self) -> Uuid {
+        self.0.id
+    }
+
+    async fn plan_id(&self) -> Uuid {
+        self.0.subscription_id
+    }
+
+    async fn is_active(&self) -> bool {
+        self.0.is_active
+    }
+
+    async fn payment_status(&self) -> Option<&str> {
+        self.0.payment_status.as_deref()
+    }
+
+    async fn billing_interval(&self) -> BillingInterval {
+        self.0.billing_interval
+    }
+
+    async fn cancel_at_period_end(&self) -> bool {
+        self.0.cancel_at_period_end
+    }
+
+    async fn starts_at(&self) -> DateTime<Utc> {
+        self.0.starts_at
+    }
+
+    async fn ends_at(&self) -> Option<DateTime<Utc>> {
+        self.0.ends_at
+    }
+
+    async fn current_period_end(&self) -> Option<DateTime<Utc>> {
+        self.0.current_period_end
+    }
+
+    /// The plan this subscription is on.
+    async fn plan(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<Plan>> {
+        let state = ctx.data::<AppState>()?;
+        let plan = Subscription::get_by_id(&state.pool, self.0.subscription_id)
+            .await
+            .map_err(gql_error)?;
+        Ok(plan.map(Plan))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::CreateReview;
+    use crate::test_support::{app_state, create_user, new_model, published};
+    use axum::{body::Body, http::Request};
+    use serde_json::{json, Value};
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    #[sqlx::test]
+    async fn a_model_comes_back_with_its_reviews_and_average_rating(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = published(&pool, owner, new_model("Reviewed")).await;
+        let state = app_state(&pool).await;
+        for rating in [4, 5] {
+            let review = CreateReview {
+                rating,
+                comment: Some(format!("{} stars", rating)),
+            };
+            let reviewer = create_user(&pool).await;
+            state.reviews.create_review(model.id, reviewer, review).await.unwrap();
+        }
+        let query = json!({
+            "query": "query($id: UUID!) {
+                model(id: $id) { name averageRating reviews { rating } }
+            }",
+            "variables": { "id": model.id },
+        });
+        let request = Request::post("/graphql")
+            .header("content-type", "application/json")
+            .body(Body::from(query.to_string()))
+            .unwrap();
+
+        let response = graphql_routes().with_state(state).oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["errors"], Value::Null, "{}", body);
+        assert_eq!(
+            body["data"]["model"],
+            json!({
+                "name": "Reviewed",
+                "averageRating": 4.5,
+                "reviews": [{ "rating": 5 }, { "rating": 4 }],
+            })
+        );
+    }
+}
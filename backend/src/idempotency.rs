@@ -0,0 +1,223 @@
+use axum::{
+    body::{to_bytes, Body},
+    extract::{FromRequestParts, Request, State},
+    http::{header, HeaderName, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    auth::Caller,
+    error::AppError,
+    models::{IdempotencyClaim, IdempotencyKey},
+    AppState,
+};
+
+pub static IDEMPOTENCY_KEY_HEADER: HeaderName = HeaderName::from_static("idempotency-key");
+/// Set on responses replayed from an earlier request.
+pub static IDEMPOTENT_REPLAYED_HEADER: HeaderName =
+    HeaderName::from_static("idempotent-replayed");
+
+const MAX_KEY_LENGTH: usize = 255;
+const MAX_BODY_BYTES: usize = 10 * 1024 * 1024;
+
+/// Makes authenticated `POST`s carrying an `Idempotency-Key` safe to retry:
+/// the first response for a (user, key, path) is stored and replayed for
+/// repeats until the key expires, without running the handler again.
+/// Reusing a key with a different body is a `409`. Server errors aren't
+/// stored, so those requests can be retried with the same key.
+pub async fn idempotent_posts(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method() != Method::POST {
+        return next.run(request).await;
+    }
+    let Some(key) = request
+        .headers()
+        .get(&IDEMPOTENCY_KEY_HEADER)
+        .map(|value| value.to_str().map(str::to_owned))
+    else {
+        return next.run(request).await;
+    };
+
+    let result = match key {
+        Ok(key) => replay_or_run(&state, key, request, next).await,
+        Err(_) => Err(AppError::BadRequest("Invalid Idempotency-Key".into())),
+    };
+    result.unwrap_or_else(IntoResponse::into_response)
+}
+
+async fn replay_or_run(
+    state: &AppState,
+    key: String,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+        return Err(AppError::BadRequest(format!(
+            "Idempotency-Key must be 1 to {} characters",
+            MAX_KEY_LENGTH
+        )));
+    }
+
+    let (mut parts, body) = request.into_parts();
+    // Anonymous requests aren't tracked; the handler deals with them as usual.
+    let Ok(caller) = Caller::from_request_parts(&mut parts, state).await else {
+        return Ok(next.run(Request::from_parts(parts, body)).await);
+    };
+    let route = parts.uri.path().to_owned();
+    let bytes = to_bytes(body, MAX_BODY_BYTES)
+        .await
+        .map_err(|_| AppError::PayloadTooLarge("Request body is too large".into()))?;
+    let request_hash = hex::encode(Sha256::digest(&bytes));
+
+    let claim =
+        IdempotencyKey::claim(&state.pool, caller.user_id, &key, &route, &request_hash).await?;
+    if let IdempotencyClaim::Existing(existing) = claim {
+        if existing.request_hash != request_hash {
+            return Err(AppError::conflict(
+                "This Idempotency-Key was already used for a different request",
+            ));
+        }
+        let Some(status) = existing.response_status else {
+            return Err(AppError::conflict(
+                "A request with this Idempotency-Key is still being processed",
+            ));
+        };
+        return Ok(replay(
+            status,
+            existing.response_body.unwrap_or_default(),
+            existing.response_content_type,
+        ));
+    }
+
+    let response = next
+        .run(Request::from_parts(parts, Body::from(bytes)))
+        .await;
+    if response.status().is_server_error() {
+        IdempotencyKey::release(&state.pool, caller.user_id, &key, &route).await?;
+        return Ok(response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => {
+            IdempotencyKey::release(&state.pool, caller.user_id, &key, &route).await?;
+            return Err(anyhow::anyhow!("failed to buffer response: {}", e).into());
+        }
+    };
+    let content_type = parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+    IdempotencyKey::complete(
+        &state.pool,
+        caller.user_id,
+        &key,
+        &route,
+        parts.status.as_u16(),
+        &body,
+        content_type,
+    )
+    .await?;
+
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+fn replay(status: i16, body: Vec<u8>, content_type: Option<String>) -> Response {
+    let status = u16::try_from(status)
+        .ok()
+        .and_then(|status| StatusCode::from_u16(status).ok())
+        .unwrap_or(StatusCode::OK);
+    let mut response = (status, body).into_response();
+    let headers = response.headers_mut();
+    if let Some(content_type) = content_type.and_then(|v| HeaderValue::from_str(&v).ok()) {
+        headers.insert(header::CONTENT_TYPE, content_type);
+    }
+    headers.insert(IDEMPOTENT_REPLAYED_HEADER.clone(), HeaderValue::from_static("true"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{app_state, bearer, create_user, user};
+    use axum::{middleware, routing::post, Json, Router};
+    use serde_json::{json, Value};
+    use sqlx::PgPool;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    /// Echoes the body with how many times the handler has run.
+    async fn app(pool: &PgPool, runs: Arc<AtomicUsize>) -> Router {
+        let state = app_state(pool).await;
+        Router::new()
+            .route(
+                "/api/things",
+                post(move |Json(body): Json<Value>| async move {
+                    let run = runs.fetch_add(1, Ordering::SeqCst) + 1;
+                    (StatusCode::CREATED, Json(json!({ "run": run, "body": body })))
+                }),
+            )
+            .layer(middleware::from_fn_with_state(state.clone(), idempotent_posts))
+            .with_state(state)
+    }
+
+    async fn post_with_key(app: &Router, token: &str, key: &str, body: Value) -> Response {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/api/things")
+            .header(header::AUTHORIZATION, token)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(&IDEMPOTENCY_KEY_HEADER, key)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        app.clone().oneshot(request).await.unwrap()
+    }
+
+    async fn json_body(response: Response) -> Value {
+        serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap()
+    }
+
+    #[sqlx::test]
+    async fn a_repeated_key_replays_the_first_response(pool: PgPool) {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let app = app(&pool, runs.clone()).await;
+        let token = bearer(user(create_user(&pool).await));
+
+        let first = post_with_key(&app, &token, "key-1", json!({ "name": "a" })).await;
+        assert_eq!(first.status(), StatusCode::CREATED);
+        assert!(first.headers().get(&IDEMPOTENT_REPLAYED_HEADER).is_none());
+        let first = json_body(first).await;
+
+        let repeat = post_with_key(&app, &token, "key-1", json!({ "name": "a" })).await;
+        assert_eq!(repeat.status(), StatusCode::CREATED);
+        assert_eq!(repeat.headers()[&IDEMPOTENT_REPLAYED_HEADER], "true");
+        assert_eq!(repeat.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(json_body(repeat).await, first);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        // Keys are per user, so someone else's identical key runs afresh.
+        let other = bearer(user(create_user(&pool).await));
+        post_with_key(&app, &other, "key-1", json!({ "name": "a" })).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[sqlx::test]
+    async fn reusing_a_key_for_a_different_body_conflicts(pool: PgPool) {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let app = app(&pool, runs.clone()).await;
+        let token = bearer(user(create_user(&pool).await));
+
+        post_with_key(&app, &token, "key-1", json!({ "name": "a" })).await;
+        let conflict = post_with_key(&app, &token, "key-1", json!({ "name": "b" })).await;
+
+        assert_eq!(conflict.status(), StatusCode::CONFLICT);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,305 @@
+use chrono::Utc;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use crate::db::{AIModelRepository, DownloadBuffer};
+use crate::services::stripe::StripeService;
+use crate::models::{Notification, UserSubscription};
+
+/// Tells background jobs to stop. Jobs finish the pass they're in, then
+/// exit, so awaiting their handles after signalling the sender drains them.
+#[derive(Debug, Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    pub fn channel() -> (watch::Sender<bool>, Self) {
+        let (sender, receiver) = watch::channel(false);
+        (sender, Self(receiver))
+    }
+
+    /// Resolves once shutdown has been requested (or the sender is gone).
+    pub async fn wait(&mut self) {
+        let _ = self.0.wait_for(|stopping| *stopping).await;
+    }
+}
+
+/// How long soft-deleted models are kept and how often the purge runs.
+#[derive(Debug, Clone)]
+pub struct PurgeSettings {
+    pub retention: chrono::Duration,
+    pub interval: Duration,
+}
+
+impl PurgeSettings {
+    pub fn from_env() -> Self {
+        let retention_days = env::var("SOFT_DELETE_RETENTION_DAYS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30);
+        let interval_secs = env::var("PURGE_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(3600);
+
+        Self {
+            retention: chrono::Duration::days(retention_days),
+            interval: Duration::from_secs(interval_secs),
+        }
+    }
+}
+
+/// Run a single purge pass, hard-deleting models soft-deleted longer ago
+/// than the retention window.
+pub async fn purge_soft_deleted(
+    repo: &AIModelRepository,
+    settings: &PurgeSettings,
+) -> Result<u64, sqlx::Error> {
+    let cutoff = Utc::now() - settings.retention;
+    let purged = repo.purge_deleted_before(cutoff).await?;
+    tracing::info!("purged {} soft-deleted models older than {}", purged, cutoff);
+    Ok(purged)
+}
+
+pub fn spawn_purge_job(
+    repo: AIModelRepository,
+    settings: PurgeSettings,
+    mut shutdown: Shutdown,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(settings.interval);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown.wait() => break,
+            }
+
+            if let Err(e) = purge_soft_deleted(&repo, &settings).await {
+                tracing::error!("failed to purge soft-deleted models: {}", e);
+            }
+        }
+    })
+}
+
+/// Run one subscription sweep: resume paused subscriptions that are due,
+/// warn about and end free trials, end subscriptions cancelled at period
+/// end, and deactivate ones whose paid period ran out.
+pub async fn sweep_subscriptions(pool: &sqlx::PgPool) {
+    // Resume first so a pause ending now doesn't hold up expiry.
+    match UserSubscription::resume_due(pool).await {
+        Ok(0) => {}
+        Ok(resumed) => tracing::info!("resumed {} paused subscriptions", resumed),
+        Err(e) => tracing::error!("failed to resume paused subscriptions: {}", e),
+    }
+
+    match UserSubscription::flag_trials_ending(pool, chrono::Duration::days(3)).await {
+        Ok(ending) => {
+            for (user_id, trial_ends_at) in ending {
+                let message = format!(
+                    "Your free trial ends on {}",
+                    trial_ends_at.format("%B %-d, %Y")
+                );
+                let data = serde_json::json!({ "trial_ends_at": trial_ends_at });
+                Notification::send_best_effort(
+                    pool,
+                    user_id,
+                    "trial_will_end",
+                    &message,
+                    data,
+                )
+                .await;
+            }
+        }
+        Err(e) => tracing::error!("failed to flag ending trials: {}", e),
+    }
+
+    match UserSubscription::end_trials(pool).await {
+        Ok(ended) => {
+            if !ended.is_empty() {
+                tracing::info!("ended {} trials", ended.len());
+            }
+            for (user_id, plan) in ended {
+                let message = format!(
+                    "Your free trial of {} has ended; pay to keep your plan",
+                    plan.name
+                );
+                Notification::send_best_effort(
+                    pool,
+                    user_id,
+                    "trial_ended",
+                    &message,
+                    plan.notification_details(),
+                )
+                .await;
+            }
+        }
+        Err(e) => tracing::error!("failed to end trials: {}", e),
+    }
+
+    match UserSubscription::expire_lapsed(pool).await {
+        Ok(0) => {}
+        Ok(expired) => tracing::info!("ended {} lapsed subscriptions", expired),
+        Err(e) => tracing::error!("failed to end lapsed subscriptions: {}", e),
+    }
+
+    match UserSubscription::deactivate_expired(pool).await {
+        Ok(0) => {}
+        Ok(expired) => tracing::info!("deactivated {} unrenewed subscriptions", expired),
+        Err(e) => tracing::error!("failed to deactivate unrenewed subscriptions: {}", e),
+    }
+}
+
+/// Periodically run [`sweep_subscriptions`], every `SWEEP_INTERVAL_SECS`
+/// (default 300), until shutdown.
+pub fn spawn_subscription_sweep(pool: sqlx::PgPool, mut shutdown: Shutdown) -> JoinHandle<()> {
+    let interval_secs = env::var("SWEEP_INTERVAL_SECS")
+        .or_else(|_| env::var("SUBSCRIPTION_EXPIRY_INTERVAL_SECS"))
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(300);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown.wait() => break,
+            }
+
+            sweep_subscriptions(&pool).await;
+        }
+    })
+}
+
+/// Periodically expire payment intents left pending past their expiry.
+pub fn spawn_payment_intent_expiry_job(
+    stripe: Arc<StripeService>,
+    mut shutdown: Shutdown,
+) -> JoinHandle<()> {
+    let interval_secs = env::var("PAYMENT_INTENT_EXPIRY_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(900);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown.wait() => break,
+            }
+
+            match stripe.expire_lapsed_intents().await {
+                Ok(0) => {}
+                Ok(expired) => tracing::info!("expired {} lapsed payment intents", expired),
+                Err(e) => tracing::error!("failed to expire lapsed payment intents: {}", e),
+            }
+        }
+    })
+}
+
+/// Periodically retry the Stripe cleanup of closed accounts, every
+/// `STRIPE_CLEANUP_INTERVAL_SECS` (default 3600).
+pub fn spawn_stripe_cleanup_job(stripe: Arc<StripeService>, mut shutdown: Shutdown) -> JoinHandle<()> {
+    let interval_secs = env::var("STRIPE_CLEANUP_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(3600);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown.wait() => break,
+            }
+
+            match stripe.finish_pending_account_cleanups().await {
+                Ok(0) => {}
+                Ok(accounts) => tracing::info!("retried Stripe cleanup for {} closed accounts", accounts),
+                Err(e) => tracing::error!("failed to retry Stripe cleanup of closed accounts: {}", e),
+            }
+        }
+    })
+}
+
+/// Flush buffered download counts every `interval`, and once more on
+/// shutdown so none are lost.
+pub fn spawn_download_count_flush(
+    buffer: DownloadBuffer,
+    pool: sqlx::PgPool,
+    interval: Duration,
+    mut shutdown: Shutdown,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            let stopping = tokio::select! {
+                _ = interval.tick() => false,
+                _ = shutdown.wait() => true,
+            };
+
+            if let Err(e) = buffer.flush(&pool).await {
+                tracing::error!("failed to flush download counts: {}", e);
+            }
+            if stopping {
+                break;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::SubscriptionTier;
+    use crate::test_support::{create_user, subscribe};
+    use sqlx::PgPool;
+
+    async fn is_active(pool: &PgPool, subscription: &UserSubscription) -> bool {
+        sqlx::query_scalar!(
+            "SELECT is_active FROM user_subscriptions WHERE id = $1",
+            subscription.id
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn a_sweep_deactivates_only_expired_subscriptions(pool: PgPool) {
+        let expired = subscribe(&pool, create_user(&pool).await, SubscriptionTier::Pro).await;
+        let current = subscribe(&pool, create_user(&pool).await, SubscriptionTier::Pro).await;
+        sqlx::query!(
+            "UPDATE user_subscriptions SET ends_at = NOW() - INTERVAL '1 day' WHERE id = $1",
+            expired.id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        sweep_subscriptions(&pool).await;
+
+        assert!(!is_active(&pool, &expired).await);
+        assert!(is_active(&pool, &current).await);
+    }
+
+    #[sqlx::test]
+    async fn the_sweep_job_stops_on_shutdown(pool: PgPool) {
+        let (stop, shutdown) = Shutdown::channel();
+        let job = spawn_subscription_sweep(pool, shutdown);
+
+        stop.send(true).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), job)
+            .await
+            .expect("sweep job kept running after shutdown")
+            .unwrap();
+    }
+}
@@ -0,0 +1,142 @@
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    http::{header, HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde_json::Value as JsonValue;
+
+/// Request header a client sets to `camelCase` to get camelCase JSON keys.
+/// Anything else, or no header, keeps the default snake_case.
+pub static JSON_CASE_HEADER: HeaderName = HeaderName::from_static("x-json-case");
+
+/// Free-form JSON supplied by users or admins. Their keys are data, not
+/// field names, so they're passed through untouched.
+const OPAQUE_KEYS: &[&str] = &["metadata", "features", "extra"];
+
+fn wants_camel_case(request: &Request) -> bool {
+    request
+        .headers()
+        .get(&JSON_CASE_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().eq_ignore_ascii_case("camelcase") || v.trim().eq_ignore_ascii_case("camel"))
+        .unwrap_or(false)
+}
+
+fn to_camel_case(key: &str) -> String {
+    let mut camel = String::with_capacity(key.len());
+    let mut upper_next = false;
+    for c in key.chars() {
+        if c == '_' && !camel.is_empty() {
+            upper_next = true;
+        } else if upper_next {
+            camel.extend(c.to_uppercase());
+            upper_next = false;
+        } else {
+            camel.push(c);
+        }
+    }
+    camel
+}
+
+/// Rename object keys to camelCase at any depth, except inside opaque fields.
+fn camelize(value: JsonValue) -> JsonValue {
+    match value {
+        JsonValue::Object(map) => JsonValue::Object(
+            map.into_iter()
+                .map(|(key, value)| {
+                    let value = if OPAQUE_KEYS.contains(&key.as_str()) {
+                        value
+                    } else {
+                        camelize(value)
+                    };
+                    (to_camel_case(&key), value)
+                })
+                .collect(),
+        ),
+        JsonValue::Array(items) => JsonValue::Array(items.into_iter().map(camelize).collect()),
+        other => other,
+    }
+}
+
+/// Rewrite JSON response bodies to camelCase when the client asks for it.
+pub async fn negotiate_case(request: Request, next: Next) -> Response {
+    let camel = wants_camel_case(&request);
+    let mut response = next.run(request).await;
+
+    // Responses differ by the header, so caches must key on it.
+    response
+        .headers_mut()
+        .append(header::VARY, HeaderValue::from_static("x-json-case"));
+
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.starts_with("application/json"))
+        .unwrap_or(false);
+    if !camel || !is_json {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::error!("failed to buffer response for case conversion: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let Ok(value) = serde_json::from_slice::<JsonValue>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+
+    let body = camelize(value).to_string();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::CreateAIModel;
+    use crate::test_support::{create_user, new_model, published};
+    use axum::{middleware, routing::get, Json, Router};
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    async fn fetch(app: Router, case: Option<&str>) -> JsonValue {
+        let mut request = Request::builder().uri("/model");
+        if let Some(case) = case {
+            request = request.header(&JSON_CASE_HEADER, case);
+        }
+        let response = app.oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[sqlx::test]
+    async fn a_model_is_camel_cased_only_on_request(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = CreateAIModel {
+            metadata: Some(serde_json::json!({ "input_shape": [224, 224] })),
+            ..new_model("Cased")
+        };
+        let model = serde_json::to_value(published(&pool, owner, model).await).unwrap();
+        let app = Router::new()
+            .route("/model", get(move || async move { Json(model) }))
+            .layer(middleware::from_fn(negotiate_case));
+
+        let camel = fetch(app.clone(), Some("camelCase")).await;
+        assert!(camel.get("createdAt").is_some());
+        assert_eq!(camel["downloadCount"], 0);
+        assert!(camel.get("download_count").is_none());
+        assert_eq!(camel["metadata"], serde_json::json!({ "input_shape": [224, 224] }));
+
+        let snake = fetch(app, None).await;
+        assert!(snake.get("created_at").is_some());
+        assert_eq!(snake["download_count"], 0);
+        assert!(snake.get("downloadCount").is_none());
+    }
+}
@@ -0,0 +1,218 @@
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{Request, State},
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use serde_json::Value as JsonValue;
+use std::env;
+use std::time::Instant;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Keys whose values are never logged, matched case-insensitively.
+const SENSITIVE_KEYS: &[&str] = &[
+    "client_secret",
+    "password",
+    "token",
+    "access_token",
+    "refresh_token",
+    "authorization",
+    "api_key",
+    "key",
+    "secret",
+    "card",
+    "card_number",
+    "number",
+    "cvc",
+    "cvv",
+    "last4",
+    "exp_month",
+    "exp_year",
+];
+
+/// Request/response logging settings.
+///
+/// Method, path, status and latency are always logged. Bodies are only logged
+/// when `log_bodies` is set, and are cut off after `max_body_bytes`.
+#[derive(Debug, Clone)]
+pub struct LoggingSettings {
+    pub log_bodies: bool,
+    pub max_body_bytes: usize,
+}
+
+impl LoggingSettings {
+    pub fn from_env() -> Self {
+        Self {
+            log_bodies: env::var("LOG_HTTP_BODIES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(false),
+            max_body_bytes: env::var("LOG_HTTP_BODY_MAX_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(4096),
+        }
+    }
+}
+
+fn is_sensitive(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    SENSITIVE_KEYS.contains(&key.as_str())
+        || key.starts_with("card_")
+        || key.ends_with("_secret")
+        || key.ends_with("_token")
+}
+
+/// Replace the values of sensitive fields, at any depth, with a placeholder.
+fn redact(value: &mut JsonValue) {
+    match value {
+        JsonValue::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if is_sensitive(key) {
+                    *value = JsonValue::String(REDACTED.into());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        JsonValue::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+/// Render a body for the log. Only JSON is logged verbatim (after redaction);
+/// anything else is summarised by size so raw payloads never reach the logs.
+fn render_body(headers: &HeaderMap, bytes: &Bytes, max_bytes: usize) -> String {
+    if bytes.is_empty() {
+        return String::new();
+    }
+
+    let is_json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.starts_with("application/json"))
+        .unwrap_or(false);
+
+    let mut value = match serde_json::from_slice::<JsonValue>(bytes) {
+        Ok(value) if is_json => value,
+        _ => return format!("<{} bytes>", bytes.len()),
+    };
+    redact(&mut value);
+
+    let mut rendered = value.to_string();
+    if rendered.len() > max_bytes {
+        let mut end = max_bytes;
+        while !rendered.is_char_boundary(end) {
+            end -= 1;
+        }
+        rendered.truncate(end);
+        rendered.push_str("...");
+    }
+    rendered
+}
+
+async fn buffer(body: Body) -> Result<Bytes, Response> {
+    to_bytes(body, usize::MAX).await.map_err(|e| {
+        // Over-limit bodies are turned into a JSON 413 by `limit_body`.
+        let status = if crate::body_limit::is_length_limit_error(&e) {
+            axum::http::StatusCode::PAYLOAD_TOO_LARGE
+        } else {
+            tracing::warn!("failed to buffer body for logging: {}", e);
+            axum::http::StatusCode::BAD_REQUEST
+        };
+        Response::builder().status(status).body(Body::empty()).unwrap()
+    })
+}
+
+pub async fn log_requests(
+    State(settings): State<LoggingSettings>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+    let start = Instant::now();
+
+    if !settings.log_bodies {
+        let response = next.run(request).await;
+        tracing::info!(
+            %method,
+            %path,
+            status = response.status().as_u16(),
+            latency_ms = start.elapsed().as_millis() as u64,
+            "request"
+        );
+        return response;
+    }
+
+    let (parts, body) = request.into_parts();
+    let request_bytes = match buffer(body).await {
+        Ok(bytes) => bytes,
+        Err(response) => return response,
+    };
+    let request_body = render_body(&parts.headers, &request_bytes, settings.max_body_bytes);
+
+    let response = next
+        .run(Request::from_parts(parts, Body::from(request_bytes)))
+        .await;
+
+    let (parts, body) = response.into_parts();
+    let response_bytes = match buffer(body).await {
+        Ok(bytes) => bytes,
+        Err(response) => return response,
+    };
+    let response_body = render_body(&parts.headers, &response_bytes, settings.max_body_bytes);
+
+    tracing::info!(
+        %method,
+        %path,
+        status = parts.status.as_u16(),
+        latency_ms = start.elapsed().as_millis() as u64,
+        request_body,
+        response_body,
+        "request"
+    );
+
+    Response::from_parts(parts, Body::from(response_bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn json_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers
+    }
+
+    #[test]
+    fn a_client_secret_is_redacted_from_a_logged_response() {
+        let body = Bytes::from(
+            r#"{"id":"pi_1","client_secret":"pi_1_secret_abc","payment_method":{"card":{"last4":"4242"}}}"#,
+        );
+
+        let logged = render_body(&json_headers(), &body, 4096);
+
+        assert!(!logged.contains("pi_1_secret_abc"), "{}", logged);
+        assert!(!logged.contains("4242"), "{}", logged);
+        let logged: JsonValue = serde_json::from_str(&logged).unwrap();
+        assert_eq!(logged["client_secret"], REDACTED);
+        assert_eq!(logged["payment_method"]["card"], REDACTED);
+        assert_eq!(logged["id"], "pi_1");
+    }
+
+    #[test]
+    fn bodies_are_capped_and_non_json_is_summarised() {
+        let body = Bytes::from(format!(r#"{{"description":"{}"}}"#, "x".repeat(100)));
+        let logged = render_body(&json_headers(), &body, 20);
+        assert_eq!(logged.len(), 20 + "...".len());
+        assert!(logged.ends_with("..."));
+
+        let text = Bytes::from("client_secret=abc");
+        assert_eq!(render_body(&HeaderMap::new(), &text, 4096), "<17 bytes>");
+    }
+}
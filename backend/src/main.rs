@@ -1,17 +1,115 @@
+mod auth;
+mod backfill;
+mod body_limit;
+mod concurrency;
 mod config;
 mod db;
+mod email;
+mod entitlements;
+mod error;
+mod events;
+mod features;
+mod graphql;
+mod idempotency;
+mod jobs;
+mod json_case;
+mod logging;
+mod metrics;
 mod models;
+mod rate_limit;
+mod request_id;
 mod routes;
+mod seed;
+mod server;
+mod services;
+mod storage;
+mod tax;
+#[cfg(test)]
+mod test_support;
+mod tier_cache;
+mod validated_query;
 
 use axum::{
+    extract::{DefaultBodyLimit, FromRef},
+    middleware,
     Router,
-    routing::{get, post, put, delete},
+    routing::{get, head, post, put, delete},
 };
+use sqlx::PgPool;
 use std::net::SocketAddr;
 use std::env;
 use std::error::Error;
+use std::sync::Arc;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: PgPool,
+    pub repo: db::AIModelRepository,
+    pub reviews: db::ReviewRepository,
+    pub jwt_secret: Arc<str>,
+    pub stripe_service: Arc<services::stripe::StripeService>,
+    pub plan_cache: routes::subscription::PlanCache,
+    pub tier_cache: tier_cache::TierCache,
+    pub storage: Option<storage::StorageSettings>,
+    pub feature_flags: features::FeatureFlags,
+    pub events: events::EventBus,
+    pub payment_events: events::PaymentStatusBus,
+    pub review_limiter: rate_limit::RateLimiter,
+    pub route_limiter: rate_limit::RouteLimiter,
+    pub concurrency_limits: concurrency::ConcurrencyLimits,
+    pub tax: Arc<dyn tax::TaxProvider>,
+    pub mailer: email::Mailer,
+    pub receipt_signer: Option<entitlements::ReceiptSigner>,
+    pub graphql: graphql::ApiSchema,
+}
+
+impl FromRef<AppState> for PgPool {
+    fn from_ref(state: &AppState) -> Self {
+        state.pool.clone()
+    }
+}
+
+impl FromRef<AppState> for db::AIModelRepository {
+    fn from_ref(state: &AppState) -> Self {
+        state.repo.clone()
+    }
+}
+
+impl FromRef<AppState> for db::ReviewRepository {
+    fn from_ref(state: &AppState) -> Self {
+        state.reviews.clone()
+    }
+}
+
+impl FromRef<AppState> for events::EventBus {
+    fn from_ref(state: &AppState) -> Self {
+        state.events.clone()
+    }
+}
+
+impl FromRef<AppState> for rate_limit::RateLimiter {
+    fn from_ref(state: &AppState) -> Self {
+        state.review_limiter.clone()
+    }
+}
+
+/// Environment settings logged at startup to help debug configuration.
+const LOGGED_SETTINGS: &[&str] = &[
+    "HOST",
+    "PORT",
+    "RUST_LOG",
+    "PUBLIC_BASE_URL",
+    "CORS_ALLOWED_ORIGINS",
+    "FEATURE_FLAGS",
+    "METRICS_PORT",
+    "DB_MAX_CONNECTIONS",
+    "DB_MIN_CONNECTIONS",
+    "DB_STATEMENT_TIMEOUT_MS",
+    "LICENSE_CHECK_MODE",
+    "STORAGE_REGION",
+];
+
 #[tokio::main]
 async fn main() {
     // Load environment variables from .env file
@@ -20,21 +118,32 @@ async fn main() {
     // Set up tracing
     tracing_subscriber::registry()
         .with(tracing_subscriber::fmt::layer())
+        .with(db::SlowQueryLayer::from_env())
         .init();
 
-    // Print environment variables for debugging
-    println!("Environment variables:");
-    for (key, value) in env::vars() {
-        println!("  {} = {}", key, value);
+    // Only settings known not to hold credentials are logged; URLs, keys
+    // and secrets never are.
+    for key in LOGGED_SETTINGS {
+        if let Ok(value) = env::var(key) {
+            tracing::info!("{} = {}", key, value);
+        }
     }
 
     // Get command from args
     let args: Vec<String> = env::args().collect();
     let command = args.get(1).map(|s| s.as_str());
 
+    let config = match config::Config::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
     println!("Creating database pool...");
     // Create database connection pool
-    let pool = match config::create_pool().await {
+    let pool = match config::create_pool(&config).await {
         Ok(pool) => {
             println!("Database pool created successfully");
             pool
@@ -62,6 +171,40 @@ async fn main() {
             }
             return;
         }
+        Some("seed") => {
+            println!("Seeding baseline data...");
+            match seed::run(&pool).await {
+                Ok(summary) => {
+                    println!(
+                        "Seeded {} subscription plans and {} models",
+                        summary.plans, summary.models
+                    );
+                }
+                Err(e) => {
+                    eprintln!("Failed to seed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            pool.close().await;
+            return;
+        }
+        Some("backfill-download-stats") => {
+            println!("Backfilling download history...");
+            match backfill::download_stats(&pool).await {
+                Ok(summary) => {
+                    println!(
+                        "Backfilled {} downloads across {} models",
+                        summary.downloads, summary.models
+                    );
+                }
+                Err(e) => {
+                    eprintln!("Failed to backfill download history: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            pool.close().await;
+            return;
+        }
         Some(cmd) => {
             eprintln!("Unknown command: {}", cmd);
             std::process::exit(1);
@@ -79,30 +222,195 @@ async fn main() {
             println!("Migrations completed successfully!");
 
             // Create AI model repository
-            let repo = db::AIModelRepository::new(pool);
+            let mut repo = db::AIModelRepository::new(pool.clone());
+
+            // Background jobs only run alongside the server, never for `migrate`.
+            let (stop_jobs, shutdown) = jobs::Shutdown::channel();
+            let mut job_handles = vec![
+                jobs::spawn_purge_job(repo.clone(), jobs::PurgeSettings::from_env(), shutdown.clone()),
+                jobs::spawn_subscription_sweep(pool.clone(), shutdown.clone()),
+            ];
+            if let Some(interval) = db::DownloadBuffer::flush_interval_from_env() {
+                let buffer = db::DownloadBuffer::default();
+                repo = repo.with_download_buffer(buffer.clone());
+                job_handles.push(jobs::spawn_download_count_flush(
+                    buffer,
+                    pool.clone(),
+                    interval,
+                    shutdown.clone(),
+                ));
+            }
+
+            let reviews = db::ReviewRepository::new(pool.clone());
+            let mailer = email::Mailer::from_env(pool.clone());
+            let payment_events = events::PaymentStatusBus::default();
+            let feature_flags = features::FeatureFlags::from_env();
+            let tier_cache = tier_cache::TierCache::from_env(&feature_flags);
+            let stripe_service = Arc::new(services::stripe::StripeService::new(
+                &config,
+                pool.clone(),
+                mailer.clone(),
+                payment_events.clone(),
+                tier_cache.clone(),
+            ));
+            job_handles.push(jobs::spawn_payment_intent_expiry_job(
+                stripe_service.clone(),
+                shutdown.clone(),
+            ));
+            job_handles.push(jobs::spawn_stripe_cleanup_job(stripe_service.clone(), shutdown));
+            let state = AppState {
+                pool: pool.clone(),
+                repo,
+                reviews,
+                jwt_secret: config.jwt_secret.clone().into(),
+                stripe_service,
+                plan_cache: Default::default(),
+                tier_cache,
+                storage: storage::StorageSettings::from_env(),
+                feature_flags,
+                events: Default::default(),
+                payment_events,
+                review_limiter: rate_limit::RateLimiter::reviews_from_env(),
+                route_limiter: rate_limit::RouteLimiter::from_env().await,
+                concurrency_limits: concurrency::ConcurrencyLimits::from_env(),
+                tax: Arc::new(tax::FlatRateTax::from_env()),
+                mailer,
+                receipt_signer: entitlements::ReceiptSigner::from_env(),
+                graphql: graphql::schema(),
+            };
+
+            let metrics_settings = metrics::MetricsSettings::from_env();
 
             // Build our application with routes
-            let app = Router::new()
-                .route("/api/health", get(|| async { "OK" }))
+            let mut app = Router::new()
+                .route("/api/health", get(routes::health::ready))
+                .route("/api/ready", get(routes::health::ready))
+                .route("/api/live", get(routes::health::live))
+                .route("/healthz/deep", get(routes::health::deep))
+                .route("/api/features", get(features::list_features))
+                .route("/api/entitlements/verify", get(entitlements::verify_entitlements))
+                .route(
+                    "/api/entitlements/public-key",
+                    get(entitlements::entitlement_public_key),
+                )
                 .route("/api/models", post(routes::create_model))
                 .route("/api/models", get(routes::list_models))
+                .route("/api/models/batch", post(routes::create_models_batch))
+                .route("/api/models/import/url", post(routes::import_model_from_url))
+                .route("/api/models/export", get(routes::export_models))
+                .route("/api/models/compare", post(routes::compare_models))
                 .route("/api/models/:id", get(routes::get_model))
                 .route("/api/models/:id", put(routes::update_model))
                 .route("/api/models/:id", delete(routes::delete_model))
                 .route("/api/models/:id/downloads", post(routes::increment_downloads))
-                .with_state(repo);
+                .route("/api/models/:id/tags", post(routes::add_tags))
+                .route("/api/models/:id/tags/:tag", delete(routes::remove_tag))
+                .route("/api/models/:id/fork", post(routes::fork_model))
+                .route("/api/models/:id/rotate-key", post(routes::rotate_model_key))
+                .route("/api/models/:id/verify-checksum", post(routes::verify_checksum))
+                .route("/api/models/:id/claim", post(routes::claim_model))
+                .route(
+                    "/api/models/:id/claim/challenge",
+                    post(routes::create_claim_challenge),
+                )
+                .route("/api/models/:id/downloads/stats", get(routes::get_download_stats))
+                .route(
+                    "/api/models/:id/downloads/campaigns",
+                    get(routes::get_campaign_downloads),
+                )
+                .route("/api/models/:id/analytics", get(routes::get_model_analytics))
+                .route("/api/models/:id/live", get(routes::live_model_updates))
+                .route(
+                    "/api/models/:id/favorite",
+                    post(routes::add_favorite).delete(routes::remove_favorite),
+                )
+                .route("/api/models/:id/download-url", get(routes::get_download_url))
+                .route("/api/models/:id/download-link", get(routes::get_download_link))
+                .route("/api/models/:id/restore", post(routes::restore_model))
+                .route("/api/models/:id/submit", post(routes::submit_model))
+                .route("/api/models/:id/publish", post(routes::publish_model))
+                .route("/api/models/:id/reject", post(routes::reject_model))
+                .route("/api/models/:id/withdraw-from-sale", post(routes::withdraw_from_sale))
+                .route("/api/models/:id/summary", get(routes::get_model_summary))
+                .route("/api/models/:id/versions", get(routes::list_versions))
+                .route("/api/models/:id/versions/:version", get(routes::get_version))
+                .route(
+                    "/api/models/:id/audit-diff/:v1/:v2",
+                    get(routes::get_version_diff),
+                )
+                .route("/api/models/:id/similar-pricing", get(routes::get_similar_pricing))
+                .route("/api/models/:id/usage-examples", get(routes::get_usage_examples))
+                .route("/api/models/:id/dependencies", post(routes::add_dependency))
+                .route("/api/models/:id/flag-stale", post(routes::flag_stale))
+                .route("/api/models/:id/qrcode.png", get(routes::get_model_qrcode))
+                .route("/api/models/:id/artifact", head(routes::head_model_artifact))
+                .route("/api/me", delete(routes::delete_my_account))
+                .route("/api/me/activity", get(routes::my_activity))
+                .route("/api/me/favorites", get(routes::my_favorites))
+                .route("/api/me/models/tier-report", get(routes::my_tier_report))
+                .route("/api/me/views", get(routes::list_views).post(routes::save_view))
+                .route(
+                    "/api/models/:id/reviews",
+                    get(routes::list_reviews).post(routes::create_review),
+                )
+                .nest("/api", routes::subscription::subscription_routes())
+                .nest("/api", routes::payment::payment_routes())
+                .nest("/api", routes::api_keys::api_key_routes())
+                .nest("/api", routes::admin::admin_routes())
+                .merge(graphql::graphql_routes())
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    rate_limit::limit_requests,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    concurrency::shed_load,
+                ))
+                .route_layer(middleware::from_fn(metrics::track_requests));
+
+            // Scrapes don't go through auth; keep them on a separate port
+            // when the API port is public.
+            match metrics_settings.port {
+                Some(metrics_port) => {
+                    let metrics_app =
+                        Router::new().route(&metrics_settings.path, get(metrics::render));
+                    let metrics_addr = SocketAddr::from(([0, 0, 0, 0], metrics_port));
+                    let metrics_listener = tokio::net::TcpListener::bind(metrics_addr)
+                        .await
+                        .expect("Failed to bind metrics address");
+                    tracing::info!("metrics listening on {}", metrics_addr);
+                    tokio::spawn(async move {
+                        if let Err(e) = axum::serve(metrics_listener, metrics_app).await {
+                            tracing::error!("metrics server error: {}", e);
+                        }
+                    });
+                }
+                None => {
+                    app = app.route(&metrics_settings.path, get(metrics::render));
+                }
+            }
 
-            // Get host and port from environment variables or use defaults
-            let host = env::var("HOST").unwrap_or_else(|_| "0.0.0.0".to_string());
-            let port = env::var("PORT")
-                .ok()
-                .and_then(|p| p.parse().ok())
-                .unwrap_or(3000);
+            let app = app
+                .layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    idempotency::idempotent_posts,
+                ))
+                // `limit_body` enforces the configured limit instead.
+                .layer(DefaultBodyLimit::disable())
+                .with_state(state)
+                .layer(middleware::from_fn(json_case::negotiate_case))
+                .layer(config::CorsSettings::from_env().layer())
+                .layer(middleware::from_fn_with_state(
+                    logging::LoggingSettings::from_env(),
+                    logging::log_requests,
+                ))
+                .layer(middleware::from_fn_with_state(
+                    body_limit::BodyLimits::from_env(),
+                    body_limit::limit_body,
+                ))
+                .layer(middleware::from_fn(request_id::propagate_request_id));
 
-            // Create the address to bind to
-            let addr: SocketAddr = format!("{}:{}", host, port)
-                .parse()
-                .expect("Failed to parse address");
+            let addr = config.addr;
 
             tracing::info!("listening on {}", addr);
             
@@ -112,7 +420,25 @@ async fn main() {
 
             println!("Server starting on {}", addr);
             
-            match axum::serve(listener, app).await {
+            let settings = server::ServerSettings::from_env();
+            tracing::info!(
+                "http2 {}, keep-alive {}",
+                if settings.http2 { "enabled" } else { "disabled" },
+                if settings.keep_alive { "enabled" } else { "disabled" },
+            );
+
+            let result = server::serve(listener, app, settings, server::shutdown_signal()).await;
+
+            // Let jobs finish the pass they're in before the pool goes away.
+            let _ = stop_jobs.send(true);
+            for handle in job_handles {
+                if let Err(e) = handle.await {
+                    tracing::error!("background job failed: {}", e);
+                }
+            }
+            pool.close().await;
+
+            match result {
                 Ok(_) => {
                     println!("Server shutdown gracefully");
                     std::process::exit(0);
@@ -0,0 +1,165 @@
+use axum::{
+    extract::{MatchedPath, Request},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, Opts, Registry, TextEncoder,
+};
+use std::env;
+use std::time::Instant;
+
+pub static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+pub static HTTP_REQUESTS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("http_requests_total", "HTTP requests handled"),
+        &["method", "path", "status"],
+    ))
+});
+
+pub static HTTP_REQUEST_DURATION_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new("http_request_duration_seconds", "HTTP request latency"),
+        &["method", "path"],
+    ))
+});
+
+pub static PAYMENT_SUCCEEDED_TOTAL: Lazy<IntCounter> = Lazy::new(|| {
+    register(IntCounter::new(
+        "payment_succeeded_total",
+        "Payments confirmed by Stripe",
+    ))
+});
+
+pub static PAYMENT_FAILED_TOTAL: Lazy<IntCounter> = Lazy::new(|| {
+    register(IntCounter::new(
+        "payment_failed_total",
+        "Payments reported as failed by Stripe",
+    ))
+});
+
+pub static MODEL_DOWNLOAD_TOTAL: Lazy<IntCounter> = Lazy::new(|| {
+    register(IntCounter::new(
+        "model_download_total",
+        "Model downloads counted",
+    ))
+});
+
+fn register<M>(metric: prometheus::Result<M>) -> M
+where
+    M: prometheus::core::Collector + Clone + 'static,
+{
+    let metric = metric.expect("metric definition is valid");
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("metric is registered once");
+    metric
+}
+
+/// Where metrics are exposed.
+///
+/// With `METRICS_PORT` set, `/metrics` is served on its own listener so it
+/// can be kept off the public port; otherwise it's mounted on the API router.
+#[derive(Debug, Clone)]
+pub struct MetricsSettings {
+    pub path: String,
+    pub port: Option<u16>,
+}
+
+impl MetricsSettings {
+    pub fn from_env() -> Self {
+        Self {
+            path: env::var("METRICS_PATH").unwrap_or_else(|_| "/metrics".into()),
+            port: env::var("METRICS_PORT").ok().and_then(|v| v.parse().ok()),
+        }
+    }
+}
+
+/// Record a count and latency for every request, labelled by route template
+/// rather than raw path to keep label cardinality bounded.
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_else(|| "unmatched".into());
+    let start = Instant::now();
+
+    let response = next.run(request).await;
+
+    HTTP_REQUEST_DURATION_SECONDS
+        .with_label_values(&[&method, &path])
+        .observe(start.elapsed().as_secs_f64());
+    HTTP_REQUESTS_TOTAL
+        .with_label_values(&[&method, &path, response.status().as_str()])
+        .inc();
+
+    response
+}
+
+/// Render every registered metric in the Prometheus text format.
+pub async fn render() -> Response {
+    // Touch the custom counters so they're exported at zero before first use.
+    Lazy::force(&PAYMENT_SUCCEEDED_TOTAL);
+    Lazy::force(&PAYMENT_FAILED_TOTAL);
+    Lazy::force(&MODEL_DOWNLOAD_TOTAL);
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    if let Err(e) = encoder.encode(&REGISTRY.gather(), &mut buffer) {
+        tracing::error!("failed to encode metrics: {}", e);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    ([(header::CONTENT_TYPE, encoder.format_type().to_owned())], buffer).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        Router::new()
+            .route("/metrics-test/:id", get(|| async { "ok" }))
+            .route_layer(middleware::from_fn(track_requests))
+            .route("/metrics", get(render))
+    }
+
+    async fn get_body(app: &Router, uri: &str) -> String {
+        let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    /// The value of the sample named exactly `series` in a scrape.
+    fn sample(scrape: &str, series: &str) -> Option<f64> {
+        scrape
+            .lines()
+            .find_map(|line| line.strip_prefix(series)?.trim().parse().ok())
+    }
+
+    #[tokio::test]
+    async fn a_request_shows_up_in_the_scrape_under_its_route() {
+        let app = app();
+        let series = r#"http_requests_total{method="GET",path="/metrics-test/:id",status="200"}"#;
+
+        let before = get_body(&app, "/metrics").await;
+        assert_eq!(sample(&before, series), None);
+        assert!(sample(&before, "payment_succeeded_total").is_some());
+
+        get_body(&app, "/metrics-test/1").await;
+        get_body(&app, "/metrics-test/2").await;
+
+        let after = get_body(&app, "/metrics").await;
+        assert_eq!(sample(&after, series), Some(2.0));
+        assert!(after.contains(r#"http_request_duration_seconds_count{method="GET",path="/metrics-test/:id"} 2"#));
+    }
+}
@@ -0,0 +1,331 @@
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// What closing an account removed. Payment intents, payment history,
+/// invoices, coupon redemptions and disputes are kept for accounting and
+/// stay attached to the anonymized user row, so they aren't counted here.
+/// Owned models are soft-deleted rather than handed to anyone else.
+#[derive(Debug, Default, Serialize)]
+pub struct AccountDeletion {
+    /// Set when the account was already closed. Nothing local is removed
+    /// again, but Stripe cleanup left pending last time is retried.
+    pub already_deleted: bool,
+    pub subscriptions_canceled: u64,
+    pub stripe_subscriptions_canceled: u64,
+    pub payment_methods_detached: u64,
+    pub stripe_payment_methods_detached: u64,
+    pub models_deleted: u64,
+    pub reviews_deleted: u64,
+    pub api_keys_revoked: u64,
+    pub favorites_deleted: u64,
+    pub views_deleted: u64,
+    pub notifications_deleted: u64,
+}
+
+/// Stripe subscriptions and payment methods a closed account still has to
+/// have canceled and detached. They're recorded when the account is closed
+/// and cleared once Stripe confirms, so nothing is lost if Stripe is down.
+#[derive(Debug, Default)]
+pub struct PendingStripeCleanup {
+    pub user_id: Uuid,
+    pub subscription_ids: Vec<String>,
+    pub payment_method_ids: Vec<String>,
+}
+
+impl PendingStripeCleanup {
+    /// Everything still pending, per account; only `user_id`'s if given.
+    pub async fn pending(pool: &PgPool, user_id: Option<Uuid>) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"
+            SELECT user_id,
+                   COALESCE(array_agg(stripe_id) FILTER (WHERE kind = 'subscription'), '{}')
+                       AS "subscription_ids!",
+                   COALESCE(array_agg(stripe_id) FILTER (WHERE kind = 'payment_method'), '{}')
+                       AS "payment_method_ids!"
+            FROM pending_stripe_cleanups
+            WHERE $1::uuid IS NULL OR user_id = $1
+            GROUP BY user_id
+            "#,
+            user_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Forget `stripe_ids` once Stripe has dealt with them.
+    pub async fn resolve(pool: &PgPool, stripe_ids: &[String]) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "DELETE FROM pending_stripe_cleanups WHERE stripe_id = ANY($1)",
+            stripe_ids
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+/// Whether `user_id` is an existing account that hasn't been closed.
+/// Tokens issued before an account was closed stay valid until they
+/// expire, so authentication checks this on every request.
+pub async fn account_is_open(pool: &PgPool, user_id: Uuid) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM users WHERE id = $1 AND deleted_at IS NULL) AS "open!""#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+impl AccountDeletion {
+    /// Close the account in one transaction: cancel subscriptions, drop
+    /// stored payment methods, revoke API keys, soft-delete owned models,
+    /// remove personal data and anonymize the user row. Stripe isn't
+    /// touched; what to clean up there is recorded as a
+    /// [`PendingStripeCleanup`] instead. Returns `None` if there's no user
+    /// with this id.
+    pub async fn erase(pool: &PgPool, user_id: Uuid) -> Result<Option<Self>, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        // Locking the row serializes concurrent deletions of the same account.
+        let deleted_at = sqlx::query_scalar!(
+            "SELECT deleted_at FROM users WHERE id = $1 FOR UPDATE",
+            user_id
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+        match deleted_at {
+            None => return Ok(None),
+            Some(Some(_)) => {
+                return Ok(Some(Self {
+                    already_deleted: true,
+                    ..Self::default()
+                }))
+            }
+            Some(None) => {}
+        }
+
+        let mut deletion = Self::default();
+        let stripe_subscription_ids =
+            super::UserSubscription::active_stripe_ids(&mut tx, user_id).await?;
+
+        deletion.subscriptions_canceled = sqlx::query!(
+            r#"
+            UPDATE user_subscriptions
+            SET is_active = false,
+                ends_at = NOW(),
+                updated_at = NOW()
+            WHERE user_id = $1 AND is_active = true
+            "#,
+            user_id
+        )
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+
+        let stripe_payment_method_ids = sqlx::query_scalar!(
+            "DELETE FROM payment_methods WHERE user_id = $1 RETURNING stripe_payment_method_id",
+            user_id
+        )
+        .fetch_all(&mut tx)
+        .await?;
+        deletion.payment_methods_detached = stripe_payment_method_ids.len() as u64;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO pending_stripe_cleanups (user_id, kind, stripe_id)
+            SELECT $1::uuid, 'subscription', unnest($2::text[])
+            UNION ALL
+            SELECT $1::uuid, 'payment_method', unnest($3::text[])
+            ON CONFLICT (kind, stripe_id) DO NOTHING
+            "#,
+            user_id,
+            &stripe_subscription_ids,
+            &stripe_payment_method_ids
+        )
+        .execute(&mut tx)
+        .await?;
+
+        deletion.models_deleted = sqlx::query!(
+            r#"
+            UPDATE ai_models
+            SET deleted_at = NOW(), updated_at = NOW()
+            WHERE owner_id = $1 AND deleted_at IS NULL
+            "#,
+            user_id
+        )
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+
+        let reviewed = sqlx::query_scalar!(
+            "DELETE FROM model_reviews WHERE user_id = $1 RETURNING model_id",
+            user_id
+        )
+        .fetch_all(&mut tx)
+        .await?;
+        deletion.reviews_deleted = reviewed.len() as u64;
+
+        sqlx::query!(
+            r#"
+            UPDATE ai_models
+            SET avg_rating = (
+                SELECT AVG(rating) FROM model_reviews WHERE model_id = ai_models.id
+            )
+            WHERE id = ANY($1)
+            "#,
+            &reviewed
+        )
+        .execute(&mut tx)
+        .await?;
+
+        deletion.api_keys_revoked =
+            sqlx::query!("DELETE FROM api_keys WHERE user_id = $1", user_id)
+                .execute(&mut tx)
+                .await?
+                .rows_affected();
+
+        deletion.favorites_deleted =
+            sqlx::query!("DELETE FROM favorites WHERE user_id = $1", user_id)
+                .execute(&mut tx)
+                .await?
+                .rows_affected();
+
+        deletion.views_deleted =
+            sqlx::query!("DELETE FROM model_views WHERE user_id = $1", user_id)
+                .execute(&mut tx)
+                .await?
+                .rows_affected();
+
+        deletion.notifications_deleted =
+            sqlx::query!("DELETE FROM notifications WHERE user_id = $1", user_id)
+                .execute(&mut tx)
+                .await?
+                .rows_affected();
+
+        sqlx::query!("DELETE FROM model_stale_reports WHERE user_id = $1", user_id)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query!(
+            "DELETE FROM subscription_seat_assignments WHERE user_id = $1",
+            user_id
+        )
+        .execute(&mut tx)
+        .await?;
+        sqlx::query!("DELETE FROM idempotency_keys WHERE user_id = $1", user_id)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query!(
+            "DELETE FROM model_claim_challenges WHERE user_id = $1",
+            user_id
+        )
+        .execute(&mut tx)
+        .await?;
+        sqlx::query!(
+            "UPDATE model_downloads SET user_id = NULL WHERE user_id = $1",
+            user_id
+        )
+        .execute(&mut tx)
+        .await?;
+
+        // The row stays so retained financial records keep their foreign
+        // key, but nothing on it identifies the person any more.
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET email = 'deleted-' || id || '@deleted.invalid',
+                name = 'Deleted user',
+                password_hash = '',
+                is_active = false,
+                billing_country = NULL,
+                billing_region = NULL,
+                deleted_at = NOW(),
+                updated_at = NOW()
+            WHERE id = $1
+            "#,
+            user_id
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(Some(deletion))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ApiKey;
+    use crate::test_support::create_user;
+
+    #[sqlx::test]
+    async fn erased_account_is_closed_and_its_api_keys_stop_working(pool: PgPool) {
+        let user_id = create_user(&pool).await;
+        let created = ApiKey::create(&pool, user_id, "ci").await.unwrap();
+        assert!(account_is_open(&pool, user_id).await.unwrap());
+        assert!(ApiKey::authenticate(&pool, &created.key).await.unwrap().is_some());
+
+        let deletion = AccountDeletion::erase(&pool, user_id).await.unwrap().unwrap();
+        assert_eq!(deletion.api_keys_revoked, 1);
+
+        assert!(!account_is_open(&pool, user_id).await.unwrap());
+        assert!(ApiKey::authenticate(&pool, &created.key).await.unwrap().is_none());
+    }
+
+    #[sqlx::test]
+    async fn erase_records_the_stripe_ids_to_clean_up(pool: PgPool) {
+        let user_id = create_user(&pool).await;
+        sqlx::query!(
+            r#"
+            INSERT INTO payment_methods (user_id, stripe_payment_method_id, is_default)
+            VALUES ($1, 'pm_test', true)
+            "#,
+            user_id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query!(
+            r#"
+            INSERT INTO user_subscriptions (user_id, subscription_id, starts_at, is_active, stripe_subscription_id)
+            SELECT $1, id, NOW(), true, 'sub_test' FROM subscriptions WHERE tier = 'pro'
+            "#,
+            user_id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let deletion = AccountDeletion::erase(&pool, user_id).await.unwrap().unwrap();
+        assert_eq!(deletion.subscriptions_canceled, 1);
+        assert_eq!(deletion.payment_methods_detached, 1);
+
+        let pending = PendingStripeCleanup::pending(&pool, Some(user_id)).await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].subscription_ids, vec!["sub_test".to_string()]);
+        assert_eq!(pending[0].payment_method_ids, vec!["pm_test".to_string()]);
+
+        PendingStripeCleanup::resolve(&pool, &pending[0].subscription_ids).await.unwrap();
+        let pending = PendingStripeCleanup::pending(&pool, Some(user_id)).await.unwrap();
+        assert!(pending[0].subscription_ids.is_empty());
+        assert_eq!(pending[0].payment_method_ids, vec!["pm_test".to_string()]);
+    }
+
+    #[sqlx::test]
+    async fn erasing_twice_reports_already_deleted(pool: PgPool) {
+        let user_id = create_user(&pool).await;
+        AccountDeletion::erase(&pool, user_id).await.unwrap();
+
+        let again = AccountDeletion::erase(&pool, user_id).await.unwrap().unwrap();
+        assert!(again.already_deleted);
+    }
+
+    #[sqlx::test]
+    async fn unknown_user_is_not_open(pool: PgPool) {
+        assert!(!account_is_open(&pool, Uuid::new_v4()).await.unwrap());
+        assert!(AccountDeletion::erase(&pool, Uuid::new_v4()).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,355 @@
+use chrono::{DateTime, Months, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{Money, UserSubscription};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Addon {
+    pub id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    /// In the currency's minor unit.
+    pub price_monthly: i64,
+    pub currency: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserSubscriptionAddon {
+    pub id: Uuid,
+    pub user_subscription_id: Uuid,
+    pub addon_id: Uuid,
+    pub quantity: i32,
+    pub added_at: DateTime<Utc>,
+    pub removed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SubscriptionCredit {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    /// In the currency's minor unit.
+    pub amount: i64,
+    pub currency: String,
+    pub reason: String,
+    pub payment_intent_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddAddonRequest {
+    pub addon_id: Uuid,
+    #[serde(default = "default_quantity")]
+    pub quantity: i32,
+}
+
+fn default_quantity() -> i32 {
+    1
+}
+
+impl UserSubscription {
+    /// The monthly billing period containing `at`.
+    pub fn current_period(&self, at: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+        self.period_containing(at, Months::new(1))
+    }
+
+    /// The period of `length` containing `at`, counted from the start.
+    fn period_containing(&self, at: DateTime<Utc>, length: Months) -> (DateTime<Utc>, DateTime<Utc>) {
+        let mut start = self.starts_at;
+        let mut end = start + length;
+        while end <= at {
+            start = end;
+            end = start + length;
+        }
+        (start, end)
+    }
+
+    /// Fraction of the current monthly period still to run at `at`.
+    pub fn remaining_period_fraction(&self, at: DateTime<Utc>) -> f64 {
+        remaining_fraction(self.current_period(at), at)
+    }
+
+    /// Fraction of the plan's billing period, a month or a year, still to
+    /// run at `at`.
+    pub fn remaining_billing_fraction(&self, at: DateTime<Utc>) -> f64 {
+        let months = Months::new(self.billing_interval.months() as u32);
+        remaining_fraction(self.period_containing(at, months), at)
+    }
+}
+
+/// Fraction of the period from `start` to `end` still to run at `at`.
+fn remaining_fraction((start, end): (DateTime<Utc>, DateTime<Utc>), at: DateTime<Utc>) -> f64 {
+    let total = (end - start).num_seconds() as f64;
+    let remaining = (end - at).num_seconds().max(0) as f64;
+    if total > 0.0 {
+        remaining / total
+    } else {
+        0.0
+    }
+}
+
+impl Addon {
+    pub async fn get_all(pool: &PgPool) -> Result<Vec<Addon>, sqlx::Error> {
+        sqlx::query_as!(
+            Addon,
+            r#"
+            SELECT id, name, description, price_monthly, currency,
+                   created_at, updated_at
+            FROM addons
+            ORDER BY price_monthly ASC
+            "#
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn get_by_id(pool: &PgPool, id: Uuid) -> Result<Option<Addon>, sqlx::Error> {
+        sqlx::query_as!(
+            Addon,
+            r#"
+            SELECT id, name, description, price_monthly, currency,
+                   created_at, updated_at
+            FROM addons
+            WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_optional(pool)
+        .await
+    }
+}
+
+impl UserSubscriptionAddon {
+    pub async fn list_active(
+        pool: &PgPool,
+        user_subscription_id: Uuid,
+    ) -> Result<Vec<UserSubscriptionAddon>, sqlx::Error> {
+        sqlx::query_as!(
+            UserSubscriptionAddon,
+            r#"
+            SELECT id, user_subscription_id, addon_id, quantity, added_at, removed_at
+            FROM user_subscription_addons
+            WHERE user_subscription_id = $1 AND removed_at IS NULL
+            ORDER BY added_at ASC
+            "#,
+            user_subscription_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Monthly cost of the add-ons currently on a subscription that are
+    /// priced in `currency`.
+    pub async fn monthly_total(
+        pool: &PgPool,
+        user_subscription_id: Uuid,
+        currency: &str,
+    ) -> Result<Money, sqlx::Error> {
+        let total = sqlx::query_scalar!(
+            r#"
+            SELECT COALESCE(SUM(a.price_monthly * usa.quantity), 0)::BIGINT AS "total!"
+            FROM user_subscription_addons usa
+            JOIN addons a ON a.id = usa.addon_id
+            WHERE usa.user_subscription_id = $1 AND usa.removed_at IS NULL
+            AND UPPER(a.currency) = UPPER($2)
+            "#,
+            user_subscription_id,
+            currency
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(Money::from_minor(total, currency))
+    }
+
+    /// Returns `None` if the add-on is already on the subscription.
+    pub async fn add(
+        pool: &PgPool,
+        user_subscription_id: Uuid,
+        addon_id: Uuid,
+        quantity: i32,
+    ) -> Result<Option<UserSubscriptionAddon>, sqlx::Error> {
+        sqlx::query_as!(
+            UserSubscriptionAddon,
+            r#"
+            INSERT INTO user_subscription_addons (user_subscription_id, addon_id, quantity)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_subscription_id, addon_id) WHERE removed_at IS NULL DO NOTHING
+            RETURNING id, user_subscription_id, addon_id, quantity, added_at, removed_at
+            "#,
+            user_subscription_id,
+            addon_id,
+            quantity
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Remove an add-on and credit the unused part of the current period.
+    /// Returns `None` if the add-on isn't active on the subscription.
+    pub async fn remove(
+        pool: &PgPool,
+        subscription: &UserSubscription,
+        id: Uuid,
+    ) -> Result<Option<SubscriptionCredit>, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let Some(removed) = sqlx::query!(
+            r#"
+            UPDATE user_subscription_addons usa
+            SET removed_at = NOW()
+            FROM addons a
+            WHERE usa.id = $1
+            AND usa.user_subscription_id = $2
+            AND usa.removed_at IS NULL
+            AND a.id = usa.addon_id
+            RETURNING a.name, a.price_monthly, a.currency, usa.quantity, usa.removed_at AS "removed_at!"
+            "#,
+            id,
+            subscription.id
+        )
+        .fetch_optional(&mut tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        let credit = Money::from_minor(removed.price_monthly, &removed.currency)
+            .times(removed.quantity.into())
+            .scale(subscription.remaining_period_fraction(removed.removed_at));
+
+        let credit = sqlx::query_as!(
+            SubscriptionCredit,
+            r#"
+            INSERT INTO subscription_credits (user_id, amount, currency, reason)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, user_id, amount, currency, reason, payment_intent_id, created_at
+            "#,
+            subscription.user_id,
+            credit.minor_units(),
+            credit.currency(),
+            format!("Prorated refund for {}", removed.name)
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(Some(credit))
+    }
+}
+
+impl SubscriptionCredit {
+    /// Credits not yet spent on a payment, in `currency`. Credits in other
+    /// currencies are kept for payments in theirs.
+    pub async fn unapplied_total(
+        pool: &PgPool,
+        user_id: Uuid,
+        currency: &str,
+    ) -> Result<Money, sqlx::Error> {
+        let total = sqlx::query_scalar!(
+            r#"
+            SELECT COALESCE(SUM(amount), 0)::BIGINT AS "total!"
+            FROM subscription_credits
+            WHERE user_id = $1 AND payment_intent_id IS NULL
+            AND UPPER(currency) = UPPER($2)
+            "#,
+            user_id,
+            currency
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(Money::from_minor(total, currency))
+    }
+
+    /// Mark the outstanding credits in `currency` as consumed by
+    /// `payment_intent_id`.
+    pub async fn apply_to(
+        pool: &PgPool,
+        user_id: Uuid,
+        currency: &str,
+        payment_intent_id: Uuid,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE subscription_credits
+            SET payment_intent_id = $3
+            WHERE user_id = $1 AND payment_intent_id IS NULL
+            AND UPPER(currency) = UPPER($2)
+            "#,
+            user_id,
+            currency,
+            payment_intent_id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{BillingInterval, SubscriptionTier};
+    use crate::test_support::{create_user, plan};
+    use chrono::TimeZone;
+
+    #[sqlx::test]
+    async fn removing_an_addon_credits_the_rest_of_the_period(pool: PgPool) {
+        let user_id = create_user(&pool).await;
+        let pro = plan(&pool, SubscriptionTier::Pro).await;
+        let subscription = UserSubscription::create(&pool, user_id, pro.id, BillingInterval::Monthly)
+            .await
+            .unwrap();
+        let addon_id = sqlx::query_scalar!(
+            "INSERT INTO addons (name, price_monthly) VALUES ('Extra storage', 500) RETURNING id"
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let added = UserSubscriptionAddon::add(&pool, subscription.id, addon_id, 2)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // The period has only just started, so nearly all of it is credited.
+        let credit = UserSubscriptionAddon::remove(&pool, &subscription, added.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((credit.amount, credit.currency.as_str()), (1000, "USD"));
+        assert_eq!(
+            SubscriptionCredit::unapplied_total(&pool, user_id, "USD").await.unwrap(),
+            Money::from_minor(1000, "USD")
+        );
+
+        assert!(UserSubscriptionAddon::remove(&pool, &subscription, added.id)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[sqlx::test]
+    async fn remaining_fraction_follows_the_monthly_period(pool: PgPool) {
+        let user_id = create_user(&pool).await;
+        let pro = plan(&pool, SubscriptionTier::Pro).await;
+        let mut subscription = UserSubscription::create(&pool, user_id, pro.id, BillingInterval::Monthly)
+            .await
+            .unwrap();
+        subscription.starts_at = Utc.with_ymd_and_hms(2024, 4, 1, 0, 0, 0).unwrap();
+
+        let mid_june = Utc.with_ymd_and_hms(2024, 6, 16, 0, 0, 0).unwrap();
+        assert_eq!(
+            subscription.current_period(mid_june),
+            (
+                Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2024, 7, 1, 0, 0, 0).unwrap()
+            )
+        );
+        assert_eq!(subscription.remaining_period_fraction(mid_june), 0.5);
+    }
+}
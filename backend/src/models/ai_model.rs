@@ -1,44 +1,735 @@
+use chrono::{Months, Utc};
 use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
 use sqlx::FromRow;
 use serde_json::Value as JsonValue;
+use uuid::Uuid;
+
+use super::{is_http_url, is_semver_like, LicenseCompatibility, Patch, SubscriptionTier, ValidationErrors};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, async_graphql::Enum)]
+#[sqlx(type_name = "model_type", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ModelType {
+    Classification,
+    Regression,
+    Llm,
+    Embedding,
+    Vision,
+    Other,
+}
+
+/// Where a model is in the review workflow. Only `Published` models are
+/// shown to the public.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, async_graphql::Enum)]
+#[sqlx(type_name = "model_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ModelStatus {
+    Draft,
+    PendingReview,
+    Published,
+    Rejected,
+}
+
+impl ModelStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModelStatus::Draft => "draft",
+            ModelStatus::PendingReview => "pending_review",
+            ModelStatus::Published => "published",
+            ModelStatus::Rejected => "rejected",
+        }
+    }
+}
+
+/// Benchmark results reported for a model.
+///
+/// Keys other than the ones below are kept in `extra`, so metrics we don't
+/// model yet survive a read-modify-write.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PerformanceMetrics {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accuracy: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub f1: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub throughput: Option<f64>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, JsonValue>,
+}
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct AIModel {
-    pub id: i32,
+    pub id: Uuid,
     pub name: String,
     pub description: String,
-    pub model_type: String,
+    pub model_type: ModelType,
     pub framework: String,
     pub version: String,
+    pub status: ModelStatus,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
     #[serde(default)]
     pub metadata: JsonValue,
+    pub performance_metrics: Option<Json<PerformanceMetrics>>,
     pub repository_url: Option<String>,
+    pub tags: Vec<String>,
     pub download_count: i32,
+    /// Counter from the original marketplace schema. Nothing updates it;
+    /// `download_count` is the live count.
+    pub downloads: i32,
     pub is_public: bool,
+    /// One-off purchase price; `None` means the model comes with the
+    /// subscription tier instead.
+    pub price: Option<f64>,
+    pub required_tier: SubscriptionTier,
+    pub owner_id: Option<Uuid>,
+    pub avg_rating: Option<f64>,
+    pub license: Option<String>,
+    pub deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_by: Option<Uuid>,
+    pub updated_by: Option<Uuid>,
+    pub file_size_bytes: Option<i64>,
+    /// Hex-encoded SHA-256 of the artifact.
+    pub artifact_sha256: Option<String>,
+    /// Object key of the artifact in our storage bucket, if we host it.
+    pub storage_key: Option<String>,
+    pub withdrawn_from_sale_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Withdrawn without being made free: only past downloaders keep access.
+    pub archived: bool,
+    /// The model this one was forked from, if it still exists.
+    pub forked_from: Option<Uuid>,
+    /// When the artifact last moved to a new `storage_key`.
+    pub key_rotated_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Proof-of-control token for claiming an unowned model: it must appear
+/// in the page served at the model's `repository_url` (a README works).
+#[derive(Debug, Serialize)]
+pub struct ClaimChallenge {
+    pub token: String,
+    pub repository_url: String,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl ClaimChallenge {
+    /// How long a challenge token stays valid.
+    pub const TTL_HOURS: i64 = 24;
+
+    pub fn generate_token() -> String {
+        use rand::RngCore;
+        let mut bytes = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        format!("model-claim-{}", hex::encode(bytes))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DownloadUrl {
+    pub url: String,
+    /// `None` when the URL points at the model's own repository.
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub file_size_bytes: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyChecksum {
+    /// Hex-encoded SHA-256 of the file the client has.
+    pub sha256: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChecksumVerification {
+    pub matches: bool,
+    pub expected: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateAIModel {
     pub name: String,
     pub description: String,
-    pub model_type: String,
+    pub model_type: ModelType,
     pub framework: String,
     pub version: String,
     pub metadata: Option<JsonValue>,
+    pub performance_metrics: Option<PerformanceMetrics>,
     pub repository_url: Option<String>,
+    pub tags: Option<Vec<String>>,
     pub is_public: bool,
+    #[serde(default)]
+    pub price: Option<f64>,
+    /// Defaults to `free`.
+    #[serde(default)]
+    pub required_tier: Option<SubscriptionTier>,
+    #[serde(default)]
+    pub license: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct UpdateAIModel {
     pub name: Option<String>,
     pub description: Option<String>,
-    pub model_type: Option<String>,
+    pub model_type: Option<ModelType>,
     pub framework: Option<String>,
     pub version: Option<String>,
-    pub metadata: Option<JsonValue>,
-    pub repository_url: Option<String>,
+    /// Clearing resets it to an empty object.
+    #[serde(default, skip_serializing_if = "Patch::is_keep")]
+    pub metadata: Patch<JsonValue>,
+    #[serde(default, skip_serializing_if = "Patch::is_keep")]
+    pub performance_metrics: Patch<PerformanceMetrics>,
+    #[serde(default, skip_serializing_if = "Patch::is_keep")]
+    pub repository_url: Patch<String>,
+    pub tags: Option<Vec<String>>,
     pub is_public: Option<bool>,
-} 
\ No newline at end of file
+    pub price: Option<f64>,
+    pub required_tier: Option<SubscriptionTier>,
+    pub license: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AddTags {
+    pub tags: Vec<String>,
+}
+
+/// A model's tags after adding or removing some.
+#[derive(Debug, Serialize)]
+pub struct ModelTags {
+    pub tags: Vec<String>,
+}
+
+/// How many of an owner's models sit behind a tier, and how many users can
+/// reach them.
+#[derive(Debug, Serialize)]
+pub struct TierReportEntry {
+    pub required_tier: SubscriptionTier,
+    pub models: i64,
+    /// Users whose tier grants `required_tier`.
+    pub reach: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TierReport {
+    pub total_models: i64,
+    pub tiers: Vec<TierReportEntry>,
+}
+
+/// An action a user took on a model, as shown in their activity feed.
+#[derive(Debug, Serialize)]
+pub struct ModelActivity {
+    pub id: Uuid,
+    pub model_id: Uuid,
+    pub model_name: String,
+    pub action: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A model the user downloaded, newest first.
+#[derive(Debug, Serialize)]
+pub struct UserDownload {
+    pub model_id: Uuid,
+    pub model_name: String,
+    pub downloaded_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A staleness report the user filed that the model's owner hasn't yet
+/// addressed with an update.
+#[derive(Debug, Serialize)]
+pub struct OpenStaleReport {
+    pub id: Uuid,
+    pub model_id: Uuid,
+    pub model_name: String,
+    pub reason: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ActivityQueryParams {
+    pub limit: Option<i64>,
+}
+
+impl AIModel {
+    /// Whether anyone, signed in or not, may see the model.
+    pub fn is_publicly_visible(&self) -> bool {
+        self.is_public && self.status == ModelStatus::Published
+    }
+
+    /// Unpublished and private models are visible only to their owner and
+    /// to admins.
+    pub fn is_visible_to(&self, viewer: Option<Uuid>, is_admin: bool) -> bool {
+        self.is_publicly_visible() || is_admin || (viewer.is_some() && viewer == self.owner_id)
+    }
+
+    /// A model is stale when it hasn't been updated in `stale_after_months`.
+    pub fn is_stale(&self, stale_after_months: u32) -> bool {
+        Utc::now()
+            .checked_sub_months(Months::new(stale_after_months))
+            .map(|threshold| self.updated_at < threshold)
+            .unwrap_or(false)
+    }
+}
+
+/// Number of months without an update after which a model is considered stale.
+pub fn stale_after_months() -> u32 {
+    std::env::var("STALE_AFTER_MONTHS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(12)
+}
+
+/// The model's page on the public site, e.g. for sharing links.
+pub fn public_model_url(id: Uuid) -> String {
+    let base = std::env::var("PUBLIC_BASE_URL").unwrap_or_else(|_| "http://localhost:5173".into());
+    format!("{}/models/{}", base.trim_end_matches('/'), id)
+}
+
+pub const MAX_NAME_LENGTH: usize = 200;
+pub const MAX_DESCRIPTION_LENGTH: usize = 10_000;
+pub const MAX_TAGS: usize = 20;
+pub const MAX_TAG_LENGTH: usize = 50;
+/// Combined length of all of a model's tags.
+pub const MAX_TAGS_TOTAL_LENGTH: usize = 500;
+/// Fits the `license` column.
+pub const MAX_LICENSE_LENGTH: usize = 100;
+/// Most models `POST /api/models/batch` accepts in one request.
+pub const MAX_BATCH_SIZE: usize = 500;
+
+fn validate_name(name: &str, errors: &mut ValidationErrors) {
+    if name.trim().is_empty() {
+        errors.add("name", "must not be empty");
+    } else if name.chars().count() > MAX_NAME_LENGTH {
+        errors.add("name", format!("must be at most {} characters", MAX_NAME_LENGTH));
+    }
+}
+
+fn validate_description(description: &str, errors: &mut ValidationErrors) {
+    if description.chars().count() > MAX_DESCRIPTION_LENGTH {
+        errors.add(
+            "description",
+            format!("must be at most {} characters", MAX_DESCRIPTION_LENGTH),
+        );
+    }
+}
+
+fn validate_version(version: &str, errors: &mut ValidationErrors) {
+    if version.trim().is_empty() {
+        errors.add("version", "must not be empty");
+    } else if !is_semver_like(version) {
+        errors.add("version", "must look like a semantic version, e.g. 1.2.0");
+    }
+}
+
+fn validate_repository_url(url: &str, errors: &mut ValidationErrors) {
+    if !is_http_url(url) {
+        errors.add("repository_url", "must be a valid http(s) URL");
+    }
+}
+
+pub fn validate_tags(tags: &[String], errors: &mut ValidationErrors) {
+    validate_tag_limits(tags, errors);
+    validate_tag_format(tags, errors);
+}
+
+/// Tag count and length limits for a model's whole tag list.
+pub fn validate_tag_limits(tags: &[String], errors: &mut ValidationErrors) {
+    if tags.len() > MAX_TAGS {
+        errors.add("tags", format!("must have at most {} tags", MAX_TAGS));
+    }
+    if tags.iter().any(|tag| tag.trim().is_empty()) {
+        errors.add("tags", "must not contain empty tags");
+    }
+    if tags.iter().any(|tag| tag.chars().count() > MAX_TAG_LENGTH) {
+        errors.add("tags", format!("each tag must be at most {} characters", MAX_TAG_LENGTH));
+    }
+    let total: usize = tags.iter().map(|tag| tag.chars().count()).sum();
+    if total > MAX_TAGS_TOTAL_LENGTH {
+        errors.add(
+            "tags",
+            format!("must be at most {} characters altogether", MAX_TAGS_TOTAL_LENGTH),
+        );
+    }
+}
+
+/// Tags are lowercase and contain no whitespace, so `NLP` and `nlp` don't
+/// end up as separate tags.
+pub fn validate_tag_format(tags: &[String], errors: &mut ValidationErrors) {
+    if tags
+        .iter()
+        .any(|tag| tag.chars().any(|c| c.is_uppercase() || c.is_whitespace()))
+    {
+        errors.add("tags", "must be lowercase with no spaces");
+    }
+}
+
+fn validate_price(price: f64, errors: &mut ValidationErrors) {
+    if !price.is_finite() || price < 0.0 {
+        errors.add("price", "must not be negative");
+    }
+}
+
+fn validate_license(license: &str, errors: &mut ValidationErrors) {
+    if license.trim().is_empty() {
+        errors.add("license", "must not be empty");
+    } else if license.chars().count() > MAX_LICENSE_LENGTH {
+        errors.add("license", format!("must be at most {} characters", MAX_LICENSE_LENGTH));
+    }
+}
+
+fn validate_performance_metrics(metrics: &PerformanceMetrics, errors: &mut ValidationErrors) {
+    for (field, value) in [("accuracy", metrics.accuracy), ("f1", metrics.f1)] {
+        if value.is_some_and(|v| !(0.0..=1.0).contains(&v)) {
+            errors.add("performance_metrics", format!("{} must be between 0 and 1", field));
+        }
+    }
+    for (field, value) in [("latency_ms", metrics.latency_ms), ("throughput", metrics.throughput)] {
+        if value.is_some_and(|v| !v.is_finite() || v < 0.0) {
+            errors.add("performance_metrics", format!("{} must not be negative", field));
+        }
+    }
+}
+
+impl CreateAIModel {
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+
+        validate_name(&self.name, &mut errors);
+        validate_description(&self.description, &mut errors);
+        validate_version(&self.version, &mut errors);
+        if let Some(url) = &self.repository_url {
+            validate_repository_url(url, &mut errors);
+        }
+        if let Some(metrics) = &self.performance_metrics {
+            validate_performance_metrics(metrics, &mut errors);
+        }
+        if let Some(tags) = &self.tags {
+            validate_tags(tags, &mut errors);
+        }
+        if let Some(price) = self.price {
+            validate_price(price, &mut errors);
+        }
+        if let Some(license) = &self.license {
+            validate_license(license, &mut errors);
+        }
+
+        errors.into_result()
+    }
+}
+
+impl UpdateAIModel {
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+
+        if let Some(name) = &self.name {
+            validate_name(name, &mut errors);
+        }
+        if let Some(description) = &self.description {
+            validate_description(description, &mut errors);
+        }
+        if let Some(version) = &self.version {
+            validate_version(version, &mut errors);
+        }
+        if let Some(url) = self.repository_url.as_set() {
+            validate_repository_url(url, &mut errors);
+        }
+        if let Some(metrics) = self.performance_metrics.as_set() {
+            validate_performance_metrics(metrics, &mut errors);
+        }
+        if let Some(tags) = &self.tags {
+            validate_tags(tags, &mut errors);
+        }
+        if let Some(price) = self.price {
+            validate_price(price, &mut errors);
+        }
+        if let Some(license) = &self.license {
+            validate_license(license, &mut errors);
+        }
+
+        errors.into_result()
+    }
+}
+
+/// Download statistics for a single model. `unique_downloaders` counts
+/// distinct signed-in users; anonymous downloads are reported separately,
+/// leaving out history backfilled from before downloads were recorded.
+#[derive(Debug, Serialize)]
+pub struct ModelSummary {
+    pub id: Uuid,
+    pub download_count: i32,
+    pub unique_downloaders: i64,
+    pub anonymous_downloads: i64,
+}
+
+/// Prices of published models comparable to a given one: same type, and
+/// sharing at least one tag if the model has any. The price fields are
+/// `None` when there's nothing to compare against.
+#[derive(Debug, Serialize)]
+pub struct PriceDistribution {
+    pub model_id: Uuid,
+    pub comparable_models: i64,
+    pub min_price: Option<f64>,
+    pub median_price: Option<f64>,
+    pub max_price: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SimilarPricingQuery {
+    /// Leave free and unpriced models out of the distribution.
+    #[serde(default)]
+    pub exclude_free: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct WithdrawFromSale {
+    /// Archive the model instead of making it free.
+    #[serde(default)]
+    pub archive: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsBucket {
+    #[default]
+    Day,
+    Week,
+}
+
+impl StatsBucket {
+    /// Name as understood by Postgres' `date_trunc`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StatsBucket::Day => "day",
+            StatsBucket::Week => "week",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DownloadStatsQuery {
+    /// Defaults to 30 days before `to`.
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+    /// Defaults to now.
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default)]
+    pub bucket: StatsBucket,
+}
+
+/// Downloads in the bucket starting at `bucket_start`. Buckets without
+/// downloads are included with a count of zero.
+#[derive(Debug, Serialize)]
+pub struct DownloadBucket {
+    pub bucket_start: chrono::DateTime<chrono::Utc>,
+    pub downloads: i64,
+}
+
+pub const MAX_UTM_LENGTH: usize = 100;
+
+/// Campaign attribution accepted on the download endpoints as `utm_*`
+/// query parameters.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UtmParams {
+    pub utm_source: Option<String>,
+    pub utm_medium: Option<String>,
+    pub utm_campaign: Option<String>,
+    pub utm_term: Option<String>,
+    pub utm_content: Option<String>,
+}
+
+impl UtmParams {
+    /// Values may use letters, digits, spaces and `-_.+`, up to
+    /// `MAX_UTM_LENGTH` characters.
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        for (field, value) in self.fields() {
+            let Some(value) = value else { continue };
+            if value.chars().count() > MAX_UTM_LENGTH {
+                errors.add(field, format!("must be at most {} characters", MAX_UTM_LENGTH));
+            }
+            if !value
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_' | '.' | '+'))
+            {
+                errors.add(field, "may only contain letters, digits, spaces and -_.+");
+            }
+        }
+        errors.into_result()
+    }
+
+    /// Trimmed and lowercased so `Twitter` and `twitter ` count together;
+    /// blank values are dropped.
+    pub fn normalized(self) -> Self {
+        let clean = |value: Option<String>| {
+            value
+                .map(|value| value.trim().to_lowercase())
+                .filter(|value| !value.is_empty())
+        };
+        Self {
+            utm_source: clean(self.utm_source),
+            utm_medium: clean(self.utm_medium),
+            utm_campaign: clean(self.utm_campaign),
+            utm_term: clean(self.utm_term),
+            utm_content: clean(self.utm_content),
+        }
+    }
+
+    fn fields(&self) -> [(&'static str, &Option<String>); 5] {
+        [
+            ("utm_source", &self.utm_source),
+            ("utm_medium", &self.utm_medium),
+            ("utm_campaign", &self.utm_campaign),
+            ("utm_term", &self.utm_term),
+            ("utm_content", &self.utm_content),
+        ]
+    }
+}
+
+/// Downloads attributed to one source and campaign. Unattributed downloads
+/// have both unset.
+#[derive(Debug, Serialize)]
+pub struct CampaignDownloads {
+    pub utm_source: Option<String>,
+    pub utm_campaign: Option<String>,
+    pub downloads: i64,
+}
+
+/// Who is downloading a model, for its owner. `unique_users` counts
+/// signed-in downloaders; `top_tier` is the tier most of them are on now
+/// (users without an active subscription count as Free), and is unset if
+/// the model has no signed-in downloads.
+#[derive(Debug, Serialize)]
+pub struct ModelAnalytics {
+    pub model_id: Uuid,
+    pub total_downloads: i64,
+    pub unique_users: i64,
+    pub downloads_last_7_days: i64,
+    pub downloads_last_30_days: i64,
+    pub top_tier: Option<SubscriptionTier>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CampaignStatsQuery {
+    /// Defaults to 30 days before `to`.
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+    /// Defaults to now.
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AddModelDependency {
+    pub dependency_id: Uuid,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelDependency {
+    pub model_id: Uuid,
+    pub dependency_id: Uuid,
+    pub compatibility: LicenseCompatibility,
+}
+
+/// A live model that another model declares as a dependency.
+#[derive(Debug, Serialize)]
+pub struct DependencySummary {
+    pub id: Uuid,
+    pub name: String,
+    pub version: String,
+    pub license: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FlagStaleRequest {
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StaleReportSummary {
+    pub model_id: Uuid,
+    pub is_stale: bool,
+    pub reports: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::new_model;
+    use axum::{body::Body, extract::FromRequest, http::Request, http::StatusCode, Json};
+
+    #[tokio::test]
+    async fn an_unknown_model_type_is_rejected_with_the_valid_ones() {
+        let mut body = serde_json::to_value(new_model("Typo")).unwrap();
+        body["model_type"] = "classifcation".into();
+        let request = Request::post("/api/models")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        let rejection = Json::<CreateAIModel>::from_request(request, &()).await.unwrap_err();
+
+        assert_eq!(rejection.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let message = rejection.body_text();
+        assert!(message.contains("unknown variant `classifcation`"), "{}", message);
+        assert!(message.contains("`classification`"), "{}", message);
+    }
+
+    fn rejected_fields(model: CreateAIModel) -> String {
+        model.validate().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn create_rejects_each_invalid_field() {
+        assert!(new_model("Fine").validate().is_ok());
+
+        let cases = [
+            ("name", CreateAIModel { name: " ".into(), ..new_model("") }),
+            ("name", new_model(&"n".repeat(MAX_NAME_LENGTH + 1))),
+            ("version", CreateAIModel { version: "".into(), ..new_model("Empty version") }),
+            ("version", CreateAIModel { version: "latest".into(), ..new_model("Bad version") }),
+            ("price", CreateAIModel { price: Some(-1.0), ..new_model("Negative") }),
+            (
+                "repository_url",
+                CreateAIModel {
+                    repository_url: Some("ftp://example.com/model".into()),
+                    ..new_model("Not http")
+                },
+            ),
+            (
+                "description",
+                CreateAIModel {
+                    description: "d".repeat(MAX_DESCRIPTION_LENGTH + 1),
+                    ..new_model("Wordy")
+                },
+            ),
+        ];
+        for (field, model) in cases {
+            let message = rejected_fields(model);
+            assert!(message.starts_with(&format!("{}: ", field)), "{}", message);
+        }
+    }
+
+    #[test]
+    fn update_checks_only_the_fields_it_changes() {
+        assert!(UpdateAIModel::default().validate().is_ok());
+
+        let update = UpdateAIModel {
+            name: Some("".into()),
+            price: Some(-5.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            update.validate().unwrap_err().to_string(),
+            "name: must not be empty; price: must not be negative"
+        );
+    }
+
+    #[test]
+    fn partial_metrics_round_trip_with_their_extra_keys() {
+        let stored = serde_json::json!({ "accuracy": 0.91, "latency_ms": 12.5, "auc": 0.88 });
+
+        let metrics: PerformanceMetrics = serde_json::from_value(stored.clone()).unwrap();
+
+        assert_eq!(metrics.accuracy, Some(0.91));
+        assert_eq!(metrics.latency_ms, Some(12.5));
+        assert_eq!((metrics.f1, metrics.throughput), (None, None));
+        assert_eq!(metrics.extra.get("auc"), Some(&serde_json::json!(0.88)));
+        assert_eq!(serde_json::to_value(&metrics).unwrap(), stored);
+    }
+}
@@ -0,0 +1,124 @@
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Prefix on every key, so leaked keys are easy to recognise in scans.
+const KEY_PREFIX: &str = "mk_";
+
+/// An API key as listed to its owner. The key itself is never stored.
+#[derive(Debug, Serialize)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub name: String,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Returned from creation only; this is the one time `key` is visible.
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub key: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKey {
+    pub name: String,
+}
+
+pub fn hash_api_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+fn generate_api_key() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!(
+        "{}{}",
+        KEY_PREFIX,
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+    )
+}
+
+impl ApiKey {
+    pub async fn create(pool: &PgPool, user_id: Uuid, name: &str) -> Result<CreatedApiKey, sqlx::Error> {
+        let key = generate_api_key();
+
+        let api_key = sqlx::query_as!(
+            ApiKey,
+            r#"
+            INSERT INTO api_keys (user_id, key_hash, name)
+            VALUES ($1, $2, $3)
+            RETURNING id, name, last_used_at, revoked, created_at
+            "#,
+            user_id,
+            hash_api_key(&key),
+            name
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(CreatedApiKey { api_key, key })
+    }
+
+    pub async fn list_for_user(pool: &PgPool, user_id: Uuid) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            ApiKey,
+            r#"
+            SELECT id, name, last_used_at, revoked, created_at
+            FROM api_keys
+            WHERE user_id = $1
+            ORDER BY created_at DESC
+            "#,
+            user_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Returns `None` if the key doesn't exist or belongs to someone else.
+    pub async fn revoke(pool: &PgPool, user_id: Uuid, id: Uuid) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            ApiKey,
+            r#"
+            UPDATE api_keys
+            SET revoked = true
+            WHERE id = $1 AND user_id = $2
+            RETURNING id, name, last_used_at, revoked, created_at
+            "#,
+            id,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Resolve a presented key to its owner and whether they're an admin,
+    /// recording the use. Revoked and unknown keys resolve to `None`.
+    pub async fn authenticate(
+        pool: &PgPool,
+        key: &str,
+    ) -> Result<Option<(Uuid, bool)>, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            UPDATE api_keys k
+            SET last_used_at = NOW()
+            FROM users u
+            WHERE k.key_hash = $1 AND k.revoked = false
+            AND u.id = k.user_id AND u.deleted_at IS NULL
+            RETURNING k.user_id, u.role = 'admin' AS "is_admin!"
+            "#,
+            hash_api_key(key)
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(row.map(|row| (row.user_id, row.is_admin)))
+    }
+}
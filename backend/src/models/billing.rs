@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::ValidationErrors;
+
+/// Where a user is billed: an ISO 3166-1 alpha-2 country and, where tax
+/// varies within it, the subdivision code (`CA` for California).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BillingLocation {
+    pub country: String,
+    pub region: Option<String>,
+}
+
+impl BillingLocation {
+    /// Upper-cases the codes and drops a blank region.
+    pub fn normalized(self) -> Self {
+        Self {
+            country: self.country.trim().to_ascii_uppercase(),
+            region: self
+                .region
+                .map(|region| region.trim().to_ascii_uppercase())
+                .filter(|region| !region.is_empty()),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        if self.country.len() != 2 || !self.country.chars().all(|c| c.is_ascii_alphabetic()) {
+            errors.add("country", "must be a two-letter country code");
+        }
+        if self.region.as_ref().is_some_and(|region| {
+            region.len() > 10 || !region.chars().all(|c| c.is_ascii_alphanumeric())
+        }) {
+            errors.add("region", "must be a subdivision code of up to 10 letters or digits");
+        }
+        errors.into_result()
+    }
+
+    pub async fn get_for_user(pool: &PgPool, user_id: Uuid) -> Result<Option<Self>, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"SELECT billing_country, billing_region FROM users WHERE id = $1"#,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(row.and_then(|row| {
+            Some(Self {
+                country: row.billing_country?,
+                region: row.billing_region,
+            })
+        }))
+    }
+
+    pub async fn set_for_user(&self, pool: &PgPool, user_id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE users
+            SET billing_country = $2, billing_region = $3, updated_at = NOW()
+            WHERE id = $1
+            "#,
+            user_id,
+            self.country,
+            self.region
+        )
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
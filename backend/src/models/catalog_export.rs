@@ -0,0 +1,97 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{ModelStatus, ModelType, SubscriptionTier};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+/// One model as it appears in a catalog export.
+#[derive(Debug, Serialize)]
+pub struct CatalogRow {
+    pub id: Uuid,
+    pub name: String,
+    pub description: String,
+    pub model_type: ModelType,
+    pub framework: String,
+    pub version: String,
+    pub status: ModelStatus,
+    pub license: Option<String>,
+    pub price: Option<f64>,
+    pub required_tier: SubscriptionTier,
+    pub download_count: i32,
+    pub is_public: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Column order of the CSV export. Append new columns at the end so
+/// spreadsheets built on earlier exports keep working.
+pub const CATALOG_CSV_HEADER: &str = "id,name,description,model_type,framework,version,status,\
+license,price,required_tier,download_count,is_public,created_at,updated_at\r\n";
+
+/// Quote a field if it contains a delimiter, quote, or line break, doubling
+/// any quotes inside (RFC 4180).
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn tier_name(tier: SubscriptionTier) -> &'static str {
+    match tier {
+        SubscriptionTier::Free => "free",
+        SubscriptionTier::Pro => "pro",
+        SubscriptionTier::Enterprise => "enterprise",
+    }
+}
+
+fn model_type_name(model_type: ModelType) -> &'static str {
+    match model_type {
+        ModelType::Classification => "classification",
+        ModelType::Regression => "regression",
+        ModelType::Llm => "llm",
+        ModelType::Embedding => "embedding",
+        ModelType::Vision => "vision",
+        ModelType::Other => "other",
+    }
+}
+
+impl CatalogRow {
+    /// The row as a CRLF-terminated CSV record in `CATALOG_CSV_HEADER` order.
+    pub fn to_csv_record(&self) -> String {
+        let fields = [
+            self.id.to_string(),
+            csv_field(&self.name),
+            csv_field(&self.description),
+            model_type_name(self.model_type).to_string(),
+            csv_field(&self.framework),
+            csv_field(&self.version),
+            self.status.as_str().to_string(),
+            self.license.as_deref().map(csv_field).unwrap_or_default(),
+            self.price.map(|price| format!("{:.2}", price)).unwrap_or_default(),
+            tier_name(self.required_tier).to_string(),
+            self.download_count.to_string(),
+            self.is_public.to_string(),
+            self.created_at.to_rfc3339(),
+            self.updated_at.to_rfc3339(),
+        ];
+        let mut record = fields.join(",");
+        record.push_str("\r\n");
+        record
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{AIModel, ModelType, PerformanceMetrics};
+
+/// Most models `POST /api/models/compare` takes at once.
+pub const MAX_COMPARED_MODELS: usize = 5;
+
+#[derive(Debug, Deserialize)]
+pub struct CompareModels {
+    pub ids: Vec<Uuid>,
+}
+
+/// The fields buyers weigh when choosing between models.
+#[derive(Debug, Serialize)]
+pub struct ComparedModel {
+    pub id: Uuid,
+    pub name: String,
+    pub model_type: ModelType,
+    pub framework: String,
+    pub version: String,
+    pub license: Option<String>,
+    pub avg_rating: Option<f64>,
+    pub download_count: i32,
+    pub tags: Vec<String>,
+    pub performance_metrics: Option<PerformanceMetrics>,
+}
+
+impl ComparedModel {
+    pub fn new(model: AIModel) -> Self {
+        Self {
+            id: model.id,
+            name: model.name,
+            model_type: model.model_type,
+            framework: model.framework,
+            version: model.version,
+            license: model.license,
+            avg_rating: model.avg_rating,
+            download_count: model.download_count,
+            tags: model.tags,
+            performance_metrics: model.performance_metrics.map(|metrics| metrics.0),
+        }
+    }
+}
+
+/// How the models that report one metric stack up. `normalized` scales
+/// each value to 0..=1 with 1 the best, and `winner` is `None` when they
+/// all tie.
+#[derive(Debug, Serialize)]
+pub struct MetricComparison {
+    pub lower_is_better: bool,
+    pub min: f64,
+    pub max: f64,
+    pub winner: Option<Uuid>,
+    pub normalized: BTreeMap<Uuid, f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelComparison {
+    pub models: Vec<ComparedModel>,
+    pub metrics: BTreeMap<String, MetricComparison>,
+    /// Requested ids that don't exist or that the caller can't see.
+    pub not_found: Vec<Uuid>,
+}
+
+impl ModelComparison {
+    pub fn new(models: Vec<ComparedModel>, not_found: Vec<Uuid>) -> Self {
+        let mut values: BTreeMap<String, Vec<(Uuid, f64)>> = BTreeMap::new();
+        for model in &models {
+            let Some(metrics) = &model.performance_metrics else {
+                continue;
+            };
+            for (name, value) in numeric_metrics(metrics) {
+                values.entry(name).or_default().push((model.id, value));
+            }
+        }
+
+        let metrics = values
+            .into_iter()
+            .map(|(name, values)| {
+                let comparison = compare_metric(&name, &values);
+                (name, comparison)
+            })
+            .collect();
+
+        Self {
+            models,
+            metrics,
+            not_found,
+        }
+    }
+}
+
+/// Latencies and losses improve as they shrink; everything else we track
+/// (accuracy, F1, throughput) as it grows.
+fn lower_is_better(metric: &str) -> bool {
+    metric.ends_with("_ms") || metric.contains("latency") || metric.contains("loss")
+}
+
+/// The named metrics plus any numeric ones kept in `extra`.
+fn numeric_metrics(metrics: &PerformanceMetrics) -> Vec<(String, f64)> {
+    let named = [
+        ("accuracy", metrics.accuracy),
+        ("f1", metrics.f1),
+        ("latency_ms", metrics.latency_ms),
+        ("throughput", metrics.throughput),
+    ];
+    named
+        .into_iter()
+        .filter_map(|(name, value)| Some((name.to_owned(), value?)))
+        .chain(
+            metrics
+                .extra
+                .iter()
+                .filter_map(|(name, value)| Some((name.clone(), value.as_f64()?))),
+        )
+        .filter(|(_, value)| value.is_finite())
+        .collect()
+}
+
+fn compare_metric(name: &str, values: &[(Uuid, f64)]) -> MetricComparison {
+    let lower_is_better = lower_is_better(name);
+    let min = values.iter().map(|(_, v)| *v).fold(f64::INFINITY, f64::min);
+    let max = values.iter().map(|(_, v)| *v).fold(f64::NEG_INFINITY, f64::max);
+    let best = if lower_is_better { min } else { max };
+
+    let range = max - min;
+    let normalized = values
+        .iter()
+        .map(|(id, value)| {
+            let score = if range > 0.0 {
+                (range - (best - value).abs()) / range
+            } else {
+                1.0
+            };
+            (*id, score)
+        })
+        .collect();
+
+    MetricComparison {
+        lower_is_better,
+        min,
+        max,
+        winner: (range > 0.0)
+            .then(|| values.iter().find(|(_, value)| *value == best).map(|(id, _)| *id))
+            .flatten(),
+        normalized,
+    }
+}
@@ -0,0 +1,219 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::Money;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Coupon {
+    pub id: Uuid,
+    pub code: String,
+    pub percent_off: Option<f64>,
+    /// In `currency`'s minor unit.
+    pub amount_off: Option<i64>,
+    pub currency: Option<String>,
+    pub valid_until: Option<DateTime<Utc>>,
+    pub max_redemptions: Option<i32>,
+    pub times_redeemed: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Result of trying to redeem a code.
+#[derive(Debug)]
+pub enum Redemption {
+    Redeemed(Coupon),
+    NotFound,
+    Expired,
+    Exhausted,
+}
+
+impl Coupon {
+    /// Discount this coupon gives on `amount`, or `None` if it's a fixed
+    /// amount in a different currency.
+    pub fn discount_on(&self, amount: &Money) -> Option<Money> {
+        let discount = match (self.percent_off, self.amount_off) {
+            (Some(percent), _) => amount.scale(percent / 100.0),
+            (None, Some(off)) => {
+                if !self.currency.as_deref()?.eq_ignore_ascii_case(amount.currency()) {
+                    return None;
+                }
+                Money::from_minor(off, amount.currency())
+            }
+            (None, None) => Money::zero(amount.currency()),
+        };
+
+        Some(Money::from_minor(
+            discount.minor_units().min(amount.minor_units()),
+            amount.currency(),
+        ))
+    }
+
+    /// Claim one redemption of `code`. Expiry and the redemption cap are
+    /// checked in the same statement that increments the counter, so
+    /// concurrent checkouts can't exceed `max_redemptions`.
+    pub async fn validate_and_redeem(pool: &PgPool, code: &str) -> Result<Redemption, sqlx::Error> {
+        let redeemed = sqlx::query_as!(
+            Coupon,
+            r#"
+            UPDATE coupons
+            SET times_redeemed = times_redeemed + 1
+            WHERE UPPER(code) = UPPER($1)
+            AND (valid_until IS NULL OR valid_until > NOW())
+            AND (max_redemptions IS NULL OR times_redeemed < max_redemptions)
+            RETURNING id, code, percent_off::float8, amount_off, currency,
+                      valid_until, max_redemptions, times_redeemed, created_at
+            "#,
+            code
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        if let Some(coupon) = redeemed {
+            return Ok(Redemption::Redeemed(coupon));
+        }
+
+        // Work out why it was rejected.
+        let coupon = sqlx::query!(
+            r#"
+            SELECT valid_until, max_redemptions, times_redeemed
+            FROM coupons
+            WHERE UPPER(code) = UPPER($1)
+            "#,
+            code
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(match coupon {
+            None => Redemption::NotFound,
+            Some(c) if c.valid_until.map(|t| t <= Utc::now()).unwrap_or(false) => {
+                Redemption::Expired
+            }
+            Some(_) => Redemption::Exhausted,
+        })
+    }
+
+    /// Give back a redemption claimed for a checkout that didn't go ahead.
+    pub async fn release(pool: &PgPool, coupon_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE coupons
+            SET times_redeemed = times_redeemed - 1
+            WHERE id = $1 AND times_redeemed > 0
+            "#,
+            coupon_id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn record_redemption(
+        pool: &PgPool,
+        coupon_id: Uuid,
+        user_id: Uuid,
+        payment_intent_id: Uuid,
+        amount_off: &Money,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO coupon_redemptions (coupon_id, user_id, payment_intent_id, amount_off)
+            VALUES ($1, $2, $3, $4)
+            "#,
+            coupon_id,
+            user_id,
+            payment_intent_id,
+            amount_off.minor_units()
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coupon(percent_off: Option<f64>, amount_off: Option<i64>, currency: Option<&str>) -> Coupon {
+        Coupon {
+            id: Uuid::new_v4(),
+            code: "SAVE".into(),
+            percent_off,
+            amount_off,
+            currency: currency.map(str::to_string),
+            valid_until: None,
+            max_redemptions: None,
+            times_redeemed: 0,
+            created_at: Utc::now(),
+        }
+    }
+
+    async fn insert_coupon(pool: &PgPool, code: &str, max_redemptions: Option<i32>) {
+        sqlx::query!(
+            "INSERT INTO coupons (code, percent_off, max_redemptions) VALUES ($1, 10, $2)",
+            code,
+            max_redemptions
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn percent_off_rounds_to_the_minor_unit() {
+        let discount = coupon(Some(15.0), None, None)
+            .discount_on(&Money::from_minor(2999, "USD"))
+            .unwrap();
+        assert_eq!(discount, Money::from_minor(450, "USD"));
+    }
+
+    #[test]
+    fn fixed_discount_is_capped_at_the_amount() {
+        let fixed = coupon(None, Some(5000), Some("usd"));
+        assert_eq!(
+            fixed.discount_on(&Money::from_minor(2999, "USD")),
+            Some(Money::from_minor(2999, "USD"))
+        );
+        assert_eq!(fixed.discount_on(&Money::from_minor(2999, "EUR")), None);
+    }
+
+    #[sqlx::test]
+    async fn redemptions_stop_at_the_cap_and_release_gives_one_back(pool: PgPool) {
+        insert_coupon(&pool, "ONCE", Some(1)).await;
+
+        let Redemption::Redeemed(coupon) = Coupon::validate_and_redeem(&pool, "once").await.unwrap()
+        else {
+            panic!("first redemption should succeed");
+        };
+        assert!(matches!(
+            Coupon::validate_and_redeem(&pool, "ONCE").await.unwrap(),
+            Redemption::Exhausted
+        ));
+
+        Coupon::release(&pool, coupon.id).await.unwrap();
+        assert!(matches!(
+            Coupon::validate_and_redeem(&pool, "ONCE").await.unwrap(),
+            Redemption::Redeemed(_)
+        ));
+    }
+
+    #[sqlx::test]
+    async fn unknown_and_expired_codes_are_told_apart(pool: PgPool) {
+        insert_coupon(&pool, "OLD", None).await;
+        sqlx::query!("UPDATE coupons SET valid_until = NOW() - INTERVAL '1 day' WHERE code = 'OLD'")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            Coupon::validate_and_redeem(&pool, "NOPE").await.unwrap(),
+            Redemption::NotFound
+        ));
+        assert!(matches!(
+            Coupon::validate_and_redeem(&pool, "OLD").await.unwrap(),
+            Redemption::Expired
+        ));
+    }
+}
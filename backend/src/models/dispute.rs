@@ -0,0 +1,115 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// A chargeback raised against one of our payments.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaymentDispute {
+    pub id: Uuid,
+    pub stripe_dispute_id: String,
+    pub stripe_charge_id: String,
+    pub payment_intent_id: Option<Uuid>,
+    pub user_id: Option<Uuid>,
+    /// Minor units, as reported by Stripe.
+    pub amount: i64,
+    pub currency: String,
+    pub reason: String,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    pub closed_at: Option<DateTime<Utc>>,
+}
+
+/// Whether a user's paid access is withheld while a dispute is open
+/// (`DISPUTE_SUSPENDS_ACCESS`, default true).
+pub fn dispute_suspends_access() -> bool {
+    std::env::var("DISPUTE_SUSPENDS_ACCESS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(true)
+}
+
+impl PaymentDispute {
+    /// Record a new dispute. Stripe may deliver the event more than once, so
+    /// a repeat only refreshes the status.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn record(
+        pool: &PgPool,
+        stripe_dispute_id: &str,
+        stripe_charge_id: &str,
+        payment_intent_id: Option<Uuid>,
+        user_id: Option<Uuid>,
+        amount: i64,
+        currency: &str,
+        reason: &str,
+        status: &str,
+    ) -> Result<PaymentDispute, sqlx::Error> {
+        sqlx::query_as!(
+            PaymentDispute,
+            r#"
+            INSERT INTO payment_disputes (
+                stripe_dispute_id, stripe_charge_id, payment_intent_id, user_id,
+                amount, currency, reason, status
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (stripe_dispute_id) DO UPDATE
+            SET status = EXCLUDED.status
+            RETURNING id, stripe_dispute_id, stripe_charge_id, payment_intent_id, user_id,
+                      amount, currency, reason, status, created_at, closed_at
+            "#,
+            stripe_dispute_id,
+            stripe_charge_id,
+            payment_intent_id,
+            user_id,
+            amount,
+            currency,
+            reason,
+            status,
+        )
+        .fetch_one(pool)
+        .await
+    }
+
+    pub async fn list_open_for_user(
+        pool: &PgPool,
+        user_id: Uuid,
+    ) -> Result<Vec<PaymentDispute>, sqlx::Error> {
+        sqlx::query_as!(
+            PaymentDispute,
+            r#"
+            SELECT id, stripe_dispute_id, stripe_charge_id, payment_intent_id, user_id,
+                   amount, currency, reason, status, created_at, closed_at
+            FROM payment_disputes
+            WHERE user_id = $1 AND closed_at IS NULL
+            ORDER BY created_at DESC
+            "#,
+            user_id,
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Record the final status of a dispute. Returns `None` for disputes we
+    /// never saw opened.
+    pub async fn close(
+        pool: &PgPool,
+        stripe_dispute_id: &str,
+        status: &str,
+    ) -> Result<Option<PaymentDispute>, sqlx::Error> {
+        sqlx::query_as!(
+            PaymentDispute,
+            r#"
+            UPDATE payment_disputes
+            SET status = $2,
+                closed_at = COALESCE(closed_at, NOW())
+            WHERE stripe_dispute_id = $1
+            RETURNING id, stripe_dispute_id, stripe_charge_id, payment_intent_id, user_id,
+                      amount, currency, reason, status, created_at, closed_at
+            "#,
+            stripe_dispute_id,
+            status,
+        )
+        .fetch_optional(pool)
+        .await
+    }
+}
@@ -0,0 +1,136 @@
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Outcome of [`IdempotencyKey::claim`].
+#[derive(Debug)]
+pub enum IdempotencyClaim {
+    /// The key is ours; run the handler and record its response.
+    Acquired,
+    /// An earlier request holds the key.
+    Existing(IdempotencyKey),
+}
+
+/// A stored idempotent request. `response_status` is `None` while the
+/// first request with the key is still in flight.
+#[derive(Debug)]
+pub struct IdempotencyKey {
+    pub request_hash: String,
+    pub response_status: Option<i16>,
+    pub response_body: Option<Vec<u8>>,
+    pub response_content_type: Option<String>,
+}
+
+/// How long a key's response is replayed, `IDEMPOTENCY_KEY_TTL_HOURS`
+/// (default 24).
+pub fn idempotency_key_ttl() -> chrono::Duration {
+    let hours = std::env::var("IDEMPOTENCY_KEY_TTL_HOURS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(24);
+    chrono::Duration::hours(hours)
+}
+
+impl IdempotencyKey {
+    /// Claim `key` for this request, or return the earlier request holding
+    /// it. Expired keys for the user are cleared first, so they can be
+    /// reused.
+    pub async fn claim(
+        pool: &PgPool,
+        user_id: Uuid,
+        key: &str,
+        route: &str,
+        request_hash: &str,
+    ) -> Result<IdempotencyClaim, sqlx::Error> {
+        sqlx::query!(
+            "DELETE FROM idempotency_keys WHERE user_id = $1 AND expires_at <= NOW()",
+            user_id
+        )
+        .execute(pool)
+        .await?;
+
+        let claimed = sqlx::query!(
+            r#"
+            INSERT INTO idempotency_keys (user_id, key, route, request_hash, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (user_id, key, route) DO NOTHING
+            "#,
+            user_id,
+            key,
+            route,
+            request_hash,
+            Utc::now() + idempotency_key_ttl(),
+        )
+        .execute(pool)
+        .await?
+        .rows_affected()
+            > 0;
+        if claimed {
+            return Ok(IdempotencyClaim::Acquired);
+        }
+
+        let existing = sqlx::query_as!(
+            IdempotencyKey,
+            r#"
+            SELECT request_hash, response_status, response_body,
+                   response_content_type
+            FROM idempotency_keys
+            WHERE user_id = $1 AND key = $2 AND route = $3
+            "#,
+            user_id,
+            key,
+            route,
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        // Released between our insert and select: nobody holds it now.
+        Ok(existing.map_or(IdempotencyClaim::Acquired, IdempotencyClaim::Existing))
+    }
+
+    pub async fn complete(
+        pool: &PgPool,
+        user_id: Uuid,
+        key: &str,
+        route: &str,
+        status: u16,
+        body: &[u8],
+        content_type: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE idempotency_keys
+            SET response_status = $4, response_body = $5, response_content_type = $6
+            WHERE user_id = $1 AND key = $2 AND route = $3
+            "#,
+            user_id,
+            key,
+            route,
+            status as i16,
+            body,
+            content_type,
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Give up a claim so the request can be retried with the same key,
+    /// e.g. after a server error.
+    pub async fn release(
+        pool: &PgPool,
+        user_id: Uuid,
+        key: &str,
+        route: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "DELETE FROM idempotency_keys WHERE user_id = $1 AND key = $2 AND route = $3",
+            user_id,
+            key,
+            route,
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
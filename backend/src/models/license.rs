@@ -0,0 +1,102 @@
+use serde::Serialize;
+
+/// Broad license families, ordered by how much they constrain a downstream model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LicenseFamily {
+    Permissive,
+    WeakCopyleft,
+    StrongCopyleft,
+    NetworkCopyleft,
+    NonCommercial,
+    Proprietary,
+    Unknown,
+}
+
+impl LicenseFamily {
+    /// Classify an SPDX-style license identifier.
+    pub fn classify(license: &str) -> Self {
+        let id = license.trim().to_ascii_lowercase();
+
+        match id.as_str() {
+            "mit" | "apache-2.0" | "bsd-2-clause" | "bsd-3-clause" | "isc" | "unlicense"
+            | "cc0-1.0" | "cc-by-4.0" | "openrail" | "bigscience-openrail-m" => Self::Permissive,
+            "lgpl-2.1" | "lgpl-3.0" | "mpl-2.0" | "epl-2.0" | "cc-by-sa-4.0" => Self::WeakCopyleft,
+            "gpl-2.0" | "gpl-3.0" => Self::StrongCopyleft,
+            "agpl-3.0" => Self::NetworkCopyleft,
+            "proprietary" | "commercial" => Self::Proprietary,
+            _ if id.starts_with("cc-by-nc") => Self::NonCommercial,
+            _ if id.starts_with("gpl-") => Self::StrongCopyleft,
+            _ if id.starts_with("lgpl-") => Self::WeakCopyleft,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", content = "reason", rename_all = "snake_case")]
+pub enum LicenseCompatibility {
+    Compatible,
+    Incompatible(String),
+    Unknown(String),
+}
+
+/// Check whether a model licensed under `model_license` may depend on a model
+/// licensed under `dependency_license`.
+pub fn check_license_compatibility(
+    model_license: Option<&str>,
+    dependency_license: Option<&str>,
+) -> LicenseCompatibility {
+    let (model_license, dependency_license) = match (model_license, dependency_license) {
+        (Some(m), Some(d)) => (m, d),
+        _ => {
+            return LicenseCompatibility::Unknown(
+                "license not declared on one or both models".to_string(),
+            )
+        }
+    };
+
+    let model = LicenseFamily::classify(model_license);
+    let dependency = LicenseFamily::classify(dependency_license);
+
+    use LicenseFamily::*;
+    let compatible = match (dependency, model) {
+        (Unknown, _) | (_, Unknown) => {
+            return LicenseCompatibility::Unknown(format!(
+                "cannot determine compatibility of {} with {}",
+                dependency_license, model_license
+            ))
+        }
+        (Permissive, _) | (WeakCopyleft, _) => true,
+        (StrongCopyleft, StrongCopyleft | NetworkCopyleft) => true,
+        (NetworkCopyleft, NetworkCopyleft) => true,
+        (NonCommercial, NonCommercial) => true,
+        (Proprietary, Proprietary) => true,
+        _ => false,
+    };
+
+    if compatible {
+        LicenseCompatibility::Compatible
+    } else {
+        LicenseCompatibility::Incompatible(format!(
+            "a {} dependency cannot be used by a {} model",
+            dependency_license, model_license
+        ))
+    }
+}
+
+/// Whether an incompatible dependency is reported as a warning or rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LicenseCheckMode {
+    Warn,
+    Error,
+}
+
+impl LicenseCheckMode {
+    pub fn from_env() -> Self {
+        match std::env::var("LICENSE_CHECK_MODE").as_deref() {
+            Ok("error") => Self::Error,
+            _ => Self::Warn,
+        }
+    }
+}
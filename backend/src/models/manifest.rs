@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+use sqlx::types::JsonValue;
+
+use super::{CreateAIModel, ModelType, PerformanceMetrics};
+
+/// The only manifest schema version we understand.
+pub const MANIFEST_SCHEMA_VERSION: u32 = 1;
+
+/// A model manifest served by a third party, imported with
+/// `POST /api/models/import/url`:
+///
+/// ```json
+/// {
+///   "schema_version": 1,
+///   "name": "sentiment-small",
+///   "description": "Binary sentiment classifier",
+///   "model_type": "classification",
+///   "framework": "pytorch",
+///   "version": "1.2.0",
+///   "repository_url": "https://example.com/sentiment-small",
+///   "tags": ["nlp", "sentiment"],
+///   "metadata": {},
+///   "performance_metrics": { "accuracy": 0.93 }
+/// }
+/// ```
+///
+/// Unknown fields are rejected so typos don't silently drop data.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelManifest {
+    pub schema_version: u32,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub model_type: ModelType,
+    pub framework: String,
+    pub version: String,
+    pub repository_url: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub metadata: Option<JsonValue>,
+    pub performance_metrics: Option<PerformanceMetrics>,
+}
+
+impl ModelManifest {
+    /// Imported models start private; the owner publishes them through
+    /// the usual review flow.
+    pub fn into_create(self) -> CreateAIModel {
+        CreateAIModel {
+            name: self.name,
+            description: self.description,
+            model_type: self.model_type,
+            framework: self.framework,
+            version: self.version,
+            metadata: self.metadata,
+            performance_metrics: self.performance_metrics,
+            repository_url: self.repository_url,
+            tags: self.tags,
+            is_public: false,
+            price: None,
+            required_tier: None,
+            license: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportFromUrl {
+    pub url: String,
+}
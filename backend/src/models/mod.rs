@@ -1,26 +1,208 @@
+mod account;
+mod addon;
+mod api_key;
+mod billing;
 mod ai_model;
-mod payment;
-mod subscription;
+mod catalog_export;
+mod comparison;
+mod coupon;
+mod dispute;
+mod idempotency;
+mod license;
+mod manifest;
+mod model_version;
+mod money;
+mod notification;
+mod patch;
+pub mod payment;
+mod review;
+mod seat;
+pub mod subscription;
+mod usage_example;
+mod validation;
+mod view;
 
+pub use account::*;
+pub use addon::*;
+pub use api_key::*;
+pub use billing::*;
 pub use ai_model::*;
+pub use catalog_export::*;
+pub use comparison::*;
+pub use coupon::*;
+pub use dispute::*;
+pub use idempotency::*;
+pub use license::*;
+pub use manifest::*;
+pub use model_version::*;
+pub use money::*;
+pub use notification::*;
+pub use patch::*;
 pub use payment::*;
+pub use review::*;
+pub use seat::*;
 pub use subscription::*;
+pub use usage_example::*;
+pub use validation::*;
+pub use view::*;
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Largest page for callers whose plan doesn't set `max_page_size`.
+pub const FALLBACK_MAX_PAGE_SIZE: i64 = 50;
+
+/// Largest page anyone gets, whatever their plan says.
+pub const MAX_PAGE_SIZE: i64 = 100;
+
+/// Page size used when a listing request doesn't give one
+/// (`DEFAULT_PAGE_SIZE`, default 10).
+pub fn default_page_size() -> i64 {
+    std::env::var("DEFAULT_PAGE_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|size| *size > 0)
+        .unwrap_or(10)
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ListQueryParams {
-    pub model_type: Option<String>,
+    pub model_type: Option<ModelType>,
     pub min_accuracy: Option<f64>,
-    pub required_tier: Option<String>,
+    pub required_tier: Option<SubscriptionTier>,
+    /// Models without a price count as costing 0.
+    pub min_price: Option<f64>,
+    pub max_price: Option<f64>,
+    /// Only models that are free or come with the subscription tier.
+    pub free_only: Option<bool>,
+    /// Sync mode: only models changed since this time, oldest change first,
+    /// including deleted ones.
+    pub updated_after: Option<chrono::DateTime<chrono::Utc>>,
+    /// Only admins may filter by status; everyone else sees public published
+    /// models plus their own.
+    pub status: Option<ModelStatus>,
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+    /// Name of one of the caller's saved views to use as defaults.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub view: Option<String>,
+}
+
+impl Validate for ListQueryParams {
+    /// Reject bad paging, and price bounds that are negative or the wrong
+    /// way round.
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        add_paging_errors(self.page, self.per_page, &mut errors);
+        if self.min_price.is_some_and(|price| price < 0.0) {
+            errors.add("min_price", "must not be negative");
+        }
+        if self.max_price.is_some_and(|price| price < 0.0) {
+            errors.add("max_price", "must not be negative");
+        }
+        if let (Some(min), Some(max)) = (self.min_price, self.max_price) {
+            if min > max {
+                errors.add("min_price", "must not exceed max_price");
+            }
+        }
+        errors.into_result()
+    }
+}
+
+impl ListQueryParams {
+    /// Fill any parameters not given explicitly from `preset`.
+    pub fn with_defaults_from(self, preset: ListQueryParams) -> Self {
+        Self {
+            model_type: self.model_type.or(preset.model_type),
+            min_accuracy: self.min_accuracy.or(preset.min_accuracy),
+            required_tier: self.required_tier.or(preset.required_tier),
+            min_price: self.min_price.or(preset.min_price),
+            max_price: self.max_price.or(preset.max_price),
+            free_only: self.free_only.or(preset.free_only),
+            updated_after: self.updated_after.or(preset.updated_after),
+            status: self.status.or(preset.status),
+            page: self.page.or(preset.page),
+            per_page: self.per_page.or(preset.per_page),
+            view: self.view,
+        }
+    }
+
+    /// Resolve `per_page` to the default if missing and cap it at `max`
+    /// (itself capped at `MAX_PAGE_SIZE`).
+    pub fn clamp_per_page(self, max: i64) -> Self {
+        let per_page = self.per_page.unwrap_or_else(default_page_size);
+        Self {
+            per_page: Some(per_page.clamp(1, max.clamp(1, MAX_PAGE_SIZE))),
+            ..self
+        }
+    }
+}
+
+/// Reject page numbers below 1 and page sizes outside `1..=MAX_PAGE_SIZE`.
+/// Sizes within that but above the caller's plan cap are clamped instead,
+/// since the cap depends on who's asking.
+pub fn validate_paging(page: Option<i64>, per_page: Option<i64>) -> Result<(), ValidationErrors> {
+    let mut errors = ValidationErrors::default();
+    add_paging_errors(page, per_page, &mut errors);
+    errors.into_result()
+}
+
+fn add_paging_errors(page: Option<i64>, per_page: Option<i64>, errors: &mut ValidationErrors) {
+    if page.is_some_and(|page| page < 1) {
+        errors.add("page", "must be at least 1");
+    }
+    if per_page.is_some_and(|per_page| !(1..=MAX_PAGE_SIZE).contains(&per_page)) {
+        errors.add("per_page", format!("must be between 1 and {}", MAX_PAGE_SIZE));
+    }
+}
+
+/// `page` and `per_page` on their own, for listings with no other filters.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct PageParams {
     pub page: Option<i64>,
     pub per_page: Option<i64>,
 }
 
+impl Validate for PageParams {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        validate_paging(self.page, self.per_page)
+    }
+}
+
+/// A model as it appears in listings, with fields computed at read time.
+#[derive(Debug, Serialize)]
+pub struct ModelListItem {
+    #[serde(flatten)]
+    pub model: AIModel,
+    pub is_stale: bool,
+    /// Only ever set when syncing with `updated_after`.
+    pub deleted: bool,
+}
+
 #[derive(Debug, Serialize)]
 pub struct ModelList {
-    pub models: Vec<AIModel>,
+    pub models: Vec<ModelListItem>,
     pub total: i64,
     pub page: i64,
     pub per_page: i64,
+    pub total_pages: i64,
+    pub has_next: bool,
+    pub has_prev: bool,
+}
+
+impl ModelList {
+    /// A page of results with navigation computed from `total`. Pages past
+    /// the end are empty, with `has_prev` set so clients can find their way
+    /// back.
+    pub fn new(models: Vec<ModelListItem>, total: i64, page: i64, per_page: i64) -> Self {
+        let total_pages = (total + per_page - 1) / per_page;
+        Self {
+            models,
+            total,
+            page,
+            per_page,
+            total_pages,
+            has_next: page < total_pages,
+            has_prev: page > 1,
+        }
+    }
 } 
\ No newline at end of file
@@ -0,0 +1,101 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+use sqlx::types::JsonValue;
+use uuid::Uuid;
+
+/// A model's descriptive fields after one change. Edits made without
+/// bumping the version get a snapshot of their own under the same
+/// `version`; `sequence` numbers a model's snapshots from 1.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ModelVersion {
+    pub id: Uuid,
+    pub model_id: Uuid,
+    pub version: String,
+    pub sequence: i32,
+    pub snapshot: JsonValue,
+    pub created_by: Option<Uuid>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// One field (or metadata key) that differs between two versions.
+/// `old` is absent for additions and `new` for removals.
+#[derive(Debug, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub kind: ChangeKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old: Option<JsonValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new: Option<JsonValue>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VersionDiff {
+    pub from: String,
+    pub to: String,
+    /// Top-level fields other than `metadata`.
+    pub fields: Vec<FieldChange>,
+    /// `metadata`, compared key by key.
+    pub metadata: Vec<FieldChange>,
+}
+
+/// Compare two JSON objects key by key. Keys that are null or missing count
+/// as absent. Non-objects are treated as empty.
+fn diff_objects(old: &JsonValue, new: &JsonValue, skip: &[&str]) -> Vec<FieldChange> {
+    let empty = serde_json::Map::new();
+    let old = old.as_object().unwrap_or(&empty);
+    let new = new.as_object().unwrap_or(&empty);
+    let present = |map: &serde_json::Map<String, JsonValue>, key: &str| {
+        map.get(key).filter(|value| !value.is_null()).cloned()
+    };
+
+    old.keys()
+        .chain(new.keys())
+        .map(String::as_str)
+        .filter(|key| !skip.contains(key))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter_map(|key| {
+            let (old, new) = (present(old, key), present(new, key));
+            let kind = match (&old, &new) {
+                (None, None) => return None,
+                (None, Some(_)) => ChangeKind::Added,
+                (Some(_), None) => ChangeKind::Removed,
+                (Some(a), Some(b)) if a == b => return None,
+                (Some(_), Some(_)) => ChangeKind::Changed,
+            };
+            Some(FieldChange {
+                field: key.to_string(),
+                kind,
+                old,
+                new,
+            })
+        })
+        .collect()
+}
+
+impl VersionDiff {
+    pub fn between(from: &ModelVersion, to: &ModelVersion) -> Self {
+        let null = JsonValue::Null;
+        Self {
+            from: from.version.clone(),
+            to: to.version.clone(),
+            fields: diff_objects(&from.snapshot, &to.snapshot, &["metadata"]),
+            metadata: diff_objects(
+                from.snapshot.get("metadata").unwrap_or(&null),
+                to.snapshot.get("metadata").unwrap_or(&null),
+                &[],
+            ),
+        }
+    }
+}
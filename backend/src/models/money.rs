@@ -0,0 +1,158 @@
+use std::fmt;
+
+/// Currencies Stripe charges in whole units rather than cents.
+const ZERO_DECIMAL_CURRENCIES: &[&str] = &["JPY"];
+
+/// An amount held in its currency's smallest unit (cents, or whole yen),
+/// so sums don't drift and Stripe gets exactly the amount we computed.
+/// The database stores the minor units as `BIGINT`. Amounts shown to
+/// customers in major units convert with [`Money::to_major`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Money {
+    minor: i64,
+    currency: String,
+}
+
+impl Money {
+    pub fn from_minor(minor: i64, currency: &str) -> Self {
+        Self {
+            minor,
+            currency: currency.to_ascii_uppercase(),
+        }
+    }
+
+    /// Round a major-unit amount to the nearest minor unit.
+    pub fn from_major(amount: f64, currency: &str) -> Self {
+        let currency = currency.to_ascii_uppercase();
+        let minor = (amount * minor_per_major(&currency) as f64).round() as i64;
+        Self { minor, currency }
+    }
+
+    pub fn zero(currency: &str) -> Self {
+        Self::from_minor(0, currency)
+    }
+
+    /// The amount Stripe expects.
+    pub fn minor_units(&self) -> i64 {
+        self.minor
+    }
+
+    pub fn to_major(&self) -> f64 {
+        self.minor as f64 / minor_per_major(&self.currency) as f64
+    }
+
+    pub fn currency(&self) -> &str {
+        &self.currency
+    }
+
+    /// `count` of this amount, e.g. a monthly price over several months.
+    pub fn times(&self, count: i64) -> Money {
+        Self {
+            minor: self.minor * count,
+            currency: self.currency.clone(),
+        }
+    }
+
+    /// `factor` of this amount (a tax rate, a percentage off, the unused
+    /// share of a period), rounded once to the nearest minor unit.
+    pub fn scale(&self, factor: f64) -> Money {
+        Self {
+            minor: (self.minor as f64 * factor).round() as i64,
+            currency: self.currency.clone(),
+        }
+    }
+
+    /// The larger of this amount and zero.
+    pub fn at_least_zero(&self) -> Money {
+        Self {
+            minor: self.minor.max(0),
+            currency: self.currency.clone(),
+        }
+    }
+
+    /// `None` if the currencies differ.
+    pub fn checked_add(&self, other: &Money) -> Option<Money> {
+        (self.currency == other.currency).then(|| Self {
+            minor: self.minor + other.minor,
+            currency: self.currency.clone(),
+        })
+    }
+
+    /// `None` if the currencies differ.
+    pub fn checked_sub(&self, other: &Money) -> Option<Money> {
+        (self.currency == other.currency).then(|| Self {
+            minor: self.minor - other.minor,
+            currency: self.currency.clone(),
+        })
+    }
+}
+
+/// `serialize_with` for amounts shown in major units, e.g. `29.99`.
+pub fn serialize_major<S: serde::Serializer>(money: &Money, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(money.to_major())
+}
+
+impl std::ops::Neg for Money {
+    type Output = Money;
+
+    fn neg(self) -> Money {
+        Self {
+            minor: -self.minor,
+            currency: self.currency,
+        }
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if minor_per_major(&self.currency) == 1 {
+            write!(f, "{} {}", self.minor, self.currency)
+        } else {
+            write!(f, "{:.2} {}", self.to_major(), self.currency)
+        }
+    }
+}
+
+fn minor_per_major(currency: &str) -> i64 {
+    if ZERO_DECIMAL_CURRENCIES.contains(&currency) {
+        1
+    } else {
+        100
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn major_amounts_round_to_the_nearest_minor_unit() {
+        assert_eq!(Money::from_major(29.99, "usd").minor_units(), 2999);
+        assert_eq!(Money::from_major(0.1 + 0.2, "USD").minor_units(), 30);
+        assert_eq!(Money::from_major(1500.0, "JPY").minor_units(), 1500);
+        assert_eq!(Money::from_minor(2999, "USD").to_major(), 29.99);
+    }
+
+    #[test]
+    fn scaling_rounds_once() {
+        let price = Money::from_minor(2999, "USD");
+        assert_eq!(price.scale(0.2).minor_units(), 600);
+        assert_eq!(price.scale(0.5).minor_units(), 1500);
+        assert_eq!(price.times(12).minor_units(), 35988);
+        assert_eq!((-price).at_least_zero().minor_units(), 0);
+    }
+
+    #[test]
+    fn mixing_currencies_is_refused() {
+        let usd = Money::from_minor(100, "USD");
+        let eur = Money::from_minor(100, "EUR");
+        assert_eq!(usd.checked_add(&eur), None);
+        assert_eq!(usd.checked_sub(&usd), Some(Money::zero("USD")));
+    }
+
+    #[test]
+    fn displays_in_major_units() {
+        assert_eq!(Money::from_minor(2999, "usd").to_string(), "29.99 USD");
+        assert_eq!(Money::from_minor(1500, "JPY").to_string(), "1500 JPY");
+    }
+}
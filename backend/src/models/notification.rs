@@ -0,0 +1,55 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::JsonValue;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Notification {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub kind: String,
+    pub message: String,
+    pub data: JsonValue,
+    pub read_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Notification {
+    pub async fn create(
+        pool: &PgPool,
+        user_id: Uuid,
+        kind: &str,
+        message: &str,
+        data: JsonValue,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as!(
+            Notification,
+            r#"
+            INSERT INTO notifications (user_id, kind, message, data)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, user_id, kind, message, data, read_at, created_at
+            "#,
+            user_id,
+            kind,
+            message,
+            data,
+        )
+        .fetch_one(pool)
+        .await
+    }
+
+    /// Create a notification, logging rather than failing if it can't be
+    /// stored. For side effects that mustn't undo the action they report.
+    pub async fn send_best_effort(
+        pool: &PgPool,
+        user_id: Uuid,
+        kind: &str,
+        message: &str,
+        data: JsonValue,
+    ) {
+        if let Err(e) = Self::create(pool, user_id, kind, message, data).await {
+            tracing::warn!("failed to send {} notification to user {}: {}", kind, user_id, e);
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A change to a nullable field in a partial update. An absent field is
+/// `Keep`, an explicit `null` is `Clear`, and a value is `Set`. Use with
+/// `#[serde(default)]` so absent fields deserialize to `Keep`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Patch<T> {
+    #[default]
+    Keep,
+    Clear,
+    Set(T),
+}
+
+impl<T> Patch<T> {
+    pub fn is_keep(&self) -> bool {
+        matches!(self, Patch::Keep)
+    }
+
+    pub fn is_clear(&self) -> bool {
+        matches!(self, Patch::Clear)
+    }
+
+    /// The new value, if one was given.
+    pub fn as_set(&self) -> Option<&T> {
+        match self {
+            Patch::Set(value) => Some(value),
+            Patch::Keep | Patch::Clear => None,
+        }
+    }
+
+    pub fn into_set(self) -> Option<T> {
+        match self {
+            Patch::Set(value) => Some(value),
+            Patch::Keep | Patch::Clear => None,
+        }
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Patch<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match Option::<T>::deserialize(deserializer)? {
+            Some(value) => Patch::Set(value),
+            None => Patch::Clear,
+        })
+    }
+}
+
+/// Serializes as the value or `null`; skip `Keep` fields with
+/// `#[serde(skip_serializing_if = "Patch::is_keep")]`.
+impl<T: Serialize> Serialize for Patch<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.as_set().serialize(serializer)
+    }
+}
@@ -3,16 +3,30 @@ use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize)]
+use super::{Money, ValidationErrors};
+
+/// Currencies we accept payments in (ISO 4217, upper case).
+pub const SUPPORTED_CURRENCIES: &[&str] = &["USD", "EUR", "GBP", "CAD", "AUD", "JPY"];
+
+/// Normalise a currency code, returning `None` if it isn't supported.
+pub fn normalize_currency(code: &str) -> Option<String> {
+    let code = code.trim().to_ascii_uppercase();
+    SUPPORTED_CURRENCIES.contains(&code.as_str()).then_some(code)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PaymentIntent {
     pub id: Uuid,
     pub stripe_payment_intent_id: String,
     pub user_id: Uuid,
     pub subscription_id: Uuid,
-    pub amount: f64,
+    /// What the user is charged, tax included, in the currency's minor unit.
+    pub amount: i64,
+    pub tax_amount: i64,
     pub currency: String,
     pub status: String,
     pub client_secret: String,
+    pub expires_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -26,6 +40,9 @@ pub struct PaymentMethod {
     pub card_last4: Option<String>,
     pub card_exp_month: Option<i32>,
     pub card_exp_year: Option<i32>,
+    /// Same for every token of one physical card; used to spot duplicates.
+    #[serde(skip_serializing)]
+    pub card_fingerprint: Option<String>,
     pub is_default: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
@@ -37,47 +54,204 @@ pub struct PaymentHistory {
     pub user_id: Uuid,
     pub subscription_id: Uuid,
     pub payment_intent_id: Uuid,
-    pub amount: f64,
+    /// In the currency's minor unit.
+    pub amount: i64,
     pub currency: String,
     pub status: String,
     pub created_at: DateTime<Utc>,
 }
 
+/// An invoice for a successful payment. `number` is unique and increases
+/// with issue order.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Invoice {
+    pub id: Uuid,
+    pub number: i64,
+    pub user_id: Uuid,
+    pub payment_intent_id: Uuid,
+    /// In the currency's minor unit.
+    pub amount: i64,
+    pub currency: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InvoiceLineItem {
+    pub description: String,
+    /// Negative for discounts and credits.
+    pub amount: i64,
+}
+
+/// An invoice as shown to its owner, with the charge broken into lines.
+/// The plan line is what was owed before the coupon and credits applied
+/// to the payment, and tax is added on top; `total` equals the amount
+/// paid. Amounts are in the currency's minor unit.
+#[derive(Debug, Serialize)]
+pub struct InvoiceDetail {
+    pub id: Uuid,
+    pub number: String,
+    pub payment_intent_id: Uuid,
+    pub subscription_id: Uuid,
+    pub subscription_name: String,
+    pub currency: String,
+    pub line_items: Vec<InvoiceLineItem>,
+    pub subtotal: i64,
+    pub tax: i64,
+    pub total: i64,
+    pub issued_at: DateTime<Utc>,
+    pub paid_at: DateTime<Utc>,
+}
+
+/// Smallest charge Stripe accepts, in each supported currency's minor unit.
+const STRIPE_MINIMUM_CHARGES: &[(&str, i64)] = &[
+    ("USD", 50),
+    ("EUR", 50),
+    ("GBP", 30),
+    ("CAD", 50),
+    ("AUD", 50),
+    ("JPY", 50),
+];
+
+/// Smallest amount we'll charge in `currency`: Stripe's minimum, or
+/// `PAYMENT_MIN_AMOUNT` (major units) if that's higher.
+pub fn minimum_charge(currency: &str) -> Money {
+    let stripe_minimum = STRIPE_MINIMUM_CHARGES
+        .iter()
+        .find(|(code, _)| code.eq_ignore_ascii_case(currency))
+        .map_or(50, |(_, minimum)| *minimum);
+    let configured = std::env::var("PAYMENT_MIN_AMOUNT")
+        .ok()
+        .and_then(|v| v.parse::<f64>().ok())
+        .map_or(0, |amount| Money::from_major(amount, currency).minor_units());
+    Money::from_minor(stripe_minimum.max(configured), currency)
+}
+
+/// Largest amount we'll charge in one payment, `PAYMENT_MAX_AMOUNT` in
+/// major units (default 999,999.99, Stripe's own limit for most currencies).
+pub fn maximum_charge(currency: &str) -> Money {
+    let amount = std::env::var("PAYMENT_MAX_AMOUNT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(999_999.99);
+    Money::from_major(amount, currency)
+}
+
+/// Check a charge is something Stripe will take: more than zero, at least
+/// [`minimum_charge`] and at most [`maximum_charge`].
+pub fn validate_charge(total: &Money) -> Result<(), ValidationErrors> {
+    let mut errors = ValidationErrors::default();
+    let minimum = minimum_charge(total.currency());
+    let maximum = maximum_charge(total.currency());
+    if total.minor_units() <= 0 {
+        errors.add("amount", "there is nothing to pay");
+    } else if total.minor_units() < minimum.minor_units() {
+        errors.add(
+            "amount",
+            format!("{} is below the minimum charge of {}", total, minimum),
+        );
+    } else if total.minor_units() > maximum.minor_units() {
+        errors.add(
+            "amount",
+            format!("{} is above the maximum charge of {}", total, maximum),
+        );
+    }
+    errors.into_result()
+}
+
+/// How many times a failed checkout may be retried
+/// (`MAX_PAYMENT_RETRIES`, default 3).
+pub fn max_payment_retries() -> i64 {
+    std::env::var("MAX_PAYMENT_RETRIES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(3)
+}
+
+/// How long a pending intent may be confirmed before we expire it.
+///
+/// Stripe PaymentIntents don't expire on their own, so we pick the window and
+/// cancel the Stripe side when it lapses.
+pub fn payment_intent_ttl() -> chrono::Duration {
+    let hours = std::env::var("PAYMENT_INTENT_TTL_HOURS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(24);
+    chrono::Duration::hours(hours)
+}
+
+/// Whether an intent with this status is done changing, as far as the
+/// checkout is concerned. Retrying a failed payment starts a new intent.
+pub fn is_terminal_payment_status(status: &str) -> bool {
+    matches!(
+        status,
+        "succeeded" | "failed" | "expired" | "refunded" | "canceled"
+    )
+}
+
 impl PaymentIntent {
+    /// What the user is charged, tax included.
+    pub fn total(&self) -> Money {
+        Money::from_minor(self.amount, &self.currency)
+    }
+
+    pub fn tax(&self) -> Money {
+        Money::from_minor(self.tax_amount, &self.currency)
+    }
+
+    /// A pending intent past its expiry, whether or not the cleanup job has
+    /// marked it yet.
+    pub fn is_expired(&self) -> bool {
+        self.status == "expired"
+            || (self.status == "pending"
+                && self.expires_at.map(|t| t <= Utc::now()).unwrap_or(false))
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub async fn create(
         pool: &PgPool,
         user_id: Uuid,
         subscription_id: Uuid,
         stripe_payment_intent_id: String,
-        amount: f64,
+        total: &Money,
+        tax: &Money,
         client_secret: String,
+        retry_of: Option<Uuid>,
     ) -> Result<Self, sqlx::Error> {
         sqlx::query_as!(
             PaymentIntent,
             r#"
             INSERT INTO payment_intents (
                 user_id, subscription_id, stripe_payment_intent_id,
-                amount, status, client_secret
+                amount, tax_amount, currency, status, client_secret, expires_at,
+                retry_of
             )
-            VALUES ($1, $2, $3, $4, 'pending', $5)
+            VALUES ($1, $2, $3, $4, $5, $6, 'pending', $7, $8, $9)
             RETURNING id, stripe_payment_intent_id, user_id, subscription_id,
-                      amount, currency, status, client_secret, created_at, updated_at
+                      amount, tax_amount, currency, status, client_secret, expires_at,
+                      created_at, updated_at
             "#,
             user_id,
             subscription_id,
             stripe_payment_intent_id,
-            amount,
+            total.minor_units(),
+            tax.minor_units(),
+            total.currency(),
             client_secret,
+            Utc::now() + payment_intent_ttl(),
+            retry_of,
         )
         .fetch_one(pool)
         .await
     }
 
-    pub async fn update_status(
-        pool: &PgPool,
+    pub async fn update_status<'e, E>(
+        executor: E,
         stripe_payment_intent_id: &str,
         status: &str,
-    ) -> Result<(), sqlx::Error> {
+    ) -> Result<(), sqlx::Error>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
         sqlx::query!(
             r#"
             UPDATE payment_intents
@@ -87,48 +261,230 @@ impl PaymentIntent {
             status,
             stripe_payment_intent_id,
         )
-        .execute(pool)
+        .execute(executor)
         .await?;
         Ok(())
     }
 
-    pub async fn get_by_stripe_id(
-        pool: &PgPool,
+    pub async fn get_by_stripe_id<'e, E>(
+        executor: E,
         stripe_payment_intent_id: &str,
-    ) -> Result<Option<Self>, sqlx::Error> {
+    ) -> Result<Option<Self>, sqlx::Error>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
         sqlx::query_as!(
             PaymentIntent,
             r#"
             SELECT id, stripe_payment_intent_id, user_id, subscription_id,
-                   amount, currency, status, client_secret, created_at, updated_at
+                   amount, tax_amount, currency, status, client_secret, expires_at,
+                   created_at, updated_at
             FROM payment_intents
             WHERE stripe_payment_intent_id = $1
             "#,
             stripe_payment_intent_id,
         )
+        .fetch_optional(executor)
+        .await
+    }
+
+    pub async fn get_by_id(pool: &PgPool, id: Uuid) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            PaymentIntent,
+            r#"
+            SELECT id, stripe_payment_intent_id, user_id, subscription_id,
+                   amount, tax_amount, currency, status, client_secret, expires_at,
+                   created_at, updated_at
+            FROM payment_intents
+            WHERE id = $1
+            "#,
+            id,
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Total refunded so far against this intent.
+    pub async fn refunded_total(&self, pool: &PgPool) -> Result<Money, sqlx::Error> {
+        let refunded = sqlx::query_scalar!(
+            "SELECT amount_refunded FROM payment_intents WHERE id = $1",
+            self.id,
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(Money::from_minor(refunded, &self.currency))
+    }
+
+    /// Raise the refunded total to `refunded_total` and set the status to
+    /// `refunded` or `partially_refunded` to match. Returns the intent and
+    /// the newly refunded amount, or `None` if the total was already at least
+    /// this much, so a refund reported twice is only recorded once.
+    pub async fn record_refund(
+        pool: &PgPool,
+        stripe_payment_intent_id: &str,
+        refunded_total: &Money,
+    ) -> Result<Option<(Self, Money)>, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            WITH previous AS (
+                SELECT id, amount_refunded FROM payment_intents
+                WHERE stripe_payment_intent_id = $1
+                FOR UPDATE
+            )
+            UPDATE payment_intents p
+            SET amount_refunded = $2,
+                status = CASE WHEN $2 >= p.amount THEN 'refunded'
+                              ELSE 'partially_refunded' END,
+                updated_at = NOW()
+            FROM previous
+            WHERE p.id = previous.id AND previous.amount_refunded < $2
+            RETURNING p.id, ($2 - previous.amount_refunded) AS "refunded!"
+            "#,
+            stripe_payment_intent_id,
+            refunded_total.minor_units(),
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let intent = Self::get_by_id(pool, row.id)
+            .await?
+            .ok_or(sqlx::Error::RowNotFound)?;
+
+        let refunded = Money::from_minor(row.refunded, &intent.currency);
+        Ok(Some((intent, refunded)))
+    }
+
+    /// The intent that started this checkout, and how many retries of it
+    /// have been made so far.
+    pub async fn retry_chain(pool: &PgPool, id: Uuid) -> Result<(Uuid, i64), sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            SELECT COALESCE(p.retry_of, p.id) AS "root!",
+                   (SELECT COUNT(*) FROM payment_intents r
+                    WHERE r.retry_of = COALESCE(p.retry_of, p.id)) AS "retries!"
+            FROM payment_intents p
+            WHERE p.id = $1
+            "#,
+            id,
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok((row.root, row.retries))
+    }
+
+    /// The most recent intent for this user and plan, in any status.
+    pub async fn latest_for(
+        pool: &PgPool,
+        user_id: Uuid,
+        subscription_id: Uuid,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            PaymentIntent,
+            r#"
+            SELECT id, stripe_payment_intent_id, user_id, subscription_id,
+                   amount, tax_amount, currency, status, client_secret, expires_at,
+                   created_at, updated_at
+            FROM payment_intents
+            WHERE user_id = $1 AND subscription_id = $2
+            ORDER BY created_at DESC
+            LIMIT 1
+            "#,
+            user_id,
+            subscription_id,
+        )
         .fetch_optional(pool)
         .await
     }
+
+    /// Stripe ids of pending intents whose expiry has passed.
+    pub async fn list_lapsed(pool: &PgPool) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT stripe_payment_intent_id
+            FROM payment_intents
+            WHERE status = 'pending' AND expires_at <= NOW()
+            "#
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Mark a pending intent as settling, for payment methods that confirm
+    /// asynchronously. Returns `false` if it was no longer pending, e.g.
+    /// because the `succeeded` event arrived first.
+    pub async fn mark_processing<'e, E>(
+        executor: E,
+        stripe_payment_intent_id: &str,
+    ) -> Result<bool, sqlx::Error>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query!(
+            r#"
+            UPDATE payment_intents
+            SET status = 'processing', updated_at = NOW()
+            WHERE stripe_payment_intent_id = $1 AND status = 'pending'
+            "#,
+            stripe_payment_intent_id,
+        )
+        .execute(executor)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Mark a lapsed pending intent as expired. Returns `false` if it was no
+    /// longer pending.
+    pub async fn mark_expired(
+        pool: &PgPool,
+        stripe_payment_intent_id: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE payment_intents
+            SET status = 'expired', updated_at = NOW()
+            WHERE stripe_payment_intent_id = $1 AND status = 'pending'
+            "#,
+            stripe_payment_intent_id,
+        )
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
 }
 
 impl PaymentMethod {
+    /// Store a payment method. The user's first method becomes their default.
+    /// Returns `None` if the token or the card (by fingerprint) is already
+    /// stored; see [`PaymentMethod::find_duplicate`].
     pub async fn create(
         pool: &PgPool,
         user_id: Uuid,
         stripe_payment_method_id: String,
         card_details: Option<CardDetails>,
-    ) -> Result<Self, sqlx::Error> {
+    ) -> Result<Option<Self>, sqlx::Error> {
         sqlx::query_as!(
             PaymentMethod,
             r#"
             INSERT INTO payment_methods (
                 user_id, stripe_payment_method_id, card_brand,
-                card_last4, card_exp_month, card_exp_year
+                card_last4, card_exp_month, card_exp_year, card_fingerprint,
+                is_default
             )
-            VALUES ($1, $2, $3, $4, $5, $6)
+            VALUES (
+                $1, $2, $3, $4, $5, $6, $7,
+                NOT EXISTS (
+                    SELECT 1 FROM payment_methods
+                    WHERE user_id = $1 AND is_default = true
+                )
+            )
+            ON CONFLICT DO NOTHING
             RETURNING id, user_id, stripe_payment_method_id, card_brand,
                       card_last4, card_exp_month, card_exp_year,
-                      is_default, created_at, updated_at
+                      card_fingerprint, is_default, created_at, updated_at
             "#,
             user_id,
             stripe_payment_method_id,
@@ -136,8 +492,36 @@ impl PaymentMethod {
             card_details.as_ref().map(|c| &c.last4),
             card_details.as_ref().map(|c| c.exp_month),
             card_details.as_ref().map(|c| c.exp_year),
+            card_details.as_ref().and_then(|c| c.fingerprint.as_ref()),
         )
-        .fetch_one(pool)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// The user's stored method with this token or for the same card.
+    pub async fn find_duplicate(
+        pool: &PgPool,
+        user_id: Uuid,
+        stripe_payment_method_id: &str,
+        card_fingerprint: Option<&str>,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            PaymentMethod,
+            r#"
+            SELECT id, user_id, stripe_payment_method_id, card_brand,
+                   card_last4, card_exp_month, card_exp_year,
+                   card_fingerprint, is_default, created_at, updated_at
+            FROM payment_methods
+            WHERE user_id = $1
+              AND (stripe_payment_method_id = $2 OR card_fingerprint = $3)
+            ORDER BY stripe_payment_method_id = $2 DESC
+            LIMIT 1
+            "#,
+            user_id,
+            stripe_payment_method_id,
+            card_fingerprint,
+        )
+        .fetch_optional(pool)
         .await
     }
 
@@ -150,7 +534,7 @@ impl PaymentMethod {
             r#"
             SELECT id, user_id, stripe_payment_method_id, card_brand,
                    card_last4, card_exp_month, card_exp_year,
-                   is_default, created_at, updated_at
+                   card_fingerprint, is_default, created_at, updated_at
             FROM payment_methods
             WHERE user_id = $1 AND is_default = true
             "#,
@@ -159,35 +543,174 @@ impl PaymentMethod {
         .fetch_optional(pool)
         .await
     }
+
+    /// The payment method, if the user owns it.
+    pub async fn get_for_user(
+        pool: &PgPool,
+        user_id: Uuid,
+        payment_method_id: Uuid,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            PaymentMethod,
+            r#"
+            SELECT id, user_id, stripe_payment_method_id, card_brand,
+                   card_last4, card_exp_month, card_exp_year,
+                   card_fingerprint, is_default, created_at, updated_at
+            FROM payment_methods
+            WHERE id = $1 AND user_id = $2
+            "#,
+            payment_method_id,
+            user_id,
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    pub async fn list_for_user(pool: &PgPool, user_id: Uuid) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            PaymentMethod,
+            r#"
+            SELECT id, user_id, stripe_payment_method_id, card_brand,
+                   card_last4, card_exp_month, card_exp_year,
+                   card_fingerprint, is_default, created_at, updated_at
+            FROM payment_methods
+            WHERE user_id = $1
+            ORDER BY is_default DESC, created_at DESC
+            "#,
+            user_id,
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Make `payment_method_id` the user's default, clearing the previous one.
+    /// Returns `None` if the user doesn't own the payment method.
+    pub async fn set_default(
+        pool: &PgPool,
+        user_id: Uuid,
+        payment_method_id: Uuid,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE payment_methods
+            SET is_default = false, updated_at = NOW()
+            WHERE user_id = $1 AND is_default = true AND id <> $2
+            "#,
+            user_id,
+            payment_method_id,
+        )
+        .execute(&mut tx)
+        .await?;
+
+        let payment_method = sqlx::query_as!(
+            PaymentMethod,
+            r#"
+            UPDATE payment_methods
+            SET is_default = true, updated_at = NOW()
+            WHERE id = $1 AND user_id = $2
+            RETURNING id, user_id, stripe_payment_method_id, card_brand,
+                      card_last4, card_exp_month, card_exp_year,
+                      card_fingerprint, is_default, created_at, updated_at
+            "#,
+            payment_method_id,
+            user_id,
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+
+        // Dropping the transaction rolls back the cleared default.
+        if payment_method.is_some() {
+            tx.commit().await?;
+        }
+
+        Ok(payment_method)
+    }
+
+    /// Remove a payment method locally. If it was the default, the most
+    /// recently added remaining method is promoted. Returns the removed row,
+    /// or `None` if the user doesn't own it.
+    pub async fn detach(
+        pool: &PgPool,
+        user_id: Uuid,
+        payment_method_id: Uuid,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let Some(removed) = sqlx::query_as!(
+            PaymentMethod,
+            r#"
+            DELETE FROM payment_methods
+            WHERE id = $1 AND user_id = $2
+            RETURNING id, user_id, stripe_payment_method_id, card_brand,
+                      card_last4, card_exp_month, card_exp_year,
+                      card_fingerprint, is_default, created_at, updated_at
+            "#,
+            payment_method_id,
+            user_id,
+        )
+        .fetch_optional(&mut tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        if removed.is_default {
+            sqlx::query!(
+                r#"
+                UPDATE payment_methods
+                SET is_default = true, updated_at = NOW()
+                WHERE id = (
+                    SELECT id FROM payment_methods
+                    WHERE user_id = $1
+                    ORDER BY created_at DESC
+                    LIMIT 1
+                )
+                "#,
+                user_id,
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(Some(removed))
+    }
 }
 
 impl PaymentHistory {
-    pub async fn create(
-        pool: &PgPool,
+    pub async fn create<'e, E>(
+        executor: E,
         user_id: Uuid,
         subscription_id: Uuid,
         payment_intent_id: Uuid,
-        amount: f64,
+        amount: &Money,
         status: &str,
-    ) -> Result<Self, sqlx::Error> {
+    ) -> Result<Self, sqlx::Error>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
         sqlx::query_as!(
             PaymentHistory,
             r#"
             INSERT INTO payment_history (
                 user_id, subscription_id, payment_intent_id,
-                amount, status
+                amount, currency, status
             )
-            VALUES ($1, $2, $3, $4, $5)
+            VALUES ($1, $2, $3, $4, $5, $6)
             RETURNING id, user_id, subscription_id, payment_intent_id,
                       amount, currency, status, created_at
             "#,
             user_id,
             subscription_id,
             payment_intent_id,
-            amount,
+            amount.minor_units(),
+            amount.currency(),
             status,
         )
-        .fetch_one(pool)
+        .fetch_one(executor)
         .await
     }
 
@@ -195,6 +718,7 @@ impl PaymentHistory {
         pool: &PgPool,
         user_id: Uuid,
         limit: i64,
+        offset: i64,
     ) -> Result<Vec<Self>, sqlx::Error> {
         sqlx::query_as!(
             PaymentHistory,
@@ -204,6 +728,77 @@ impl PaymentHistory {
             FROM payment_history
             WHERE user_id = $1
             ORDER BY created_at DESC
+            LIMIT $2 OFFSET $3
+            "#,
+            user_id,
+            limit,
+            offset,
+        )
+        .fetch_all(pool)
+        .await
+    }
+}
+
+impl Invoice {
+    /// The number as printed on the invoice, e.g. `INV-000042`.
+    pub fn display_number(&self) -> String {
+        display_invoice_number(self.number)
+    }
+
+    /// Issue the invoice for a successful payment, or return the one already
+    /// issued for it, so webhook redeliveries don't number it twice.
+    pub async fn issue_for(
+        conn: &mut sqlx::PgConnection,
+        intent: &PaymentIntent,
+    ) -> Result<Self, sqlx::Error> {
+        let issued = sqlx::query_as!(
+            Invoice,
+            r#"
+            INSERT INTO invoices (user_id, payment_intent_id, amount, currency)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (payment_intent_id) DO NOTHING
+            RETURNING id, number, user_id, payment_intent_id,
+                      amount, currency, created_at
+            "#,
+            intent.user_id,
+            intent.id,
+            intent.amount,
+            intent.currency,
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        if let Some(invoice) = issued {
+            return Ok(invoice);
+        }
+
+        sqlx::query_as!(
+            Invoice,
+            r#"
+            SELECT id, number, user_id, payment_intent_id,
+                   amount, currency, created_at
+            FROM invoices
+            WHERE payment_intent_id = $1
+            "#,
+            intent.id,
+        )
+        .fetch_one(conn)
+        .await
+    }
+
+    pub async fn get_for_user(
+        pool: &PgPool,
+        user_id: Uuid,
+        limit: i64,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Invoice,
+            r#"
+            SELECT id, number, user_id, payment_intent_id,
+                   amount, currency, created_at
+            FROM invoices
+            WHERE user_id = $1
+            ORDER BY number DESC
             LIMIT $2
             "#,
             user_id,
@@ -212,12 +807,310 @@ impl PaymentHistory {
         .fetch_all(pool)
         .await
     }
+
+    /// One of the user's invoices with its line items.
+    pub async fn detail_for_user(
+        pool: &PgPool,
+        user_id: Uuid,
+        id: Uuid,
+    ) -> Result<Option<InvoiceDetail>, sqlx::Error> {
+        let Some(row) = sqlx::query!(
+            r#"
+            SELECT i.id, i.number, i.payment_intent_id,
+                   i.amount, i.currency, i.created_at,
+                   pi.tax_amount, pi.updated_at AS paid_at,
+                   s.id AS subscription_id, s.name AS subscription_name,
+                   COALESCE((
+                       SELECT SUM(amount_off) FROM coupon_redemptions
+                       WHERE payment_intent_id = i.payment_intent_id
+                   ), 0)::BIGINT AS "discount!",
+                   COALESCE((
+                       SELECT SUM(amount) FROM subscription_credits
+                       WHERE payment_intent_id = i.payment_intent_id
+                   ), 0)::BIGINT AS "credits!"
+            FROM invoices i
+            JOIN payment_intents pi ON pi.id = i.payment_intent_id
+            JOIN subscriptions s ON s.id = pi.subscription_id
+            WHERE i.id = $1 AND i.user_id = $2
+            "#,
+            id,
+            user_id,
+        )
+        .fetch_optional(pool)
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        let subtotal = row.amount - row.tax_amount + row.discount + row.credits;
+        let mut line_items = vec![InvoiceLineItem {
+            description: row.subscription_name.clone(),
+            amount: subtotal,
+        }];
+        if row.discount > 0 {
+            line_items.push(InvoiceLineItem {
+                description: "Coupon discount".into(),
+                amount: -row.discount,
+            });
+        }
+        if row.credits > 0 {
+            line_items.push(InvoiceLineItem {
+                description: "Account credit".into(),
+                amount: -row.credits,
+            });
+        }
+
+        Ok(Some(InvoiceDetail {
+            id: row.id,
+            number: display_invoice_number(row.number),
+            payment_intent_id: row.payment_intent_id,
+            subscription_id: row.subscription_id,
+            subscription_name: row.subscription_name,
+            currency: row.currency,
+            line_items,
+            subtotal,
+            tax: row.tax_amount,
+            total: row.amount,
+            issued_at: row.created_at,
+            paid_at: row.paid_at,
+        }))
+    }
+}
+
+fn display_invoice_number(number: i64) -> String {
+    format!("INV-{:06}", number)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CardDetails {
     pub brand: String,
     pub last4: String,
     pub exp_month: i32,
     pub exp_year: i32,
-} 
\ No newline at end of file
+    pub fingerprint: Option<String>,
+} 
+/// The Stripe customer each user's charges and payment methods go to.
+pub struct StripeCustomer;
+
+impl StripeCustomer {
+    pub async fn find(pool: &PgPool, user_id: Uuid) -> Result<Option<String>, sqlx::Error> {
+        sqlx::query_scalar!(
+            "SELECT stripe_customer_id FROM stripe_customers WHERE user_id = $1",
+            user_id
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Record `stripe_customer_id` as the user's customer unless they
+    /// already have one, and return whichever is recorded. A concurrent
+    /// caller that created its own customer gets the winner's back.
+    pub async fn record(
+        pool: &PgPool,
+        user_id: Uuid,
+        stripe_customer_id: &str,
+    ) -> Result<String, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO stripe_customers (user_id, stripe_customer_id)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id) DO NOTHING
+            "#,
+            user_id,
+            stripe_customer_id
+        )
+        .execute(&mut tx)
+        .await?;
+        let recorded = sqlx::query_scalar!(
+            "SELECT stripe_customer_id FROM stripe_customers WHERE user_id = $1",
+            user_id
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(recorded)
+    }
+
+    /// Point the user at `stripe_customer_id`, e.g. after their old
+    /// customer was deleted in Stripe.
+    pub async fn replace(
+        pool: &PgPool,
+        user_id: Uuid,
+        stripe_customer_id: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO stripe_customers (user_id, stripe_customer_id)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id) DO UPDATE
+            SET stripe_customer_id = EXCLUDED.stripe_customer_id, updated_at = NOW()
+            "#,
+            user_id,
+            stripe_customer_id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+/// A Stripe webhook event we've accepted, keyed by Stripe's event id.
+pub struct WebhookEvent;
+
+impl WebhookEvent {
+    /// Record the event as handled. Returns `false` if it already was,
+    /// i.e. this delivery is a replay.
+    pub async fn record(pool: &PgPool, event_id: &str, event_type: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO stripe_webhook_events (event_id, event_type)
+            VALUES ($1, $2)
+            ON CONFLICT (event_id) DO NOTHING
+            "#,
+            event_id,
+            event_type
+        )
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Undo [`WebhookEvent::record`] after handling failed, so Stripe's
+    /// retry is processed.
+    pub async fn forget(pool: &PgPool, event_id: &str) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM stripe_webhook_events WHERE event_id = $1", event_id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test]
+    async fn refunds_raise_the_total_once_per_amount(pool: PgPool) {
+        let user_id = crate::test_support::create_user(&pool).await;
+        let pro = crate::test_support::plan(&pool, crate::models::SubscriptionTier::Pro).await;
+        let usd = |minor| Money::from_minor(minor, "USD");
+        PaymentIntent::create(
+            &pool,
+            user_id,
+            pro.id,
+            "pi_1".to_string(),
+            &usd(2999),
+            &usd(0),
+            "secret".to_string(),
+            None,
+        )
+        .await
+        .unwrap();
+
+        let (intent, refunded) = PaymentIntent::record_refund(&pool, "pi_1", &usd(1000))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((intent.status.as_str(), refunded), ("partially_refunded", usd(1000)));
+        assert!(PaymentIntent::record_refund(&pool, "pi_1", &usd(1000)).await.unwrap().is_none());
+
+        let (intent, refunded) = PaymentIntent::record_refund(&pool, "pi_1", &usd(2999))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((intent.status.as_str(), refunded), ("refunded", usd(1999)));
+        assert_eq!(intent.refunded_total(&pool).await.unwrap(), usd(2999));
+    }
+
+    #[sqlx::test]
+    async fn the_first_recorded_customer_wins(pool: PgPool) {
+        let user_id = crate::test_support::create_user(&pool).await;
+        assert_eq!(StripeCustomer::find(&pool, user_id).await.unwrap(), None);
+
+        assert_eq!(StripeCustomer::record(&pool, user_id, "cus_a").await.unwrap(), "cus_a");
+        assert_eq!(StripeCustomer::record(&pool, user_id, "cus_b").await.unwrap(), "cus_a");
+        assert_eq!(StripeCustomer::find(&pool, user_id).await.unwrap().as_deref(), Some("cus_a"));
+    }
+
+    #[sqlx::test]
+    async fn replace_points_the_user_at_a_new_customer(pool: PgPool) {
+        let user_id = crate::test_support::create_user(&pool).await;
+        StripeCustomer::record(&pool, user_id, "cus_a").await.unwrap();
+
+        StripeCustomer::replace(&pool, user_id, "cus_b").await.unwrap();
+        assert_eq!(StripeCustomer::find(&pool, user_id).await.unwrap().as_deref(), Some("cus_b"));
+    }
+
+    #[sqlx::test]
+    async fn a_replayed_webhook_is_recorded_once(pool: PgPool) {
+        assert!(WebhookEvent::record(&pool, "evt_1", "invoice.paid").await.unwrap());
+        assert!(!WebhookEvent::record(&pool, "evt_1", "invoice.paid").await.unwrap());
+        assert!(WebhookEvent::record(&pool, "evt_2", "invoice.paid").await.unwrap());
+    }
+
+    #[sqlx::test]
+    async fn a_forgotten_webhook_is_handled_again(pool: PgPool) {
+        assert!(WebhookEvent::record(&pool, "evt_1", "invoice.paid").await.unwrap());
+        WebhookEvent::forget(&pool, "evt_1").await.unwrap();
+        assert!(WebhookEvent::record(&pool, "evt_1", "invoice.paid").await.unwrap());
+    }
+
+    #[sqlx::test]
+    async fn concurrent_invoices_get_distinct_consecutive_numbers(pool: PgPool) {
+        let pro = crate::test_support::plan(&pool, crate::models::SubscriptionTier::Pro).await;
+        let mut intents = Vec::new();
+        for i in 0..8 {
+            // One pending intent per user and plan, so each payer is a new user.
+            let intent = PaymentIntent::create(
+                &pool,
+                crate::test_support::create_user(&pool).await,
+                pro.id,
+                format!("pi_{}", i),
+                &Money::from_minor(2999, "USD"),
+                &Money::from_minor(0, "USD"),
+                "secret".to_string(),
+                None,
+            )
+            .await
+            .unwrap();
+            intents.push(intent);
+        }
+
+        let issuing = intents.iter().cloned().map(|intent| {
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                let mut conn = pool.acquire().await.unwrap();
+                Invoice::issue_for(&mut conn, &intent).await.unwrap().number
+            })
+        });
+        let mut numbers = Vec::new();
+        for handle in issuing.collect::<Vec<_>>() {
+            numbers.push(handle.await.unwrap());
+        }
+        numbers.sort_unstable();
+        let first = numbers[0];
+        assert_eq!(numbers, (first..first + 8).collect::<Vec<_>>());
+
+        // A redelivered success keeps the number it was first given.
+        let mut conn = pool.acquire().await.unwrap();
+        let again = Invoice::issue_for(&mut conn, &intents[0]).await.unwrap();
+        assert!(numbers.contains(&again.number));
+        let issued = Invoice::get_for_user(&pool, intents[0].user_id, 100).await.unwrap();
+        assert_eq!(issued.len(), 1);
+    }
+
+    #[test]
+    fn a_charge_below_the_currency_minimum_or_of_nothing_is_rejected() {
+        let message = |minor, currency| {
+            validate_charge(&Money::from_minor(minor, currency)).map_err(|e| e.to_string())
+        };
+
+        assert!(message(49, "USD").unwrap_err().contains("below the minimum charge of"));
+        assert!(message(29, "GBP").is_err());
+        assert!(message(0, "USD").unwrap_err().contains("nothing to pay"));
+        assert!(message(-100, "USD").is_err());
+        assert_eq!(message(50, "USD"), Ok(()));
+        assert_eq!(message(30, "GBP"), Ok(()));
+    }
+}
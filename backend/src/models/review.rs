@@ -0,0 +1,51 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::ValidationErrors;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ModelReview {
+    pub id: Uuid,
+    pub model_id: Uuid,
+    pub user_id: Uuid,
+    pub rating: i32,
+    pub comment: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateReview {
+    pub rating: i32,
+    pub comment: Option<String>,
+}
+
+pub const MAX_COMMENT_LENGTH: usize = 5_000;
+
+impl CreateReview {
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+
+        if !(1..=5).contains(&self.rating) {
+            errors.add("rating", "must be between 1 and 5");
+        }
+        if let Some(comment) = &self.comment {
+            if comment.chars().count() > MAX_COMMENT_LENGTH {
+                errors.add(
+                    "comment",
+                    format!("must be at most {} characters", MAX_COMMENT_LENGTH),
+                );
+            }
+        }
+
+        errors.into_result()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReviewList {
+    pub reviews: Vec<ModelReview>,
+    pub average_rating: Option<f64>,
+    pub page: i64,
+    pub per_page: i64,
+}
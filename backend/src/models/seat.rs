@@ -0,0 +1,255 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// A team member using one of a subscription's seats.
+#[derive(Debug, Serialize)]
+pub struct SeatAssignment {
+    pub id: Uuid,
+    pub user_subscription_id: Uuid,
+    pub user_id: Uuid,
+    pub assigned_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AssignSeatRequest {
+    pub user_id: Uuid,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangeSeatsRequest {
+    pub seats: i32,
+}
+
+/// A subscription's seat count and who is using them.
+#[derive(Debug, Serialize)]
+pub struct SeatUsage {
+    pub seats: i32,
+    pub assigned: Vec<SeatAssignment>,
+}
+
+/// Result of assigning a seat.
+#[derive(Debug)]
+pub enum SeatAssignmentOutcome {
+    Assigned(SeatAssignment),
+    AlreadyAssigned,
+    /// Every seat is taken.
+    NoFreeSeat,
+    /// The subscription isn't active.
+    NotFound,
+}
+
+/// Result of changing a subscription's seat count.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SeatChange {
+    Changed { seats: i32 },
+    /// Fewer seats than members currently assigned; nothing was changed.
+    BelowAssigned { assigned: i64 },
+    /// The subscription isn't active.
+    NotFound,
+}
+
+impl SeatAssignment {
+    pub async fn usage(pool: &PgPool, user_subscription_id: Uuid) -> Result<SeatUsage, sqlx::Error> {
+        let seats = sqlx::query_scalar!(
+            "SELECT seats FROM user_subscriptions WHERE id = $1",
+            user_subscription_id
+        )
+        .fetch_one(pool)
+        .await?;
+        let assigned = sqlx::query_as!(
+            SeatAssignment,
+            r#"
+            SELECT id, user_subscription_id, user_id, assigned_at
+            FROM subscription_seat_assignments
+            WHERE user_subscription_id = $1
+            ORDER BY assigned_at ASC
+            "#,
+            user_subscription_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(SeatUsage { seats, assigned })
+    }
+
+    /// Give `user_id` one of the subscription's free seats. The
+    /// subscription row is locked while seats are counted, so concurrent
+    /// assignments and seat reductions can't oversubscribe it.
+    pub async fn assign(
+        pool: &PgPool,
+        user_subscription_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<SeatAssignmentOutcome, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let Some(seats) = lock_active_seats(&mut tx, user_subscription_id).await? else {
+            return Ok(SeatAssignmentOutcome::NotFound);
+        };
+        if assigned_count(&mut tx, user_subscription_id).await? >= i64::from(seats) {
+            return Ok(SeatAssignmentOutcome::NoFreeSeat);
+        }
+
+        let assignment = sqlx::query_as!(
+            SeatAssignment,
+            r#"
+            INSERT INTO subscription_seat_assignments (user_subscription_id, user_id)
+            VALUES ($1, $2)
+            ON CONFLICT (user_subscription_id, user_id) DO NOTHING
+            RETURNING id, user_subscription_id, user_id, assigned_at
+            "#,
+            user_subscription_id,
+            user_id
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(match assignment {
+            Some(assignment) => SeatAssignmentOutcome::Assigned(assignment),
+            None => SeatAssignmentOutcome::AlreadyAssigned,
+        })
+    }
+
+    /// Free the seat `user_id` holds. Returns `false` if they had none.
+    pub async fn unassign(
+        pool: &PgPool,
+        user_subscription_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM subscription_seat_assignments
+            WHERE user_subscription_id = $1 AND user_id = $2
+            "#,
+            user_subscription_id,
+            user_id
+        )
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Set the subscription's seat count. A reduction below the number of
+    /// members assigned is refused; they have to be unassigned first.
+    pub async fn change_seats(
+        pool: &PgPool,
+        user_subscription_id: Uuid,
+        seats: i32,
+    ) -> Result<SeatChange, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        if lock_active_seats(&mut tx, user_subscription_id).await?.is_none() {
+            return Ok(SeatChange::NotFound);
+        }
+        let assigned = assigned_count(&mut tx, user_subscription_id).await?;
+        if i64::from(seats) < assigned {
+            return Ok(SeatChange::BelowAssigned { assigned });
+        }
+
+        sqlx::query!(
+            "UPDATE user_subscriptions SET seats = $2, updated_at = NOW() WHERE id = $1",
+            user_subscription_id,
+            seats
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(SeatChange::Changed { seats })
+    }
+}
+
+/// Lock an active subscription's row and return its seat count.
+async fn lock_active_seats(
+    conn: &mut sqlx::PgConnection,
+    user_subscription_id: Uuid,
+) -> Result<Option<i32>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT seats FROM user_subscriptions WHERE id = $1 AND is_active = true FOR UPDATE",
+        user_subscription_id
+    )
+    .fetch_optional(conn)
+    .await
+}
+
+async fn assigned_count(
+    conn: &mut sqlx::PgConnection,
+    user_subscription_id: Uuid,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM subscription_seat_assignments
+        WHERE user_subscription_id = $1
+        "#,
+        user_subscription_id
+    )
+    .fetch_one(conn)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{BillingInterval, SubscriptionTier, UserSubscription};
+    use crate::test_support::{create_user, plan};
+
+    async fn subscription_with_members(pool: &PgPool, seats: i32, members: usize) -> Uuid {
+        let owner = create_user(pool).await;
+        let pro = plan(pool, SubscriptionTier::Pro).await;
+        let subscription = UserSubscription::create(pool, owner, pro.id, BillingInterval::Monthly)
+            .await
+            .unwrap();
+        assert_eq!(
+            SeatAssignment::change_seats(pool, subscription.id, seats).await.unwrap(),
+            SeatChange::Changed { seats }
+        );
+        for _ in 0..members {
+            let member = create_user(pool).await;
+            let outcome = SeatAssignment::assign(pool, subscription.id, member).await.unwrap();
+            assert!(matches!(outcome, SeatAssignmentOutcome::Assigned(_)));
+        }
+        subscription.id
+    }
+
+    #[sqlx::test]
+    async fn reducing_below_assigned_seats_is_rejected(pool: PgPool) {
+        let subscription_id = subscription_with_members(&pool, 5, 3).await;
+
+        let change = SeatAssignment::change_seats(&pool, subscription_id, 2).await.unwrap();
+        assert_eq!(change, SeatChange::BelowAssigned { assigned: 3 });
+        assert_eq!(SeatAssignment::usage(&pool, subscription_id).await.unwrap().seats, 5);
+    }
+
+    #[sqlx::test]
+    async fn reducing_to_exactly_the_assigned_seats_succeeds(pool: PgPool) {
+        let subscription_id = subscription_with_members(&pool, 5, 3).await;
+
+        let change = SeatAssignment::change_seats(&pool, subscription_id, 3).await.unwrap();
+        assert_eq!(change, SeatChange::Changed { seats: 3 });
+        assert_eq!(SeatAssignment::usage(&pool, subscription_id).await.unwrap().seats, 3);
+    }
+
+    #[sqlx::test]
+    async fn assigning_past_the_seat_count_is_refused(pool: PgPool) {
+        let subscription_id = subscription_with_members(&pool, 2, 2).await;
+        let member = create_user(&pool).await;
+
+        let outcome = SeatAssignment::assign(&pool, subscription_id, member).await.unwrap();
+        assert!(matches!(outcome, SeatAssignmentOutcome::NoFreeSeat));
+    }
+
+    #[sqlx::test]
+    async fn unassigning_frees_the_seat_for_a_reduction(pool: PgPool) {
+        let subscription_id = subscription_with_members(&pool, 3, 3).await;
+        let member = SeatAssignment::usage(&pool, subscription_id).await.unwrap().assigned[0].user_id;
+
+        assert!(SeatAssignment::unassign(&pool, subscription_id, member).await.unwrap());
+        let change = SeatAssignment::change_seats(&pool, subscription_id, 2).await.unwrap();
+        assert_eq!(change, SeatChange::Changed { seats: 2 });
+    }
+}
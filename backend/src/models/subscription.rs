@@ -2,27 +2,153 @@ use serde::{Deserialize, Serialize};
 use sqlx::types::JsonValue;
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
+use std::cmp::Ordering;
 
-#[derive(Debug, Serialize, Deserialize, sqlx::Type)]
+use super::{Money, SubscriptionCredit};
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Default,
+    Serialize,
+    Deserialize,
+    sqlx::Type,
+    async_graphql::Enum,
+)]
 #[sqlx(type_name = "subscription_tier", rename_all = "lowercase")]
 pub enum SubscriptionTier {
+    #[default]
     Free,
     Pro,
     Enterprise,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl SubscriptionTier {
+    pub const ALL: [SubscriptionTier; 3] = [
+        SubscriptionTier::Free,
+        SubscriptionTier::Pro,
+        SubscriptionTier::Enterprise,
+    ];
+
+    /// Numeric position in the tier hierarchy, starting at 0 for Free.
+    pub fn rank(self) -> u8 {
+        match self {
+            SubscriptionTier::Free => 0,
+            SubscriptionTier::Pro => 1,
+            SubscriptionTier::Enterprise => 2,
+        }
+    }
+
+    /// Whether a subscriber on this tier may access content that requires
+    /// `required`.
+    pub fn grants(self, required: SubscriptionTier) -> bool {
+        self >= required
+    }
+}
+
+/// Tiers are ordered by their position in the hierarchy (`Free < Pro < Enterprise`).
+impl Ord for SubscriptionTier {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.rank().cmp(&other.rank())
+    }
+}
+
+impl PartialOrd for SubscriptionTier {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Subscription {
+    pub id: Uuid,
+    pub name: String,
+    pub tier: SubscriptionTier,
+    /// In the currency's minor unit; see [`Subscription::price`].
+    pub price_monthly: i64,
+    pub price_yearly: i64,
+    /// ISO 4217 code the prices are expressed in.
+    pub currency: String,
+    /// Length of the free trial new subscribers get, if the plan has one.
+    pub trial_days: Option<i32>,
+    pub features: JsonValue,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A plan's `features` document. Keys not listed here are kept in `other`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlanFeatures {
+    /// Models a subscriber may own; `-1` means unlimited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_limit: Option<i64>,
+    /// `-1` means unlimited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requests_per_day: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub support: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_page_size: Option<i64>,
+    /// Selling points shown on the pricing page.
+    #[serde(default)]
+    pub features: Vec<String>,
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, JsonValue>,
+}
+
+/// A plan as listed to customers, with its features parsed and its prices
+/// in major units.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlanListing {
     pub id: Uuid,
     pub name: String,
     pub tier: SubscriptionTier,
     pub price_monthly: f64,
     pub price_yearly: f64,
-    pub features: JsonValue,
+    pub currency: String,
+    pub trial_days: Option<i32>,
+    pub features: PlanFeatures,
+    /// How much cheaper a year is than twelve months, when asked for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub yearly_savings_percent: Option<f64>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// How often a subscription is paid for. Each payment extends `ends_at` by
+/// one interval.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Default,
+    Serialize,
+    Deserialize,
+    sqlx::Type,
+    async_graphql::Enum,
+)]
+#[sqlx(type_name = "billing_interval", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum BillingInterval {
+    #[default]
+    Monthly,
+    Yearly,
+}
+
+impl BillingInterval {
+    pub fn months(self) -> i32 {
+        match self {
+            BillingInterval::Monthly => 1,
+            BillingInterval::Yearly => 12,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserSubscription {
     pub id: Uuid,
@@ -32,17 +158,104 @@ pub struct UserSubscription {
     pub ends_at: Option<DateTime<Utc>>,
     pub is_active: bool,
     pub payment_status: Option<String>,
+    pub cancel_at_period_end: bool,
+    pub current_period_end: Option<DateTime<Utc>>,
+    pub paused_at: Option<DateTime<Utc>>,
+    /// When a paused subscription resumes on its own.
+    pub resume_at: Option<DateTime<Utc>>,
+    /// Set while or after trialing; `payment_status` is `trialing` until then.
+    pub trial_ends_at: Option<DateTime<Utc>>,
+    pub billing_interval: BillingInterval,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// The result of moving a subscription to another plan.
+#[derive(Debug, Serialize)]
+pub struct PlanChange {
+    pub subscription: UserSubscription,
+    /// Prorated price difference for the rest of the period. Positive when
+    /// the user owes more, negative when they're owed a credit.
+    #[serde(serialize_with = "super::money::serialize_major")]
+    pub proration: Money,
+    pub credit: Option<SubscriptionCredit>,
+}
+
 impl Subscription {
+    /// The price for one `interval`.
+    pub fn price(&self, interval: BillingInterval) -> Money {
+        let amount = match interval {
+            BillingInterval::Monthly => self.price_monthly,
+            BillingInterval::Yearly => self.price_yearly,
+        };
+        Money::from_minor(amount, &self.currency)
+    }
+
+    /// The `features` document, parsed. A malformed one reads as empty.
+    pub fn plan_features(&self) -> PlanFeatures {
+        serde_json::from_value(self.features.clone()).unwrap_or_else(|e| {
+            tracing::warn!("plan {} has malformed features: {}", self.id, e);
+            PlanFeatures::default()
+        })
+    }
+
+    /// Percentage saved by paying yearly instead of twelve months, to two
+    /// decimal places. `None` for free plans.
+    pub fn yearly_savings_percent(&self) -> Option<f64> {
+        let twelve_months = self.price(BillingInterval::Monthly).minor_units() * 12;
+        if twelve_months <= 0 {
+            return None;
+        }
+        let saved = twelve_months - self.price(BillingInterval::Yearly).minor_units();
+        let percent = saved as f64 * 100.0 / twelve_months as f64;
+        Some((percent * 100.0).round() / 100.0)
+    }
+
+    pub fn listing(&self, with_savings: bool) -> PlanListing {
+        PlanListing {
+            id: self.id,
+            name: self.name.clone(),
+            tier: self.tier,
+            price_monthly: self.price(BillingInterval::Monthly).to_major(),
+            price_yearly: self.price(BillingInterval::Yearly).to_major(),
+            currency: self.currency.clone(),
+            trial_days: self.trial_days,
+            features: self.plan_features(),
+            yearly_savings_percent: if with_savings {
+                self.yearly_savings_percent()
+            } else {
+                None
+            },
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        }
+    }
+
+    /// What the user is told about the plan in tier-change notifications.
+    pub fn notification_details(&self) -> JsonValue {
+        serde_json::json!({
+            "subscription_id": self.id,
+            "plan": self.name,
+            "tier": self.tier,
+            "price_monthly": self.price(BillingInterval::Monthly).to_major(),
+            "currency": self.currency,
+        })
+    }
+
+    /// Largest `per_page` this plan allows on listings, from its features.
+    pub fn max_page_size(&self) -> Option<i64> {
+        self.features
+            .get("max_page_size")
+            .and_then(JsonValue::as_i64)
+            .filter(|size| *size > 0)
+    }
+
     pub async fn get_all(pool: &sqlx::PgPool) -> Result<Vec<Subscription>, sqlx::Error> {
         sqlx::query_as!(
             Subscription,
             r#"
             SELECT id, name, tier as "tier: SubscriptionTier",
-                   price_monthly, price_yearly, features,
+                   price_monthly, price_yearly, currency, trial_days, features,
                    created_at, updated_at
             FROM subscriptions
             ORDER BY price_monthly ASC
@@ -52,12 +265,28 @@ impl Subscription {
         .await
     }
 
+    /// A version stamp for the plan list that changes whenever a plan is
+    /// added, edited, or removed.
+    pub async fn catalog_version(pool: &sqlx::PgPool) -> Result<String, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            SELECT MAX(updated_at) AS last_updated, COUNT(*) AS "plans!"
+            FROM subscriptions
+            "#
+        )
+        .fetch_one(pool)
+        .await?;
+
+        let last_updated = row.last_updated.map(|t| t.timestamp_micros()).unwrap_or(0);
+        Ok(format!("{}-{}", last_updated, row.plans))
+    }
+
     pub async fn get_by_id(pool: &sqlx::PgPool, id: Uuid) -> Result<Option<Subscription>, sqlx::Error> {
         sqlx::query_as!(
             Subscription,
             r#"
             SELECT id, name, tier as "tier: SubscriptionTier",
-                   price_monthly, price_yearly, features,
+                   price_monthly, price_yearly, currency, trial_days, features,
                    created_at, updated_at
             FROM subscriptions
             WHERE id = $1
@@ -67,9 +296,142 @@ impl Subscription {
         .fetch_optional(pool)
         .await
     }
+
+    /// The cheapest plan on `tier`, whose features are what the tier means
+    /// when a user isn't on one of its plans (e.g. a paused Pro user is Free).
+    pub async fn get_by_tier(
+        pool: &sqlx::PgPool,
+        tier: SubscriptionTier,
+    ) -> Result<Option<Subscription>, sqlx::Error> {
+        sqlx::query_as!(
+            Subscription,
+            r#"
+            SELECT id, name, tier as "tier: SubscriptionTier",
+                   price_monthly, price_yearly, currency, trial_days, features,
+                   created_at, updated_at
+            FROM subscriptions
+            WHERE tier = $1
+            ORDER BY price_monthly, created_at
+            LIMIT 1
+            "#,
+            tier as _
+        )
+        .fetch_optional(pool)
+        .await
+    }
+}
+
+/// How long a subscription whose renewal failed keeps its tier while the
+/// charge is retried (`PAST_DUE_GRACE_DAYS`, default 7).
+pub fn past_due_grace_period() -> chrono::Duration {
+    let days = std::env::var("PAST_DUE_GRACE_DAYS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|days| *days >= 0)
+        .unwrap_or(7);
+    chrono::Duration::days(days)
 }
 
 impl UserSubscription {
+    pub fn is_paused(&self) -> bool {
+        self.paused_at.is_some()
+    }
+
+    /// Whether the payment status entitles the subscription to its plan's
+    /// tier at `now`: it's paid, trialing, or past due for less than the
+    /// grace period since the period it failed to renew. Subscriptions still
+    /// awaiting their first payment get nothing.
+    pub fn grants_plan_tier(&self, now: DateTime<Utc>) -> bool {
+        match self.payment_status.as_deref() {
+            Some("paid") | Some("trialing") => true,
+            Some("past_due") => {
+                let due = self
+                    .current_period_end
+                    .or(self.ends_at)
+                    .unwrap_or(self.updated_at);
+                now < due + past_due_grace_period()
+            }
+            Some("disputed") => !super::dispute_suspends_access(),
+            _ => false,
+        }
+    }
+
+    /// Resolve the tier a user currently has access to. Users without an
+    /// active, paid-up subscription are treated as Free, and those without
+    /// any active subscription are given a Free one on the way.
+    pub async fn tier_for_user(
+        pool: &sqlx::PgPool,
+        user_id: Uuid,
+    ) -> Result<SubscriptionTier, sqlx::Error> {
+        let Some(active) = Self::get_active_for_user(pool, user_id).await? else {
+            Self::ensure_free_tier(pool, user_id).await?;
+            return Ok(SubscriptionTier::Free);
+        };
+
+        if active.is_paused() || !active.grants_plan_tier(Utc::now()) {
+            return Ok(SubscriptionTier::Free);
+        }
+
+        let tier = Subscription::get_by_id(pool, active.subscription_id)
+            .await?
+            .map(|subscription| subscription.tier)
+            .unwrap_or_default();
+
+        Ok(tier)
+    }
+
+    /// Page size cap from the user's active plan, if it sets one. Users
+    /// without a subscription are put on Free first.
+    pub async fn max_page_size_for_user(
+        pool: &sqlx::PgPool,
+        user_id: Uuid,
+    ) -> Result<Option<i64>, sqlx::Error> {
+        let active = match Self::get_active_for_user(pool, user_id).await? {
+            Some(active) => Some(active),
+            None => {
+                Self::ensure_free_tier(pool, user_id).await?;
+                Self::get_active_for_user(pool, user_id).await?
+            }
+        };
+        let Some(active) = active else {
+            return Ok(None);
+        };
+
+        let max_page_size = Subscription::get_by_id(pool, active.subscription_id)
+            .await?
+            .and_then(|subscription| subscription.max_page_size());
+
+        Ok(max_page_size)
+    }
+
+    /// Number of users currently entitled to each tier. Users without an
+    /// active subscription count towards Free.
+    pub async fn user_counts_by_tier(
+        pool: &sqlx::PgPool,
+    ) -> Result<Vec<(SubscriptionTier, i64)>, sqlx::Error> {
+        let paid = sqlx::query!(
+            r#"
+            SELECT s.tier AS "tier: SubscriptionTier", COUNT(DISTINCT us.user_id) AS "users!"
+            FROM user_subscriptions us
+            JOIN subscriptions s ON s.id = us.subscription_id
+            WHERE us.is_active = true AND s.tier <> 'free'
+            GROUP BY s.tier
+            "#
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let total_users = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM users"#)
+            .fetch_one(pool)
+            .await?;
+
+        let paid_users: i64 = paid.iter().map(|row| row.users).sum();
+        let mut counts = vec![(SubscriptionTier::Free, (total_users - paid_users).max(0))];
+        counts.extend(paid.into_iter().map(|row| (row.tier, row.users)));
+
+        Ok(counts)
+    }
+
     pub async fn get_active_for_user(
         pool: &sqlx::PgPool,
         user_id: Uuid,
@@ -79,6 +441,9 @@ impl UserSubscription {
             r#"
             SELECT id, user_id, subscription_id, starts_at,
                    ends_at, is_active, payment_status,
+                   cancel_at_period_end, current_period_end,
+                   paused_at, resume_at, trial_ends_at,
+                   billing_interval AS "billing_interval: BillingInterval",
                    created_at, updated_at
             FROM user_subscriptions
             WHERE user_id = $1 AND is_active = true
@@ -91,30 +456,428 @@ impl UserSubscription {
         .await
     }
 
+    /// Every subscription the user has had, newest first, including
+    /// canceled and ended ones.
+    pub async fn list_for_user(
+        pool: &sqlx::PgPool,
+        user_id: Uuid,
+    ) -> Result<Vec<UserSubscription>, sqlx::Error> {
+        sqlx::query_as!(
+            UserSubscription,
+            r#"
+            SELECT id, user_id, subscription_id, starts_at,
+                   ends_at, is_active, payment_status,
+                   cancel_at_period_end, current_period_end,
+                   paused_at, resume_at, trial_ends_at,
+                   billing_interval AS "billing_interval: BillingInterval",
+                   created_at, updated_at
+            FROM user_subscriptions
+            WHERE user_id = $1
+            ORDER BY created_at DESC
+            "#,
+            user_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Subscribe the user to a plan. Plans with `trial_days` start out
+    /// `trialing` for users who haven't had a trial on any plan, or who
+    /// were granted another by an admin; everyone else pays right away.
+    /// Free plans have nothing to pay and start out `paid`.
     pub async fn create(
         pool: &sqlx::PgPool,
         user_id: Uuid,
         subscription_id: Uuid,
+        billing_interval: BillingInterval,
     ) -> Result<UserSubscription, sqlx::Error> {
         sqlx::query_as!(
             UserSubscription,
             r#"
+            WITH trial AS (
+                SELECT make_interval(days => s.trial_days) AS length
+                FROM subscriptions s
+                WHERE s.id = $2
+                AND s.trial_days IS NOT NULL
+                AND EXISTS (
+                    SELECT 1 FROM users u
+                    WHERE u.id = $1
+                    AND (u.trial_used_at IS NULL OR u.trial_exception)
+                )
+            ), used AS (
+                UPDATE users
+                SET trial_used_at = NOW(), trial_exception = false
+                WHERE id = $1 AND EXISTS (SELECT 1 FROM trial)
+            )
             INSERT INTO user_subscriptions (
                 user_id, subscription_id, starts_at,
-                is_active, payment_status
+                is_active, payment_status, trial_ends_at, billing_interval
             )
-            VALUES ($1, $2, NOW(), true, 'pending')
+            SELECT $1, $2, NOW(), true,
+                   CASE WHEN plan.price_monthly = 0 AND plan.price_yearly = 0 THEN 'paid'
+                        WHEN trial.length IS NULL THEN 'pending'
+                        ELSE 'trialing' END,
+                   NOW() + trial.length, $3
+            FROM subscriptions AS plan
+            LEFT JOIN trial ON true
+            WHERE plan.id = $2
             RETURNING id, user_id, subscription_id, starts_at,
                       ends_at, is_active, payment_status,
+                      cancel_at_period_end, current_period_end,
+                      paused_at, resume_at, trial_ends_at,
+                      billing_interval AS "billing_interval: BillingInterval",
                       created_at, updated_at
             "#,
             user_id,
-            subscription_id
+            subscription_id,
+            billing_interval as _
         )
         .fetch_one(pool)
         .await
     }
 
+    /// Let a user who has already trialed get one more trial on their next
+    /// subscription. Returns `false` if the user doesn't exist.
+    pub async fn grant_trial_exception(
+        pool: &sqlx::PgPool,
+        user_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "UPDATE users SET trial_exception = true, updated_at = NOW() WHERE id = $1",
+            user_id
+        )
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Give the user an active Free subscription unless they already have
+    /// an active one. Safe to call concurrently: only one row is ever
+    /// created. Returns `true` if this call created it.
+    pub async fn ensure_free_tier(pool: &sqlx::PgPool, user_id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO user_subscriptions (
+                user_id, subscription_id, starts_at, is_active
+            )
+            SELECT $1, id, NOW(), true
+            FROM subscriptions
+            WHERE tier = 'free'
+            ORDER BY created_at
+            LIMIT 1
+            ON CONFLICT (user_id) WHERE is_active = true DO NOTHING
+            "#,
+            user_id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Mark the subscription paid and extend `ends_at` by one billing
+    /// interval: from `starts_at` on the first payment, from the previous
+    /// end on renewals.
+    pub async fn activate<'e, E>(
+        executor: E,
+        user_id: Uuid,
+        subscription_id: Uuid,
+    ) -> Result<(), sqlx::Error>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        sqlx::query!(
+            r#"
+            UPDATE user_subscriptions
+            SET payment_status = 'paid',
+                ends_at = COALESCE(ends_at, starts_at) + CASE billing_interval
+                    WHEN 'yearly' THEN INTERVAL '1 year'
+                    ELSE INTERVAL '1 month'
+                END,
+                updated_at = NOW()
+            WHERE user_id = $1 AND subscription_id = $2 AND is_active = true
+            "#,
+            user_id,
+            subscription_id
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// Prorated difference between two plans' prices for the subscription's
+    /// billing interval, for what's left of the current period at `at`.
+    /// Both plans are expected to share a currency; `to`'s is used.
+    pub fn proration(&self, from: &Subscription, to: &Subscription, at: DateTime<Utc>) -> Money {
+        let interval = self.billing_interval;
+        Money::from_minor(
+            to.price(interval).minor_units() - from.price(interval).minor_units(),
+            &to.currency,
+        )
+        .scale(self.remaining_billing_fraction(at))
+    }
+
+    /// Move the subscription from `from` to `to`, keeping its billing period
+    /// and add-ons. A downgrade credits the unused difference if
+    /// `credit_downgrade`; Stripe credits the subscriptions it bills itself.
+    /// Returns `None` if the subscription changed plan or ended in the
+    /// meantime.
+    pub async fn change_plan(
+        pool: &sqlx::PgPool,
+        current: &UserSubscription,
+        from: &Subscription,
+        to: &Subscription,
+        at: DateTime<Utc>,
+        credit_downgrade: bool,
+    ) -> Result<Option<PlanChange>, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let Some(subscription) = sqlx::query_as!(
+            UserSubscription,
+            r#"
+            UPDATE user_subscriptions
+            SET subscription_id = $3,
+                updated_at = NOW()
+            WHERE id = $1 AND subscription_id = $2
+            AND is_active = true AND paused_at IS NULL
+            RETURNING id, user_id, subscription_id, starts_at,
+                      ends_at, is_active, payment_status,
+                      cancel_at_period_end, current_period_end,
+                      paused_at, resume_at, trial_ends_at,
+                      billing_interval AS "billing_interval: BillingInterval",
+                      created_at, updated_at
+            "#,
+            current.id,
+            from.id,
+            to.id
+        )
+        .fetch_optional(&mut tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        let proration = current.proration(from, to, at);
+        let credit = if credit_downgrade && proration.minor_units() < 0 {
+            let credit = sqlx::query_as!(
+                SubscriptionCredit,
+                r#"
+                INSERT INTO subscription_credits (user_id, amount, currency, reason)
+                VALUES ($1, $2, $3, $4)
+                RETURNING id, user_id, amount, currency, reason, payment_intent_id, created_at
+                "#,
+                current.user_id,
+                -proration.minor_units(),
+                from.currency,
+                format!(
+                    "Prorated credit for switching from {} to {}",
+                    from.name, to.name
+                )
+            )
+            .fetch_one(&mut tx)
+            .await?;
+            Some(credit)
+        } else {
+            None
+        };
+
+        tx.commit().await?;
+
+        Ok(Some(PlanChange {
+            subscription,
+            proration,
+            credit,
+        }))
+    }
+
+    /// Flag the subscription paid for by a disputed charge.
+    pub async fn flag_disputed(
+        pool: &sqlx::PgPool,
+        user_id: Uuid,
+        subscription_id: Uuid,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE user_subscriptions
+            SET payment_status = 'disputed',
+                updated_at = NOW()
+            WHERE user_id = $1 AND subscription_id = $2 AND is_active = true
+            "#,
+            user_id,
+            subscription_id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Settle a disputed subscription: restore it if we won, otherwise end
+    /// it, since the payment was reversed.
+    pub async fn resolve_dispute(
+        pool: &sqlx::PgPool,
+        user_id: Uuid,
+        subscription_id: Uuid,
+        won: bool,
+    ) -> Result<(), sqlx::Error> {
+        if won {
+            sqlx::query!(
+                r#"
+                UPDATE user_subscriptions
+                SET payment_status = 'paid',
+                    updated_at = NOW()
+                WHERE user_id = $1 AND subscription_id = $2
+                AND is_active = true AND payment_status = 'disputed'
+                "#,
+                user_id,
+                subscription_id
+            )
+            .execute(pool)
+            .await?;
+        } else {
+            sqlx::query!(
+                r#"
+                UPDATE user_subscriptions
+                SET payment_status = 'chargeback',
+                    is_active = false,
+                    ends_at = NOW(),
+                    updated_at = NOW()
+                WHERE user_id = $1 AND subscription_id = $2
+                AND is_active = true AND payment_status = 'disputed'
+                "#,
+                user_id,
+                subscription_id
+            )
+            .execute(pool)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// The Stripe subscription billing this one, if Stripe bills it.
+    pub async fn stripe_id(pool: &sqlx::PgPool, id: Uuid) -> Result<Option<String>, sqlx::Error> {
+        sqlx::query_scalar!(
+            "SELECT stripe_subscription_id FROM user_subscriptions WHERE id = $1",
+            id
+        )
+        .fetch_optional(pool)
+        .await
+        .map(Option::flatten)
+    }
+
+    /// Stripe subscription ids behind the user's active subscriptions.
+    pub async fn active_stripe_ids<'e, E>(
+        executor: E,
+        user_id: Uuid,
+    ) -> Result<Vec<String>, sqlx::Error>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        sqlx::query_scalar!(
+            r#"
+            SELECT stripe_subscription_id AS "stripe_subscription_id!"
+            FROM user_subscriptions
+            WHERE user_id = $1 AND is_active = true
+            AND stripe_subscription_id IS NOT NULL
+            "#,
+            user_id
+        )
+        .fetch_all(executor)
+        .await
+    }
+
+    /// End the subscription linked to a Stripe subscription that was
+    /// canceled on Stripe's side. Returns its user if it was active.
+    pub async fn end_by_stripe_id(
+        pool: &sqlx::PgPool,
+        stripe_subscription_id: &str,
+    ) -> Result<Option<Uuid>, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"
+            UPDATE user_subscriptions
+            SET payment_status = 'canceled',
+                is_active = false,
+                ends_at = NOW(),
+                updated_at = NOW()
+            WHERE stripe_subscription_id = $1 AND is_active = true
+            RETURNING user_id
+            "#,
+            stripe_subscription_id
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Flag the subscription while a payment for it settles. It isn't
+    /// activated until the payment succeeds.
+    pub async fn flag_payment_processing<'e, E>(
+        executor: E,
+        user_id: Uuid,
+        subscription_id: Uuid,
+    ) -> Result<(), sqlx::Error>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        sqlx::query!(
+            r#"
+            UPDATE user_subscriptions
+            SET payment_status = 'processing',
+                updated_at = NOW()
+            WHERE user_id = $1 AND subscription_id = $2 AND is_active = true
+            "#,
+            user_id,
+            subscription_id
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// Record that a payment which was settling has failed. Subscriptions
+    /// not waiting on one are left alone.
+    pub async fn fail_processing_payment<'e, E>(
+        executor: E,
+        user_id: Uuid,
+        subscription_id: Uuid,
+    ) -> Result<(), sqlx::Error>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        sqlx::query!(
+            r#"
+            UPDATE user_subscriptions
+            SET payment_status = 'failed',
+                updated_at = NOW()
+            WHERE user_id = $1 AND subscription_id = $2
+            AND is_active = true AND payment_status = 'processing'
+            "#,
+            user_id,
+            subscription_id
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// Flag the subscription linked to a Stripe subscription whose renewal
+    /// failed. It stays active while Stripe retries the charge.
+    pub async fn flag_past_due_by_stripe_id(
+        pool: &sqlx::PgPool,
+        stripe_subscription_id: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE user_subscriptions
+            SET payment_status = 'past_due',
+                updated_at = NOW()
+            WHERE stripe_subscription_id = $1 AND is_active = true
+            "#,
+            stripe_subscription_id
+        )
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
     pub async fn cancel(
         pool: &sqlx::PgPool,
         user_id: Uuid,
@@ -133,4 +896,470 @@ impl UserSubscription {
         .await?;
         Ok(())
     }
-} 
\ No newline at end of file
+
+    /// Schedule the active subscription to end when the current paid
+    /// period lapses (or, before the first payment, the current month). The subscription stays active until then.
+    pub async fn cancel_at_period_end(
+        pool: &sqlx::PgPool,
+        user_id: Uuid,
+    ) -> Result<Option<UserSubscription>, sqlx::Error> {
+        sqlx::query_as!(
+            UserSubscription,
+            r#"
+            UPDATE user_subscriptions
+            SET cancel_at_period_end = true,
+                current_period_end = COALESCE(ends_at, starts_at + make_interval(months =>
+                    (EXTRACT(YEAR FROM age(NOW(), starts_at)) * 12
+                        + EXTRACT(MONTH FROM age(NOW(), starts_at)))::int + 1)),
+                updated_at = NOW()
+            WHERE user_id = $1 AND is_active = true
+            RETURNING id, user_id, subscription_id, starts_at,
+                      ends_at, is_active, payment_status,
+                      cancel_at_period_end, current_period_end,
+                      paused_at, resume_at, trial_ends_at,
+                      billing_interval AS "billing_interval: BillingInterval",
+                      created_at, updated_at
+            "#,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Pause the active subscription until `resume_at`. It keeps its place
+    /// but grants no access and can't be billed while paused. Returns `None`
+    /// if there's no active, unpaused subscription.
+    pub async fn pause(
+        pool: &sqlx::PgPool,
+        user_id: Uuid,
+        resume_at: DateTime<Utc>,
+    ) -> Result<Option<UserSubscription>, sqlx::Error> {
+        sqlx::query_as!(
+            UserSubscription,
+            r#"
+            UPDATE user_subscriptions
+            SET paused_at = NOW(),
+                resume_at = $2,
+                updated_at = NOW()
+            WHERE user_id = $1 AND is_active = true AND paused_at IS NULL
+            RETURNING id, user_id, subscription_id, starts_at,
+                      ends_at, is_active, payment_status,
+                      cancel_at_period_end, current_period_end,
+                      paused_at, resume_at, trial_ends_at,
+                      billing_interval AS "billing_interval: BillingInterval",
+                      created_at, updated_at
+            "#,
+            user_id,
+            resume_at
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Resume a paused subscription. A scheduled cancellation is pushed back
+    /// by however long the pause lasted, so paused time isn't lost.
+    pub async fn unpause(
+        pool: &sqlx::PgPool,
+        user_id: Uuid,
+    ) -> Result<Option<UserSubscription>, sqlx::Error> {
+        sqlx::query_as!(
+            UserSubscription,
+            r#"
+            UPDATE user_subscriptions
+            SET current_period_end = current_period_end + (NOW() - paused_at),
+                ends_at = ends_at + (NOW() - paused_at),
+                paused_at = NULL,
+                resume_at = NULL,
+                updated_at = NOW()
+            WHERE user_id = $1 AND is_active = true AND paused_at IS NOT NULL
+            RETURNING id, user_id, subscription_id, starts_at,
+                      ends_at, is_active, payment_status,
+                      cancel_at_period_end, current_period_end,
+                      paused_at, resume_at, trial_ends_at,
+                      billing_interval AS "billing_interval: BillingInterval",
+                      created_at, updated_at
+            "#,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Resume paused subscriptions whose `resume_at` has passed. Returns the
+    /// number resumed.
+    pub async fn resume_due(pool: &sqlx::PgPool) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE user_subscriptions
+            SET current_period_end = current_period_end + (resume_at - paused_at),
+                ends_at = ends_at + (resume_at - paused_at),
+                paused_at = NULL,
+                resume_at = NULL,
+                updated_at = NOW()
+            WHERE is_active = true
+            AND paused_at IS NOT NULL
+            AND resume_at <= NOW()
+            "#
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Mark trials ending within `notice` as flagged, returning the user and
+    /// trial end of each so they can be warned. Each trial is flagged once.
+    pub async fn flag_trials_ending(
+        pool: &sqlx::PgPool,
+        notice: chrono::Duration,
+    ) -> Result<Vec<(Uuid, DateTime<Utc>)>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            UPDATE user_subscriptions
+            SET trial_ending_notified_at = NOW(),
+                updated_at = NOW()
+            WHERE is_active = true
+            AND payment_status = 'trialing'
+            AND trial_ending_notified_at IS NULL
+            AND trial_ends_at <= $1
+            RETURNING user_id, trial_ends_at AS "trial_ends_at!"
+            "#,
+            Utc::now() + notice
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.user_id, row.trial_ends_at))
+            .collect())
+    }
+
+    /// Move subscriptions whose trial is over to awaiting payment. Returns
+    /// the user and plan of each trial ended.
+    pub async fn end_trials(pool: &sqlx::PgPool) -> Result<Vec<(Uuid, Subscription)>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            UPDATE user_subscriptions us
+            SET payment_status = 'pending',
+                updated_at = NOW()
+            FROM subscriptions s
+            WHERE s.id = us.subscription_id
+            AND us.is_active = true
+            AND us.payment_status = 'trialing'
+            AND us.trial_ends_at <= NOW()
+            RETURNING us.user_id, s.id, s.name, s.tier AS "tier: SubscriptionTier",
+                      s.price_monthly, s.price_yearly, s.currency, s.trial_days,
+                      s.features, s.created_at, s.updated_at
+            "#
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let plan = Subscription {
+                    id: row.id,
+                    name: row.name,
+                    tier: row.tier,
+                    price_monthly: row.price_monthly,
+                    price_yearly: row.price_yearly,
+                    currency: row.currency,
+                    trial_days: row.trial_days,
+                    features: row.features,
+                    created_at: row.created_at,
+                    updated_at: row.updated_at,
+                };
+                (row.user_id, plan)
+            })
+            .collect())
+    }
+
+    /// Deactivate subscriptions whose paid period has ended without a
+    /// renewal. Paused subscriptions are left alone; resuming pushes
+    /// `ends_at` back by the time spent paused. Safe to run concurrently.
+    /// Returns the number of subscriptions deactivated.
+    pub async fn deactivate_expired(pool: &sqlx::PgPool) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE user_subscriptions
+            SET is_active = false,
+                updated_at = NOW()
+            WHERE is_active = true
+            AND paused_at IS NULL
+            AND ends_at <= NOW()
+            "#
+        )
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Deactivate subscriptions whose scheduled cancellation has come due.
+    /// Paused subscriptions are left alone until they resume.
+    /// Returns the number of subscriptions ended.
+    pub async fn expire_lapsed(pool: &sqlx::PgPool) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE user_subscriptions
+            SET is_active = false,
+                ends_at = current_period_end,
+                updated_at = NOW()
+            WHERE is_active = true
+            AND paused_at IS NULL
+            AND cancel_at_period_end = true
+            AND current_period_end <= NOW()
+            "#
+        )
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{create_user, plan};
+
+    #[test]
+    fn tiers_are_ordered_free_pro_enterprise() {
+        use SubscriptionTier::*;
+        assert!(Free < Pro && Pro < Enterprise);
+        let mut tiers = [Enterprise, Free, Pro];
+        tiers.sort();
+        assert_eq!(tiers, SubscriptionTier::ALL);
+    }
+
+    #[test]
+    fn a_tier_grants_itself_and_everything_below() {
+        for user_tier in SubscriptionTier::ALL {
+            for required in SubscriptionTier::ALL {
+                assert_eq!(
+                    user_tier.grants(required),
+                    required.rank() <= user_tier.rank(),
+                    "{:?} for {:?}",
+                    user_tier,
+                    required
+                );
+            }
+        }
+        assert!(!SubscriptionTier::Pro.grants(SubscriptionTier::Enterprise));
+        assert!(SubscriptionTier::Enterprise.grants(SubscriptionTier::Free));
+    }
+
+    fn subscription(payment_status: Option<&str>) -> UserSubscription {
+        let now = Utc::now();
+        UserSubscription {
+            id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            subscription_id: Uuid::new_v4(),
+            starts_at: now,
+            ends_at: None,
+            is_active: true,
+            payment_status: payment_status.map(str::to_string),
+            cancel_at_period_end: false,
+            current_period_end: None,
+            paused_at: None,
+            resume_at: None,
+            trial_ends_at: None,
+            billing_interval: BillingInterval::Monthly,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    #[test]
+    fn only_paid_up_subscriptions_grant_their_tier() {
+        let now = Utc::now();
+        assert!(subscription(Some("paid")).grants_plan_tier(now));
+        assert!(subscription(Some("trialing")).grants_plan_tier(now));
+        for status in [
+            None,
+            Some("pending"),
+            Some("processing"),
+            Some("failed"),
+            Some("canceled"),
+        ] {
+            assert!(!subscription(status).grants_plan_tier(now), "{:?}", status);
+        }
+    }
+
+    #[test]
+    fn past_due_subscriptions_keep_their_tier_during_the_grace_period() {
+        let now = Utc::now();
+        let mut past_due = subscription(Some("past_due"));
+        past_due.current_period_end = Some(now - chrono::Duration::days(1));
+        assert!(past_due.grants_plan_tier(now));
+
+        past_due.current_period_end =
+            Some(now - past_due_grace_period() - chrono::Duration::days(1));
+        assert!(!past_due.grants_plan_tier(now));
+    }
+
+    #[sqlx::test]
+    async fn pending_subscription_is_free_until_paid(pool: sqlx::PgPool) {
+        let user_id = create_user(&pool).await;
+        let pro = plan(&pool, SubscriptionTier::Pro).await;
+        let subscription =
+            UserSubscription::create(&pool, user_id, pro.id, BillingInterval::Monthly)
+                .await
+                .unwrap();
+        assert_eq!(subscription.payment_status.as_deref(), Some("pending"));
+
+        let tier = UserSubscription::tier_for_user(&pool, user_id)
+            .await
+            .unwrap();
+        assert_eq!(tier, SubscriptionTier::Free);
+
+        UserSubscription::activate(&pool, user_id, pro.id)
+            .await
+            .unwrap();
+        let tier = UserSubscription::tier_for_user(&pool, user_id)
+            .await
+            .unwrap();
+        assert_eq!(tier, SubscriptionTier::Pro);
+    }
+
+    #[sqlx::test]
+    async fn user_without_subscription_is_put_on_free(pool: sqlx::PgPool) {
+        let user_id = create_user(&pool).await;
+
+        let tier = UserSubscription::tier_for_user(&pool, user_id)
+            .await
+            .unwrap();
+        assert_eq!(tier, SubscriptionTier::Free);
+        assert!(UserSubscription::get_active_for_user(&pool, user_id)
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[sqlx::test]
+    async fn proration_uses_the_price_for_the_billing_interval(pool: sqlx::PgPool) {
+        let pro = plan(&pool, SubscriptionTier::Pro).await;
+        let enterprise = plan(&pool, SubscriptionTier::Enterprise).await;
+        let mut current = subscription(Some("paid"));
+        let start = current.starts_at;
+
+        let monthly = current.proration(&pro, &enterprise, start);
+        assert_eq!(
+            monthly.minor_units(),
+            enterprise.price_monthly - pro.price_monthly
+        );
+
+        current.billing_interval = BillingInterval::Yearly;
+        let yearly = current.proration(&pro, &enterprise, start);
+        assert_eq!(
+            yearly.minor_units(),
+            enterprise.price_yearly - pro.price_yearly
+        );
+
+        // Two months into the year, five sixths of it are left.
+        let later = current.proration(&pro, &enterprise, start + chrono::Months::new(2));
+        let expected = (enterprise.price_yearly - pro.price_yearly) as f64 * 10.0 / 12.0;
+        assert!((later.minor_units() as f64 - expected).abs() <= expected * 0.01);
+    }
+
+    #[sqlx::test]
+    async fn a_plan_with_trial_days_starts_subscribers_trialing(pool: sqlx::PgPool) {
+        let pro = plan(&pool, SubscriptionTier::Pro).await;
+        let enterprise = plan(&pool, SubscriptionTier::Enterprise).await;
+        sqlx::query!(
+            "UPDATE subscriptions SET trial_days = CASE WHEN id = $1 THEN 14 END",
+            pro.id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let (trialist, payer) = (create_user(&pool).await, create_user(&pool).await);
+
+        let trial = UserSubscription::create(&pool, trialist, pro.id, Default::default())
+            .await
+            .unwrap();
+        assert!(trial.is_active);
+        assert_eq!(trial.payment_status.as_deref(), Some("trialing"));
+        let length = trial.trial_ends_at.unwrap() - trial.starts_at;
+        assert_eq!(length.num_days(), 14);
+        assert_eq!(
+            UserSubscription::tier_for_user(&pool, trialist).await.unwrap(),
+            SubscriptionTier::Pro
+        );
+
+        let paid = UserSubscription::create(&pool, payer, enterprise.id, Default::default())
+            .await
+            .unwrap();
+        assert_eq!(paid.payment_status.as_deref(), Some("pending"));
+        assert_eq!(paid.trial_ends_at, None);
+        assert_eq!(
+            UserSubscription::tier_for_user(&pool, payer).await.unwrap(),
+            SubscriptionTier::Free
+        );
+    }
+
+    #[sqlx::test]
+    async fn only_a_first_subscription_or_an_admin_exception_gets_a_trial(pool: sqlx::PgPool) {
+        let pro = plan(&pool, SubscriptionTier::Pro).await;
+        let enterprise = plan(&pool, SubscriptionTier::Enterprise).await;
+        sqlx::query!("UPDATE subscriptions SET trial_days = 14")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let user_id = create_user(&pool).await;
+        let pool = &pool;
+        let resubscribe = |plan_id| async move {
+            UserSubscription::cancel(pool, user_id).await.unwrap();
+            UserSubscription::create(pool, user_id, plan_id, Default::default())
+                .await
+                .unwrap()
+        };
+
+        let first = resubscribe(pro.id).await;
+        assert_eq!(first.payment_status.as_deref(), Some("trialing"));
+
+        // The trial is per user, so another plan doesn't get one either.
+        for plan_id in [pro.id, enterprise.id] {
+            let returning = resubscribe(plan_id).await;
+            assert_eq!(returning.payment_status.as_deref(), Some("pending"));
+            assert_eq!(returning.trial_ends_at, None);
+        }
+
+        assert!(UserSubscription::grant_trial_exception(pool, user_id).await.unwrap());
+        let granted = resubscribe(enterprise.id).await;
+        assert_eq!(granted.payment_status.as_deref(), Some("trialing"));
+        let spent = resubscribe(pro.id).await;
+        assert_eq!(spent.payment_status.as_deref(), Some("pending"));
+
+        assert!(!UserSubscription::grant_trial_exception(pool, Uuid::new_v4()).await.unwrap());
+    }
+
+    fn plan_priced(price_monthly: i64, price_yearly: i64) -> Subscription {
+        let now = Utc::now();
+        Subscription {
+            id: Uuid::new_v4(),
+            name: "Pro".into(),
+            tier: SubscriptionTier::Pro,
+            price_monthly,
+            price_yearly,
+            currency: "USD".into(),
+            trial_days: None,
+            features: serde_json::json!({ "model_limit": 10, "badge": "gold" }),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    #[test]
+    fn a_year_priced_at_ten_months_saves_a_sixth() {
+        let pro = plan_priced(1999, 19990);
+        assert_eq!(pro.yearly_savings_percent(), Some(16.67));
+        assert_eq!(plan_priced(0, 0).yearly_savings_percent(), None);
+
+        let listing = pro.listing(true);
+        assert_eq!(listing.yearly_savings_percent, Some(16.67));
+        assert_eq!(listing.features.model_limit, Some(10));
+        assert_eq!(listing.features.other["badge"], "gold");
+        assert_eq!(pro.listing(false).yearly_savings_percent, None);
+    }
+}
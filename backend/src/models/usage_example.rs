@@ -0,0 +1,128 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+use super::AIModel;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    Python,
+    Rust,
+}
+
+/// A snippet template as stored under `metadata.usage_examples`.
+///
+/// Templates may use `{{id}}`, `{{name}}`, `{{version}}`,
+/// `{{repository_url}}` and `{{api_base}}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageExampleTemplate {
+    pub language: Language,
+    pub framework: Option<String>,
+    pub template: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsageExample {
+    pub language: Language,
+    pub framework: Option<String>,
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UsageExamplesQuery {
+    pub lang: Option<Language>,
+}
+
+const PYTHON_TEMPLATE: &str = r#"import requests
+
+MODEL_ID = "{{id}}"
+MODEL_VERSION = "{{version}}"
+
+# Fetch a download link for {{name}} {{version}}
+response = requests.get(f"{{api_base}}/api/models/{MODEL_ID}/download-url", timeout=30)
+response.raise_for_status()
+print(response.json()["url"])
+"#;
+
+const RUST_TEMPLATE: &str = r#"const MODEL_ID: &str = "{{id}}";
+const MODEL_VERSION: &str = "{{version}}";
+
+// Fetch a download link for {{name}} {{version}}
+let download: serde_json::Value =
+    reqwest::blocking::get(format!("{{api_base}}/api/models/{MODEL_ID}/download-url"))?
+        .error_for_status()?
+        .json()?;
+println!("{}", download["url"]);
+"#;
+
+/// Base URL of this API as seen by clients (`PUBLIC_API_URL`).
+pub fn public_api_url() -> String {
+    std::env::var("PUBLIC_API_URL")
+        .unwrap_or_else(|_| "http://localhost:3000".into())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+fn default_templates() -> Vec<UsageExampleTemplate> {
+    vec![
+        UsageExampleTemplate {
+            language: Language::Python,
+            framework: None,
+            template: PYTHON_TEMPLATE.into(),
+        },
+        UsageExampleTemplate {
+            language: Language::Rust,
+            framework: None,
+            template: RUST_TEMPLATE.into(),
+        },
+    ]
+}
+
+impl AIModel {
+    /// Templates from `metadata.usage_examples`, ignoring malformed entries.
+    fn usage_example_templates(&self) -> Vec<UsageExampleTemplate> {
+        match self.metadata.get("usage_examples") {
+            Some(JsonValue::Array(entries)) => entries
+                .iter()
+                .filter_map(|entry| serde_json::from_value(entry.clone()).ok())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Render the model's snippets, falling back to the built-in ones for
+    /// any language the owner hasn't provided.
+    pub fn usage_examples(&self, lang: Option<Language>) -> Vec<UsageExample> {
+        let mut templates = self.usage_example_templates();
+        for default in default_templates() {
+            if !templates.iter().any(|t| t.language == default.language) {
+                templates.push(default);
+            }
+        }
+
+        templates
+            .into_iter()
+            .filter(|template| lang.map_or(true, |lang| template.language == lang))
+            .map(|template| UsageExample {
+                language: template.language,
+                framework: template.framework,
+                code: self.render_usage_template(&template.template),
+            })
+            .collect()
+    }
+
+    fn render_usage_template(&self, template: &str) -> String {
+        let repository_url = self.repository_url.clone().unwrap_or_default();
+        [
+            ("{{id}}", self.id.to_string()),
+            ("{{name}}", self.name.clone()),
+            ("{{version}}", self.version.clone()),
+            ("{{repository_url}}", repository_url),
+            ("{{api_base}}", public_api_url()),
+        ]
+        .iter()
+        .fold(template.to_string(), |code, (placeholder, value)| {
+            code.replace(placeholder, value)
+        })
+    }
+}
@@ -0,0 +1,62 @@
+use std::fmt;
+
+/// Field-level validation failures collected while checking a request body.
+#[derive(Debug, Default)]
+pub struct ValidationErrors {
+    errors: Vec<(&'static str, String)>,
+}
+
+impl ValidationErrors {
+    pub fn add(&mut self, field: &'static str, message: impl Into<String>) {
+        self.errors.push((field, message.into()));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn into_result(self) -> Result<(), Self> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (field, message)) in self.errors.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}: {}", field, message)?;
+        }
+        Ok(())
+    }
+}
+
+/// Request input that can check itself, e.g. query parameters taken with
+/// `ValidatedQuery`.
+pub trait Validate {
+    fn validate(&self) -> Result<(), ValidationErrors>;
+}
+
+/// Accepts `1`, `1.2`, `1.2.3`, an optional leading `v`, and a
+/// pre-release/build suffix such as `1.2.3-beta.1`.
+pub fn is_semver_like(version: &str) -> bool {
+    let version = version.strip_prefix('v').unwrap_or(version);
+    let core = version.split(['-', '+']).next().unwrap_or_default();
+    let parts: Vec<&str> = core.split('.').collect();
+
+    (1..=3).contains(&parts.len())
+        && parts
+            .iter()
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+pub fn is_http_url(value: &str) -> bool {
+    url::Url::parse(value)
+        .map(|url| matches!(url.scheme(), "http" | "https") && url.host().is_some())
+        .unwrap_or(false)
+}
@@ -0,0 +1,118 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::{Json, JsonValue};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::ListQueryParams;
+
+/// A named `ListQueryParams` preset saved by a user.
+#[derive(Debug, Serialize)]
+pub struct ModelView {
+    pub id: Uuid,
+    pub name: String,
+    pub query: ListQueryParams,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SaveModelView {
+    pub name: String,
+    pub query: ListQueryParams,
+}
+
+struct ModelViewRow {
+    id: Uuid,
+    name: String,
+    query_json: JsonValue,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<ModelViewRow> for ModelView {
+    fn from(row: ModelViewRow) -> Self {
+        Self {
+            id: row.id,
+            name: row.name,
+            // Presets are written by `save`, so a parse failure means the row
+            // predates a params change; fall back to no filters.
+            query: serde_json::from_value(row.query_json).unwrap_or_default(),
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }
+    }
+}
+
+impl ModelView {
+    pub async fn list_for_user(pool: &PgPool, user_id: Uuid) -> Result<Vec<Self>, sqlx::Error> {
+        let rows = sqlx::query_as!(
+            ModelViewRow,
+            r#"
+            SELECT id, name, query_json, created_at, updated_at
+            FROM model_views
+            WHERE user_id = $1
+            ORDER BY name
+            "#,
+            user_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    pub async fn find(
+        pool: &PgPool,
+        user_id: Uuid,
+        name: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let row = sqlx::query_as!(
+            ModelViewRow,
+            r#"
+            SELECT id, name, query_json, created_at, updated_at
+            FROM model_views
+            WHERE user_id = $1 AND name = $2
+            "#,
+            user_id,
+            name
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(row.map(Into::into))
+    }
+
+    /// Create or replace the user's view with this name.
+    pub async fn save(
+        pool: &PgPool,
+        user_id: Uuid,
+        view: SaveModelView,
+    ) -> Result<Self, sqlx::Error> {
+        // A preset can't refer to another preset, and a sync checkpoint
+        // belongs to the client, not the view.
+        let query = ListQueryParams {
+            view: None,
+            updated_after: None,
+            ..view.query
+        };
+
+        let row = sqlx::query_as!(
+            ModelViewRow,
+            r#"
+            INSERT INTO model_views (user_id, name, query_json)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_id, name)
+            DO UPDATE SET query_json = EXCLUDED.query_json, updated_at = NOW()
+            RETURNING id, name, query_json, created_at, updated_at
+            "#,
+            user_id,
+            view.name,
+            Json(&query) as _
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(row.into())
+    }
+}
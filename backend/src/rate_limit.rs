@@ -0,0 +1,458 @@
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, MatchedPath, Request, State},
+    http::{request::Parts, Method},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use uuid::Uuid;
+
+use crate::{auth::Caller, error::AppError, AppState};
+
+/// Past this many tracked keys, idle ones are swept on the next acquire.
+const SWEEP_THRESHOLD: usize = 10_000;
+
+/// Sliding-window limiter keyed by user: at most `limit` acquisitions per
+/// `window`. State is per process.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    limit: usize,
+    window: Duration,
+    hits: Arc<Mutex<HashMap<Uuid, VecDeque<Instant>>>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: usize, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            hits: Default::default(),
+        }
+    }
+
+    /// Review submissions per user, `REVIEW_RATE_LIMIT_PER_HOUR` (default 10).
+    pub fn reviews_from_env() -> Self {
+        let limit = env::var("REVIEW_RATE_LIMIT_PER_HOUR")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10);
+        Self::new(limit, Duration::from_secs(3600))
+    }
+
+    /// Records a hit for `key`, or returns how long until the oldest hit in
+    /// the window expires if the limit has been reached.
+    pub fn try_acquire(&self, key: Uuid) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut hits = self.hits.lock().unwrap_or_else(|e| e.into_inner());
+
+        if hits.len() > SWEEP_THRESHOLD {
+            hits.retain(|_, times| {
+                times
+                    .back()
+                    .is_some_and(|last| now.duration_since(*last) < self.window)
+            });
+        }
+
+        let times = hits.entry(key).or_default();
+        while times
+            .front()
+            .is_some_and(|first| now.duration_since(*first) >= self.window)
+        {
+            times.pop_front();
+        }
+
+        if times.len() >= self.limit {
+            let oldest = times.front().copied().unwrap_or(now);
+            return Err(self.window.saturating_sub(now.duration_since(oldest)));
+        }
+        times.push_back(now);
+        Ok(())
+    }
+}
+
+/// Who a bucket belongs to: the signed-in user, or the client address for
+/// anonymous requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateKey {
+    User(Uuid),
+    Ip(IpAddr),
+}
+
+impl fmt::Display for RateKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RateKey::User(id) => write!(f, "user:{}", id),
+            RateKey::Ip(ip) => write!(f, "ip:{}", ip),
+        }
+    }
+}
+
+/// Routes limited together. Each caller gets one bucket per class.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RouteClass {
+    Payments,
+    Downloads,
+    Checksums,
+}
+
+impl RouteClass {
+    const ALL: [RouteClass; 3] = [
+        RouteClass::Payments,
+        RouteClass::Downloads,
+        RouteClass::Checksums,
+    ];
+
+    /// The class of a matched route, if it is limited at all.
+    fn of(method: &Method, path: &str) -> Option<Self> {
+        match (method, path) {
+            (&Method::POST, "/api/payments/create-intent") => Some(RouteClass::Payments),
+            (&Method::POST, "/api/models/:id/downloads") => Some(RouteClass::Downloads),
+            (&Method::POST, "/api/models/:id/verify-checksum") => Some(RouteClass::Checksums),
+            _ => None,
+        }
+    }
+
+    /// Names the class in shared Redis bucket keys.
+    #[cfg(feature = "redis")]
+    fn name(self) -> &'static str {
+        match self {
+            RouteClass::Payments => "payments",
+            RouteClass::Downloads => "downloads",
+            RouteClass::Checksums => "checksums",
+        }
+    }
+
+    fn env_var(self) -> &'static str {
+        match self {
+            RouteClass::Payments => "RATE_LIMIT_PAYMENTS_PER_MINUTE",
+            RouteClass::Downloads => "RATE_LIMIT_DOWNLOADS_PER_MINUTE",
+            RouteClass::Checksums => "RATE_LIMIT_CHECKSUMS_PER_MINUTE",
+        }
+    }
+
+    fn default_per_minute(self) -> u32 {
+        match self {
+            RouteClass::Payments => 10,
+            RouteClass::Downloads => 60,
+            RouteClass::Checksums => 30,
+        }
+    }
+}
+
+/// A bucket holds up to `capacity` tokens and regains one every
+/// `refill_every`.
+#[derive(Debug, Clone, Copy)]
+pub struct BucketSettings {
+    pub capacity: u32,
+    pub refill_every: Duration,
+}
+
+impl BucketSettings {
+    /// `per_minute` requests a minute, all of which may arrive at once.
+    pub fn per_minute(per_minute: u32) -> Self {
+        let capacity = per_minute.max(1);
+        Self {
+            capacity,
+            refill_every: Duration::from_secs(60) / capacity,
+        }
+    }
+
+    /// How long an untouched bucket takes to fill back up.
+    fn full_after(&self) -> Duration {
+        self.refill_every * self.capacity
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Refill for the time since the last update, then take a token or
+    /// return how long until the next one.
+    fn take(&mut self, settings: BucketSettings, now: Instant) -> Result<(), Duration> {
+        let refilled =
+            now.duration_since(self.updated).as_secs_f64() / settings.refill_every.as_secs_f64();
+        self.tokens = (self.tokens + refilled).min(f64::from(settings.capacity));
+        self.updated = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(settings.refill_every.mul_f64(1.0 - self.tokens))
+        }
+    }
+}
+
+/// Same algorithm as [`Bucket::take`], run atomically in Redis so every
+/// instance shares one bucket. Returns the wait in milliseconds, 0 when a
+/// token was taken.
+#[cfg(feature = "redis")]
+const TAKE_TOKEN_SCRIPT: &str = r#"
+local capacity = tonumber(ARGV[1])
+local refill_ms = tonumber(ARGV[2])
+local now = tonumber(ARGV[3])
+local state = redis.call('HMGET', KEYS[1], 'tokens', 'updated')
+local tokens = tonumber(state[1]) or capacity
+local updated = tonumber(state[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - updated) / refill_ms)
+local wait = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+else
+    wait = math.ceil((1 - tokens) * refill_ms)
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'updated', now)
+redis.call('PEXPIRE', KEYS[1], math.ceil(capacity * refill_ms))
+return wait
+"#;
+
+#[derive(Clone)]
+enum BucketStore {
+    Memory(Arc<Mutex<HashMap<(RouteClass, RateKey), Bucket>>>),
+    #[cfg(feature = "redis")]
+    Redis(redis::aio::ConnectionManager),
+}
+
+/// Token-bucket limits on abuse-prone routes, keyed by user id and falling
+/// back to the client IP for anonymous callers.
+///
+/// | Variable | Meaning |
+/// | --- | --- |
+/// | `RATE_LIMIT_PAYMENTS_PER_MINUTE` | `POST /api/payments/create-intent` (default 10). |
+/// | `RATE_LIMIT_DOWNLOADS_PER_MINUTE` | `POST /api/models/:id/downloads` (default 60). |
+/// | `RATE_LIMIT_CHECKSUMS_PER_MINUTE` | `POST /api/models/:id/verify-checksum` (default 30). |
+/// | `RATE_LIMIT_TRUST_FORWARDED_FOR` | Take the client IP from `X-Forwarded-For`; only behind a proxy that sets it (default false). |
+/// | `RATE_LIMIT_REDIS_URL` | Share buckets across instances through Redis. Needs the `redis` feature; buckets are per process otherwise. |
+#[derive(Clone)]
+pub struct RouteLimiter {
+    limits: HashMap<RouteClass, BucketSettings>,
+    store: BucketStore,
+    trust_forwarded_for: bool,
+}
+
+impl RouteLimiter {
+    pub async fn from_env() -> Self {
+        let limits = RouteClass::ALL
+            .into_iter()
+            .map(|class| {
+                let per_minute = env::var(class.env_var())
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(class.default_per_minute());
+                (class, BucketSettings::per_minute(per_minute))
+            })
+            .collect();
+
+        Self {
+            limits,
+            store: BucketStore::from_env().await,
+            trust_forwarded_for: env::var("RATE_LIMIT_TRUST_FORWARDED_FOR")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(false),
+        }
+    }
+
+    /// Takes a token from `key`'s bucket for `class`, or returns how long
+    /// until one is available.
+    pub async fn try_acquire(&self, class: RouteClass, key: RateKey) -> Result<(), Duration> {
+        let settings = self.limits[&class];
+        match &self.store {
+            BucketStore::Memory(buckets) => {
+                let now = Instant::now();
+                let mut buckets = buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+                if buckets.len() > SWEEP_THRESHOLD {
+                    buckets.retain(|(class, _), bucket| {
+                        now.duration_since(bucket.updated) < self.limits[class].full_after()
+                    });
+                }
+
+                buckets
+                    .entry((class, key))
+                    .or_insert(Bucket {
+                        tokens: f64::from(settings.capacity),
+                        updated: now,
+                    })
+                    .take(settings, now)
+            }
+            #[cfg(feature = "redis")]
+            BucketStore::Redis(conn) => {
+                let now_ms = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64;
+                let wait_ms: Result<u64, _> = redis::Script::new(TAKE_TOKEN_SCRIPT)
+                    .key(format!("rate_limit:{}:{}", class.name(), key))
+                    .arg(settings.capacity)
+                    .arg(settings.refill_every.as_secs_f64() * 1000.0)
+                    .arg(now_ms)
+                    .invoke_async(&mut conn.clone())
+                    .await;
+                match wait_ms {
+                    Ok(0) => Ok(()),
+                    Ok(wait_ms) => Err(Duration::from_millis(wait_ms)),
+                    // An unreachable Redis shouldn't take the routes down with it.
+                    Err(e) => {
+                        tracing::warn!("rate limiter: redis unavailable, allowing request: {}", e);
+                        Ok(())
+                    }
+                }
+            }
+        }
+    }
+
+    fn client_ip(&self, parts: &Parts) -> Option<IpAddr> {
+        if self.trust_forwarded_for {
+            let forwarded = parts
+                .headers
+                .get("x-forwarded-for")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.split(',').next())
+                .and_then(|first| first.trim().parse().ok());
+            if forwarded.is_some() {
+                return forwarded;
+            }
+        }
+        parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip())
+    }
+}
+
+impl BucketStore {
+    #[cfg(feature = "redis")]
+    async fn from_env() -> Self {
+        let Ok(url) = env::var("RATE_LIMIT_REDIS_URL") else {
+            return BucketStore::Memory(Default::default());
+        };
+        let client = redis::Client::open(url).expect("RATE_LIMIT_REDIS_URL must be a Redis URL");
+        let conn = redis::aio::ConnectionManager::new(client)
+            .await
+            .expect("Failed to connect to the rate limit Redis");
+        BucketStore::Redis(conn)
+    }
+
+    #[cfg(not(feature = "redis"))]
+    async fn from_env() -> Self {
+        if env::var_os("RATE_LIMIT_REDIS_URL").is_some() {
+            tracing::warn!("RATE_LIMIT_REDIS_URL is set but the redis feature is off; limiting per process");
+        }
+        BucketStore::Memory(Default::default())
+    }
+}
+
+/// Applies [`RouteLimiter`] to the routes in [`RouteClass`], answering
+/// `429` with `Retry-After` once a caller's bucket is empty. Needs
+/// `MatchedPath`, so install it with `route_layer`.
+pub async fn limit_requests(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let class = request
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|path| RouteClass::of(request.method(), path.as_str()));
+    let Some(class) = class else {
+        return next.run(request).await;
+    };
+
+    let (mut parts, body) = request.into_parts();
+    let key = match Caller::from_request_parts(&mut parts, &state).await {
+        Ok(caller) => Some(RateKey::User(caller.user_id)),
+        Err(_) => state.route_limiter.client_ip(&parts).map(RateKey::Ip),
+    };
+    if let Some(key) = key {
+        if let Err(retry_after) = state.route_limiter.try_acquire(class, key).await {
+            return AppError::TooManyRequests {
+                message: "Too many requests; try again later".into(),
+                retry_after,
+            }
+            .into_response();
+        }
+    }
+
+    next.run(Request::from_parts(parts, body)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::app_state;
+    use axum::{body::Body, http::StatusCode, middleware, routing::post, Router};
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    #[test]
+    fn a_bucket_empties_after_its_capacity_and_refills_over_time() {
+        let settings = BucketSettings::per_minute(3);
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            tokens: 3.0,
+            updated: start,
+        };
+
+        for _ in 0..3 {
+            assert!(bucket.take(settings, start).is_ok());
+        }
+        assert_eq!(bucket.take(settings, start), Err(Duration::from_secs(20)));
+
+        let later = start + Duration::from_secs(20);
+        assert!(bucket.take(settings, later).is_ok());
+        assert!(bucket.take(settings, later).is_err());
+
+        // An idle bucket fills up to its capacity and no further.
+        let much_later = later + Duration::from_secs(3600);
+        for _ in 0..3 {
+            assert!(bucket.take(settings, much_later).is_ok());
+        }
+        assert!(bucket.take(settings, much_later).is_err());
+    }
+
+    #[sqlx::test]
+    async fn the_request_past_the_limit_gets_a_429_with_retry_after(pool: PgPool) {
+        let mut state = app_state(&pool).await;
+        state.route_limiter = RouteLimiter {
+            limits: RouteClass::ALL
+                .into_iter()
+                .map(|class| (class, BucketSettings::per_minute(2)))
+                .collect(),
+            store: BucketStore::Memory(Default::default()),
+            trust_forwarded_for: true,
+        };
+        let app = Router::new()
+            .route("/api/models/:id/downloads", post(|| async { StatusCode::OK }))
+            .route_layer(middleware::from_fn_with_state(state.clone(), limit_requests))
+            .with_state(state);
+        let download = |ip: &'static str| {
+            let request = Request::builder()
+                .method(Method::POST)
+                .uri(format!("/api/models/{}/downloads", Uuid::new_v4()))
+                .header("x-forwarded-for", ip)
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+
+        for _ in 0..2 {
+            assert_eq!(download("203.0.113.7").await.unwrap().status(), StatusCode::OK);
+        }
+        let limited = download("203.0.113.7").await.unwrap();
+        assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(limited.headers()["retry-after"], "30");
+
+        assert_eq!(download("198.51.100.1").await.unwrap().status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,77 @@
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use tracing::Instrument;
+use uuid::Uuid;
+
+pub static REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest client-supplied id we'll reuse; anything longer gets replaced.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+fn is_acceptable(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LENGTH
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+}
+
+/// Reuses the caller's `X-Request-Id` (or generates one), echoes it on the
+/// response, and runs the rest of the request inside a span carrying it.
+/// `user_id` is filled in by the auth extractors once the caller is known.
+pub async fn propagate_request_id(request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_acceptable(id))
+        .map(str::to_owned)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let span = tracing::info_span!(
+        "request",
+        request_id = %id,
+        user_id = tracing::field::Empty,
+    );
+
+    let mut response = next.run(request).instrument(span).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER.clone(), value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    async fn request_id_for(header: Option<&str>) -> String {
+        let app = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(middleware::from_fn(propagate_request_id));
+        let mut request = Request::builder().uri("/");
+        if let Some(id) = header {
+            request = request.header(&REQUEST_ID_HEADER, id);
+        }
+
+        let response = app.oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+        response.headers()[&REQUEST_ID_HEADER].to_str().unwrap().to_owned()
+    }
+
+    #[tokio::test]
+    async fn echoes_a_provided_request_id() {
+        assert_eq!(request_id_for(Some("checkout-42")).await, "checkout-42");
+    }
+
+    #[tokio::test]
+    async fn replaces_a_missing_or_unusable_request_id() {
+        assert!(Uuid::parse_str(&request_id_for(None).await).is_ok());
+        assert!(Uuid::parse_str(&request_id_for(Some("not ok")).await).is_ok());
+    }
+}
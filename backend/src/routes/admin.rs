@@ -0,0 +1,269 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::{get, post, put},
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    auth::AdminUser,
+    error::AppError,
+    models::{
+        payment::{PaymentHistory, PaymentMethod},
+        subscription::{Subscription, UserSubscription},
+        ChangeSeatsRequest, OpenStaleReport, PaymentDispute, SeatAssignment, SeatChange,
+        UserDownload,
+    },
+    services::stripe::CustomerReconciliation,
+    AppState,
+};
+
+const DEFAULT_LIMIT: i64 = 10;
+const MAX_LIMIT: i64 = 50;
+
+pub fn admin_routes() -> Router<AppState> {
+    Router::new()
+        .route("/admin/users/:id/diagnostics", get(user_diagnostics))
+        .route("/admin/users/:id/trial-exception", post(grant_trial_exception))
+        .route(
+            "/admin/users/:id/stripe-customers/reconcile",
+            post(reconcile_stripe_customers),
+        )
+        .route("/admin/subscriptions/:id/seats", put(change_seats))
+}
+
+#[derive(Debug, Deserialize)]
+struct DiagnosticsQuery {
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct SubscriptionDiagnostics {
+    plan: Option<Subscription>,
+    #[serde(flatten)]
+    user_subscription: UserSubscription,
+}
+
+/// A payment method with the Stripe id shortened to its tail, enough to find
+/// it in the dashboard without exposing the full reference.
+#[derive(Debug, Serialize)]
+struct MaskedPaymentMethod {
+    id: Uuid,
+    stripe_payment_method_id: String,
+    card_brand: Option<String>,
+    card_last4: Option<String>,
+    card_exp_month: Option<i32>,
+    card_exp_year: Option<i32>,
+    is_default: bool,
+    created_at: DateTime<Utc>,
+}
+
+fn mask_id(id: &str) -> String {
+    let tail = &id[id.char_indices().rev().nth(3).map_or(0, |(i, _)| i)..];
+    match id.split_once('_') {
+        Some((prefix, _)) => format!("{}_...{}", prefix, tail),
+        None => format!("...{}", tail),
+    }
+}
+
+impl From<PaymentMethod> for MaskedPaymentMethod {
+    fn from(method: PaymentMethod) -> Self {
+        Self {
+            id: method.id,
+            stripe_payment_method_id: mask_id(&method.stripe_payment_method_id),
+            card_brand: method.card_brand,
+            card_last4: method.card_last4,
+            card_exp_month: method.card_exp_month,
+            card_exp_year: method.card_exp_year,
+            is_default: method.is_default,
+            created_at: method.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct UserDiagnostics {
+    user_id: Uuid,
+    subscription: Option<SubscriptionDiagnostics>,
+    payments: Vec<PaymentHistory>,
+    payment_methods: Vec<MaskedPaymentMethod>,
+    downloads: Vec<UserDownload>,
+    open_reports: Vec<OpenStaleReport>,
+    open_disputes: Vec<PaymentDispute>,
+}
+
+/// Everything support usually needs about an account, in one call.
+#[axum::debug_handler(state = AppState)]
+async fn user_diagnostics(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(user_id): Path<Uuid>,
+    Query(query): Query<DiagnosticsQuery>,
+) -> Result<Json<UserDiagnostics>, AppError> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    let subscription = match UserSubscription::get_active_for_user(&state.pool, user_id).await? {
+        Some(user_subscription) => Some(SubscriptionDiagnostics {
+            plan: Subscription::get_by_id(&state.pool, user_subscription.subscription_id).await?,
+            user_subscription,
+        }),
+        None => None,
+    };
+
+    let payments = PaymentHistory::get_for_user(&state.pool, user_id, limit, 0).await?;
+    let payment_methods = PaymentMethod::list_for_user(&state.pool, user_id)
+        .await?
+        .into_iter()
+        .map(MaskedPaymentMethod::from)
+        .collect();
+    let downloads = state.repo.downloads_for_user(user_id, limit).await?;
+    let open_reports = state.repo.open_stale_reports_by_user(user_id).await?;
+    let open_disputes = PaymentDispute::list_open_for_user(&state.pool, user_id).await?;
+
+    Ok(Json(UserDiagnostics {
+        user_id,
+        subscription,
+        payments,
+        payment_methods,
+        downloads,
+        open_reports,
+        open_disputes,
+    }))
+}
+
+/// Allow a user who has already had a free trial one more.
+async fn grant_trial_exception(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(user_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    if !UserSubscription::grant_trial_exception(&state.pool, user_id).await? {
+        return Err(AppError::NotFound("User not found".into()));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Collapse a user's duplicate Stripe customers onto one.
+async fn reconcile_stripe_customers(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<CustomerReconciliation>, AppError> {
+    let reconciliation = state.stripe_service.reconcile_customers(user_id).await?;
+    Ok(Json(reconciliation))
+}
+
+#[derive(Debug, Serialize)]
+struct SeatsResponse {
+    seats: i32,
+}
+
+/// Set how many seats a subscription has. Members keep their seats, so it
+/// can't go below the number assigned.
+async fn change_seats(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(user_subscription_id): Path<Uuid>,
+    Json(request): Json<ChangeSeatsRequest>,
+) -> Result<Json<SeatsResponse>, AppError> {
+    if request.seats < 1 {
+        return Err(AppError::BadRequest("seats must be at least 1".into()));
+    }
+
+    match SeatAssignment::change_seats(&state.pool, user_subscription_id, request.seats).await? {
+        SeatChange::Changed { seats } => Ok(Json(SeatsResponse { seats })),
+        SeatChange::BelowAssigned { assigned } => Err(AppError::conflict(format!(
+            "{} seats are assigned to members; unassign members before reducing seats below that",
+            assigned
+        ))),
+        SeatChange::NotFound => Err(AppError::NotFound("Subscription not found".into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{payment::PaymentIntent, Money, SubscriptionTier, UtmParams};
+    use crate::test_support::{app_state, create_user, new_model, published, subscribe};
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    async fn diagnostics_cover_every_part_of_the_account(pool: PgPool) {
+        let user_id = create_user(&pool).await;
+        let subscription = subscribe(&pool, user_id, SubscriptionTier::Pro).await;
+        let intent = PaymentIntent::create(
+            &pool,
+            user_id,
+            subscription.subscription_id,
+            "pi_diagnosed".into(),
+            &Money::from_minor(1999, "USD"),
+            &Money::from_minor(0, "USD"),
+            "pi_diagnosed_secret_do_not_show".into(),
+            None,
+        )
+        .await
+        .unwrap();
+        PaymentHistory::create(
+            &pool,
+            user_id,
+            subscription.subscription_id,
+            intent.id,
+            &intent.total(),
+            "succeeded",
+        )
+        .await
+        .unwrap();
+        PaymentMethod::create(&pool, user_id, "pm_1234567890abcd".into(), None)
+            .await
+            .unwrap();
+        let state = app_state(&pool).await;
+        let model = published(&pool, create_user(&pool).await, new_model("Used")).await;
+        state
+            .repo
+            .increment_downloads(model.id, Some(user_id), &UtmParams::default())
+            .await
+            .unwrap();
+        state.repo.flag_stale(model.id, user_id, Some("Outdated".into())).await.unwrap();
+        PaymentDispute::record(
+            &pool,
+            "dp_open",
+            "ch_diagnosed",
+            Some(intent.id),
+            Some(user_id),
+            1999,
+            "USD",
+            "fraudulent",
+            "needs_response",
+        )
+        .await
+        .unwrap();
+
+        let Json(diagnostics) = user_diagnostics(
+            State(state),
+            AdminUser(create_user(&pool).await),
+            Path(user_id),
+            Query(DiagnosticsQuery { limit: None }),
+        )
+        .await
+        .unwrap();
+
+        let plan = diagnostics.subscription.as_ref().and_then(|s| s.plan.as_ref());
+        assert_eq!(plan.unwrap().tier, SubscriptionTier::Pro);
+        assert_eq!(diagnostics.payments.len(), 1);
+        let methods: Vec<_> = diagnostics
+            .payment_methods
+            .iter()
+            .map(|method| method.stripe_payment_method_id.as_str())
+            .collect();
+        assert_eq!(methods, ["pm_...abcd"]);
+        assert_eq!(diagnostics.downloads.len(), 1);
+        assert_eq!(diagnostics.open_reports.len(), 1);
+        assert_eq!(diagnostics.open_disputes.len(), 1);
+        let body = serde_json::to_string(&diagnostics).unwrap();
+        assert!(!body.contains("pm_1234567890abcd"));
+        assert!(!body.contains("do_not_show"));
+    }
+}
@@ -1,108 +1,3319 @@
 use axum::{
     extract::{Path, Query, State},
-    http::StatusCode,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
+use base64::Engine;
+use image::{ImageBuffer, ImageFormat, Luma};
+use qrcode::{EcLevel, QrCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::io::Cursor;
 use uuid::Uuid;
 
 use crate::{
+    auth::{AdminUser, AuthUser, Caller},
     db::AIModelRepository,
-    models::{AIModel, CreateAIModel, UpdateAIModel, ModelList, ListQueryParams},
+    error::AppError,
+    events::ModelEvent,
+    services::{fetch::fetch_public, manifest_import::fetch_manifest},
+    models::{
+        check_license_compatibility, default_page_size, DependencySummary, ModelReview, public_model_url, FALLBACK_MAX_PAGE_SIZE, ActivityQueryParams, ModelActivity, stale_after_months, AIModel, AddModelDependency,
+        CatalogRow, CreateAIModel, PageParams, DownloadBucket, DownloadStatsQuery, ExportFormat, ExportQuery, FlagStaleRequest, MAX_BATCH_SIZE, CATALOG_CSV_HEADER, LicenseCheckMode, LicenseCompatibility, ListQueryParams,
+        ModelDependency, ModelList, ModelListItem, ModelStatus, ModelSummary, ModelView, PriceDistribution, SimilarPricingQuery, SaveModelView, WithdrawFromSale, TierReport, TierReportEntry, DownloadUrl, UsageExample, UsageExamplesQuery, Notification, StaleReportSummary,
+        SubscriptionTier, UpdateAIModel, UserSubscription, AddTags, ModelTags, validate_tag_format, validate_tag_limits, ValidationErrors, MAX_TAGS, ImportFromUrl, VersionDiff, ModelVersion, ClaimChallenge,
+        CompareModels, ComparedModel, ModelComparison, MAX_COMPARED_MODELS,
+        UtmParams, CampaignDownloads, CampaignStatsQuery, ModelAnalytics, VerifyChecksum, ChecksumVerification,
+        AccountDeletion, PendingStripeCleanup, Validate,
+    },
+    validated_query::ValidatedQuery,
+    AppState,
 };
 
-#[axum::debug_handler]
+#[axum::debug_handler(state = AppState)]
 pub async fn create_model(
     State(repo): State<AIModelRepository>,
+    AuthUser(user_id): AuthUser,
     Json(model): Json<CreateAIModel>,
-) -> Result<Json<AIModel>, StatusCode> {
-    match repo.create(model).await {
-        Ok(model) => Ok(Json(model)),
-        Err(e) => {
-            eprintln!("Failed to create model: {}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+) -> Result<Json<AIModel>, AppError> {
+    model.validate()?;
+
+    let model = repo.create(model, user_id).await?;
+    Ok(Json(model))
+}
+
+/// Create a draft from a JSON manifest served at a public URL. See
+/// [`ModelManifest`](crate::models::ModelManifest) for the schema.
+#[axum::debug_handler(state = AppState)]
+pub async fn import_model_from_url(
+    State(repo): State<AIModelRepository>,
+    AuthUser(user_id): AuthUser,
+    Json(request): Json<ImportFromUrl>,
+) -> Result<(StatusCode, Json<AIModel>), AppError> {
+    let manifest = fetch_manifest(&request.url)
+        .await
+        .map_err(|e| AppError::BadRequest(e.to_string()))?;
+    let model = manifest.into_create();
+    model.validate()?;
+
+    let model = repo.create(model, user_id).await?;
+    Ok((StatusCode::CREATED, Json(model)))
+}
+
+/// Create up to `MAX_BATCH_SIZE` models at once. Every item is validated
+/// first, and nothing is created unless all of them are.
+#[axum::debug_handler(state = AppState)]
+pub async fn create_models_batch(
+    State(repo): State<AIModelRepository>,
+    AuthUser(user_id): AuthUser,
+    Json(models): Json<Vec<CreateAIModel>>,
+) -> Result<Json<Vec<AIModel>>, AppError> {
+    if models.is_empty() {
+        return Err(AppError::BadRequest("Batch must contain at least one model".into()));
+    }
+    if models.len() > MAX_BATCH_SIZE {
+        return Err(AppError::BadRequest(format!(
+            "Batch may contain at most {} models",
+            MAX_BATCH_SIZE
+        )));
+    }
+
+    let invalid: Vec<String> = models
+        .iter()
+        .enumerate()
+        .filter_map(|(i, model)| model.validate().err().map(|e| format!("[{}] {}", i, e)))
+        .collect();
+    if !invalid.is_empty() {
+        return Err(AppError::BadRequest(invalid.join("; ")));
+    }
+
+    let models = repo.create_many(models, user_id).await?;
+    Ok(Json(models))
+}
+
+/// Relations that can be embedded in `GET /api/models/:id` via `?include=`.
+/// Benchmark runs aren't stored yet, so `benchmarks` is rejected like any
+/// other unknown relation.
+const INCLUDABLE: &[&str] = &["reviews", "versions", "dependencies"];
+
+/// Reviews embedded by `?include=reviews`; clients page further through
+/// `/reviews`.
+const INCLUDED_REVIEWS: i64 = 10;
+
+#[derive(Debug, Deserialize)]
+pub struct GetModelParams {
+    /// Comma-separated relations from `INCLUDABLE`.
+    include: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelWithIncludes {
+    #[serde(flatten)]
+    model: AIModel,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reviews: Option<Vec<ModelReview>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    versions: Option<Vec<ModelVersion>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dependencies: Option<Vec<DependencySummary>>,
+}
+
+fn parse_includes(include: Option<&str>) -> Result<Vec<&str>, AppError> {
+    let mut includes = Vec::new();
+    for relation in include.unwrap_or_default().split(',').map(str::trim) {
+        if relation.is_empty() || includes.contains(&relation) {
+            continue;
         }
+        if !INCLUDABLE.contains(&relation) {
+            return Err(AppError::BadRequest(format!(
+                "Unknown include {:?}; expected one of: {}",
+                relation,
+                INCLUDABLE.join(", ")
+            )));
+        }
+        includes.push(relation);
     }
+    Ok(includes)
 }
 
-#[axum::debug_handler]
+#[axum::debug_handler(state = AppState)]
 pub async fn get_model(
-    State(repo): State<AIModelRepository>,
+    State(state): State<AppState>,
+    caller: Option<Caller>,
     Path(id): Path<Uuid>,
-) -> Result<Json<AIModel>, StatusCode> {
-    match repo.get(id).await {
-        Ok(Some(model)) => Ok(Json(model)),
-        Ok(None) => Err(StatusCode::NOT_FOUND),
-        Err(e) => {
-            eprintln!("Failed to get model: {}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+    Query(params): Query<GetModelParams>,
+) -> Result<Json<ModelWithIncludes>, AppError> {
+    let includes = parse_includes(params.include.as_deref())?;
+
+    // Unpublished models look missing to everyone but the owner and admins.
+    let model = state
+        .repo
+        .get(id)
+        .await?
+        .filter(|model| {
+            model.is_visible_to(
+                caller.map(|caller| caller.user_id),
+                caller.is_some_and(|caller| caller.is_admin()),
+            )
+        })
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))?;
+
+    let reviews = async {
+        if !includes.contains(&"reviews") {
+            return Ok(None);
+        }
+        state
+            .reviews
+            .list_reviews(id, 1, INCLUDED_REVIEWS)
+            .await
+            .map(Some)
+    };
+    let versions = async {
+        if !includes.contains(&"versions") {
+            return Ok(None);
+        }
+        state.repo.list_versions(id).await.map(Some)
+    };
+    let dependencies = async {
+        if !includes.contains(&"dependencies") {
+            return Ok(None);
+        }
+        state.repo.list_dependencies(id).await.map(Some)
+    };
+    let (reviews, versions, dependencies) = tokio::try_join!(reviews, versions, dependencies)?;
+
+    Ok(Json(ModelWithIncludes {
+        model,
+        reviews,
+        versions,
+        dependencies,
+    }))
+}
+
+/// Apply the caller's saved view, if named, and check they may use the
+/// filters given.
+async fn resolve_list_params(
+    state: &AppState,
+    caller: Option<Caller>,
+    params: ListQueryParams,
+) -> Result<ListQueryParams, AppError> {
+    let params = match params.view.as_deref() {
+        Some(name) => {
+            let caller = caller.ok_or_else(|| {
+                AppError::Unauthorized("Sign in to use saved views".into())
+            })?;
+            let view = ModelView::find(&state.pool, caller.user_id, name)
+                .await?
+                .ok_or_else(|| AppError::NotFound(format!("No saved view named {:?}", name)))?;
+            params.with_defaults_from(view.query)
         }
+        None => params,
+    };
+
+    if params.status.is_some() && !caller.is_some_and(|caller| caller.is_admin()) {
+        return Err(AppError::Forbidden(
+            "Only admins can filter models by status".into(),
+        ));
     }
+    // A saved view may have filled in parameters the request didn't give.
+    params.validate()?;
+
+    Ok(params)
 }
 
-#[axum::debug_handler]
+#[axum::debug_handler(state = AppState)]
 pub async fn list_models(
-    State(repo): State<AIModelRepository>,
-    Query(params): Query<ListQueryParams>,
-) -> Result<Json<ModelList>, StatusCode> {
-    match repo.list(&params).await {
-        Ok((models, total)) => {
-            let page = params.page.unwrap_or(1);
-            let per_page = params.per_page.unwrap_or(10);
-            Ok(Json(ModelList {
-                models,
-                total,
-                page,
-                per_page,
-            }))
+    State(state): State<AppState>,
+    caller: Option<Caller>,
+    ValidatedQuery(params): ValidatedQuery<ListQueryParams>,
+) -> Result<Json<ModelList>, AppError> {
+    let user = caller.map(|caller| AuthUser(caller.user_id));
+    let params = resolve_list_params(&state, caller, params).await?;
+
+    // Higher tiers may page through the catalogue in bigger chunks.
+    let max_page_size = match &user {
+        Some(AuthUser(user_id)) => {
+            UserSubscription::max_page_size_for_user(&state.pool, *user_id).await?
         }
-        Err(e) => {
-            eprintln!("Failed to list models: {}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        None => None,
+    };
+    let params = params.clamp_per_page(max_page_size.unwrap_or(FALLBACK_MAX_PAGE_SIZE));
+
+    let viewer = user.map(|AuthUser(user_id)| user_id);
+    let (models, total) = match params.updated_after {
+        Some(since) => state.repo.list_updated_after(&params, viewer, since).await?,
+        None => state.repo.list(&params, viewer).await?,
+    };
+    let page = params.page.unwrap_or(1);
+    let per_page = params.per_page.unwrap_or_else(default_page_size);
+    let stale_after = stale_after_months();
+    let models = models
+        .into_iter()
+        .map(|model| ModelListItem {
+            is_stale: model.is_stale(stale_after),
+            deleted: model.deleted_at.is_some(),
+            model,
+        })
+        .collect();
+
+    Ok(Json(ModelList::new(models, total, page, per_page)))
+}
+
+/// Rows fetched per query while exporting, bounding memory use.
+const EXPORT_CHUNK_SIZE: i64 = 500;
+
+enum ExportCursor {
+    Start,
+    /// After the row with this `(created_at, id)`, or from the top.
+    Rows {
+        after: Option<(chrono::DateTime<chrono::Utc>, Uuid)>,
+        first: bool,
+    },
+    Done,
+}
+
+/// Stream the whole catalog, filtered like `list_models` but unpaginated,
+/// as CSV (the default) or as a JSON array.
+#[axum::debug_handler(state = AppState)]
+pub async fn export_models(
+    State(state): State<AppState>,
+    caller: Option<Caller>,
+    Query(params): Query<ListQueryParams>,
+    Query(export): Query<ExportQuery>,
+) -> Result<Response, AppError> {
+    let params = resolve_list_params(&state, caller, params).await?;
+    let viewer = caller.map(|caller| caller.user_id);
+    let repo = state.repo.clone();
+    let format = export.format;
+
+    let chunks = futures::stream::try_unfold(ExportCursor::Start, move |cursor| {
+        let repo = repo.clone();
+        let params = params.clone();
+        async move {
+            let (after, first) = match cursor {
+                ExportCursor::Start => {
+                    let opening = match format {
+                        ExportFormat::Csv => CATALOG_CSV_HEADER,
+                        ExportFormat::Json => "[",
+                    };
+                    let next = ExportCursor::Rows {
+                        after: None,
+                        first: true,
+                    };
+                    return Ok(Some((opening.to_string(), next)));
+                }
+                ExportCursor::Rows { after, first } => (after, first),
+                ExportCursor::Done => return Ok(None),
+            };
+
+            let rows = repo
+                .export_chunk(&params, viewer, after, EXPORT_CHUNK_SIZE)
+                .await
+                .map_err(|e| {
+                    tracing::error!("catalog export failed: {}", e);
+                    e
+                })?;
+            let Some(last) = rows.last() else {
+                return Ok(match format {
+                    ExportFormat::Csv => None,
+                    ExportFormat::Json => Some(("]".to_string(), ExportCursor::Done)),
+                });
+            };
+            let next = ExportCursor::Rows {
+                after: Some((last.created_at, last.id)),
+                first: false,
+            };
+
+            let chunk = match format {
+                ExportFormat::Csv => rows.iter().map(CatalogRow::to_csv_record).collect(),
+                ExportFormat::Json => {
+                    let mut chunk = String::new();
+                    for (i, row) in rows.iter().enumerate() {
+                        if !(first && i == 0) {
+                            chunk.push(',');
+                        }
+                        chunk.push_str(&serde_json::to_string(row)?);
+                    }
+                    chunk
+                }
+            };
+
+            Ok::<_, anyhow::Error>(Some((chunk, next)))
         }
-    }
+    });
+
+    let headers = match format {
+        ExportFormat::Csv => [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"models.csv\""),
+        ],
+        ExportFormat::Json => [
+            (header::CONTENT_TYPE, "application/json"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"models.json\""),
+        ],
+    };
+
+    Ok((headers, axum::body::Body::from_stream(chunks)).into_response())
 }
 
-#[axum::debug_handler]
+#[axum::debug_handler(state = AppState)]
 pub async fn update_model(
     State(repo): State<AIModelRepository>,
+    caller: Caller,
     Path(id): Path<Uuid>,
     Json(model): Json<UpdateAIModel>,
-) -> Result<Json<AIModel>, StatusCode> {
-    match repo.update(id, model).await {
-        Ok(Some(model)) => Ok(Json(model)),
-        Ok(None) => Err(StatusCode::NOT_FOUND),
-        Err(e) => {
-            eprintln!("Failed to update model: {}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
-        }
-    }
+) -> Result<Json<AIModel>, AppError> {
+    model.validate()?;
+
+    let existing = repo
+        .get(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))?;
+    caller.ensure_owner_or_admin(existing.owner_id)?;
+
+    let model = repo
+        .update(id, model, caller.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))?;
+    Ok(Json(model))
 }
 
-#[axum::debug_handler]
+#[axum::debug_handler(state = AppState)]
 pub async fn delete_model(
     State(repo): State<AIModelRepository>,
+    caller: Caller,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let existing = repo
+        .get(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))?;
+    caller.ensure_owner_or_admin(existing.owner_id)?;
+
+    if !repo.delete(id).await? {
+        return Err(AppError::NotFound("Model not found".into()));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Owner hands a draft (or a rejected model, after changes) to the admins.
+#[axum::debug_handler(state = AppState)]
+pub async fn submit_model(
+    State(repo): State<AIModelRepository>,
+    caller: Caller,
+    Path(id): Path<Uuid>,
+) -> Result<Json<AIModel>, AppError> {
+    let existing = repo
+        .get(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))?;
+    caller.ensure_owner_or_admin(existing.owner_id)?;
+
+    let model = repo
+        .transition_status(
+            id,
+            &[ModelStatus::Draft, ModelStatus::Rejected],
+            ModelStatus::PendingReview,
+            caller.user_id,
+        )
+        .await?
+        .ok_or_else(|| AppError::conflict("Only draft or rejected models can be submitted"))?;
+
+    Ok(Json(model))
+}
+
+#[axum::debug_handler(state = AppState)]
+pub async fn publish_model(
+    State(repo): State<AIModelRepository>,
+    AdminUser(admin_id): AdminUser,
+    Path(id): Path<Uuid>,
+) -> Result<Json<AIModel>, AppError> {
+    review_model(&repo, id, ModelStatus::Published, admin_id).await
+}
+
+#[axum::debug_handler(state = AppState)]
+pub async fn reject_model(
+    State(repo): State<AIModelRepository>,
+    AdminUser(admin_id): AdminUser,
+    Path(id): Path<Uuid>,
+) -> Result<Json<AIModel>, AppError> {
+    review_model(&repo, id, ModelStatus::Rejected, admin_id).await
+}
+
+async fn review_model(
+    repo: &AIModelRepository,
+    id: Uuid,
+    outcome: ModelStatus,
+    admin_id: Uuid,
+) -> Result<Json<AIModel>, AppError> {
+    if repo.get(id).await?.is_none() {
+        return Err(AppError::NotFound("Model not found".into()));
+    }
+
+    let model = repo
+        .transition_status(id, &[ModelStatus::PendingReview], outcome, admin_id)
+        .await?
+        .ok_or_else(|| AppError::conflict("Model is not awaiting review"))?;
+
+    Ok(Json(model))
+}
+
+#[axum::debug_handler(state = AppState)]
+pub async fn restore_model(
+    State(repo): State<AIModelRepository>,
+    _admin: AdminUser,
     Path(id): Path<Uuid>,
-) -> Result<StatusCode, StatusCode> {
-    match repo.delete(id).await {
-        Ok(true) => Ok(StatusCode::NO_CONTENT),
-        Ok(false) => Err(StatusCode::NOT_FOUND),
-        Err(e) => {
-            eprintln!("Failed to delete model: {}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+) -> Result<Json<AIModel>, AppError> {
+    let model = repo
+        .restore(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("No deleted model with this id".into()))?;
+
+    Ok(Json(model))
+}
+
+/// Reject callers whose subscription doesn't grant the model's required tier.
+async fn ensure_tier_access(
+    state: &AppState,
+    id: Uuid,
+    user: Option<&AuthUser>,
+) -> Result<(), AppError> {
+    let required_tier = state
+        .repo
+        .required_tier(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))?;
+
+    if !SubscriptionTier::Free.grants(required_tier) {
+        let user_tier = match user {
+            Some(AuthUser(user_id)) => state.tier_cache.tier_for_user(&state.pool, *user_id).await?,
+            None => SubscriptionTier::Free,
+        };
+
+        if !user_tier.grants(required_tier) {
+            return Err(AppError::Forbidden(format!(
+                "This model requires the {:?} tier",
+                required_tier
+            )));
         }
     }
+
+    Ok(())
 }
 
-#[axum::debug_handler]
+/// Private and unpublished models are only downloadable by their owner.
+fn ensure_downloadable(model: &AIModel, user: Option<&AuthUser>) -> Result<(), AppError> {
+    if model.is_publicly_visible() {
+        return Ok(());
+    }
+
+    let Some(AuthUser(user_id)) = user else {
+        return Err(AppError::Unauthorized("Sign in to download this model".into()));
+    };
+    if model.owner_id != Some(*user_id) {
+        return Err(AppError::NotFound("Model not found".into()));
+    }
+
+    Ok(())
+}
+
+/// Archived models are only available to users who downloaded them before
+/// they were withdrawn from sale, and to their owner.
+async fn ensure_not_archived(
+    state: &AppState,
+    id: Uuid,
+    model: &AIModel,
+    user: Option<&AuthUser>,
+) -> Result<(), AppError> {
+    if !model.archived {
+        return Ok(());
+    }
+
+    let entitled = match user {
+        Some(AuthUser(user_id)) => {
+            model.owner_id == Some(*user_id) || state.repo.has_downloaded(id, *user_id).await?
+        }
+        None => false,
+    };
+    if !entitled {
+        return Err(AppError::Forbidden(
+            "This model has been withdrawn from sale".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[axum::debug_handler(state = AppState)]
 pub async fn increment_downloads(
+    State(state): State<AppState>,
+    user: Option<AuthUser>,
+    Path(id): Path<Uuid>,
+    Query(utm): Query<UtmParams>,
+) -> Result<StatusCode, AppError> {
+    utm.validate()?;
+    let model = state
+        .repo
+        .get(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))?;
+    ensure_downloadable(&model, user.as_ref())?;
+    ensure_not_archived(&state, id, &model, user.as_ref()).await?;
+    ensure_tier_access(&state, id, user.as_ref()).await?;
+
+    let download_count = state
+        .repo
+        .increment_downloads(id, user.map(|AuthUser(user_id)| user_id), &utm.normalized())
+        .await?
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))?;
+    crate::metrics::MODEL_DOWNLOAD_TOTAL.inc();
+    state.events.publish(ModelEvent::Downloaded {
+        model_id: id,
+        download_count,
+    });
+
+    Ok(StatusCode::OK)
+}
+
+#[axum::debug_handler(state = AppState)]
+pub async fn get_download_url(
+    State(state): State<AppState>,
+    user: Option<AuthUser>,
+    Path(id): Path<Uuid>,
+    Query(utm): Query<UtmParams>,
+) -> Result<Json<DownloadUrl>, AppError> {
+    utm.validate()?;
+    let download = resolve_download_url(&state, id, user.as_ref()).await?;
+
+    // Only count downloads we actually handed out a URL for.
+    let counted = state
+        .repo
+        .increment_downloads(id, user.map(|AuthUser(user_id)| user_id), &utm.normalized())
+        .await?;
+    if let Some(download_count) = counted {
+        crate::metrics::MODEL_DOWNLOAD_TOTAL.inc();
+        state.events.publish(ModelEvent::Downloaded {
+            model_id: id,
+            download_count,
+        });
+    }
+
+    Ok(Json(download))
+}
+
+/// Same URL as `download-url`, without counting a download. For clients
+/// that show or share the link before anyone fetches the artifact; the
+/// download (and its UTM attribution) is recorded when they call
+/// `download-url` or `POST downloads`.
+#[axum::debug_handler(state = AppState)]
+pub async fn get_download_link(
+    State(state): State<AppState>,
+    user: Option<AuthUser>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<DownloadUrl>, AppError> {
+    Ok(Json(resolve_download_url(&state, id, user.as_ref()).await?))
+}
+
+async fn resolve_download_url(
+    state: &AppState,
+    id: Uuid,
+    user: Option<&AuthUser>,
+) -> Result<DownloadUrl, AppError> {
+    let model = state
+        .repo
+        .get(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))?;
+    ensure_downloadable(&model, user)?;
+    ensure_not_archived(state, id, &model, user).await?;
+    ensure_tier_access(state, id, user).await?;
+
+    match (&model.storage_key, &state.storage, &model.repository_url) {
+        (Some(key), Some(storage), _) => {
+            let now = chrono::Utc::now();
+            Ok(DownloadUrl {
+                url: storage.presign_get(key, now),
+                expires_at: Some(
+                    now + chrono::Duration::seconds(storage.url_expiry.as_secs() as i64),
+                ),
+                file_size_bytes: model.file_size_bytes,
+            })
+        }
+        (_, _, Some(repository_url)) => Ok(DownloadUrl {
+            url: repository_url.clone(),
+            expires_at: None,
+            file_size_bytes: model.file_size_bytes,
+        }),
+        _ => Err(AppError::NotFound("Model has no downloadable artifact".into())),
+    }
+}
+
+#[axum::debug_handler(state = AppState)]
+pub async fn get_usage_examples(
+    State(repo): State<AIModelRepository>,
+    caller: Option<Caller>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<UsageExamplesQuery>,
+) -> Result<Json<Vec<UsageExample>>, AppError> {
+    let model = ensure_visible(&repo, id, caller).await?;
+
+    Ok(Json(model.usage_examples(query.lang)))
+}
+
+/// Side-by-side view of up to `MAX_COMPARED_MODELS` models. Ids the caller
+/// can't see are listed in `not_found` rather than failing the request.
+#[axum::debug_handler(state = AppState)]
+pub async fn compare_models(
+    State(repo): State<AIModelRepository>,
+    caller: Option<Caller>,
+    Json(request): Json<CompareModels>,
+) -> Result<Json<ModelComparison>, AppError> {
+    let mut ids = request.ids;
+    let mut seen = std::collections::HashSet::new();
+    ids.retain(|id| seen.insert(*id));
+    if ids.len() < 2 || ids.len() > MAX_COMPARED_MODELS {
+        return Err(AppError::BadRequest(format!(
+            "Compare between 2 and {} distinct models",
+            MAX_COMPARED_MODELS
+        )));
+    }
+
+    let mut models = Vec::with_capacity(ids.len());
+    let mut not_found = Vec::new();
+    for id in ids {
+        match ensure_visible(&repo, id, caller).await {
+            Ok(model) => models.push(ComparedModel::new(model)),
+            Err(AppError::NotFound(_)) => not_found.push(id),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(Json(ModelComparison::new(models, not_found)))
+}
+
+#[axum::debug_handler(state = AppState)]
+pub async fn get_model_summary(
+    State(repo): State<AIModelRepository>,
+    caller: Option<Caller>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ModelSummary>, AppError> {
+    ensure_visible(&repo, id, caller).await?;
+    let summary = repo
+        .summary(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))?;
+
+    Ok(Json(summary))
+}
+
+/// Owner stops selling the model, making it free or archiving it.
+#[axum::debug_handler(state = AppState)]
+pub async fn withdraw_from_sale(
+    State(repo): State<AIModelRepository>,
+    caller: Caller,
+    Path(id): Path<Uuid>,
+    request: Option<Json<WithdrawFromSale>>,
+) -> Result<Json<AIModel>, AppError> {
+    let existing = repo
+        .get(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))?;
+    if existing.owner_id != Some(caller.user_id) {
+        return Err(AppError::Forbidden(
+            "Only the owner can withdraw a model from sale".into(),
+        ));
+    }
+    if existing.withdrawn_from_sale_at.is_some() {
+        return Err(AppError::conflict("Model has already been withdrawn from sale"));
+    }
+
+    let Json(request) = request.unwrap_or_default();
+    let model = repo
+        .withdraw_from_sale(id, request.archive, caller.user_id)
+        .await?
+        .ok_or_else(|| AppError::conflict("Model changed while withdrawing; try again"))?;
+
+    Ok(Json(model))
+}
+
+/// Adds tags to a model, skipping ones it already has. The limits apply to
+/// the resulting tag set, not just the tags in the request.
+#[axum::debug_handler(state = AppState)]
+pub async fn add_tags(
+    State(repo): State<AIModelRepository>,
+    caller: Caller,
+    Path(id): Path<Uuid>,
+    Json(request): Json<AddTags>,
+) -> Result<Json<ModelTags>, AppError> {
+    let mut errors = ValidationErrors::default();
+    validate_tag_format(&request.tags, &mut errors);
+    errors.into_result()?;
+
+    let existing = repo
+        .get(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))?;
+    caller.ensure_owner_or_admin(existing.owner_id)?;
+
+    let mut combined = existing.tags.clone();
+    for tag in &request.tags {
+        if !combined.contains(tag) {
+            combined.push(tag.clone());
+        }
+    }
+    if combined.len() == existing.tags.len() {
+        return Ok(Json(ModelTags {
+            tags: existing.tags,
+        }));
+    }
+    let mut errors = ValidationErrors::default();
+    validate_tag_limits(&combined, &mut errors);
+    errors.into_result()?;
+
+    // The repository re-checks the count against the tags it actually
+    // updates, so concurrent adds can't push the model past the limit.
+    let tags = repo
+        .add_tags(id, &request.tags, MAX_TAGS, caller.user_id)
+        .await?
+        .ok_or_else(|| AppError::conflict("Model changed while adding tags; try again"))?;
+    Ok(Json(ModelTags { tags }))
+}
+
+/// Removes one tag from a model. Removing a tag it doesn't have is a no-op.
+#[axum::debug_handler(state = AppState)]
+pub async fn remove_tag(
+    State(repo): State<AIModelRepository>,
+    caller: Caller,
+    Path((id, tag)): Path<(Uuid, String)>,
+) -> Result<Json<ModelTags>, AppError> {
+    let existing = repo
+        .get(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))?;
+    caller.ensure_owner_or_admin(existing.owner_id)?;
+
+    let tags = repo
+        .remove_tag(id, &tag, caller.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))?;
+    Ok(Json(ModelTags { tags }))
+}
+
+/// Move the model's artifact to a fresh storage key so every download URL
+/// signed for the old one stops working. The object is copied, the model
+/// repointed, and only then the old object deleted, so current downloads
+/// never see a missing artifact.
+#[axum::debug_handler(state = AppState)]
+pub async fn rotate_model_key(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(id): Path<Uuid>,
+) -> Result<Json<AIModel>, AppError> {
+    let model = state
+        .repo
+        .get(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))?;
+    caller.ensure_owner_or_admin(model.owner_id)?;
+
+    let (Some(old_key), Some(storage)) = (&model.storage_key, &state.storage) else {
+        return Err(AppError::BadRequest(
+            "This model's artifact isn't hosted by us, so it has no download key".into(),
+        ));
+    };
+    let file_name = old_key.rsplit('/').next().unwrap_or(old_key);
+    let new_key = format!("models/{}/{}/{}", id, Uuid::new_v4(), file_name);
+
+    storage.copy_object(old_key, &new_key).await?;
+    let Some(rotated) = state
+        .repo
+        .rotate_storage_key(id, old_key, &new_key, caller.user_id)
+        .await?
+    else {
+        if let Err(e) = storage.delete_object(&new_key).await {
+            tracing::warn!("failed to remove unused artifact copy {}: {}", new_key, e);
+        }
+        return Err(AppError::conflict("Model changed while rotating its key; try again"));
+    };
+
+    // Until this succeeds, URLs signed for the old key still work.
+    storage.delete_object(old_key).await.map_err(|e| {
+        tracing::error!("rotated model {} but old artifact {} remains: {}", id, old_key, e);
+        e
+    })?;
+
+    Ok(Json(rotated))
+}
+
+/// Start a new draft from an existing model. Private and unpublished
+/// models can only be forked by their owner.
+#[axum::debug_handler(state = AppState)]
+pub async fn fork_model(
+    State(repo): State<AIModelRepository>,
+    caller: Caller,
+    Path(id): Path<Uuid>,
+) -> Result<(StatusCode, Json<AIModel>), AppError> {
+    let source = ensure_visible(&repo, id, Some(caller)).await?;
+    if !source.is_publicly_visible() && source.owner_id != Some(caller.user_id) {
+        return Err(AppError::Forbidden(
+            "Only the owner can fork a private model".into(),
+        ));
+    }
+
+    let model = repo
+        .fork(id, caller.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))?;
+    Ok((StatusCode::CREATED, Json(model)))
+}
+
+/// Compare a client's SHA-256 of a downloaded artifact with ours.
+#[axum::debug_handler(state = AppState)]
+pub async fn verify_checksum(
+    State(repo): State<AIModelRepository>,
+    caller: Option<Caller>,
+    Path(id): Path<Uuid>,
+    Json(request): Json<VerifyChecksum>,
+) -> Result<Json<ChecksumVerification>, AppError> {
+    let sha256 = request.sha256.trim().to_ascii_lowercase();
+    if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(AppError::BadRequest(
+            "sha256 must be 64 hexadecimal characters".into(),
+        ));
+    }
+
+    let model = ensure_visible(&repo, id, caller).await?;
+    let expected = model
+        .artifact_sha256
+        .ok_or_else(|| AppError::NotFound("No checksum is recorded for this model".into()))?
+        .to_ascii_lowercase();
+
+    Ok(Json(ChecksumVerification {
+        matches: sha256 == expected,
+        expected,
+    }))
+}
+
+/// Repository pages are fetched whole to look for the claim token.
+const MAX_CLAIM_PAGE_BYTES: usize = 1024 * 1024;
+
+/// The model if it exists and nobody owns it yet.
+async fn claimable(repo: &AIModelRepository, id: Uuid) -> Result<(AIModel, String), AppError> {
+    let model = repo
+        .get(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))?;
+    if model.owner_id.is_some() {
+        return Err(AppError::conflict("This model already has an owner"));
+    }
+    let repository_url = model.repository_url.clone().ok_or_else(|| {
+        AppError::BadRequest("This model has no repository URL to verify against".into())
+    })?;
+    Ok((model, repository_url))
+}
+
+/// Start claiming an unowned model: publish the returned token at its
+/// `repository_url`, then call `POST /api/models/:id/claim`.
+#[axum::debug_handler(state = AppState)]
+pub async fn create_claim_challenge(
+    State(repo): State<AIModelRepository>,
+    AuthUser(user_id): AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ClaimChallenge>, AppError> {
+    let (_, repository_url) = claimable(&repo, id).await?;
+
+    let token = ClaimChallenge::generate_token();
+    let expires_at = chrono::Utc::now() + chrono::Duration::hours(ClaimChallenge::TTL_HOURS);
+    repo.create_claim_challenge(id, user_id, &token, expires_at)
+        .await?;
+
+    Ok(Json(ClaimChallenge {
+        token,
+        repository_url,
+        expires_at,
+    }))
+}
+
+/// Take ownership of an unowned model once the caller's challenge token
+/// shows up at its `repository_url`.
+#[axum::debug_handler(state = AppState)]
+pub async fn claim_model(
+    State(repo): State<AIModelRepository>,
+    AuthUser(user_id): AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<Json<AIModel>, AppError> {
+    let (_, repository_url) = claimable(&repo, id).await?;
+    let token = repo.claim_token(id, user_id).await?.ok_or_else(|| {
+        AppError::BadRequest("Request a claim challenge first, or request a new one".into())
+    })?;
+
+    let page = fetch_public(&repository_url, "text/html, text/plain, */*", MAX_CLAIM_PAGE_BYTES)
+        .await
+        .map_err(|e| AppError::BadRequest(e.to_string()))?;
+    if !String::from_utf8_lossy(&page).contains(&token) {
+        return Err(AppError::Forbidden(format!(
+            "Claim token not found at {}",
+            repository_url
+        )));
+    }
+
+    let model = repo
+        .claim(id, user_id)
+        .await?
+        .ok_or_else(|| AppError::conflict("This model already has an owner"))?;
+    Ok(Json(model))
+}
+
+/// Fails with `404` unless the model exists and `caller` may see it.
+async fn ensure_visible(
+    repo: &AIModelRepository,
+    id: Uuid,
+    caller: Option<Caller>,
+) -> Result<AIModel, AppError> {
+    repo.get(id)
+        .await?
+        .filter(|model| {
+            model.is_visible_to(
+                caller.map(|caller| caller.user_id),
+                caller.is_some_and(|caller| caller.is_admin()),
+            )
+        })
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))
+}
+
+#[axum::debug_handler(state = AppState)]
+pub async fn list_versions(
+    State(repo): State<AIModelRepository>,
+    caller: Option<Caller>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<ModelVersion>>, AppError> {
+    ensure_visible(&repo, id, caller).await?;
+    Ok(Json(repo.list_versions(id).await?))
+}
+
+#[axum::debug_handler(state = AppState)]
+pub async fn get_version(
+    State(repo): State<AIModelRepository>,
+    caller: Option<Caller>,
+    Path((id, version)): Path<(Uuid, String)>,
+) -> Result<Json<ModelVersion>, AppError> {
+    ensure_visible(&repo, id, caller).await?;
+    let version = repo
+        .get_version(id, &version)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Version {} not found", version)))?;
+    Ok(Json(version))
+}
+
+/// Field-level differences between two versions of a model.
+#[axum::debug_handler(state = AppState)]
+pub async fn get_version_diff(
+    State(repo): State<AIModelRepository>,
+    caller: Option<Caller>,
+    Path((id, from, to)): Path<(Uuid, String, String)>,
+) -> Result<Json<VersionDiff>, AppError> {
+    ensure_visible(&repo, id, caller).await?;
+
+    let (from_version, to_version) =
+        tokio::try_join!(repo.get_version(id, &from), repo.get_version(id, &to))?;
+    let from_version =
+        from_version.ok_or_else(|| AppError::NotFound(format!("Version {} not found", from)))?;
+    let to_version =
+        to_version.ok_or_else(|| AppError::NotFound(format!("Version {} not found", to)))?;
+
+    Ok(Json(VersionDiff::between(&from_version, &to_version)))
+}
+
+/// Longest range `GET /api/models/:id/downloads/stats` covers in one call.
+const MAX_STATS_RANGE_DAYS: i64 = 366;
+
+#[axum::debug_handler(state = AppState)]
+pub async fn get_download_stats(
+    State(repo): State<AIModelRepository>,
+    caller: Option<Caller>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<DownloadStatsQuery>,
+) -> Result<Json<Vec<DownloadBucket>>, AppError> {
+    let to = query.to.unwrap_or_else(chrono::Utc::now);
+    let from = query.from.unwrap_or(to - chrono::Duration::days(30));
+    if from >= to {
+        return Err(AppError::BadRequest("from must be before to".into()));
+    }
+    if to - from > chrono::Duration::days(MAX_STATS_RANGE_DAYS) {
+        return Err(AppError::BadRequest(format!(
+            "Range may cover at most {} days",
+            MAX_STATS_RANGE_DAYS
+        )));
+    }
+
+    ensure_visible(&repo, id, caller).await?;
+
+    let stats = repo.download_stats(id, from, to, query.bucket).await?;
+    Ok(Json(stats))
+}
+
+/// Owner analytics: downloads by UTM source and campaign.
+#[axum::debug_handler(state = AppState)]
+pub async fn get_campaign_downloads(
+    State(repo): State<AIModelRepository>,
+    caller: Caller,
+    Path(id): Path<Uuid>,
+    Query(query): Query<CampaignStatsQuery>,
+) -> Result<Json<Vec<CampaignDownloads>>, AppError> {
+    let to = query.to.unwrap_or_else(chrono::Utc::now);
+    let from = query.from.unwrap_or(to - chrono::Duration::days(30));
+    if from >= to {
+        return Err(AppError::BadRequest("from must be before to".into()));
+    }
+    if to - from > chrono::Duration::days(MAX_STATS_RANGE_DAYS) {
+        return Err(AppError::BadRequest(format!(
+            "Range may cover at most {} days",
+            MAX_STATS_RANGE_DAYS
+        )));
+    }
+
+    let model = repo
+        .get(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))?;
+    if model.owner_id != Some(caller.user_id) && !caller.is_admin() {
+        return Err(AppError::Forbidden(
+            "Only the owner can see campaign analytics".into(),
+        ));
+    }
+
+    Ok(Json(repo.campaign_downloads(id, from, to).await?))
+}
+
+/// Owner analytics: who downloads the model and how recently.
+#[axum::debug_handler(state = AppState)]
+pub async fn get_model_analytics(
+    State(repo): State<AIModelRepository>,
+    caller: Caller,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ModelAnalytics>, AppError> {
+    let model = repo
+        .get(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))?;
+    if model.owner_id != Some(caller.user_id) && !caller.is_admin() {
+        return Err(AppError::Forbidden(
+            "Only the owner can see model analytics".into(),
+        ));
+    }
+
+    Ok(Json(repo.analytics(id).await?))
+}
+
+#[axum::debug_handler(state = AppState)]
+pub async fn get_similar_pricing(
+    State(repo): State<AIModelRepository>,
+    caller: Option<Caller>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<SimilarPricingQuery>,
+) -> Result<Json<PriceDistribution>, AppError> {
+    ensure_visible(&repo, id, caller).await?;
+    let distribution = repo
+        .price_distribution(id, query.exclude_free)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))?;
+
+    Ok(Json(distribution))
+}
+
+#[axum::debug_handler(state = AppState)]
+pub async fn flag_stale(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(id): Path<Uuid>,
+    request: Option<Json<FlagStaleRequest>>,
+) -> Result<Json<StaleReportSummary>, AppError> {
+    let user_id = caller.user_id;
+    let model = ensure_visible(&state.repo, id, Some(caller)).await?;
+
+    let Json(request) = request.unwrap_or_default();
+    let newly_flagged = state.repo.flag_stale(id, user_id, request.reason).await?;
+    let reports = state.repo.stale_report_count(id).await?;
+
+    if let (true, Some(owner_id)) = (newly_flagged, model.owner_id) {
+        let message = format!("Your model \"{}\" was reported as out of date", model.name);
+        if let Err(e) = Notification::create(
+            &state.pool,
+            owner_id,
+            "model_flagged_stale",
+            &message,
+            json!({ "model_id": id, "reports": reports }),
+        )
+        .await
+        {
+            tracing::warn!("failed to notify owner of stale model {}: {}", id, e);
+        }
+    }
+
+    Ok(Json(StaleReportSummary {
+        model_id: id,
+        is_stale: model.is_stale(stale_after_months()),
+        reports,
+    }))
+}
+
+/// Owner declares that the model depends on another model they can see.
+#[axum::debug_handler(state = AppState)]
+pub async fn add_dependency(
     State(repo): State<AIModelRepository>,
+    caller: Caller,
     Path(id): Path<Uuid>,
-) -> Result<StatusCode, StatusCode> {
-    match repo.increment_downloads(id).await {
-        Ok(_) => Ok(StatusCode::OK),
-        Err(e) => {
-            eprintln!("Failed to increment downloads: {}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+    Json(request): Json<AddModelDependency>,
+) -> Result<Json<ModelDependency>, AppError> {
+    let model = ensure_visible(&repo, id, Some(caller)).await?;
+    caller.ensure_owner_or_admin(model.owner_id)?;
+    let dependency = ensure_visible(&repo, request.dependency_id, Some(caller))
+        .await
+        .map_err(|e| match e {
+            AppError::NotFound(_) => AppError::NotFound("Dependency model not found".into()),
+            e => e,
+        })?;
+
+    let compatibility = check_license_compatibility(
+        model.license.as_deref(),
+        dependency.license.as_deref(),
+    );
+
+    if let LicenseCompatibility::Incompatible(reason) = &compatibility {
+        if LicenseCheckMode::from_env() == LicenseCheckMode::Error {
+            return Err(AppError::BadRequest(format!("Incompatible license: {}", reason)));
         }
+        tracing::warn!("model {} depends on {}: {}", id, request.dependency_id, reason);
     }
-} 
\ No newline at end of file
+
+    repo.add_dependency(id, request.dependency_id).await?;
+
+    Ok(Json(ModelDependency {
+        model_id: id,
+        dependency_id: request.dependency_id,
+        compatibility,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QrCodeParams {
+    /// Edge length in pixels.
+    pub size: Option<u32>,
+    /// Error-correction level: `L`, `M`, `Q` or `H`.
+    pub ec: Option<String>,
+}
+
+#[axum::debug_handler]
+pub async fn get_model_qrcode(
+    State(repo): State<AIModelRepository>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<QrCodeParams>,
+) -> Result<impl IntoResponse, AppError> {
+    repo.get(id)
+        .await?
+        .filter(|model| model.is_publicly_visible())
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))?;
+
+    let size = params.size.unwrap_or(256).clamp(64, 1024);
+    let ec_level = match params.ec.as_deref().unwrap_or("M") {
+        "L" | "l" => EcLevel::L,
+        "M" | "m" => EcLevel::M,
+        "Q" | "q" => EcLevel::Q,
+        "H" | "h" => EcLevel::H,
+        other => {
+            return Err(AppError::BadRequest(format!(
+                "Unknown error-correction level: {}",
+                other
+            )))
+        }
+    };
+
+    let code = QrCode::with_error_correction_level(public_model_url(id), ec_level)
+        .map_err(|e| anyhow::anyhow!("failed to encode QR code for model {}: {}", id, e))?;
+    let code = code.render::<Luma<u8>>().max_dimensions(size, size).build();
+    // Modules are whole pixels, so the code usually comes out a little
+    // smaller than asked; centre it in a white square of the exact size.
+    // A code too dense for `size` at one pixel per module is left as is.
+    let edge = size.max(code.width());
+    let mut image = ImageBuffer::from_pixel(edge, edge, Luma([255u8]));
+    let offset = i64::from((edge - code.width()) / 2);
+    image::imageops::overlay(&mut image, &code, offset, offset);
+
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(anyhow::Error::from)?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/png"),
+            (header::CACHE_CONTROL, "public, max-age=86400"),
+        ],
+        png,
+    ))
+}
+
+#[axum::debug_handler(state = AppState)]
+pub async fn my_activity(
+    State(repo): State<AIModelRepository>,
+    AuthUser(user_id): AuthUser,
+    Query(params): Query<ActivityQueryParams>,
+) -> Result<Json<Vec<ModelActivity>>, AppError> {
+    let limit = params.limit.unwrap_or(50).clamp(1, 200);
+    let activity = repo.activity_for_user(user_id, limit).await?;
+    Ok(Json(activity))
+}
+
+/// Close the caller's account. Everything local happens first, in one
+/// transaction, so the caller's tokens stop working straight away. Stripe
+/// subscriptions are canceled and cards detached afterwards; anything
+/// Stripe doesn't confirm stays pending and is retried by the cleanup job
+/// or by closing the account again, which otherwise changes nothing.
+#[axum::debug_handler(state = AppState)]
+pub async fn delete_my_account(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+) -> Result<Json<AccountDeletion>, AppError> {
+    let mut deletion = AccountDeletion::erase(&state.pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Account not found".into()))?;
+    state.tier_cache.invalidate(user_id);
+
+    for pending in PendingStripeCleanup::pending(&state.pool, Some(user_id)).await? {
+        let (canceled, detached) = state.stripe_service.finish_account_cleanup(&pending).await?;
+        deletion.stripe_subscriptions_canceled += canceled;
+        deletion.stripe_payment_methods_detached += detached;
+    }
+
+    tracing::info!("closed account {}: {:?}", user_id, deletion);
+    Ok(Json(deletion))
+}
+
+#[axum::debug_handler(state = AppState)]
+pub async fn add_favorite(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    state
+        .repo
+        .get(id)
+        .await?
+        .filter(|model| model.is_visible_to(Some(caller.user_id), caller.is_admin()))
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))?;
+
+    state.repo.add_favorite(caller.user_id, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[axum::debug_handler(state = AppState)]
+pub async fn remove_favorite(
+    State(repo): State<AIModelRepository>,
+    AuthUser(user_id): AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    if !repo.remove_favorite(user_id, id).await? {
+        return Err(AppError::NotFound("Model is not in your favorites".into()));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[axum::debug_handler(state = AppState)]
+pub async fn my_favorites(
+    State(repo): State<AIModelRepository>,
+    AuthUser(user_id): AuthUser,
+    ValidatedQuery(params): ValidatedQuery<PageParams>,
+) -> Result<Json<ModelList>, AppError> {
+    let page = params.page.unwrap_or(1);
+    let per_page = params
+        .per_page
+        .unwrap_or_else(default_page_size)
+        .min(FALLBACK_MAX_PAGE_SIZE);
+
+    let (models, total) = repo.list_favorites(user_id, page, per_page).await?;
+    let stale_after = stale_after_months();
+    let models = models
+        .into_iter()
+        .map(|model| ModelListItem {
+            is_stale: model.is_stale(stale_after),
+            deleted: model.deleted_at.is_some(),
+            model,
+        })
+        .collect();
+
+    Ok(Json(ModelList::new(models, total, page, per_page)))
+}
+
+#[axum::debug_handler(state = AppState)]
+pub async fn list_views(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+) -> Result<Json<Vec<ModelView>>, AppError> {
+    let views = ModelView::list_for_user(&state.pool, user_id).await?;
+    Ok(Json(views))
+}
+
+#[axum::debug_handler(state = AppState)]
+pub async fn save_view(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Json(view): Json<SaveModelView>,
+) -> Result<Json<ModelView>, AppError> {
+    let name_len = view.name.trim().chars().count();
+    if name_len == 0 || name_len > 100 {
+        return Err(AppError::BadRequest("name must be 1-100 characters".into()));
+    }
+
+    let view = ModelView::save(&state.pool, user_id, view).await?;
+    Ok(Json(view))
+}
+
+#[axum::debug_handler(state = AppState)]
+pub async fn my_tier_report(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+) -> Result<Json<TierReport>, AppError> {
+    let (models, users) = tokio::try_join!(
+        state.repo.owned_counts_by_tier(user_id),
+        UserSubscription::user_counts_by_tier(&state.pool),
+    )?;
+
+    let tiers = SubscriptionTier::ALL
+        .into_iter()
+        .map(|required_tier| TierReportEntry {
+            required_tier,
+            models: models
+                .iter()
+                .filter(|(tier, _)| *tier == required_tier)
+                .map(|(_, count)| count)
+                .sum(),
+            reach: users
+                .iter()
+                .filter(|(tier, _)| tier.grants(required_tier))
+                .map(|(_, count)| count)
+                .sum(),
+        })
+        .collect();
+
+    Ok(Json(TierReport {
+        total_models: models.iter().map(|(_, count)| count).sum(),
+        tiers,
+    }))
+}
+
+/// Headers describing a model's artifact, so download managers can plan
+/// ranged/resumable downloads before fetching it.
+fn artifact_headers(model: &AIModel) -> Result<HeaderMap, AppError> {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+    if let Some(size) = model.file_size_bytes {
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(size));
+    }
+
+    if let Some(sha256) = model.artifact_sha256.as_deref() {
+        let digest = hex::decode(sha256.trim())
+            .map_err(|e| anyhow::anyhow!("invalid artifact_sha256 on model {}: {}", model.id, e))?;
+        let encoded = base64::engine::general_purpose::STANDARD.encode(digest);
+        let value = HeaderValue::from_str(&format!("sha-256={}", encoded))
+            .map_err(anyhow::Error::from)?;
+        headers.insert("digest", value);
+        headers.insert(
+            "repr-digest",
+            HeaderValue::from_str(&format!("sha-256=:{}:", encoded)).map_err(anyhow::Error::from)?,
+        );
+    }
+
+    Ok(headers)
+}
+
+async fn public_model(repo: &AIModelRepository, id: Uuid) -> Result<AIModel, AppError> {
+    repo.get(id)
+        .await?
+        .filter(|model| model.is_publicly_visible())
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))
+}
+
+/// `HEAD /api/models/:id/artifact`: artifact size, range support and digest
+/// without the body.
+#[axum::debug_handler]
+pub async fn head_model_artifact(
+    State(repo): State<AIModelRepository>,
+    Path(id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let model = public_model(&repo, id).await?;
+    if model.file_size_bytes.is_none() {
+        return Err(AppError::NotFound("Model has no artifact".into()));
+    }
+
+    Ok((artifact_headers(&model)?, ()).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{CreateReview, ModelType, PerformanceMetrics, StatsBucket, MAX_TAG_LENGTH};
+    use axum::extract::FromRequestParts;
+    use crate::test_support::{
+        admin, app_state, app_state_with_stripe, create_user, draft, new_model, plan, published,
+        subscribe, user, BucketStub, StripeStub,
+    };
+    use chrono::TimeZone;
+    use sqlx::PgPool;
+
+    fn licensed(name: &str, license: &str) -> CreateAIModel {
+        CreateAIModel {
+            license: Some(license.to_string()),
+            ..new_model(name)
+        }
+    }
+
+    #[sqlx::test]
+    async fn a_gpl_dependency_under_an_mit_model_is_incompatible(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = published(&pool, owner, licensed("App", "MIT")).await;
+        let dependency = published(&pool, owner, licensed("Lib", "GPL-3.0")).await;
+        let repo = AIModelRepository::new(pool.clone());
+
+        let Json(added) = add_dependency(
+            State(repo.clone()),
+            user(owner),
+            Path(model.id),
+            Json(AddModelDependency {
+                dependency_id: dependency.id,
+            }),
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(added.compatibility, LicenseCompatibility::Incompatible(_)));
+        assert_eq!(repo.list_dependencies(model.id).await.unwrap().len(), 1);
+    }
+
+    #[sqlx::test]
+    async fn only_the_owner_adds_dependencies_on_models_they_can_see(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let stranger = create_user(&pool).await;
+        let model = published(&pool, owner, new_model("App")).await;
+        let hidden = draft(&pool, stranger, new_model("Secret")).await;
+        let public = published(&pool, stranger, new_model("Lib")).await;
+        let repo = AIModelRepository::new(pool.clone());
+        let add = |caller: Caller, dependency_id: Uuid| {
+            add_dependency(
+                State(repo.clone()),
+                caller,
+                Path(model.id),
+                Json(AddModelDependency { dependency_id }),
+            )
+        };
+
+        assert!(matches!(add(user(stranger), public.id).await, Err(AppError::Forbidden(_))));
+        assert!(matches!(add(user(owner), hidden.id).await, Err(AppError::NotFound(_))));
+        assert!(add(user(owner), public.id).await.is_ok());
+        assert!(add(admin(stranger), hidden.id).await.is_ok());
+    }
+
+    #[sqlx::test]
+    async fn head_describes_the_artifact_without_a_body(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = published(&pool, owner, new_model("App")).await;
+        sqlx::query!(
+            r#"
+            UPDATE ai_models
+            SET file_size_bytes = 1024,
+                artifact_sha256 = 'e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855'
+            WHERE id = $1
+            "#,
+            model.id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let response = head_model_artifact(State(AIModelRepository::new(pool)), Path(model.id))
+            .await
+            .unwrap();
+
+        let headers = response.headers();
+        assert_eq!(headers[header::CONTENT_LENGTH], "1024");
+        assert_eq!(headers[header::ACCEPT_RANGES], "bytes");
+        assert_eq!(
+            headers["digest"],
+            "sha-256=47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="
+        );
+    }
+
+    #[sqlx::test]
+    async fn repeat_downloads_by_one_user_count_once(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let fan = create_user(&pool).await;
+        let model = published(&pool, owner, new_model("App")).await;
+        let repo = AIModelRepository::new(pool.clone());
+        for user_id in [Some(fan), Some(fan), None] {
+            repo.increment_downloads(model.id, user_id, &UtmParams::default())
+                .await
+                .unwrap();
+        }
+
+        let Json(summary) = get_model_summary(State(repo), None, Path(model.id)).await.unwrap();
+        assert_eq!(summary.download_count, 3);
+        assert_eq!(summary.unique_downloaders, 1);
+        assert_eq!(summary.anonymous_downloads, 1);
+    }
+
+    #[sqlx::test]
+    async fn a_draft_summary_is_hidden_from_others(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = draft(&pool, owner, new_model("App")).await;
+        let repo = AIModelRepository::new(pool.clone());
+
+        let stranger = Some(user(create_user(&pool).await));
+        assert!(matches!(
+            get_model_summary(State(repo.clone()), stranger, Path(model.id)).await,
+            Err(AppError::NotFound(_))
+        ));
+        assert!(get_model_summary(State(repo), Some(user(owner)), Path(model.id)).await.is_ok());
+    }
+
+    #[sqlx::test]
+    async fn the_python_example_names_the_model_version(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = published(
+            &pool,
+            owner,
+            CreateAIModel {
+                version: "2.3.1".into(),
+                ..new_model("App")
+            },
+        )
+        .await;
+        let repo = AIModelRepository::new(pool.clone());
+
+        let Json(examples) = get_usage_examples(
+            State(repo),
+            None,
+            Path(model.id),
+            Query(UsageExamplesQuery {
+                lang: Some(crate::models::Language::Python),
+            }),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(examples.len(), 1);
+        assert!(examples[0].code.contains(r#"MODEL_VERSION = "2.3.1""#));
+        assert!(examples[0].code.contains(&model.id.to_string()));
+    }
+
+    #[sqlx::test]
+    async fn examples_for_a_draft_are_hidden_from_others(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = draft(&pool, owner, new_model("App")).await;
+
+        let examples = get_usage_examples(
+            State(AIModelRepository::new(pool.clone())),
+            None,
+            Path(model.id),
+            Query(UsageExamplesQuery { lang: None }),
+        )
+        .await;
+        assert!(matches!(examples, Err(AppError::NotFound(_))));
+    }
+
+    fn priced(name: &str, price: f64) -> CreateAIModel {
+        CreateAIModel {
+            price: Some(price),
+            ..new_model(name)
+        }
+    }
+
+    #[sqlx::test]
+    async fn similar_pricing_spans_the_visible_peers(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let anchor = published(&pool, owner, priced("Anchor", 50.0)).await;
+        for (name, price) in [("Cheap", 10.0), ("Mid", 20.0), ("Dear", 40.0)] {
+            published(&pool, owner, priced(name, price)).await;
+        }
+        draft(&pool, owner, priced("Unreleased", 1000.0)).await;
+        published(
+            &pool,
+            owner,
+            CreateAIModel {
+                is_public: false,
+                ..priced("Private", 2000.0)
+            },
+        )
+        .await;
+
+        let Json(distribution) = get_similar_pricing(
+            State(AIModelRepository::new(pool.clone())),
+            None,
+            Path(anchor.id),
+            Query(SimilarPricingQuery::default()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(distribution.comparable_models, 3);
+        assert_eq!(distribution.min_price, Some(10.0));
+        assert_eq!(distribution.median_price, Some(20.0));
+        assert_eq!(distribution.max_price, Some(40.0));
+    }
+
+    #[sqlx::test]
+    async fn similar_pricing_for_a_draft_is_hidden_from_others(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let anchor = draft(&pool, owner, priced("Anchor", 50.0)).await;
+
+        let result = get_similar_pricing(
+            State(AIModelRepository::new(pool.clone())),
+            Some(user(create_user(&pool).await)),
+            Path(anchor.id),
+            Query(SimilarPricingQuery::default()),
+        )
+        .await;
+
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+    }
+
+    #[sqlx::test]
+    async fn download_stats_bucket_downloads_by_day(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = published(&pool, owner, new_model("Counted")).await;
+        let day = |d: u32, h: u32| chrono::Utc.with_ymd_and_hms(2024, 3, d, h, 0, 0).unwrap();
+        for at in [day(1, 9), day(1, 17), day(2, 12)] {
+            sqlx::query!(
+                "INSERT INTO model_downloads (model_id, downloaded_at) VALUES ($1, $2)",
+                model.id,
+                at
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let Json(stats) = get_download_stats(
+            State(AIModelRepository::new(pool.clone())),
+            None,
+            Path(model.id),
+            Query(DownloadStatsQuery {
+                from: Some(day(1, 0)),
+                to: Some(day(3, 0)),
+                bucket: StatsBucket::Day,
+            }),
+        )
+        .await
+        .unwrap();
+
+        let counts: Vec<_> = stats.iter().map(|b| (b.bucket_start, b.downloads)).collect();
+        assert_eq!(counts, vec![(day(1, 0), 2), (day(2, 0), 1)]);
+    }
+
+    #[sqlx::test]
+    async fn download_stats_for_a_draft_are_hidden_from_others(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = draft(&pool, owner, new_model("Unreleased")).await;
+        let repo = AIModelRepository::new(pool.clone());
+        let query = || {
+            Query(DownloadStatsQuery {
+                from: None,
+                to: None,
+                bucket: StatsBucket::Day,
+            })
+        };
+
+        let stranger = Some(user(create_user(&pool).await));
+        let result = get_download_stats(State(repo.clone()), stranger, Path(model.id), query()).await;
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+
+        let Json(stats) = get_download_stats(State(repo), Some(user(owner)), Path(model.id), query())
+            .await
+            .unwrap();
+        assert!(stats.iter().all(|bucket| bucket.downloads == 0));
+    }
+
+    #[sqlx::test]
+    async fn a_model_untouched_past_the_threshold_is_stale(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let fresh = published(&pool, owner, new_model("Fresh")).await;
+        let old = published(&pool, owner, new_model("Old")).await;
+        // The trigger would stamp the backdating itself as a fresh update.
+        sqlx::query!("ALTER TABLE ai_models DISABLE TRIGGER update_ai_models_updated_at")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query!(
+            "UPDATE ai_models SET updated_at = NOW() - interval '3 years' WHERE id = $1",
+            old.id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let repo = AIModelRepository::new(pool.clone());
+
+        let old = repo.get(old.id).await.unwrap().unwrap();
+        assert!(old.is_stale(stale_after_months()));
+        assert!(!fresh.is_stale(stale_after_months()));
+    }
+
+    #[sqlx::test]
+    async fn flagging_a_model_stale_records_one_report_per_user(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let reporter = create_user(&pool).await;
+        let model = published(&pool, owner, new_model("Reported")).await;
+        let state = app_state(&pool).await;
+        let request = || {
+            Some(Json(FlagStaleRequest {
+                reason: Some("Superseded by v2".into()),
+            }))
+        };
+
+        let Json(summary) = flag_stale(State(state.clone()), user(reporter), Path(model.id), request())
+            .await
+            .unwrap();
+        assert_eq!(summary.reports, 1);
+        let Json(summary) = flag_stale(State(state.clone()), user(reporter), Path(model.id), request())
+            .await
+            .unwrap();
+        assert_eq!(summary.reports, 1);
+
+        let reason = sqlx::query_scalar!(
+            "SELECT reason FROM model_stale_reports WHERE model_id = $1 AND user_id = $2",
+            model.id,
+            reporter
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(reason.as_deref(), Some("Superseded by v2"));
+    }
+
+    #[sqlx::test]
+    async fn drafts_cannot_be_flagged_stale_by_others(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = draft(&pool, owner, new_model("Unreleased")).await;
+        let state = app_state(&pool).await;
+
+        let result = flag_stale(
+            State(state.clone()),
+            user(create_user(&pool).await),
+            Path(model.id),
+            None,
+        )
+        .await;
+
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+        assert_eq!(state.repo.stale_report_count(model.id).await.unwrap(), 0);
+    }
+
+    fn on_tier(name: &str, tier: SubscriptionTier) -> CreateAIModel {
+        CreateAIModel {
+            required_tier: Some(tier),
+            ..new_model(name)
+        }
+    }
+
+    #[sqlx::test]
+    async fn a_free_user_cannot_download_an_enterprise_model(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let fan = create_user(&pool).await;
+        subscribe(&pool, fan, SubscriptionTier::Free).await;
+        let model = published(&pool, owner, on_tier("Big", SubscriptionTier::Enterprise)).await;
+        let state = app_state(&pool).await;
+
+        let result = increment_downloads(
+            State(state.clone()),
+            Some(AuthUser(fan)),
+            Path(model.id),
+            Query(UtmParams::default()),
+        )
+        .await;
+
+        assert!(matches!(result, Err(AppError::Forbidden(_))));
+        let model = state.repo.get(model.id).await.unwrap().unwrap();
+        assert_eq!(model.download_count, 0);
+    }
+
+    #[sqlx::test]
+    async fn a_pro_user_can_download_a_pro_model(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let fan = create_user(&pool).await;
+        subscribe(&pool, fan, SubscriptionTier::Pro).await;
+        let model = published(&pool, owner, on_tier("Mid", SubscriptionTier::Pro)).await;
+        let state = app_state(&pool).await;
+
+        let status = increment_downloads(
+            State(state.clone()),
+            Some(AuthUser(fan)),
+            Path(model.id),
+            Query(UtmParams::default()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(status, StatusCode::OK);
+        let model = state.repo.get(model.id).await.unwrap().unwrap();
+        assert_eq!(model.download_count, 1);
+    }
+
+    #[sqlx::test]
+    async fn drafts_cannot_be_downloaded_by_others(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = draft(&pool, owner, new_model("Unreleased")).await;
+        let state = app_state(&pool).await;
+
+        let stranger = Some(AuthUser(create_user(&pool).await));
+        let result =
+            increment_downloads(State(state.clone()), stranger, Path(model.id), Query(UtmParams::default())).await;
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+
+        let result = increment_downloads(State(state), None, Path(model.id), Query(UtmParams::default())).await;
+        assert!(matches!(result, Err(AppError::Unauthorized(_))));
+    }
+
+    #[sqlx::test]
+    async fn fetching_a_download_link_does_not_count_a_download(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let hosted = CreateAIModel {
+            repository_url: Some("https://example.com/weights.bin".into()),
+            ..new_model("Hosted")
+        };
+        let model = published(&pool, owner, hosted).await;
+        let state = app_state(&pool).await;
+
+        let Json(link) = get_download_link(State(state.clone()), None, Path(model.id))
+            .await
+            .unwrap();
+        assert_eq!(link.url, "https://example.com/weights.bin");
+        assert_eq!(state.repo.get(model.id).await.unwrap().unwrap().download_count, 0);
+
+        let Json(download) =
+            get_download_url(State(state.clone()), None, Path(model.id), Query(UtmParams::default()))
+                .await
+                .unwrap();
+        assert_eq!(download.url, link.url);
+        assert_eq!(state.repo.get(model.id).await.unwrap().unwrap().download_count, 1);
+    }
+
+    async fn listed(state: &AppState, caller: Option<Caller>) -> Vec<Uuid> {
+        listed_with(state, caller, ListQueryParams::default()).await
+    }
+
+    async fn listed_with(
+        state: &AppState,
+        caller: Option<Caller>,
+        params: ListQueryParams,
+    ) -> Vec<Uuid> {
+        let Json(list) = list_models(State(state.clone()), caller, ValidatedQuery(params))
+            .await
+            .unwrap();
+        list.models.into_iter().map(|item| item.model.id).collect()
+    }
+
+    #[sqlx::test]
+    async fn a_draft_is_listed_only_for_its_owner(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = draft(&pool, owner, new_model("Unreleased")).await;
+        let state = app_state(&pool).await;
+
+        assert!(!listed(&state, None).await.contains(&model.id));
+        assert!(!listed(&state, Some(user(create_user(&pool).await))).await.contains(&model.id));
+        assert!(listed(&state, Some(user(owner))).await.contains(&model.id));
+    }
+
+    #[sqlx::test]
+    async fn a_private_published_model_is_listed_only_for_its_owner(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = published(
+            &pool,
+            owner,
+            CreateAIModel {
+                is_public: false,
+                ..new_model("Private")
+            },
+        )
+        .await;
+        let state = app_state(&pool).await;
+
+        assert!(!listed(&state, None).await.contains(&model.id));
+        assert!(!listed(&state, Some(user(create_user(&pool).await))).await.contains(&model.id));
+        assert!(listed(&state, Some(user(owner))).await.contains(&model.id));
+    }
+
+    #[sqlx::test]
+    async fn a_submitted_model_is_listed_once_an_admin_publishes_it(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let reviewer = create_user(&pool).await;
+        let model = draft(&pool, owner, new_model("Candidate")).await;
+        let state = app_state(&pool).await;
+
+        let Json(submitted) = submit_model(State(state.repo.clone()), user(owner), Path(model.id))
+            .await
+            .unwrap();
+        assert_eq!(submitted.status, ModelStatus::PendingReview);
+        assert!(!listed(&state, None).await.contains(&model.id));
+
+        let Json(approved) = publish_model(State(state.repo.clone()), AdminUser(reviewer), Path(model.id))
+            .await
+            .unwrap();
+        assert_eq!(approved.status, ModelStatus::Published);
+        assert!(listed(&state, None).await.contains(&model.id));
+    }
+
+    #[sqlx::test]
+    async fn a_rejected_model_can_be_resubmitted(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let reviewer = create_user(&pool).await;
+        let model = draft(&pool, owner, new_model("Candidate")).await;
+        let repo = AIModelRepository::new(pool.clone());
+
+        assert!(matches!(
+            publish_model(State(repo.clone()), AdminUser(reviewer), Path(model.id)).await,
+            Err(AppError::Conflict { .. })
+        ));
+        let Json(submitted) = submit_model(State(repo.clone()), user(owner), Path(model.id))
+            .await
+            .unwrap();
+        assert_eq!(submitted.status, ModelStatus::PendingReview);
+        let Json(rejected) = reject_model(State(repo.clone()), AdminUser(reviewer), Path(model.id))
+            .await
+            .unwrap();
+        assert_eq!(rejected.status, ModelStatus::Rejected);
+
+        let Json(resubmitted) = submit_model(State(repo), user(owner), Path(model.id)).await.unwrap();
+        assert_eq!(resubmitted.status, ModelStatus::PendingReview);
+    }
+
+    /// A Pro subscription and card on file in Stripe, and one payment.
+    async fn paying_customer(pool: &PgPool) -> Uuid {
+        let user_id = create_user(pool).await;
+        let pro = plan(pool, SubscriptionTier::Pro).await;
+        sqlx::query!(
+            r#"
+            INSERT INTO user_subscriptions (user_id, subscription_id, starts_at, is_active, stripe_subscription_id)
+            VALUES ($1, $2, NOW(), true, 'sub_closing')
+            "#,
+            user_id,
+            pro.id
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+        sqlx::query!(
+            r#"
+            INSERT INTO payment_methods (user_id, stripe_payment_method_id, is_default)
+            VALUES ($1, 'pm_closing', true)
+            "#,
+            user_id
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+        sqlx::query!(
+            r#"
+            WITH intent AS (
+                INSERT INTO payment_intents
+                    (stripe_payment_intent_id, user_id, subscription_id, amount, status, client_secret)
+                VALUES ('pi_closing', $1, $2, 2999, 'succeeded', 'secret')
+                RETURNING id
+            )
+            INSERT INTO payment_history (user_id, subscription_id, payment_intent_id, amount, status)
+            SELECT $1, $2, id, 2999, 'succeeded' FROM intent
+            "#,
+            user_id,
+            pro.id
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+        user_id
+    }
+
+    #[sqlx::test]
+    async fn closing_an_account_cleans_up_stripe_and_keeps_anonymous_payments(pool: PgPool) {
+        let user_id = paying_customer(&pool).await;
+        let model = published(&pool, user_id, new_model("Orphan")).await;
+        let stripe = StripeStub::start().await;
+        let state = app_state_with_stripe(&pool, Some(&stripe.url)).await;
+
+        let Json(deletion) = delete_my_account(State(state.clone()), AuthUser(user_id))
+            .await
+            .unwrap();
+
+        assert!(!deletion.already_deleted);
+        assert_eq!(deletion.subscriptions_canceled, 1);
+        assert_eq!(deletion.stripe_subscriptions_canceled, 1);
+        assert_eq!(deletion.payment_methods_detached, 1);
+        assert_eq!(deletion.stripe_payment_methods_detached, 1);
+        assert_eq!(deletion.models_deleted, 1);
+        assert_eq!(
+            stripe.requests(),
+            vec![
+                "DELETE /v1/subscriptions/sub_closing".to_string(),
+                "POST /v1/payment_methods/pm_closing/detach".to_string(),
+            ]
+        );
+        assert!(PendingStripeCleanup::pending(&pool, Some(user_id)).await.unwrap().is_empty());
+
+        let payer = sqlx::query!(
+            r#"
+            SELECT u.email, u.name, COUNT(h.id) AS "payments!"
+            FROM users u JOIN payment_history h ON h.user_id = u.id
+            WHERE u.id = $1
+            GROUP BY u.id
+            "#,
+            user_id
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(payer.payments, 1);
+        assert_eq!(payer.email, format!("deleted-{}@deleted.invalid", user_id));
+        assert_eq!(payer.name, "Deleted user");
+
+        let model = state.repo.get(model.id).await.unwrap();
+        assert!(model.map_or(true, |model| model.deleted_at.is_some()));
+    }
+
+    #[sqlx::test]
+    async fn closing_an_account_twice_changes_nothing_more(pool: PgPool) {
+        let user_id = paying_customer(&pool).await;
+        let stripe = StripeStub::start().await;
+        let state = app_state_with_stripe(&pool, Some(&stripe.url)).await;
+        let Json(first) = delete_my_account(State(state.clone()), AuthUser(user_id))
+            .await
+            .unwrap();
+        assert!(!first.already_deleted);
+
+        let Json(again) = delete_my_account(State(state), AuthUser(user_id)).await.unwrap();
+
+        assert!(again.already_deleted);
+        assert_eq!(again.subscriptions_canceled, 0);
+        assert_eq!(again.stripe_subscriptions_canceled, 0);
+        assert_eq!(again.stripe_payment_methods_detached, 0);
+        assert_eq!(stripe.requests().len(), 2);
+    }
+
+    #[sqlx::test]
+    async fn stripe_cleanup_a_closure_missed_is_finished_later(pool: PgPool) {
+        let user_id = paying_customer(&pool).await;
+
+        // Stripe is unreachable: the account still closes.
+        let Json(deletion) = delete_my_account(State(app_state(&pool).await), AuthUser(user_id))
+            .await
+            .unwrap();
+        assert_eq!(deletion.subscriptions_canceled, 1);
+        assert_eq!(deletion.stripe_subscriptions_canceled, 0);
+        assert_eq!(PendingStripeCleanup::pending(&pool, None).await.unwrap().len(), 1);
+
+        let stripe = StripeStub::start().await;
+        let state = app_state_with_stripe(&pool, Some(&stripe.url)).await;
+        let retried = state.stripe_service.finish_pending_account_cleanups().await.unwrap();
+
+        assert_eq!(retried, 1);
+        assert_eq!(stripe.requests().len(), 2);
+        assert!(PendingStripeCleanup::pending(&pool, None).await.unwrap().is_empty());
+    }
+
+    fn described(description: &str, version: Option<&str>) -> Json<UpdateAIModel> {
+        Json(UpdateAIModel {
+            description: Some(description.into()),
+            version: version.map(Into::into),
+            ..Default::default()
+        })
+    }
+
+    async fn edit(repo: &AIModelRepository, owner: Uuid, id: Uuid, changes: Json<UpdateAIModel>) -> AIModel {
+        let Json(model) = update_model(State(repo.clone()), user(owner), Path(id), changes)
+            .await
+            .unwrap();
+        model
+    }
+
+    #[sqlx::test]
+    async fn each_released_version_keeps_its_own_snapshot(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = draft(&pool, owner, new_model("Versioned")).await;
+        let repo = AIModelRepository::new(pool.clone());
+        let releases = [("1.1.0", "First"), ("1.2.0", "Second"), ("1.3.0", "Third")];
+        for (version, description) in releases {
+            edit(&repo, owner, model.id, described(description, Some(version))).await;
+        }
+
+        for (version, description) in releases {
+            let Json(snapshot) = get_version(
+                State(repo.clone()),
+                Some(user(owner)),
+                Path((model.id, version.to_string())),
+            )
+            .await
+            .unwrap();
+            assert_eq!(snapshot.snapshot["description"], description);
+        }
+        let Json(versions) = list_versions(State(repo), Some(user(owner)), Path(model.id))
+            .await
+            .unwrap();
+        let listed: Vec<_> = versions.iter().map(|v| (v.sequence, v.version.as_str())).collect();
+        assert_eq!(listed, vec![(4, "1.3.0"), (3, "1.2.0"), (2, "1.1.0"), (1, "1.0.0")]);
+    }
+
+    #[sqlx::test]
+    async fn edits_without_a_version_bump_keep_earlier_snapshots(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = draft(&pool, owner, new_model("Versioned")).await;
+        let repo = AIModelRepository::new(pool.clone());
+        for description in ["Edited once", "Edited twice"] {
+            edit(&repo, owner, model.id, described(description, None)).await;
+        }
+        // Nothing versioned changes, so no snapshot.
+        let repriced = Json(UpdateAIModel {
+            price: Some(5.0),
+            ..Default::default()
+        });
+        edit(&repo, owner, model.id, repriced).await;
+
+        let versions = repo.list_versions(model.id).await.unwrap();
+        let descriptions: Vec<_> = versions.iter().map(|v| v.snapshot["description"].clone()).collect();
+        assert_eq!(descriptions, vec!["Edited twice", "Edited once", "A test model"]);
+        assert!(versions.iter().all(|v| v.version == "1.0.0"));
+
+        let latest = repo.get_version(model.id, "1.0.0").await.unwrap().unwrap();
+        assert_eq!(latest.sequence, 3);
+    }
+
+    #[sqlx::test]
+    async fn owners_can_change_a_models_license(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = draft(&pool, owner, licensed("Relicensed", "MIT")).await;
+        let repo = AIModelRepository::new(pool.clone());
+        let relicense = |license: String| {
+            Json(UpdateAIModel {
+                license: Some(license),
+                ..Default::default()
+            })
+        };
+
+        let updated = edit(&repo, owner, model.id, relicense("Apache-2.0".into())).await;
+        assert_eq!(updated.license.as_deref(), Some("Apache-2.0"));
+
+        let too_long = relicense("x".repeat(crate::models::MAX_LICENSE_LENGTH + 1));
+        let result = update_model(State(repo.clone()), user(owner), Path(model.id), too_long).await;
+        assert!(matches!(result, Err(AppError::BadRequest(message)) if message.contains("license")));
+        let stored = repo.get(model.id).await.unwrap().unwrap();
+        assert_eq!(stored.license.as_deref(), Some("Apache-2.0"));
+    }
+
+    async fn fetched(state: &AppState, id: Uuid, include: Option<&str>) -> serde_json::Value {
+        let Json(model) = get_model(
+            State(state.clone()),
+            None,
+            Path(id),
+            Query(GetModelParams {
+                include: include.map(Into::into),
+            }),
+        )
+        .await
+        .unwrap();
+        serde_json::to_value(model).unwrap()
+    }
+
+    #[sqlx::test]
+    async fn include_reviews_embeds_the_reviews(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = published(&pool, owner, new_model("Reviewed")).await;
+        let state = app_state(&pool).await;
+        let review = CreateReview {
+            rating: 4,
+            comment: Some("Solid".into()),
+        };
+        state
+            .reviews
+            .create_review(model.id, create_user(&pool).await, review)
+            .await
+            .unwrap();
+
+        let body = fetched(&state, model.id, Some("reviews")).await;
+
+        assert_eq!(body["reviews"].as_array().unwrap().len(), 1);
+        assert_eq!(body["reviews"][0]["comment"], "Solid");
+        assert!(body.get("versions").is_none());
+    }
+
+    #[sqlx::test]
+    async fn include_versions_embeds_the_version_history(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = published(&pool, owner, new_model("Versioned")).await;
+        let state = app_state(&pool).await;
+
+        let body = fetched(&state, model.id, Some("versions,reviews")).await;
+
+        assert_eq!(body["versions"][0]["version"], "1.0.0");
+        assert_eq!(body["reviews"], serde_json::json!([]));
+    }
+
+    #[sqlx::test]
+    async fn without_include_the_model_comes_alone(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = published(&pool, owner, new_model("Lean")).await;
+        let state = app_state(&pool).await;
+
+        let body = fetched(&state, model.id, None).await;
+
+        assert_eq!(body["id"], model.id.to_string());
+        for relation in INCLUDABLE {
+            assert!(body.get(relation).is_none(), "{} was embedded", relation);
+        }
+    }
+
+    #[test]
+    fn unknown_includes_are_rejected() {
+        assert!(matches!(parse_includes(Some("benchmarks")), Err(AppError::BadRequest(_))));
+        assert_eq!(parse_includes(Some("reviews, versions,reviews")).unwrap(), vec!["reviews", "versions"]);
+    }
+
+    #[sqlx::test]
+    async fn a_new_model_comes_back_with_its_tier_tags_and_price(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let created = draft(
+            &pool,
+            owner,
+            CreateAIModel {
+                required_tier: Some(SubscriptionTier::Pro),
+                tags: Some(vec!["vision".into(), "edge".into()]),
+                price: Some(19.99),
+                ..new_model("Described")
+            },
+        )
+        .await;
+        let state = app_state(&pool).await;
+
+        let Json(fetched) = get_model(
+            State(state),
+            Some(user(owner)),
+            Path(created.id),
+            Query(GetModelParams { include: None }),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(fetched.model.required_tier, SubscriptionTier::Pro);
+        assert_eq!(fetched.model.tags, vec!["vision".to_string(), "edge".to_string()]);
+        assert_eq!(fetched.model.price, Some(19.99));
+    }
+
+    #[sqlx::test]
+    async fn listing_filters_on_the_decimal_price(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let state = app_state(&pool).await;
+        let cheap = published(&pool, owner, priced("Cheap", 9.99)).await;
+        published(&pool, owner, priced("Dear", 99.5)).await;
+
+        let Json(list) = list_models(
+            State(state),
+            None,
+            ValidatedQuery(ListQueryParams {
+                max_price: Some(10.0),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+
+        let listed: Vec<_> = list.models.iter().map(|item| (item.model.id, item.model.price)).collect();
+        assert_eq!(listed, vec![(cheap.id, Some(9.99))]);
+    }
+
+    #[sqlx::test]
+    async fn a_deleted_model_is_hidden_until_an_admin_restores_it(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = published(&pool, owner, new_model("Deleted")).await;
+        let state = app_state(&pool).await;
+        let get = |caller| {
+            get_model(
+                State(state.clone()),
+                caller,
+                Path(model.id),
+                Query(GetModelParams { include: None }),
+            )
+        };
+
+        let status = delete_model(State(state.repo.clone()), user(owner), Path(model.id))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(matches!(get(Some(user(owner))).await, Err(AppError::NotFound(_))));
+        assert!(!listed(&state, Some(user(owner))).await.contains(&model.id));
+        let row = sqlx::query!("SELECT deleted_at FROM ai_models WHERE id = $1", model.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert!(row.deleted_at.is_some());
+
+        let admin_id = create_user(&pool).await;
+        let Json(restored) = restore_model(State(state.repo.clone()), AdminUser(admin_id), Path(model.id))
+            .await
+            .unwrap();
+        assert!(restored.deleted_at.is_none());
+        assert!(get(None).await.is_ok());
+        assert!(listed(&state, None).await.contains(&model.id));
+    }
+
+    async fn qrcode(repo: &AIModelRepository, id: Uuid, size: Option<u32>) -> Result<Response, AppError> {
+        let response = get_model_qrcode(
+            State(repo.clone()),
+            Path(id),
+            Query(QrCodeParams { size, ec: Some("H".into()) }),
+        )
+        .await?;
+        Ok(response.into_response())
+    }
+
+    #[sqlx::test]
+    async fn the_qr_code_is_a_png_of_the_requested_size(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = published(&pool, owner, new_model("Shared")).await;
+        let repo = AIModelRepository::new(pool.clone());
+
+        let response = qrcode(&repo, model.id, Some(300)).await.unwrap();
+
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+        let png = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let image = image::load_from_memory_with_format(&png, ImageFormat::Png).unwrap();
+        assert_eq!((image.width(), image.height()), (300, 300));
+    }
+
+    #[sqlx::test]
+    async fn private_models_have_no_qr_code(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let private = CreateAIModel {
+            is_public: false,
+            ..new_model("Private")
+        };
+        let model = published(&pool, owner, private).await;
+        let repo = AIModelRepository::new(pool.clone());
+
+        let result = qrcode(&repo, model.id, None).await;
+
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+    }
+
+    #[sqlx::test]
+    async fn an_edit_is_credited_to_whoever_made_it(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let moderator = create_user(&pool).await;
+        let model = draft(&pool, owner, new_model("Audited")).await;
+        let repo = AIModelRepository::new(pool.clone());
+
+        let Json(updated) = update_model(
+            State(repo.clone()),
+            admin(moderator),
+            Path(model.id),
+            described("Reworded", None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(updated.created_by, model.created_by);
+        assert_eq!(updated.updated_by, Some(moderator));
+
+        let feed = |user_id| {
+            let repo = repo.clone();
+            async move {
+                let Json(activity) = my_activity(
+                    State(repo),
+                    AuthUser(user_id),
+                    Query(ActivityQueryParams { limit: None }),
+                )
+                .await
+                .unwrap();
+                activity
+                    .into_iter()
+                    .map(|a| (a.model_id, a.action))
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(feed(moderator).await, vec![(model.id, "updated".to_string())]);
+        assert_eq!(feed(owner).await, vec![(model.id, "created".to_string())]);
+    }
+
+    #[sqlx::test]
+    async fn a_saved_view_filters_and_explicit_params_override_it(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let viewer = create_user(&pool).await;
+        let state = app_state(&pool).await;
+        let classifier = published(&pool, owner, new_model("Classifier")).await;
+        let llm = published(
+            &pool,
+            owner,
+            CreateAIModel {
+                model_type: ModelType::Llm,
+                ..new_model("Chat")
+            },
+        )
+        .await;
+
+        let Json(saved) = save_view(
+            State(state.clone()),
+            AuthUser(viewer),
+            Json(SaveModelView {
+                name: "llms".into(),
+                query: ListQueryParams {
+                    model_type: Some(ModelType::Llm),
+                    ..Default::default()
+                },
+            }),
+        )
+        .await
+        .unwrap();
+        let Json(views) = list_views(State(state.clone()), AuthUser(viewer)).await.unwrap();
+        assert_eq!(views.iter().map(|v| v.id).collect::<Vec<_>>(), [saved.id]);
+
+        let with_view = |model_type| ListQueryParams {
+            view: Some("llms".into()),
+            model_type,
+            ..Default::default()
+        };
+        assert_eq!(listed_with(&state, Some(user(viewer)), with_view(None)).await, [llm.id]);
+        assert_eq!(
+            listed_with(&state, Some(user(viewer)), with_view(Some(ModelType::Classification))).await,
+            [classifier.id]
+        );
+
+        let unknown = list_models(
+            State(state.clone()),
+            Some(user(owner)),
+            ValidatedQuery(with_view(None)),
+        )
+        .await;
+        assert!(matches!(unknown, Err(AppError::NotFound(_))));
+    }
+
+    #[sqlx::test]
+    async fn the_tier_report_groups_the_owners_models_by_tier(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let gated = |name, tier| CreateAIModel {
+            required_tier: Some(tier),
+            ..new_model(name)
+        };
+        published(&pool, owner, new_model("Open")).await;
+        draft(&pool, owner, gated("Also open", SubscriptionTier::Free)).await;
+        published(&pool, owner, gated("Premium", SubscriptionTier::Pro)).await;
+        published(&pool, create_user(&pool).await, gated("Not theirs", SubscriptionTier::Enterprise)).await;
+        subscribe(&pool, create_user(&pool).await, SubscriptionTier::Pro).await;
+        subscribe(&pool, create_user(&pool).await, SubscriptionTier::Enterprise).await;
+        let users: i64 = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM users"#)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+
+        let Json(report) = my_tier_report(State(app_state(&pool).await), AuthUser(owner))
+            .await
+            .unwrap();
+
+        assert_eq!(report.total_models, 3);
+        let tiers: Vec<_> = report
+            .tiers
+            .iter()
+            .map(|entry| (entry.required_tier, entry.models, entry.reach))
+            .collect();
+        assert_eq!(
+            tiers,
+            [
+                (SubscriptionTier::Free, 2, users),
+                (SubscriptionTier::Pro, 1, 2),
+                (SubscriptionTier::Enterprise, 0, 1),
+            ]
+        );
+    }
+
+    #[sqlx::test]
+    async fn a_private_artifact_is_signed_only_for_its_owner(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = published(
+            &pool,
+            owner,
+            CreateAIModel {
+                is_public: false,
+                ..new_model("Private weights")
+            },
+        )
+        .await;
+        sqlx::query!(
+            "UPDATE ai_models SET storage_key = 'models/weights.bin' WHERE id = $1",
+            model.id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let state = AppState {
+            storage: Some(crate::storage::StorageSettings {
+                endpoint: url::Url::parse("https://models.s3.amazonaws.com").unwrap(),
+                region: "us-east-1".into(),
+                access_key_id: "AKIDEXAMPLE".into(),
+                secret_access_key: "secret".into(),
+                url_expiry: std::time::Duration::from_secs(600),
+            }),
+            ..app_state(&pool).await
+        };
+        let download = |caller| {
+            get_download_url(State(state.clone()), caller, Path(model.id), Query(UtmParams::default()))
+        };
+
+        assert!(matches!(download(None).await, Err(AppError::Unauthorized(_))));
+        let stranger = AuthUser(create_user(&pool).await);
+        assert!(matches!(download(Some(stranger)).await, Err(AppError::NotFound(_))));
+        assert_eq!(state.repo.get(model.id).await.unwrap().unwrap().download_count, 0);
+
+        let requested_at = chrono::Utc::now();
+        let Json(signed) = download(Some(AuthUser(owner))).await.unwrap();
+        assert!(signed.url.starts_with("https://models.s3.amazonaws.com/models/weights.bin?"));
+        assert!(signed.url.contains("X-Amz-Expires=600"));
+        let expires_in = signed.expires_at.unwrap() - requested_at;
+        assert!((600..=602).contains(&expires_in.num_seconds()));
+        assert_eq!(state.repo.get(model.id).await.unwrap().unwrap().download_count, 1);
+    }
+
+    #[sqlx::test]
+    async fn higher_tiers_get_bigger_pages(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        for n in 0..FALLBACK_MAX_PAGE_SIZE + 5 {
+            published(&pool, owner, new_model(&format!("Model {}", n))).await;
+        }
+        let free = create_user(&pool).await;
+        let enterprise = create_user(&pool).await;
+        subscribe(&pool, enterprise, SubscriptionTier::Enterprise).await;
+        let state = app_state(&pool).await;
+
+        let page_for = |caller| {
+            list_models(
+                State(state.clone()),
+                caller,
+                ValidatedQuery(ListQueryParams {
+                    per_page: Some(100),
+                    ..Default::default()
+                }),
+            )
+        };
+        let Json(anonymous) = page_for(None).await.unwrap();
+        let Json(free) = page_for(Some(user(free))).await.unwrap();
+        let Json(enterprise) = page_for(Some(user(enterprise))).await.unwrap();
+
+        assert_eq!((anonymous.per_page, anonymous.models.len()), (50, 50));
+        assert_eq!((free.per_page, free.models.len()), (50, 50));
+        assert_eq!((enterprise.per_page, enterprise.models.len()), (100, 55));
+    }
+
+    #[sqlx::test]
+    async fn only_the_owner_or_an_admin_deletes_a_model(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let by_admin = published(&pool, owner, new_model("Admin removes")).await;
+        let by_owner = published(&pool, owner, new_model("Owner removes")).await;
+        let repo = AIModelRepository::new(pool.clone());
+        let delete = |caller, id| delete_model(State(repo.clone()), caller, Path(id));
+
+        let stranger = user(create_user(&pool).await);
+        assert!(matches!(delete(stranger, by_admin.id).await, Err(AppError::Forbidden(_))));
+        assert!(repo.get(by_admin.id).await.unwrap().is_some());
+
+        let moderator = admin(create_user(&pool).await);
+        assert_eq!(delete(moderator, by_admin.id).await.unwrap(), StatusCode::NO_CONTENT);
+        assert_eq!(delete(user(owner), by_owner.id).await.unwrap(), StatusCode::NO_CONTENT);
+        for id in [by_admin.id, by_owner.id] {
+            assert!(repo.get(id).await.unwrap().is_none());
+        }
+    }
+
+    #[sqlx::test]
+    async fn withdrawing_a_model_keeps_it_for_existing_downloaders(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let state = app_state(&pool).await;
+        let for_sale = |name| CreateAIModel {
+            repository_url: Some("https://example.com/weights.bin".into()),
+            required_tier: Some(SubscriptionTier::Pro),
+            ..priced(name, 49.0)
+        };
+        let freed = published(&pool, owner, for_sale("Freed")).await;
+        let archived = published(&pool, owner, for_sale("Archived")).await;
+        let earlier = create_user(&pool).await;
+        subscribe(&pool, earlier, SubscriptionTier::Pro).await;
+        let download = |user_id, id| {
+            let utm = Query(UtmParams::default());
+            get_download_url(State(state.clone()), Some(AuthUser(user_id)), Path(id), utm)
+        };
+        let Json(_) = download(earlier, archived.id).await.unwrap();
+        let withdraw = |caller, id, archive| {
+            withdraw_from_sale(
+                State(state.repo.clone()),
+                caller,
+                Path(id),
+                Some(Json(WithdrawFromSale { archive })),
+            )
+        };
+
+        let stranger = create_user(&pool).await;
+        let refused = withdraw(user(stranger), freed.id, false).await;
+        assert!(matches!(refused, Err(AppError::Forbidden(_))));
+
+        let Json(free) = withdraw(user(owner), freed.id, false).await.unwrap();
+        assert_eq!((free.price, free.required_tier), (Some(0.0), SubscriptionTier::Free));
+        assert!(free.withdrawn_from_sale_at.is_some() && !free.archived);
+        let Json(_) = download(stranger, freed.id).await.unwrap();
+        let again = withdraw(user(owner), freed.id, true).await;
+        assert!(matches!(again, Err(AppError::Conflict { .. })));
+
+        let Json(gone) = withdraw(user(owner), archived.id, true).await.unwrap();
+        assert!(gone.archived);
+        assert_eq!(gone.price, Some(49.0));
+        let Json(_) = download(earlier, archived.id).await.unwrap();
+        let latecomer = create_user(&pool).await;
+        subscribe(&pool, latecomer, SubscriptionTier::Pro).await;
+        assert!(matches!(download(latecomer, archived.id).await, Err(AppError::Forbidden(_))));
+    }
+
+    #[sqlx::test]
+    async fn one_bad_item_aborts_the_whole_batch(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let repo = AIModelRepository::new(pool.clone());
+        let batch =
+            |models| create_models_batch(State(repo.clone()), AuthUser(owner), Json(models));
+        let owned = || {
+            sqlx::query_scalar!(
+                r#"SELECT COUNT(*) AS "count!" FROM ai_models WHERE owner_id = $1"#,
+                owner
+            )
+            .fetch_one(&pool)
+        };
+
+        let invalid = vec![new_model("First"), new_model(""), new_model("Third")];
+        match batch(invalid).await {
+            Err(AppError::BadRequest(message)) => {
+                assert!(message.starts_with("[1] name"), "{}", message)
+            }
+            other => panic!("expected a validation error, got {:?}", other.map(|_| ())),
+        }
+        assert_eq!(owned().await.unwrap(), 0);
+
+        // Valid items that clash in the database roll back together too.
+        let clashing = vec![new_model("Twin"), new_model("Other"), new_model("Twin")];
+        assert!(matches!(batch(clashing).await, Err(AppError::Conflict { .. })));
+        assert_eq!(owned().await.unwrap(), 0);
+
+        let Json(created) = batch(vec![new_model("One"), new_model("Two")]).await.unwrap();
+        assert_eq!(created.iter().map(|m| m.name.as_str()).collect::<Vec<_>>(), ["One", "Two"]);
+        assert_eq!(owned().await.unwrap(), 2);
+    }
+
+    async fn exported(state: &AppState, format: ExportFormat) -> String {
+        let response = export_models(
+            State(state.clone()),
+            None,
+            Query(ListQueryParams::default()),
+            Query(ExportQuery { format }),
+        )
+        .await
+        .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[sqlx::test]
+    async fn the_csv_export_has_a_header_and_escapes_free_text(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = published(
+            &pool,
+            owner,
+            CreateAIModel {
+                description: "Fast, small and \"cheap\"".to_string(),
+                ..new_model("Tiny")
+            },
+        )
+        .await;
+        let state = app_state(&pool).await;
+
+        let csv = exported(&state, ExportFormat::Csv).await;
+        let mut records = csv.split("\r\n");
+        assert_eq!(format!("{}\r\n", records.next().unwrap()), CATALOG_CSV_HEADER);
+        let record = records.next().unwrap();
+        assert!(
+            record.starts_with(&format!("{},Tiny,\"Fast, small and \"\"cheap\"\"\",", model.id)),
+            "{}",
+            record
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&exported(&state, ExportFormat::Json).await).unwrap();
+        assert_eq!(json[0]["description"], "Fast, small and \"cheap\"");
+    }
+
+    #[sqlx::test]
+    async fn favorites_are_added_once_removed_and_listed_in_full(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let fan = create_user(&pool).await;
+        let model = published(&pool, owner, new_model("Keeper")).await;
+        let state = app_state(&pool).await;
+        let favorites = || async {
+            let Json(list) = my_favorites(
+                State(state.repo.clone()),
+                AuthUser(fan),
+                ValidatedQuery(PageParams::default()),
+            )
+            .await
+            .unwrap();
+            list
+        };
+
+        for _ in 0..2 {
+            let added = add_favorite(State(state.clone()), user(fan), Path(model.id)).await;
+            assert_eq!(added.unwrap(), StatusCode::NO_CONTENT);
+        }
+        let list = favorites().await;
+        assert_eq!(list.total, 1);
+        let saved = &list.models[0].model;
+        assert_eq!((saved.id, saved.name.as_str()), (model.id, "Keeper"));
+        assert_eq!((&saved.description, saved.owner_id), (&model.description, model.owner_id));
+
+        let remove = || remove_favorite(State(state.repo.clone()), AuthUser(fan), Path(model.id));
+        assert_eq!(remove().await.unwrap(), StatusCode::NO_CONTENT);
+        assert!(matches!(remove().await, Err(AppError::NotFound(_))));
+        assert_eq!(favorites().await.total, 0);
+    }
+
+    async fn page_of(state: &AppState, page: i64, per_page: i64) -> ModelList {
+        let params = ListQueryParams {
+            page: Some(page),
+            per_page: Some(per_page),
+            ..Default::default()
+        };
+        let Json(list) = list_models(State(state.clone()), None, ValidatedQuery(params))
+            .await
+            .unwrap();
+        list
+    }
+
+    #[sqlx::test]
+    async fn paging_metadata_holds_at_an_exact_multiple_and_past_the_end(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        for i in 0..10 {
+            published(&pool, owner, new_model(&format!("Model {}", i))).await;
+        }
+        let state = app_state(&pool).await;
+
+        let last = page_of(&state, 2, 5).await;
+        assert_eq!((last.models.len(), last.total, last.total_pages), (5, 10, 2));
+        assert!(!last.has_next && last.has_prev);
+
+        let first = page_of(&state, 1, 5).await;
+        assert!(first.has_next && !first.has_prev);
+
+        let beyond = page_of(&state, 3, 5).await;
+        assert!(beyond.models.is_empty());
+        assert_eq!(beyond.total_pages, 2);
+        assert!(!beyond.has_next && beyond.has_prev);
+
+        for query in ["page=0", "per_page=-1"] {
+            let (mut parts, _) = axum::http::Request::builder()
+                .uri(format!("/api/models?{}", query))
+                .body(())
+                .unwrap()
+                .into_parts();
+            let params =
+                ValidatedQuery::<ListQueryParams>::from_request_parts(&mut parts, &state).await;
+            assert!(matches!(params, Err(AppError::BadRequest(_))), "{}", query);
+        }
+    }
+
+    #[sqlx::test]
+    async fn too_many_or_too_long_tags_are_rejected_naming_the_limit(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let repo = AIModelRepository::new(pool.clone());
+        let tags = |count: usize| (0..count).map(|i| format!("tag{}", i)).collect::<Vec<_>>();
+        let create = |tags: Vec<String>| {
+            let model = CreateAIModel {
+                tags: Some(tags),
+                ..new_model("Tagged")
+            };
+            create_model(State(repo.clone()), AuthUser(owner), Json(model))
+        };
+        let rejected = |result: Result<_, AppError>, limit: &str| match result {
+            Err(AppError::BadRequest(message)) => assert!(message.contains(limit), "{}", message),
+            Err(e) => panic!("expected a bad request, got {:?}", e),
+            Ok(_) => panic!("expected {} to be enforced", limit),
+        };
+
+        rejected(create(tags(MAX_TAGS + 1)).await.map(|_| ()), "at most 20 tags");
+        rejected(create(vec!["x".repeat(MAX_TAG_LENGTH + 1)]).await.map(|_| ()), "50 characters");
+
+        let Json(model) = create(tags(MAX_TAGS - 1)).await.unwrap();
+        let add = |tags: Vec<String>| {
+            add_tags(State(repo.clone()), user(owner), Path(model.id), Json(AddTags { tags }))
+        };
+        let overflow = vec!["extra1".to_string(), "extra2".to_string()];
+        rejected(add(overflow).await.map(|_| ()), "at most 20 tags");
+        rejected(add(vec!["y".repeat(MAX_TAG_LENGTH + 1)]).await.map(|_| ()), "50 characters");
+        assert_eq!(repo.get(model.id).await.unwrap().unwrap().tags.len(), MAX_TAGS - 1);
+
+        let Json(added) = add(vec!["extra1".to_string()]).await.unwrap();
+        assert_eq!(added.tags.len(), MAX_TAGS);
+    }
+
+    #[sqlx::test]
+    async fn the_diff_reports_a_new_description_and_an_added_metadata_key(pool: PgPool) {
+        use crate::models::{ChangeKind, Patch};
+
+        let owner = create_user(&pool).await;
+        let seeded = CreateAIModel {
+            metadata: Some(json!({ "license_url": "https://example.com/license" })),
+            ..new_model("Versioned")
+        };
+        let model = published(&pool, owner, seeded).await;
+        let repo = AIModelRepository::new(pool.clone());
+        let changes = Json(UpdateAIModel {
+            description: Some("Now with more layers".into()),
+            version: Some("2.0.0".into()),
+            metadata: Patch::Set(json!({
+                "license_url": "https://example.com/license",
+                "dataset": "imdb",
+            })),
+            ..Default::default()
+        });
+        edit(&repo, owner, model.id, changes).await;
+        let diff = |from: &str, to: &str| {
+            get_version_diff(
+                State(repo.clone()),
+                None,
+                Path((model.id, from.to_string(), to.to_string())),
+            )
+        };
+
+        let Json(diff_1_2) = diff("1.0.0", "2.0.0").await.unwrap();
+        let description = diff_1_2.fields.iter().find(|c| c.field == "description").unwrap();
+        assert_eq!(description.kind, ChangeKind::Changed);
+        assert_eq!(description.old, Some(json!("A test model")));
+        assert_eq!(description.new, Some(json!("Now with more layers")));
+        assert!(diff_1_2.fields.iter().all(|c| c.field != "metadata"));
+        assert_eq!(diff_1_2.metadata.len(), 1);
+        let dataset = &diff_1_2.metadata[0];
+        assert_eq!((dataset.field.as_str(), dataset.kind), ("dataset", ChangeKind::Added));
+        assert_eq!((&dataset.old, &dataset.new), (&None, &Some(json!("imdb"))));
+
+        assert!(matches!(diff("1.0.0", "9.9.9").await, Err(AppError::NotFound(_))));
+    }
+
+    #[sqlx::test]
+    async fn an_unowned_model_is_claimed_after_verification_and_an_owned_one_never(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let claimant = create_user(&pool).await;
+        let hosted = |name| CreateAIModel {
+            repository_url: Some("http://127.0.0.1:9/repo".into()),
+            ..new_model(name)
+        };
+        let legacy = published(&pool, owner, hosted("Legacy")).await;
+        let owned = published(&pool, owner, hosted("Owned")).await;
+        sqlx::query!("UPDATE ai_models SET owner_id = NULL WHERE id = $1", legacy.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let repo = AIModelRepository::new(pool.clone());
+        let challenge =
+            |id| create_claim_challenge(State(repo.clone()), AuthUser(claimant), Path(id));
+        let claim = |id| claim_model(State(repo.clone()), AuthUser(claimant), Path(id));
+
+        let Json(issued) = challenge(legacy.id).await.unwrap();
+        // Unverifiable: the repository isn't on a public host.
+        assert!(matches!(claim(legacy.id).await, Err(AppError::BadRequest(_))));
+        assert_eq!(repo.get(legacy.id).await.unwrap().unwrap().owner_id, None);
+
+        // What `claim_model` does once the token is found at the repository.
+        assert_eq!(repo.claim_token(legacy.id, claimant).await.unwrap(), Some(issued.token));
+        let claimed = repo.claim(legacy.id, claimant).await.unwrap().unwrap();
+        assert_eq!(claimed.owner_id, Some(claimant));
+        assert_eq!(repo.claim_token(legacy.id, claimant).await.unwrap(), None);
+
+        for id in [owned.id, legacy.id] {
+            assert!(matches!(challenge(id).await, Err(AppError::Conflict { .. })));
+            assert!(matches!(claim(id).await, Err(AppError::Conflict { .. })));
+            assert!(repo.claim(id, claimant).await.unwrap().is_none());
+        }
+        assert_eq!(repo.get(owned.id).await.unwrap().unwrap().owner_id, Some(owner));
+    }
+
+    #[sqlx::test]
+    async fn a_comparison_names_the_winner_of_each_metric(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let measured = |name, accuracy, latency_ms| CreateAIModel {
+            performance_metrics: Some(PerformanceMetrics {
+                accuracy: Some(accuracy),
+                latency_ms: Some(latency_ms),
+                ..Default::default()
+            }),
+            ..new_model(name)
+        };
+        let precise = published(&pool, owner, measured("Precise", 0.95, 120.0)).await;
+        let quick = published(&pool, owner, measured("Quick", 0.80, 15.0)).await;
+        let hidden = draft(&pool, create_user(&pool).await, new_model("Hidden")).await;
+        let missing = Uuid::new_v4();
+
+        let Json(comparison) = compare_models(
+            State(AIModelRepository::new(pool.clone())),
+            None,
+            Json(CompareModels {
+                ids: vec![precise.id, quick.id, hidden.id, missing],
+            }),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(comparison.models.len(), 2);
+        assert_eq!(comparison.not_found, [hidden.id, missing]);
+        let accuracy = &comparison.metrics["accuracy"];
+        assert_eq!((accuracy.winner, accuracy.min, accuracy.max), (Some(precise.id), 0.80, 0.95));
+        assert_eq!(accuracy.normalized[&quick.id], 0.0);
+        let latency = &comparison.metrics["latency_ms"];
+        assert!(latency.lower_is_better);
+        assert_eq!(latency.winner, Some(quick.id));
+        assert_eq!(latency.normalized[&quick.id], 1.0);
+    }
+
+    #[sqlx::test]
+    async fn a_fork_is_a_new_linked_draft_and_others_private_models_cannot_be_forked(
+        pool: PgPool,
+    ) {
+        let owner = create_user(&pool).await;
+        let forker = create_user(&pool).await;
+        let tagged = CreateAIModel {
+            tags: Some(vec!["nlp".into()]),
+            ..new_model("Original")
+        };
+        let source = published(&pool, owner, tagged).await;
+        sqlx::query!("UPDATE ai_models SET download_count = 42 WHERE id = $1", source.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let private = published(
+            &pool,
+            owner,
+            CreateAIModel {
+                is_public: false,
+                ..new_model("Private")
+            },
+        )
+        .await;
+        let repo = AIModelRepository::new(pool.clone());
+        let count = || {
+            sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM ai_models"#).fetch_one(&pool)
+        };
+        let fork = |caller, id| fork_model(State(repo.clone()), caller, Path(id));
+
+        let before = count().await.unwrap();
+        let (status, Json(forked)) = fork(user(forker), source.id).await.unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(count().await.unwrap(), before + 1);
+        assert_ne!(forked.id, source.id);
+        assert_eq!(forked.forked_from, Some(source.id));
+        assert_eq!((forked.owner_id, forked.status), (Some(forker), ModelStatus::Draft));
+        assert_eq!((forked.download_count, forked.tags), (0, vec!["nlp".to_string()]));
+
+        assert!(matches!(fork(user(forker), private.id).await, Err(AppError::NotFound(_))));
+        assert!(matches!(fork(admin(forker), private.id).await, Err(AppError::Forbidden(_))));
+    }
+
+    #[sqlx::test]
+    async fn a_checksum_is_reported_as_matching_or_not_with_the_expected_value(pool: PgPool) {
+        let expected = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let model = published(&pool, create_user(&pool).await, new_model("Hashed")).await;
+        sqlx::query!(
+            "UPDATE ai_models SET artifact_sha256 = $2 WHERE id = $1",
+            model.id,
+            expected
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let repo = AIModelRepository::new(pool.clone());
+        let verify = |sha256: String| {
+            let request = Json(VerifyChecksum { sha256 });
+            verify_checksum(State(repo.clone()), None, Path(model.id), request)
+        };
+
+        // Case and surrounding whitespace don't matter.
+        let Json(matching) = verify(format!(" {} ", expected.to_uppercase())).await.unwrap();
+        assert!(matching.matches);
+        assert_eq!(matching.expected, expected);
+
+        let Json(mismatching) = verify("0".repeat(64)).await.unwrap();
+        assert!(!mismatching.matches);
+        assert_eq!(mismatching.expected, expected);
+
+        assert!(matches!(verify("abc".into()).await, Err(AppError::BadRequest(_))));
+    }
+
+    #[sqlx::test]
+    async fn an_owner_can_fork_their_own_model_under_a_distinct_name(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let source = published(&pool, owner, new_model("Mine")).await;
+        let repo = AIModelRepository::new(pool.clone());
+        let fork = |caller| fork_model(State(repo.clone()), caller, Path(source.id));
+
+        let (_, Json(first)) = fork(user(owner)).await.unwrap();
+        let (_, Json(second)) = fork(user(owner)).await.unwrap();
+        for forked in [&first, &second] {
+            assert_eq!(forked.forked_from, Some(source.id));
+            assert_eq!(forked.name, format!("Mine ({})", &forked.id.to_string()[..8]));
+        }
+        assert_ne!(first.name, second.name);
+
+        let (_, Json(theirs)) = fork(user(create_user(&pool).await)).await.unwrap();
+        assert_eq!(theirs.name, "Mine");
+    }
+
+    #[sqlx::test]
+    async fn a_duplicate_name_is_a_409_naming_the_clash(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let repo = AIModelRepository::new(pool.clone());
+        let create = |owner, name| {
+            create_model(State(repo.clone()), AuthUser(owner), Json(new_model(name)))
+        };
+        let Json(first) = create(owner, "Summarizer").await.unwrap();
+        let Json(other) = create(owner, "Other").await.unwrap();
+
+        let clash = create(owner, "summarizer").await.unwrap_err();
+        assert!(matches!(
+            &clash,
+            AppError::Conflict { message, .. } if message == "a model with this name already exists"
+        ));
+        let response = axum::response::IntoResponse::into_response(clash);
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "a model with this name already exists");
+
+        let renamed = Json(UpdateAIModel {
+            name: Some("Summarizer".into()),
+            ..Default::default()
+        });
+        let renamed = update_model(State(repo.clone()), user(owner), Path(other.id), renamed).await;
+        assert!(matches!(renamed, Err(AppError::Conflict { .. })));
+
+        let Json(theirs) = create(create_user(&pool).await, "Summarizer").await.unwrap();
+        assert_ne!(theirs.id, first.id);
+    }
+
+    #[sqlx::test]
+    async fn a_null_repository_url_clears_it_and_an_absent_one_keeps_it(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let hosted = CreateAIModel {
+            repository_url: Some("https://example.com/weights.bin".into()),
+            ..new_model("Hosted")
+        };
+        let model = draft(&pool, owner, hosted).await;
+        let repo = AIModelRepository::new(pool.clone());
+        let patch = |body| serde_json::from_value::<UpdateAIModel>(body).map(Json).unwrap();
+
+        let kept = edit(&repo, owner, model.id, patch(json!({ "description": "Renamed" }))).await;
+        assert_eq!(kept.description, "Renamed");
+        assert_eq!(kept.repository_url.as_deref(), Some("https://example.com/weights.bin"));
+
+        let cleared = edit(&repo, owner, model.id, patch(json!({ "repository_url": null }))).await;
+        assert_eq!(cleared.repository_url, None);
+        assert_eq!(cleared.description, "Renamed");
+    }
+
+    #[sqlx::test]
+    async fn models_are_listed_within_a_price_window_or_free_only(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let mut priced = Vec::new();
+        for price in [None, Some(0.0), Some(5.0), Some(15.0), Some(50.0)] {
+            let model = CreateAIModel {
+                price,
+                ..new_model(&format!("Priced {:?}", price))
+            };
+            priced.push(published(&pool, owner, model).await.id);
+        }
+        let state = app_state(&pool).await;
+        let listed_sorted = |params| {
+            let state = state.clone();
+            async move {
+                let mut ids = listed_with(&state, None, params).await;
+                ids.sort();
+                ids
+            }
+        };
+        let sorted = |mut ids: Vec<Uuid>| {
+            ids.sort();
+            ids
+        };
+
+        let window = ListQueryParams {
+            min_price: Some(5.0),
+            max_price: Some(20.0),
+            ..Default::default()
+        };
+        assert_eq!(listed_sorted(window).await, sorted(priced[2..4].to_vec()));
+        let free = ListQueryParams {
+            free_only: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(listed_sorted(free).await, sorted(priced[..2].to_vec()));
+
+        let (mut parts, _) = axum::http::Request::builder()
+            .uri("/api/models?min_price=20&max_price=5")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let inverted =
+            ValidatedQuery::<ListQueryParams>::from_request_parts(&mut parts, &state).await;
+        assert!(matches!(inverted, Err(AppError::BadRequest(_))));
+    }
+
+    #[sqlx::test]
+    async fn a_sync_after_a_checkpoint_returns_only_what_changed_including_deletions(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let mut models = Vec::new();
+        for name in ["Edited", "Untouched", "Deleted"] {
+            models.push(published(&pool, owner, new_model(name)).await);
+        }
+        let state = app_state(&pool).await;
+        let sync = |since| {
+            let params = ListQueryParams {
+                updated_after: Some(since),
+                ..Default::default()
+            };
+            let state = state.clone();
+            async move {
+                let Json(list) = list_models(State(state), None, ValidatedQuery(params))
+                    .await
+                    .unwrap();
+                list.models
+                    .into_iter()
+                    .map(|item| (item.model.id, item.deleted, item.model.updated_at))
+                    .collect::<Vec<_>>()
+            }
+        };
+        let checkpoint = models.iter().map(|model| model.updated_at).max().unwrap();
+
+        edit(&state.repo, owner, models[0].id, described("Changed", None)).await;
+        let changed = sync(checkpoint).await;
+        assert_eq!(
+            changed.iter().map(|(id, deleted, _)| (*id, *deleted)).collect::<Vec<_>>(),
+            [(models[0].id, false)]
+        );
+
+        state.repo.delete(models[2].id).await.unwrap();
+        let deleted = sync(changed[0].2).await;
+        assert_eq!(
+            deleted.iter().map(|(id, deleted, _)| (*id, *deleted)).collect::<Vec<_>>(),
+            [(models[2].id, true)]
+        );
+    }
+
+    #[sqlx::test]
+    async fn adding_a_present_tag_or_removing_an_absent_one_changes_nothing(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let tagged = CreateAIModel {
+            tags: Some(vec!["nlp".into()]),
+            ..new_model("Tagged")
+        };
+        let model = draft(&pool, owner, tagged).await;
+        let repo = AIModelRepository::new(pool.clone());
+        let add = |caller, tags: &[&str]| {
+            let tags = tags.iter().map(|tag| tag.to_string()).collect();
+            add_tags(State(repo.clone()), caller, Path(model.id), Json(AddTags { tags }))
+        };
+        let remove = |tag: &str| {
+            remove_tag(State(repo.clone()), user(owner), Path((model.id, tag.into())))
+        };
+        let updated_at = || async { repo.get(model.id).await.unwrap().unwrap().updated_at };
+
+        let Json(same) = add(user(owner), &["nlp"]).await.unwrap();
+        assert_eq!(same.tags, ["nlp"]);
+        let Json(same) = remove("absent").await.unwrap();
+        assert_eq!(same.tags, ["nlp"]);
+        assert_eq!(updated_at().await, model.updated_at);
+
+        let Json(added) = add(user(owner), &["vision", "nlp"]).await.unwrap();
+        assert_eq!(added.tags, ["nlp", "vision"]);
+        let Json(removed) = remove("nlp").await.unwrap();
+        assert_eq!(removed.tags, ["vision"]);
+
+        assert!(matches!(add(user(owner), &["Has Space"]).await, Err(AppError::BadRequest(_))));
+        let stranger = user(create_user(&pool).await);
+        assert!(matches!(add(stranger, &["mine"]).await, Err(AppError::Forbidden(_))));
+    }
+
+    #[sqlx::test]
+    async fn a_link_signed_before_a_key_rotation_stops_working(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = published(&pool, owner, new_model("Hosted")).await;
+        let old_key = format!("models/{}/weights.bin", model.id);
+        sqlx::query!("UPDATE ai_models SET storage_key = $1 WHERE id = $2", old_key, model.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let bucket = BucketStub::start().await;
+        bucket.put(&old_key);
+        let mut state = app_state(&pool).await;
+        state.storage = Some(bucket.settings.clone());
+        let link = || async {
+            let Json(link) = get_download_link(State(state.clone()), None, Path(model.id))
+                .await
+                .unwrap();
+            link.url
+        };
+
+        let signed_before = link().await;
+        assert_eq!(bucket.get(&signed_before).await, StatusCode::OK);
+
+        let stranger = user(create_user(&pool).await);
+        let refused = rotate_model_key(State(state.clone()), stranger, Path(model.id)).await;
+        assert!(matches!(refused, Err(AppError::Forbidden(_))));
+        let Json(rotated) = rotate_model_key(State(state.clone()), user(owner), Path(model.id))
+            .await
+            .unwrap();
+        assert_ne!(rotated.storage_key.as_deref(), Some(old_key.as_str()));
+        assert!(rotated.key_rotated_at.is_some());
+
+        assert_eq!(bucket.get(&signed_before).await, StatusCode::NOT_FOUND);
+        assert_eq!(bucket.get(&link().await).await, StatusCode::OK);
+    }
+
+    #[sqlx::test]
+    async fn analytics_count_unique_users_and_recent_windows(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = published(&pool, owner, new_model("Watched")).await;
+        let (pro, other_pro, free) = (
+            create_user(&pool).await,
+            create_user(&pool).await,
+            create_user(&pool).await,
+        );
+        subscribe(&pool, pro, SubscriptionTier::Pro).await;
+        subscribe(&pool, other_pro, SubscriptionTier::Pro).await;
+        let downloads = [(Some(pro), 1), (Some(pro), 10), (Some(other_pro), 40), (Some(free), 2)];
+        for (user_id, days_ago) in downloads.into_iter().chain([(None, 3)]) {
+            sqlx::query!(
+                "INSERT INTO model_downloads (model_id, user_id, downloaded_at)
+                 VALUES ($1, $2, $3)",
+                model.id,
+                user_id,
+                chrono::Utc::now() - chrono::Duration::days(days_ago)
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        let repo = AIModelRepository::new(pool.clone());
+
+        let Json(analytics) = get_model_analytics(State(repo.clone()), user(owner), Path(model.id))
+            .await
+            .unwrap();
+        assert_eq!(analytics.total_downloads, 5);
+        assert_eq!(analytics.unique_users, 3);
+        assert_eq!(analytics.downloads_last_7_days, 3);
+        assert_eq!(analytics.downloads_last_30_days, 4);
+        assert_eq!(analytics.top_tier, Some(SubscriptionTier::Pro));
+
+        let refused = get_model_analytics(State(repo.clone()), user(free), Path(model.id)).await;
+        assert!(matches!(refused, Err(AppError::Forbidden(_))));
+        let staff = create_user(&pool).await;
+        assert!(get_model_analytics(State(repo), admin(staff), Path(model.id)).await.is_ok());
+    }
+}
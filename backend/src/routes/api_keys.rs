@@ -0,0 +1,59 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{delete, get},
+    Json, Router,
+};
+use uuid::Uuid;
+
+use crate::{
+    auth::AuthUser,
+    error::AppError,
+    models::{ApiKey, CreateApiKey, CreatedApiKey},
+    AppState,
+};
+
+const MAX_NAME_LENGTH: usize = 100;
+
+pub fn api_key_routes() -> Router<AppState> {
+    Router::new()
+        .route("/keys", get(list_api_keys).post(create_api_key))
+        .route("/keys/:id", delete(revoke_api_key))
+}
+
+async fn list_api_keys(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+) -> Result<Json<Vec<ApiKey>>, AppError> {
+    let keys = ApiKey::list_for_user(&state.pool, user_id).await?;
+    Ok(Json(keys))
+}
+
+async fn create_api_key(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Json(request): Json<CreateApiKey>,
+) -> Result<(StatusCode, Json<CreatedApiKey>), AppError> {
+    let name = request.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(AppError::BadRequest(format!(
+            "Key name must be 1-{} characters",
+            MAX_NAME_LENGTH
+        )));
+    }
+
+    let created = ApiKey::create(&state.pool, user_id, name).await?;
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
+async fn revoke_api_key(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    ApiKey::revoke(&state.pool, user_id, id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("API key not found".into()))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
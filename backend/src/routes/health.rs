@@ -0,0 +1,157 @@
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::Duration;
+
+use crate::AppState;
+
+const DB_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+/// Per-dependency budget for the deep check, so one slow dependency can't
+/// hold up the whole response.
+const DEPENDENCY_CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+async fn database_is_up(pool: &PgPool) -> bool {
+    match tokio::time::timeout(DB_CHECK_TIMEOUT, sqlx::query("SELECT 1").execute(pool)).await {
+        Ok(Ok(_)) => true,
+        Ok(Err(e)) => {
+            tracing::warn!("health check query failed: {}", e);
+            false
+        }
+        Err(_) => {
+            tracing::warn!("health check query timed out after {:?}", DB_CHECK_TIMEOUT);
+            false
+        }
+    }
+}
+
+/// Readiness: the service can handle requests, which requires the database.
+#[axum::debug_handler(state = AppState)]
+pub async fn ready(State(pool): State<PgPool>) -> (StatusCode, Json<Value>) {
+    if database_is_up(&pool).await {
+        (StatusCode::OK, Json(json!({ "status": "ok", "db": "up" })))
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "unavailable", "db": "down" })),
+        )
+    }
+}
+
+/// Liveness: the process is running. Deliberately ignores the database so a
+/// database outage doesn't get the container restarted.
+pub async fn live() -> Json<Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DependencyStatus {
+    Up,
+    Down,
+    NotConfigured,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeepHealth {
+    /// `ok`, `degraded` when an optional dependency is down, or
+    /// `unavailable` when the database is.
+    pub status: &'static str,
+    pub dependencies: BTreeMap<&'static str, DependencyStatus>,
+}
+
+async fn probe_component<F, T, E>(name: &str, probe: F) -> DependencyStatus
+where
+    F: Future<Output = Result<T, E>>,
+    E: std::fmt::Display,
+{
+    match tokio::time::timeout(DEPENDENCY_CHECK_TIMEOUT, probe).await {
+        Ok(Ok(_)) => DependencyStatus::Up,
+        Ok(Err(e)) => {
+            tracing::warn!("{} health check failed: {}", name, e);
+            DependencyStatus::Down
+        }
+        Err(_) => {
+            tracing::warn!("{} health check timed out after {:?}", name, DEPENDENCY_CHECK_TIMEOUT);
+            DependencyStatus::Down
+        }
+    }
+}
+
+/// Checks every dependency concurrently. Always answers, even when some
+/// checks fail; only a database outage makes it a `503`.
+pub async fn deep(State(state): State<AppState>) -> (StatusCode, Json<DeepHealth>) {
+    let storage = async {
+        match &state.storage {
+            Some(storage) => probe_component("storage", storage.ping()).await,
+            None => DependencyStatus::NotConfigured,
+        }
+    };
+    let (db, stripe, storage) = tokio::join!(
+        probe_component("database", sqlx::query("SELECT 1").execute(&state.pool)),
+        probe_component("stripe", state.stripe_service.ping()),
+        storage,
+    );
+
+    let dependencies = BTreeMap::from([("database", db), ("stripe", stripe), ("storage", storage)]);
+    let (code, status) = overall_status(&dependencies);
+
+    (code, Json(DeepHealth { status, dependencies }))
+}
+
+fn overall_status(
+    dependencies: &BTreeMap<&'static str, DependencyStatus>,
+) -> (StatusCode, &'static str) {
+    if dependencies.get("database") == Some(&DependencyStatus::Down) {
+        (StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+    } else if dependencies.values().any(|s| *s == DependencyStatus::Down) {
+        (StatusCode::OK, "degraded")
+    } else {
+        (StatusCode::OK, "ok")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use DependencyStatus::*;
+
+    fn dependencies(
+        db: DependencyStatus,
+        stripe: DependencyStatus,
+        storage: DependencyStatus,
+    ) -> BTreeMap<&'static str, DependencyStatus> {
+        BTreeMap::from([("database", db), ("stripe", stripe), ("storage", storage)])
+    }
+
+    #[test]
+    fn only_a_database_outage_is_unavailable() {
+        assert_eq!(overall_status(&dependencies(Up, Up, NotConfigured)), (StatusCode::OK, "ok"));
+        assert_eq!(overall_status(&dependencies(Up, Down, Up)), (StatusCode::OK, "degraded"));
+        assert_eq!(
+            overall_status(&dependencies(Down, Up, Up)),
+            (StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+        );
+    }
+
+    #[tokio::test]
+    async fn a_failed_probe_is_down() {
+        assert_eq!(probe_component("ok", async { Ok::<_, String>(()) }).await, Up);
+        assert_eq!(probe_component("err", async { Err::<(), _>("boom") }).await, Down);
+    }
+
+    #[sqlx::test]
+    async fn readiness_follows_the_database(pool: PgPool) {
+        let (code, Json(body)) = ready(State(pool.clone())).await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body, json!({ "status": "ok", "db": "up" }));
+
+        pool.close().await;
+        let (code, Json(body)) = ready(State(pool)).await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["db"], "down");
+        assert_eq!(live().await.0, json!({ "status": "ok" }));
+    }
+}
@@ -0,0 +1,126 @@
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, State,
+    },
+    response::Response,
+};
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use crate::{auth::Caller, error::AppError, events::EventBus, AppState};
+
+/// Push download and review updates for one model over a WebSocket until
+/// the client goes away.
+#[axum::debug_handler(state = AppState)]
+pub async fn live_model_updates(
+    State(state): State<AppState>,
+    caller: Option<Caller>,
+    Path(id): Path<Uuid>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, AppError> {
+    state
+        .repo
+        .get(id)
+        .await?
+        .filter(|model| {
+            model.is_visible_to(
+                caller.map(|caller| caller.user_id),
+                caller.is_some_and(|caller| caller.is_admin()),
+            )
+        })
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))?;
+
+    let events = state.events.clone();
+    Ok(ws.on_upgrade(move |socket| stream_model_events(socket, events, id)))
+}
+
+async fn stream_model_events(mut socket: WebSocket, events: EventBus, id: Uuid) {
+    let mut updates = events.subscribe();
+
+    loop {
+        tokio::select! {
+            update = updates.recv() => {
+                let event = match update {
+                    Ok(event) if event.model_id() == id => event,
+                    Ok(_) => continue,
+                    // A slow client just misses some bumps; later ones carry
+                    // the current count anyway.
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::debug!("live client for model {} skipped {} events", id, skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                let Ok(text) = serde_json::to_string(&event) else {
+                    continue;
+                };
+                if socket.send(Message::Text(text)).await.is_err() {
+                    return;
+                }
+            }
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                // Pings are answered by axum; nothing else is expected.
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::UtmParams;
+    use crate::routes::increment_downloads;
+    use crate::test_support::{app_state, create_user, new_model, published};
+    use axum::{extract::Query, routing::get, Router};
+    use futures::{SinkExt, StreamExt};
+    use sqlx::PgPool;
+    use tokio_tungstenite::tungstenite;
+
+    #[sqlx::test]
+    async fn a_download_is_pushed_to_the_models_live_subscribers(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let model = published(&pool, owner, new_model("Watched")).await;
+        let other = published(&pool, owner, new_model("Ignored")).await;
+        let state = app_state(&pool).await;
+        let app = Router::new()
+            .route("/api/models/:id/live", get(live_model_updates))
+            .with_state(state.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let url = format!("ws://{}/api/models/{}/live", addr, model.id);
+        let (mut socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        // The server subscribes before it reads, so once a ping is answered
+        // no event can be missed.
+        socket.send(tungstenite::Message::Ping(Vec::new())).await.unwrap();
+        let pong = socket.next().await.unwrap().unwrap();
+        assert!(matches!(pong, tungstenite::Message::Pong(_)), "{:?}", pong);
+
+        for id in [other.id, model.id] {
+            increment_downloads(State(state.clone()), None, Path(id), Query(UtmParams::default()))
+                .await
+                .unwrap();
+        }
+
+        let message = tokio::time::timeout(std::time::Duration::from_secs(5), socket.next())
+            .await
+            .expect("no update pushed")
+            .unwrap()
+            .unwrap();
+        let tungstenite::Message::Text(text) = message else {
+            panic!("expected a text frame, got {:?}", message);
+        };
+        let event: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(event["type"], "downloaded");
+        assert_eq!(event["model_id"], model.id.to_string());
+        assert_eq!(event["download_count"], 1);
+
+        socket.close(None).await.unwrap();
+    }
+}
@@ -1,3 +1,12 @@
+pub mod admin;
 pub mod ai_models;
+pub mod api_keys;
+pub mod health;
+pub mod live;
+pub mod payment;
+pub mod reviews;
+pub mod subscription;
 
-pub use ai_models::*; 
\ No newline at end of file
+pub use ai_models::*;
+pub use live::*;
+pub use reviews::*;
@@ -1,18 +1,33 @@
+use std::time::Duration;
+
 use axum::{
     extract::{Path, State},
-    routing::{get, post},
+    http::StatusCode,
+    response::sse::{Event, KeepAlive, Sse},
+    routing::{delete, get, post},
     Json, Router,
 };
+use futures::Stream;
 use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tokio::time::Instant;
 use uuid::Uuid;
 
 use crate::{
+    auth::{AdminUser, AuthUser},
     error::AppError,
+    events::PaymentStatusEvent,
     models::{
-        payment::{PaymentHistory, PaymentIntent, PaymentMethod},
-        subscription::Subscription,
+        payment::{
+            max_payment_retries, normalize_currency, validate_charge, Invoice, InvoiceDetail, PaymentHistory, PaymentIntent, PaymentMethod, WebhookEvent,
+        },
+        subscription::{BillingInterval, Subscription, UserSubscription},
+        BillingLocation, Coupon, Money, PageParams, Redemption, SubscriptionCredit,
+        UserSubscriptionAddon,
     },
-    services::stripe::{CreatePaymentIntentRequest, StripeService},
+    services::stripe::CreatePaymentIntentRequest,
+    tax,
+    validated_query::ValidatedQuery,
     AppState,
 };
 
@@ -20,16 +35,26 @@ pub fn payment_routes() -> Router<AppState> {
     Router::new()
         .route("/payments/create-intent", post(create_payment_intent))
         .route("/payments/status/:id", get(get_payment_status))
+        .route("/payments/status/:id/stream", get(stream_payment_status))
+        .route("/payments/retry/:id", post(retry_payment))
+        .route("/payments/:id/refund", post(refund_payment))
         .route("/payments/methods", get(list_payment_methods))
         .route("/payments/methods/attach", post(attach_payment_method))
+        .route("/payments/methods/:id", delete(detach_payment_method))
+        .route("/payments/methods/:id/default", post(set_default_payment_method))
         .route("/payments/history", get(get_payment_history))
+        .route("/payments/invoices", get(list_invoices))
+        .route(
+            "/payments/billing-location",
+            get(get_billing_location).put(set_billing_location),
+        )
+        .route("/payments/invoices/:id", get(get_invoice))
         .route("/payments/webhook", post(handle_webhook))
 }
 
 async fn create_payment_intent(
     State(state): State<AppState>,
-    // TODO: Extract user_id from JWT token
-    user_id: Uuid,
+    AuthUser(user_id): AuthUser,
     Json(request): Json<CreatePaymentIntentRequest>,
 ) -> Result<Json<PaymentIntent>, AppError> {
     // Get subscription details
@@ -37,11 +62,143 @@ async fn create_payment_intent(
         .await?
         .ok_or_else(|| AppError::NotFound("Subscription not found".into()))?;
 
+    let requested = request.currency.as_deref().unwrap_or(&subscription.currency);
+    let currency = normalize_currency(requested)
+        .ok_or_else(|| AppError::BadRequest(format!("Unsupported currency: {}", requested)))?;
+
+    // Plan prices are stored in a single currency and we don't convert.
+    if !currency.eq_ignore_ascii_case(&subscription.currency) {
+        return Err(AppError::BadRequest(format!(
+            "This plan is priced in {}",
+            subscription.currency
+        )));
+    }
+
+    let active = UserSubscription::get_active_for_user(&state.pool, user_id).await?;
+    if active.as_ref().is_some_and(|active| active.is_paused()) {
+        return Err(AppError::conflict(
+            "Your subscription is paused; resume it before paying",
+        ));
+    }
+
+    // Add-ons and the billing interval only apply to the plan the user is
+    // currently on
+    let (interval, addons) = match active {
+        Some(active) if active.subscription_id == subscription.id => (
+            active.billing_interval,
+            UserSubscriptionAddon::monthly_total(&state.pool, active.id, &currency).await?,
+        ),
+        _ => (BillingInterval::Monthly, Money::zero(&currency)),
+    };
+    let price = subscription.price(interval);
+    // Free plans are activated when subscribing; there's nothing to charge.
+    if price.minor_units() == 0 && addons.minor_units() == 0 {
+        return Err(AppError::BadRequest(format!(
+            "{} is free; subscribe to it without paying",
+            subscription.name
+        )));
+    }
+    // Everything below is summed in minor units of `currency`.
+    let subtotal = Money::from_minor(
+        price.minor_units() + addons.times(interval.months().into()).minor_units(),
+        &currency,
+    );
+
+    // Look up everything else the price depends on before claiming a coupon
+    // redemption, so that past this point every failure can give it back.
+    let credits = SubscriptionCredit::unapplied_total(&state.pool, user_id, &currency).await?;
+    let location = BillingLocation::get_for_user(&state.pool, user_id).await?;
+
+    let coupon = match request.coupon_code.as_deref() {
+        Some(code) => match Coupon::validate_and_redeem(&state.pool, code).await? {
+            Redemption::Redeemed(coupon) => Some(coupon),
+            Redemption::NotFound => {
+                return Err(AppError::BadRequest("Invalid coupon code".into()))
+            }
+            Redemption::Expired => {
+                return Err(AppError::BadRequest("This coupon has expired".into()))
+            }
+            Redemption::Exhausted => {
+                return Err(AppError::BadRequest(
+                    "This coupon has reached its redemption limit".into(),
+                ))
+            }
+        },
+        None => None,
+    };
+
+    let discount = match &coupon {
+        Some(coupon) => match coupon.discount_on(&subtotal) {
+            Some(discount) => discount,
+            None => {
+                Coupon::release(&state.pool, coupon.id).await?;
+                return Err(AppError::BadRequest(format!(
+                    "This coupon can't be used with {}",
+                    currency
+                )));
+            }
+        },
+        None => Money::zero(&currency),
+    };
+
+    let amount = Money::from_minor(
+        subtotal.minor_units() - discount.minor_units() - credits.minor_units(),
+        &currency,
+    )
+    .at_least_zero();
+
+    let tax = match tax::tax_on(state.tax.as_ref(), &amount, location.as_ref()).await {
+        Ok(tax) => tax,
+        Err(e) => {
+            if let Some(coupon) = &coupon {
+                Coupon::release(&state.pool, coupon.id).await?;
+            }
+            return Err(e.into());
+        }
+    };
+
+    let total = Money::from_minor(amount.minor_units() + tax.minor_units(), &currency);
+    if let Err(errors) = validate_charge(&total) {
+        if let Some(coupon) = &coupon {
+            Coupon::release(&state.pool, coupon.id).await?;
+        }
+        return Err(errors.into());
+    }
+
     // Create payment intent
-    let payment_intent = state
+    let result = state
         .stripe_service
-        .create_payment_intent(user_id, &subscription)
-        .await?;
+        .create_payment_intent(
+            user_id,
+            &subscription,
+            &total,
+            &tax,
+            request.idempotency_key.as_deref(),
+        )
+        .await;
+
+    let (payment_intent, created) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            if let Some(coupon) = &coupon {
+                Coupon::release(&state.pool, coupon.id).await?;
+            }
+            return Err(e.into());
+        }
+    };
+
+    // A reused pending intent was priced before any newer credits or coupon.
+    // The intent is priced with the discount now, so the claim stands.
+    if created {
+        if let Some(coupon) = &coupon {
+            Coupon::record_redemption(&state.pool, coupon.id, user_id, payment_intent.id, &discount)
+                .await?;
+        }
+        SubscriptionCredit::apply_to(&state.pool, user_id, &currency, payment_intent.id)
+            .await?;
+    } else if let Some(coupon) = &coupon {
+        Coupon::release(&state.pool, coupon.id).await?;
+    }
 
     Ok(Json(payment_intent))
 }
@@ -49,17 +206,187 @@ async fn create_payment_intent(
 #[derive(Debug, Serialize)]
 struct PaymentStatusResponse {
     payment_intent: PaymentIntent,
+    /// When set, the client should create a fresh intent.
+    expired: bool,
+}
+
+/// The caller's payment intent with this Stripe id. Someone else's is
+/// reported as missing, the same as one that doesn't exist.
+async fn owned_payment_intent(
+    state: &AppState,
+    user_id: Uuid,
+    payment_intent_id: &str,
+) -> Result<PaymentIntent, AppError> {
+    PaymentIntent::get_by_stripe_id(&state.pool, payment_intent_id)
+        .await?
+        .filter(|intent| intent.user_id == user_id)
+        .ok_or_else(|| AppError::NotFound("Payment intent not found".into()))
 }
 
 async fn get_payment_status(
     State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
     Path(payment_intent_id): Path<String>,
 ) -> Result<Json<PaymentStatusResponse>, AppError> {
-    let payment_intent = PaymentIntent::get_by_stripe_id(&state.pool, &payment_intent_id)
+    let mut payment_intent = owned_payment_intent(&state, user_id, &payment_intent_id).await?;
+
+    // Report lapsed intents as expired even before the cleanup job runs.
+    let expired = payment_intent.is_expired();
+    if expired {
+        payment_intent.status = "expired".into();
+    }
+
+    Ok(Json(PaymentStatusResponse {
+        payment_intent,
+        expired,
+    }))
+}
+
+/// How long a status stream stays open waiting for a terminal status
+/// (`PAYMENT_STATUS_STREAM_TIMEOUT_SECS`, default 10 minutes).
+fn payment_status_stream_timeout() -> Duration {
+    let secs = std::env::var("PAYMENT_STATUS_STREAM_TIMEOUT_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(600);
+    Duration::from_secs(secs)
+}
+
+/// Server-sent `status` events for a payment intent: the current status
+/// straight away, then each change pushed by the webhook. The stream ends
+/// after a terminal status or once the timeout passes.
+async fn stream_payment_status(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Path(payment_intent_id): Path<String>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, AppError> {
+    // Subscribe before reading so a change in between isn't missed.
+    let updates = state.payment_events.subscribe(&payment_intent_id);
+    let payment_intent = owned_payment_intent(&state, user_id, &payment_intent_id).await?;
+
+    let current = PaymentStatusEvent {
+        status: if payment_intent.is_expired() {
+            "expired".into()
+        } else {
+            payment_intent.status
+        },
+        payment_intent_id,
+    };
+    let deadline = Instant::now() + payment_status_stream_timeout();
+
+    let events = futures::stream::unfold(
+        (Some(current), Some(updates)),
+        move |(pending, mut updates)| async move {
+            let event = match pending {
+                Some(event) => event,
+                None => next_status(updates.as_mut()?, deadline).await?,
+            };
+            let updates = if event.is_terminal() { None } else { updates };
+            let sse = Event::default().event("status").json_data(&event);
+            Some((sse, (None, updates)))
+        },
+    );
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// The next status pushed for the intent, or `None` on timeout.
+async fn next_status(
+    updates: &mut Receiver<PaymentStatusEvent>,
+    deadline: Instant,
+) -> Option<PaymentStatusEvent> {
+    loop {
+        match tokio::time::timeout_at(deadline, updates.recv()).await {
+            Ok(Ok(event)) => return Some(event),
+            // Only the latest status matters; the next one is still coming.
+            Ok(Err(RecvError::Lagged(_))) => continue,
+            Ok(Err(RecvError::Closed)) | Err(_) => return None,
+        }
+    }
+}
+
+/// Start a new intent for a failed payment, keeping the plan and amount.
+async fn retry_payment(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Path(payment_intent_id): Path<String>,
+) -> Result<Json<PaymentIntent>, AppError> {
+    let failed = owned_payment_intent(&state, user_id, &payment_intent_id).await?;
+
+    if failed.status != "failed" {
+        return Err(AppError::conflict("Only failed payments can be retried"));
+    }
+
+    // Retrying an old failure after the user has moved on would double up.
+    let latest = PaymentIntent::latest_for(&state.pool, user_id, failed.subscription_id).await?;
+    if latest.map(|latest| latest.id) != Some(failed.id) {
+        return Err(AppError::conflict(
+            "A newer payment exists for this subscription",
+        ));
+    }
+
+    let (retry_of, retries) = PaymentIntent::retry_chain(&state.pool, failed.id).await?;
+    if retries >= max_payment_retries() {
+        return Err(AppError::BadRequest(
+            "Retry limit reached; please start a new checkout".into(),
+        ));
+    }
+
+    let payment_intent = state
+        .stripe_service
+        .retry_payment_intent(&failed, retry_of, retries + 1)
+        .await?;
+
+    Ok(Json(payment_intent))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RefundRequest {
+    /// In the payment currency's minor unit. Defaults to the full remaining
+    /// amount.
+    amount: Option<i64>,
+}
+
+async fn refund_payment(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(payment_intent_id): Path<String>,
+    request: Option<Json<RefundRequest>>,
+) -> Result<Json<PaymentIntent>, AppError> {
+    let Json(request) = request.unwrap_or_default();
+
+    let intent = PaymentIntent::get_by_stripe_id(&state.pool, &payment_intent_id)
         .await?
         .ok_or_else(|| AppError::NotFound("Payment intent not found".into()))?;
 
-    Ok(Json(PaymentStatusResponse { payment_intent }))
+    if !matches!(intent.status.as_str(), "succeeded" | "partially_refunded") {
+        return Err(AppError::conflict(format!(
+            "A {} payment can't be refunded",
+            intent.status
+        )));
+    }
+
+    let refunded = intent.refunded_total(&state.pool).await?;
+    let remaining = Money::from_minor(
+        intent.total().minor_units() - refunded.minor_units(),
+        &intent.currency,
+    );
+    if let Some(amount) = request.amount {
+        if amount <= 0 || amount > remaining.minor_units() {
+            return Err(AppError::BadRequest(format!(
+                "Refund amount must be between 0 and {}",
+                remaining
+            )));
+        }
+    }
+
+    let intent = state
+        .stripe_service
+        .refund_payment(&payment_intent_id, request.amount)
+        .await?;
+
+    Ok(Json(intent))
 }
 
 #[derive(Debug, Serialize)]
@@ -69,11 +396,9 @@ struct PaymentMethodsResponse {
 
 async fn list_payment_methods(
     State(state): State<AppState>,
-    // TODO: Extract user_id from JWT token
-    user_id: Uuid,
+    AuthUser(user_id): AuthUser,
 ) -> Result<Json<PaymentMethodsResponse>, AppError> {
-    let payment_method = PaymentMethod::get_default_for_user(&state.pool, user_id).await?;
-    let payment_methods = payment_method.map(|m| vec![m]).unwrap_or_default();
+    let payment_methods = PaymentMethod::list_for_user(&state.pool, user_id).await?;
 
     Ok(Json(PaymentMethodsResponse { payment_methods }))
 }
@@ -83,37 +408,83 @@ struct AttachPaymentMethodRequest {
     payment_method_id: String,
 }
 
+#[derive(Debug, Serialize)]
+struct AttachedPaymentMethod {
+    #[serde(flatten)]
+    payment_method: PaymentMethod,
+    /// The card was already on file; the existing method is returned.
+    duplicate: bool,
+}
+
 async fn attach_payment_method(
     State(state): State<AppState>,
-    // TODO: Extract user_id from JWT token
-    user_id: Uuid,
+    AuthUser(user_id): AuthUser,
     Json(request): Json<AttachPaymentMethodRequest>,
-) -> Result<Json<PaymentMethod>, AppError> {
-    // Attach payment method in Stripe and save to database
-    state
+) -> Result<(StatusCode, Json<AttachedPaymentMethod>), AppError> {
+    let (payment_method, created) = state
         .stripe_service
         .attach_payment_method(user_id, &request.payment_method_id)
         .await?;
 
-    let payment_method = PaymentMethod::get_default_for_user(&state.pool, user_id)
+    let status = if created { StatusCode::CREATED } else { StatusCode::OK };
+    Ok((
+        status,
+        Json(AttachedPaymentMethod {
+            payment_method,
+            duplicate: !created,
+        }),
+    ))
+}
+
+async fn set_default_payment_method(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<Json<PaymentMethod>, AppError> {
+    let payment_method = PaymentMethod::set_default(&state.pool, user_id, id)
         .await?
         .ok_or_else(|| AppError::NotFound("Payment method not found".into()))?;
 
     Ok(Json(payment_method))
 }
 
+async fn detach_payment_method(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    state
+        .stripe_service
+        .detach_payment_method(user_id, id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Payment method not found".into()))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
 #[derive(Debug, Serialize)]
 struct PaymentHistoryResponse {
     payments: Vec<PaymentHistory>,
+    page: i64,
+    per_page: i64,
 }
 
+/// The user's payments, newest first, 10 to a page by default.
 async fn get_payment_history(
     State(state): State<AppState>,
-    // TODO: Extract user_id from JWT token
-    user_id: Uuid,
+    AuthUser(user_id): AuthUser,
+    ValidatedQuery(params): ValidatedQuery<PageParams>,
 ) -> Result<Json<PaymentHistoryResponse>, AppError> {
-    let payments = PaymentHistory::get_for_user(&state.pool, user_id, 10).await?;
-    Ok(Json(PaymentHistoryResponse { payments }))
+    let page = params.page.unwrap_or(1);
+    let per_page = params.per_page.unwrap_or(10);
+    let payments =
+        PaymentHistory::get_for_user(&state.pool, user_id, per_page, (page - 1) * per_page)
+            .await?;
+    Ok(Json(PaymentHistoryResponse {
+        payments,
+        page,
+        per_page,
+    }))
 }
 
 async fn handle_webhook(
@@ -127,10 +498,341 @@ async fn handle_webhook(
         .to_str()
         .map_err(|_| AppError::BadRequest("Invalid Stripe signature".into()))?;
 
-    state
+    let event = state
         .stripe_service
-        .handle_webhook(body.as_bytes(), signature)
-        .await?;
+        .verify_webhook(&body, signature)
+        .map_err(|e| {
+            tracing::warn!("rejected webhook: {}", e);
+            AppError::BadRequest(format!("Invalid webhook: {}", e))
+        })?;
+
+    // A validly signed event inside the tolerance can still be captured
+    // and resent; answer replays with 200 so Stripe stops retrying them.
+    let event_id = event.id.to_string();
+    if !WebhookEvent::record(&state.pool, &event_id, &event.type_.to_string()).await? {
+        tracing::warn!("ignoring replayed webhook event {}", event_id);
+        return Ok(());
+    }
+
+    if let Err(e) = state.stripe_service.handle_webhook(event).await {
+        WebhookEvent::forget(&state.pool, &event_id).await?;
+        return Err(e.into());
+    }
 
     Ok(())
-} 
\ No newline at end of file
+} 
+
+#[derive(Debug, Serialize)]
+struct InvoicesResponse {
+    invoices: Vec<Invoice>,
+}
+
+async fn list_invoices(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+) -> Result<Json<InvoicesResponse>, AppError> {
+    let invoices = Invoice::get_for_user(&state.pool, user_id, 50).await?;
+    Ok(Json(InvoicesResponse { invoices }))
+}
+
+async fn get_invoice(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<Json<InvoiceDetail>, AppError> {
+    let invoice = Invoice::detail_for_user(&state.pool, user_id, id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Invoice not found".into()))?;
+    Ok(Json(invoice))
+}
+
+/// Where the user is billed; tax on their payments depends on it.
+async fn get_billing_location(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+) -> Result<Json<BillingLocation>, AppError> {
+    let location = BillingLocation::get_for_user(&state.pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("No billing location set".into()))?;
+    Ok(Json(location))
+}
+
+/// Applies to payments started from now on.
+async fn set_billing_location(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Json(location): Json<BillingLocation>,
+) -> Result<Json<BillingLocation>, AppError> {
+    let location = location.normalized();
+    location.validate()?;
+
+    if !location.set_for_user(&state.pool, user_id).await? {
+        return Err(AppError::NotFound("User not found".into()));
+    }
+    Ok(Json(location))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::SubscriptionTier;
+    use crate::test_support::{
+        app_state, app_state_with_stripe, create_user, plan, stripe_event, stripe_payment_intent,
+        StripeStub,
+    };
+    use sqlx::PgPool;
+
+    async fn add_card(pool: &PgPool, user_id: Uuid, is_default: bool) -> Uuid {
+        sqlx::query_scalar!(
+            r#"
+            INSERT INTO payment_methods (user_id, stripe_payment_method_id, is_default)
+            VALUES ($1, $2, $3)
+            RETURNING id
+            "#,
+            user_id,
+            format!("pm_{}", Uuid::new_v4().simple()),
+            is_default
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn setting_a_default_clears_the_previous_one(pool: PgPool) {
+        let user_id = create_user(&pool).await;
+        add_card(&pool, user_id, true).await;
+        let second = add_card(&pool, user_id, false).await;
+        let state = app_state(&pool).await;
+
+        let Json(method) = set_default_payment_method(State(state), AuthUser(user_id), Path(second))
+            .await
+            .unwrap();
+
+        assert!(method.is_default);
+        let defaults: Vec<Uuid> = PaymentMethod::list_for_user(&pool, user_id)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|method| method.is_default)
+            .map(|method| method.id)
+            .collect();
+        assert_eq!(defaults, vec![second]);
+    }
+
+    #[sqlx::test]
+    async fn another_users_payment_method_is_not_found(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let stranger = create_user(&pool).await;
+        let card = add_card(&pool, owner, true).await;
+        let stripe = StripeStub::start().await;
+        let state = app_state_with_stripe(&pool, Some(&stripe.url)).await;
+
+        let result = detach_payment_method(State(state.clone()), AuthUser(stranger), Path(card)).await;
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+        let result = set_default_payment_method(State(state.clone()), AuthUser(stranger), Path(card)).await;
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+        assert!(stripe.requests().is_empty());
+
+        let status = detach_payment_method(State(state), AuthUser(owner), Path(card)).await.unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(stripe.requests().len(), 1);
+        assert!(PaymentMethod::list_for_user(&pool, owner).await.unwrap().is_empty());
+    }
+
+    #[sqlx::test]
+    async fn a_plan_priced_in_euros_is_charged_in_euros(pool: PgPool) {
+        let user_id = create_user(&pool).await;
+        let pro = crate::test_support::plan(&pool, crate::models::SubscriptionTier::Pro).await;
+        sqlx::query!(
+            "UPDATE subscriptions SET currency = 'EUR', price_monthly = 1999 WHERE id = $1",
+            pro.id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        crate::models::StripeCustomer::record(&pool, user_id, "cus_test").await.unwrap();
+        let stripe = StripeStub::start().await;
+        let state = app_state_with_stripe(&pool, Some(&stripe.url)).await;
+
+        let Json(intent) = create_payment_intent(
+            State(state),
+            AuthUser(user_id),
+            Json(CreatePaymentIntentRequest {
+                subscription_id: pro.id,
+                currency: None,
+                idempotency_key: None,
+                coupon_code: None,
+            }),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!((intent.currency.as_str(), intent.amount), ("EUR", 1999));
+        assert_eq!(intent.total(), Money::from_minor(1999, "EUR"));
+        let sent = stripe.form("POST /v1/payment_intents").unwrap();
+        assert!(sent.contains(&("currency".into(), "eur".into())), "{:?}", sent);
+        assert!(sent.contains(&("amount".into(), "1999".into())), "{:?}", sent);
+    }
+
+    #[sqlx::test]
+    async fn a_lapsed_intent_reports_expired_and_never_activates(pool: PgPool) {
+        let user_id = create_user(&pool).await;
+        let pro = plan(&pool, SubscriptionTier::Pro).await;
+        let pending = UserSubscription::create(&pool, user_id, pro.id, Default::default())
+            .await
+            .unwrap();
+        let intent = PaymentIntent::create(
+            &pool,
+            user_id,
+            pro.id,
+            "pi_lapsed".into(),
+            &Money::from_minor(1999, "USD"),
+            &Money::from_minor(0, "USD"),
+            "pi_lapsed_secret".into(),
+            None,
+        )
+        .await
+        .unwrap();
+        sqlx::query!(
+            "UPDATE payment_intents SET expires_at = NOW() - INTERVAL '1 minute' WHERE id = $1",
+            intent.id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let stub = StripeStub::start().await;
+        let state = app_state_with_stripe(&pool, Some(&stub.url)).await;
+
+        let status = |state: AppState| async move {
+            let Json(status) =
+                get_payment_status(State(state), AuthUser(user_id), Path("pi_lapsed".into()))
+                    .await
+                    .unwrap();
+            status
+        };
+        let before_cleanup = status(state.clone()).await;
+        assert!(before_cleanup.expired);
+        assert_eq!(before_cleanup.payment_intent.status, "expired");
+
+        assert_eq!(state.stripe_service.expire_lapsed_intents().await.unwrap(), 1);
+        assert_eq!(stub.requests(), ["POST /v1/payment_intents/pi_lapsed/cancel"]);
+        assert_eq!(status(state.clone()).await.payment_intent.status, "expired");
+
+        let succeeded = stripe_payment_intent("pi_lapsed", 1999, "usd", "succeeded");
+        state
+            .stripe_service
+            .handle_webhook(stripe_event("payment_intent.succeeded", succeeded))
+            .await
+            .unwrap();
+        let stored = PaymentIntent::get_by_stripe_id(&pool, "pi_lapsed").await.unwrap().unwrap();
+        assert_eq!(stored.status, "expired");
+        let subscription = UserSubscription::list_for_user(&pool, user_id)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|s| s.id == pending.id)
+            .unwrap();
+        assert_eq!(subscription.payment_status, pending.payment_status);
+    }
+
+    #[sqlx::test]
+    async fn a_failed_payment_can_be_retried_up_to_the_cap(pool: PgPool) {
+        let user_id = create_user(&pool).await;
+        crate::models::StripeCustomer::record(&pool, user_id, "cus_test").await.unwrap();
+        let pro = plan(&pool, SubscriptionTier::Pro).await;
+        let mut latest = PaymentIntent::create(
+            &pool,
+            user_id,
+            pro.id,
+            "pi_declined".into(),
+            &Money::from_minor(2400, "USD"),
+            &Money::from_minor(400, "USD"),
+            "pi_declined_secret".into(),
+            None,
+        )
+        .await
+        .unwrap();
+        let stub = StripeStub::start().await;
+        let state = app_state_with_stripe(&pool, Some(&stub.url)).await;
+        let retry = |id: &str| {
+            retry_payment(State(state.clone()), AuthUser(user_id), Path(id.to_string()))
+        };
+
+        let pending = retry(&latest.stripe_payment_intent_id).await;
+        assert!(matches!(pending, Err(AppError::Conflict { .. })));
+
+        for _ in 0..max_payment_retries() {
+            PaymentIntent::update_status(&pool, &latest.stripe_payment_intent_id, "failed")
+                .await
+                .unwrap();
+            let Json(retried) = retry(&latest.stripe_payment_intent_id).await.unwrap();
+            assert_ne!(retried.stripe_payment_intent_id, latest.stripe_payment_intent_id);
+            assert_eq!(retried.status, "pending");
+            assert_eq!((retried.amount, retried.tax_amount), (2400, 400));
+            latest = retried;
+        }
+
+        PaymentIntent::update_status(&pool, &latest.stripe_payment_intent_id, "failed")
+            .await
+            .unwrap();
+        let capped = retry(&latest.stripe_payment_intent_id).await;
+        assert!(matches!(capped, Err(AppError::BadRequest(_))));
+        let created = stub.requests().iter().filter(|r| *r == "POST /v1/payment_intents").count();
+        assert_eq!(created as i64, max_payment_retries());
+    }
+
+    /// The next server-sent frame, or `None` once the stream has ended.
+    async fn next_frame(frames: &mut axum::body::BodyDataStream) -> Option<String> {
+        use futures::StreamExt;
+
+        let frame = tokio::time::timeout(Duration::from_secs(5), frames.next()).await.unwrap()?;
+        Some(String::from_utf8(frame.unwrap().to_vec()).unwrap())
+    }
+
+    #[sqlx::test]
+    async fn a_webhook_success_is_pushed_to_a_status_stream_which_then_ends(pool: PgPool) {
+        use axum::response::IntoResponse;
+
+        let user_id = create_user(&pool).await;
+        let pro = plan(&pool, SubscriptionTier::Pro).await;
+        PaymentIntent::create(
+            &pool,
+            user_id,
+            pro.id,
+            "pi_live".into(),
+            &Money::from_minor(1999, "USD"),
+            &Money::from_minor(0, "USD"),
+            "pi_live_secret".into(),
+            None,
+        )
+        .await
+        .unwrap();
+        let state = app_state(&pool).await;
+
+        let sse = stream_payment_status(
+            State(state.clone()),
+            AuthUser(user_id),
+            Path("pi_live".into()),
+        )
+        .await
+        .unwrap();
+        let mut frames = sse.into_response().into_body().into_data_stream();
+        let status_frame = |status: &str| {
+            format!(
+                "event: status\ndata: {{\"payment_intent_id\":\"pi_live\",\"status\":\"{}\"}}\n\n",
+                status
+            )
+        };
+
+        assert_eq!(next_frame(&mut frames).await.unwrap(), status_frame("pending"));
+        let succeeded = stripe_payment_intent("pi_live", 1999, "usd", "succeeded");
+        state
+            .stripe_service
+            .handle_webhook(stripe_event("payment_intent.succeeded", succeeded))
+            .await
+            .unwrap();
+        assert_eq!(next_frame(&mut frames).await.unwrap(), status_frame("succeeded"));
+        assert_eq!(next_frame(&mut frames).await, None);
+    }
+}
@@ -0,0 +1,134 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use uuid::Uuid;
+
+use crate::{
+    auth::AuthUser,
+    db::{AIModelRepository, ReviewRepository},
+    error::{is_unique_violation, AppError},
+    events::{EventBus, ModelEvent},
+    models::{CreateReview, ModelReview, PageParams, ReviewList},
+    rate_limit::RateLimiter,
+    validated_query::ValidatedQuery,
+};
+
+#[axum::debug_handler(state = crate::AppState)]
+pub async fn create_review(
+    State(repo): State<AIModelRepository>,
+    State(reviews): State<ReviewRepository>,
+    State(events): State<EventBus>,
+    State(limiter): State<RateLimiter>,
+    AuthUser(user_id): AuthUser,
+    Path(model_id): Path<Uuid>,
+    Json(review): Json<CreateReview>,
+) -> Result<(StatusCode, Json<ModelReview>), AppError> {
+    review.validate()?;
+
+    repo.get(model_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Model not found".into()))?;
+
+    limiter
+        .try_acquire(user_id)
+        .map_err(|retry_after| AppError::TooManyRequests {
+            message: "Too many reviews submitted; try again later".into(),
+            retry_after,
+        })?;
+
+    let review = match reviews.create_review(model_id, user_id, review).await {
+        Ok(review) => review,
+        Err(e) if is_unique_violation(&e) => {
+            let message = "You have already reviewed this model";
+            return Err(match reviews.find_review_id(model_id, user_id).await? {
+                Some(existing) => AppError::conflict_with(
+                    message,
+                    existing,
+                    Some(format!("/api/models/{}/reviews", model_id)),
+                ),
+                None => AppError::conflict(message),
+            });
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    events.publish(ModelEvent::ReviewAdded {
+        model_id,
+        review_id: review.id,
+        rating: review.rating,
+    });
+
+    Ok((StatusCode::CREATED, Json(review)))
+}
+
+#[axum::debug_handler(state = crate::AppState)]
+pub async fn list_reviews(
+    State(reviews): State<ReviewRepository>,
+    Path(model_id): Path<Uuid>,
+    ValidatedQuery(params): ValidatedQuery<PageParams>,
+) -> Result<Json<ReviewList>, AppError> {
+    let page = params.page.unwrap_or(1);
+    let per_page = params.per_page.unwrap_or(10);
+
+    let (list, average_rating) = tokio::try_join!(
+        reviews.list_reviews(model_id, page, per_page),
+        reviews.average_rating(model_id),
+    )?;
+
+    Ok(Json(ReviewList {
+        reviews: list,
+        average_rating,
+        page,
+        per_page,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{create_user, new_model, published};
+    use sqlx::PgPool;
+    use std::time::Duration;
+
+    #[sqlx::test]
+    async fn reviews_past_the_hourly_limit_are_throttled(pool: PgPool) {
+        let owner = create_user(&pool).await;
+        let reviewer = create_user(&pool).await;
+        let other = create_user(&pool).await;
+        let mut models = Vec::new();
+        for i in 0..3 {
+            models.push(published(&pool, owner, new_model(&format!("Model {}", i))).await);
+        }
+        let limiter = RateLimiter::new(2, Duration::from_secs(3600));
+        let review = |user_id: Uuid, model_id: Uuid| {
+            create_review(
+                State(AIModelRepository::new(pool.clone())),
+                State(ReviewRepository::new(pool.clone())),
+                State(EventBus::default()),
+                State(limiter.clone()),
+                AuthUser(user_id),
+                Path(model_id),
+                Json(CreateReview {
+                    rating: 5,
+                    comment: None,
+                }),
+            )
+        };
+
+        for model in &models[..2] {
+            let (status, _) = review(reviewer, model.id).await.unwrap();
+            assert_eq!(status, StatusCode::CREATED);
+        }
+        match review(reviewer, models[2].id).await {
+            Err(AppError::TooManyRequests { retry_after, .. }) => {
+                assert!(retry_after > Duration::ZERO && retry_after <= Duration::from_secs(3600))
+            }
+            result => panic!("expected the third review to be throttled, got {:?}", result.err()),
+        }
+
+        // Someone else's reviews count against their own limit.
+        assert!(review(other, models[2].id).await.is_ok());
+    }
+}
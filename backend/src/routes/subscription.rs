@@ -1,14 +1,29 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+
 use axum::{
-    extract::{Path, State},
-    routing::{get, post},
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
     Json, Router,
 };
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
 use uuid::Uuid;
 
 use crate::{
+    auth::AuthUser,
+    email::EmailTemplate,
     error::AppError,
-    models::subscription::{Subscription, UserSubscription},
+    models::{
+        minimum_charge, validate_charge, validate_paging, AddAddonRequest, Addon, BillingLocation, Money, Notification, PlanListing, PaymentIntent, PlanChange, Subscription, SubscriptionCredit,
+        BillingInterval, SubscriptionTier, UserSubscription, UserSubscriptionAddon, Validate, ValidationErrors,
+        account_is_open, AssignSeatRequest, SeatAssignment, SeatAssignmentOutcome, SeatUsage,
+    },
+    tax,
+    validated_query::ValidatedQuery,
     AppState,
 };
 
@@ -17,20 +32,113 @@ pub fn subscription_routes() -> Router<AppState> {
         .route("/subscriptions", get(list_subscriptions))
         .route("/subscriptions/:id", get(get_subscription))
         .route("/subscriptions/user", get(get_user_subscription))
+        .route("/subscriptions/user/history", get(get_subscription_history))
         .route("/subscriptions/subscribe", post(create_subscription))
+        .route("/subscriptions/change", post(change_subscription))
+        .route("/subscriptions/change/preview", post(preview_subscription_change))
         .route("/subscriptions/cancel", post(cancel_subscription))
+        .route("/subscriptions/pause", post(pause_subscription))
+        .route("/subscriptions/resume", post(resume_subscription))
+        .route("/subscriptions/addons", get(list_addons).post(add_addon))
+        .route("/subscriptions/addons/:id", delete(remove_addon))
+        .route("/subscriptions/seats", get(get_seats).post(assign_seat))
+        .route("/subscriptions/seats/:user_id", delete(unassign_seat))
 }
 
 #[derive(Debug, Serialize)]
 struct SubscriptionResponse {
-    subscriptions: Vec<Subscription>,
+    subscriptions: Vec<PlanListing>,
+    total: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListSubscriptionsQuery {
+    /// `yearly` adds each plan's `yearly_savings_percent`.
+    billing: Option<BillingInterval>,
+    page: Option<i64>,
+    /// Every plan when omitted.
+    per_page: Option<i64>,
+}
+
+impl Validate for ListSubscriptionsQuery {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        validate_paging(self.page, self.per_page)
+    }
+}
+
+/// The last plan list served, keyed by its ETag. A plan edit changes the
+/// catalog version, so a stale entry is simply replaced on the next request.
+#[derive(Clone, Default)]
+pub struct PlanCache(Arc<RwLock<Option<CachedPlans>>>);
+
+/// A plan list and the ETag it was served under.
+type CachedPlans = (String, Vec<Subscription>);
+
+impl PlanCache {
+    pub(crate) async fn get_or_load(
+        &self,
+        pool: &sqlx::PgPool,
+    ) -> Result<CachedPlans, sqlx::Error> {
+        let etag = format!("\"{}\"", Subscription::catalog_version(pool).await?);
+
+        if let Some((cached_etag, plans)) = self.0.read().await.as_ref() {
+            if *cached_etag == etag {
+                return Ok((etag, plans.clone()));
+            }
+        }
+
+        let plans = Subscription::get_all(pool).await?;
+        *self.0.write().await = Some((etag.clone(), plans.clone()));
+        Ok((etag, plans))
+    }
+}
+
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| {
+            value
+                .split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == etag || tag == "*")
+        })
+        .unwrap_or(false)
 }
 
+/// Plans, cheapest first. There are few enough to page through the cached
+/// list rather than in SQL.
 async fn list_subscriptions(
     State(state): State<AppState>,
-) -> Result<Json<SubscriptionResponse>, AppError> {
-    let subscriptions = Subscription::get_all(&state.pool).await?;
-    Ok(Json(SubscriptionResponse { subscriptions }))
+    headers: HeaderMap,
+    ValidatedQuery(query): ValidatedQuery<ListSubscriptionsQuery>,
+) -> Result<Response, AppError> {
+    let (etag, plans) = state.plan_cache.get_or_load(&state.pool).await?;
+    let etag_header = HeaderValue::from_str(&etag).map_err(anyhow::Error::from)?;
+
+    if etag_matches(&headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag_header)]).into_response());
+    }
+
+    let total = plans.len();
+    let per_page = query.per_page.map_or(total, |per_page| per_page as usize);
+    let skip = (query.page.unwrap_or(1) as usize - 1).saturating_mul(per_page);
+    let with_savings = query.billing == Some(BillingInterval::Yearly);
+    let subscriptions = plans
+        .iter()
+        .skip(skip)
+        .take(per_page)
+        .map(|plan| plan.listing(with_savings))
+        .collect();
+
+    Ok((
+        [(header::ETAG, etag_header)],
+        Json(SubscriptionResponse {
+            subscriptions,
+            total,
+        }),
+    )
+        .into_response())
 }
 
 async fn get_subscription(
@@ -50,44 +158,922 @@ struct UserSubscriptionResponse {
 
 async fn get_user_subscription(
     State(state): State<AppState>,
-    // TODO: Extract user_id from JWT token
-    user_id: Uuid,
+    AuthUser(user_id): AuthUser,
 ) -> Result<Json<UserSubscriptionResponse>, AppError> {
     let subscription = UserSubscription::get_active_for_user(&state.pool, user_id).await?;
     Ok(Json(UserSubscriptionResponse { subscription }))
 }
 
+#[derive(Debug, Serialize)]
+struct SubscriptionHistoryResponse {
+    subscriptions: Vec<UserSubscription>,
+}
+
+async fn get_subscription_history(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+) -> Result<Json<SubscriptionHistoryResponse>, AppError> {
+    let subscriptions = UserSubscription::list_for_user(&state.pool, user_id).await?;
+    Ok(Json(SubscriptionHistoryResponse { subscriptions }))
+}
+
 #[derive(Debug, Deserialize)]
 struct CreateSubscriptionRequest {
     subscription_id: Uuid,
+    #[serde(default)]
+    billing_interval: BillingInterval,
 }
 
 async fn create_subscription(
     State(state): State<AppState>,
-    // TODO: Extract user_id from JWT token
-    user_id: Uuid,
+    AuthUser(user_id): AuthUser,
     Json(request): Json<CreateSubscriptionRequest>,
 ) -> Result<Json<UserSubscription>, AppError> {
     // Verify subscription exists
-    Subscription::get_by_id(&state.pool, request.subscription_id)
+    let plan = Subscription::get_by_id(&state.pool, request.subscription_id)
         .await?
         .ok_or_else(|| AppError::NotFound("Subscription not found".into()))?;
 
+    if let Some(existing) = UserSubscription::get_active_for_user(&state.pool, user_id).await? {
+        // The Free subscription every user gets is simply replaced.
+        let on_free = Subscription::get_by_id(&state.pool, existing.subscription_id)
+            .await?
+            .is_some_and(|plan| plan.tier == SubscriptionTier::Free);
+        if !on_free {
+            return Err(AppError::conflict_with(
+                "You already have an active subscription",
+                existing.id,
+                Some("/api/subscriptions/user".into()),
+            ));
+        }
+        UserSubscription::cancel(&state.pool, user_id).await?;
+    }
+
     // Create user subscription
     let subscription = UserSubscription::create(
         &state.pool,
         user_id,
         request.subscription_id,
+        request.billing_interval,
     ).await?;
+    state.tier_cache.invalidate(user_id);
+
+    if plan.tier > SubscriptionTier::Free {
+        Notification::send_best_effort(
+            &state.pool,
+            user_id,
+            "upgraded",
+            &format!("You've upgraded to {}", plan.name),
+            plan.notification_details(),
+        )
+        .await;
+    }
+    state.mailer.send_best_effort(
+        user_id,
+        EmailTemplate::SubscriptionStarted { plan: plan.name },
+    );
 
     Ok(Json(subscription))
 }
 
+#[derive(Debug, Deserialize)]
+struct ChangeSubscriptionRequest {
+    subscription_id: Uuid,
+}
+
+#[derive(Debug, Serialize)]
+struct ChangeSubscriptionResponse {
+    #[serde(flatten)]
+    change: PlanChange,
+    /// Charges the prorated difference on an upgrade.
+    payment_intent: Option<PaymentIntent>,
+}
+
+/// What the user would be charged for switching to another plan now,
+/// without switching.
+#[derive(Debug, Serialize)]
+struct ChangePreviewResponse {
+    /// Prorated price difference for the rest of the period; negative on a
+    /// downgrade, which is credited instead of charged.
+    proration: f64,
+    /// Unapplied credits that would go towards the charge.
+    credits_applied: f64,
+    tax: f64,
+    /// Charged now: the proration less credits, plus tax.
+    amount_due: f64,
+    currency: String,
+    next_billing_date: DateTime<Utc>,
+}
+
+/// A plan change that has passed every check and can go ahead.
+struct PlanSwitch {
+    active: UserSubscription,
+    current: Subscription,
+    target: Subscription,
+}
+
+/// The checks a plan change must pass, shared by the change and its
+/// preview.
+async fn plan_switch(
+    state: &AppState,
+    user_id: Uuid,
+    subscription_id: Uuid,
+) -> Result<PlanSwitch, AppError> {
+    let active = active_subscription(&state.pool, user_id).await?;
+    if active.is_paused() {
+        return Err(AppError::conflict(
+            "Your subscription is paused; resume it before changing plans",
+        ));
+    }
+    if active.subscription_id == subscription_id {
+        return Err(AppError::BadRequest("You're already on this plan".into()));
+    }
+
+    let target = Subscription::get_by_id(&state.pool, subscription_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Subscription not found".into()))?;
+    let current = Subscription::get_by_id(&state.pool, active.subscription_id)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("active subscription {} has no plan", active.id))?;
+
+    // Prorating across currencies would need a conversion we don't do.
+    if !target.currency.eq_ignore_ascii_case(&current.currency) {
+        return Err(AppError::BadRequest(format!(
+            "Plans priced in {} can't be switched to a plan priced in {}",
+            current.currency, target.currency
+        )));
+    }
+
+    // A downgrade mustn't leave the user gating their own models behind a
+    // tier they no longer have.
+    if target.tier < current.tier {
+        let stranded: i64 = state
+            .repo
+            .owned_counts_by_tier(user_id)
+            .await?
+            .into_iter()
+            .filter(|(tier, _)| *tier > target.tier)
+            .map(|(_, models)| models)
+            .sum();
+        if stranded > 0 {
+            return Err(AppError::conflict(format!(
+                "{} of your models require a higher tier than {}; lower their required tier first",
+                stranded, target.name
+            )));
+        }
+    }
+
+    Ok(PlanSwitch {
+        active,
+        current,
+        target,
+    })
+}
+
+/// The charge for a positive `proration`: what's left after unapplied
+/// credits, plus tax. Returns the credits used, the tax and the total.
+/// Totals too small for Stripe to charge are waived and come back zero.
+async fn proration_charge(
+    state: &AppState,
+    user_id: Uuid,
+    proration: &Money,
+) -> Result<(Money, Money, Money), AppError> {
+    let currency = proration.currency();
+    let credits = SubscriptionCredit::unapplied_total(&state.pool, user_id, currency).await?;
+    let credits_applied = Money::from_minor(
+        proration.minor_units().min(credits.minor_units()),
+        currency,
+    );
+    let amount = Money::from_minor(
+        proration.minor_units() - credits_applied.minor_units(),
+        currency,
+    );
+    let location = BillingLocation::get_for_user(&state.pool, user_id).await?;
+    let tax = tax::tax_on(state.tax.as_ref(), &amount, location.as_ref()).await?;
+    let total = Money::from_minor(amount.minor_units() + tax.minor_units(), currency);
+    if total.minor_units() < minimum_charge(currency).minor_units() {
+        return Ok((credits_applied, Money::zero(currency), Money::zero(currency)));
+    }
+    Ok((credits_applied, tax, total))
+}
+
+async fn preview_subscription_change(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Json(request): Json<ChangeSubscriptionRequest>,
+) -> Result<Json<ChangePreviewResponse>, AppError> {
+    let PlanSwitch {
+        active,
+        current,
+        target,
+    } = plan_switch(&state, user_id, request.subscription_id).await?;
+
+    let now = Utc::now();
+    let proration = active.proration(&current, &target, now);
+    let (credits_applied, tax, amount_due) = if proration.minor_units() > 0 {
+        proration_charge(&state, user_id, &proration).await?
+    } else {
+        let zero = Money::zero(proration.currency());
+        (zero.clone(), zero.clone(), zero)
+    };
+    let (_, next_billing_date) = active.current_period(now);
+
+    Ok(Json(ChangePreviewResponse {
+        proration: proration.to_major(),
+        credits_applied: credits_applied.to_major(),
+        tax: tax.to_major(),
+        amount_due: amount_due.to_major(),
+        currency: amount_due.currency().to_string(),
+        next_billing_date,
+    }))
+}
+
+async fn change_subscription(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Json(request): Json<ChangeSubscriptionRequest>,
+) -> Result<Json<ChangeSubscriptionResponse>, AppError> {
+    let PlanSwitch {
+        active,
+        current,
+        target,
+    } = plan_switch(&state, user_id, request.subscription_id).await?;
+
+    let now = Utc::now();
+    let proration = active.proration(&current, &target, now);
+    let stripe_id = UserSubscription::stripe_id(&state.pool, active.id).await?;
+
+    // Charge before switching, so a failed charge leaves the old plan intact.
+    // Stripe invoices the proration of subscriptions it bills itself.
+    let charge = if stripe_id.is_none() && proration.minor_units() > 0 {
+        let (_, tax, total) = proration_charge(&state, user_id, &proration).await?;
+        Some((tax, total)).filter(|(_, total)| total.minor_units() > 0)
+    } else {
+        None
+    };
+    let payment_intent = if let Some((tax, total)) = charge {
+        validate_charge(&total)?;
+        let (intent, created) = state
+            .stripe_service
+            .create_payment_intent(user_id, &target, &total, &tax, None)
+            .await?;
+        if created {
+            SubscriptionCredit::apply_to(&state.pool, user_id, &target.currency, intent.id)
+                .await?;
+        }
+        Some(intent)
+    } else {
+        None
+    };
+
+    if let Some(stripe_id) = &stripe_id {
+        state
+            .stripe_service
+            .switch_subscription_plan(stripe_id, &target, active.billing_interval)
+            .await?;
+    }
+
+    let change = UserSubscription::change_plan(
+        &state.pool,
+        &active,
+        &current,
+        &target,
+        now,
+        stripe_id.is_none(),
+    )
+    .await?
+        .ok_or_else(|| AppError::conflict("Subscription changed while switching plans; try again"))?;
+    state.tier_cache.invalidate(user_id);
+
+    let (kind, message) = match target.tier.cmp(&current.tier) {
+        Ordering::Greater => ("upgraded", format!("You've upgraded to {}", target.name)),
+        Ordering::Less => ("downgraded", format!("You've moved down to {}", target.name)),
+        Ordering::Equal => ("plan_changed", format!("You've switched to {}", target.name)),
+    };
+    let mut details = target.notification_details();
+    details["previous_plan"] = current.name.clone().into();
+    details["proration"] = change.proration.to_major().into();
+    Notification::send_best_effort(&state.pool, user_id, kind, &message, details).await;
+
+    Ok(Json(ChangeSubscriptionResponse {
+        change,
+        payment_intent,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct CancelSubscriptionQuery {
+    #[serde(default = "default_immediate")]
+    immediate: bool,
+}
+
+fn default_immediate() -> bool {
+    true
+}
+
 async fn cancel_subscription(
     State(state): State<AppState>,
-    // TODO: Extract user_id from JWT token
+    AuthUser(user_id): AuthUser,
+    Query(query): Query<CancelSubscriptionQuery>,
+) -> Result<Json<UserSubscriptionResponse>, AppError> {
+    if query.immediate {
+        UserSubscription::cancel(&state.pool, user_id).await?;
+        state.tier_cache.invalidate(user_id);
+        state.mailer.send_best_effort(
+            user_id,
+            EmailTemplate::SubscriptionCanceled { ends_at: None },
+        );
+        return Ok(Json(UserSubscriptionResponse { subscription: None }));
+    }
+
+    let subscription = UserSubscription::cancel_at_period_end(&state.pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("No active subscription".into()))?;
+    state.mailer.send_best_effort(
+        user_id,
+        EmailTemplate::SubscriptionCanceled {
+            ends_at: subscription.current_period_end,
+        },
+    );
+
+    Ok(Json(UserSubscriptionResponse {
+        subscription: Some(subscription),
+    }))
+}
+
+/// Longest a subscription may be paused for (`MAX_PAUSE_DAYS`, default 90).
+fn max_pause_days() -> i64 {
+    std::env::var("MAX_PAUSE_DAYS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(90)
+}
+
+#[derive(Debug, Deserialize)]
+struct PauseSubscriptionRequest {
+    resume_at: DateTime<Utc>,
+}
+
+async fn pause_subscription(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Json(request): Json<PauseSubscriptionRequest>,
+) -> Result<Json<UserSubscriptionResponse>, AppError> {
+    let now = Utc::now();
+    if request.resume_at <= now {
+        return Err(AppError::BadRequest("resume_at must be in the future".into()));
+    }
+    if request.resume_at > now + chrono::Duration::days(max_pause_days()) {
+        return Err(AppError::BadRequest(format!(
+            "Subscriptions can be paused for at most {} days",
+            max_pause_days()
+        )));
+    }
+
+    let active = active_subscription(&state.pool, user_id).await?;
+    if active.is_paused() {
+        return Err(AppError::conflict("Subscription is already paused"));
+    }
+
+    let subscription = UserSubscription::pause(&state.pool, user_id, request.resume_at)
+        .await?
+        .ok_or_else(|| AppError::conflict("Subscription changed while pausing; try again"))?;
+    state.tier_cache.invalidate(user_id);
+
+    Ok(Json(UserSubscriptionResponse {
+        subscription: Some(subscription),
+    }))
+}
+
+async fn resume_subscription(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+) -> Result<Json<UserSubscriptionResponse>, AppError> {
+    let subscription = UserSubscription::unpause(&state.pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("No paused subscription".into()))?;
+    state.tier_cache.invalidate(user_id);
+
+    Ok(Json(UserSubscriptionResponse {
+        subscription: Some(subscription),
+    }))
+}
+
+#[derive(Debug, Serialize)]
+struct AddonsResponse {
+    available: Vec<Addon>,
+    active: Vec<UserSubscriptionAddon>,
+}
+
+async fn active_subscription(
+    pool: &sqlx::PgPool,
     user_id: Uuid,
-) -> Result<(), AppError> {
-    UserSubscription::cancel(&state.pool, user_id).await?;
-    Ok(())
-} 
\ No newline at end of file
+) -> Result<UserSubscription, AppError> {
+    UserSubscription::get_active_for_user(pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("No active subscription".into()))
+}
+
+async fn list_addons(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+) -> Result<Json<AddonsResponse>, AppError> {
+    let available = Addon::get_all(&state.pool).await?;
+    let active = match UserSubscription::get_active_for_user(&state.pool, user_id).await? {
+        Some(subscription) => UserSubscriptionAddon::list_active(&state.pool, subscription.id).await?,
+        None => Vec::new(),
+    };
+
+    Ok(Json(AddonsResponse { available, active }))
+}
+
+async fn add_addon(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Json(request): Json<AddAddonRequest>,
+) -> Result<Json<UserSubscriptionAddon>, AppError> {
+    if request.quantity < 1 {
+        return Err(AppError::BadRequest("quantity must be at least 1".into()));
+    }
+
+    let subscription = active_subscription(&state.pool, user_id).await?;
+    Addon::get_by_id(&state.pool, request.addon_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Add-on not found".into()))?;
+
+    let addon = UserSubscriptionAddon::add(
+        &state.pool,
+        subscription.id,
+        request.addon_id,
+        request.quantity,
+    )
+    .await?
+    .ok_or_else(|| AppError::conflict("This add-on is already on your subscription"))?;
+
+    Ok(Json(addon))
+}
+
+async fn remove_addon(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<Json<SubscriptionCredit>, AppError> {
+    let subscription = active_subscription(&state.pool, user_id).await?;
+
+    let credit = UserSubscriptionAddon::remove(&state.pool, &subscription, id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Add-on not found".into()))?;
+
+    Ok(Json(credit))
+}
+
+async fn get_seats(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+) -> Result<Json<SeatUsage>, AppError> {
+    let subscription = active_subscription(&state.pool, user_id).await?;
+    Ok(Json(SeatAssignment::usage(&state.pool, subscription.id).await?))
+}
+
+/// Give a team member one of the subscription's free seats.
+async fn assign_seat(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Json(request): Json<AssignSeatRequest>,
+) -> Result<(StatusCode, Json<SeatAssignment>), AppError> {
+    let subscription = active_subscription(&state.pool, user_id).await?;
+    if !account_is_open(&state.pool, request.user_id).await? {
+        return Err(AppError::NotFound("User not found".into()));
+    }
+
+    match SeatAssignment::assign(&state.pool, subscription.id, request.user_id).await? {
+        SeatAssignmentOutcome::Assigned(assignment) => Ok((StatusCode::CREATED, Json(assignment))),
+        SeatAssignmentOutcome::AlreadyAssigned => {
+            Err(AppError::conflict("This member already has a seat"))
+        }
+        SeatAssignmentOutcome::NoFreeSeat => Err(AppError::conflict(
+            "Every seat is taken; unassign a member or add seats first",
+        )),
+        SeatAssignmentOutcome::NotFound => {
+            Err(AppError::NotFound("No active subscription".into()))
+        }
+    }
+}
+
+async fn unassign_seat(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Path(member_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let subscription = active_subscription(&state.pool, user_id).await?;
+    if !SeatAssignment::unassign(&state.pool, subscription.id, member_id).await? {
+        return Err(AppError::NotFound("This member has no seat".into()));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{app_state, app_state_with_stripe, create_user, plan, subscribe, StripeStub};
+    use crate::tier_cache::TierCache;
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    async fn a_stripe_billed_upgrade_switches_the_stripe_price(pool: PgPool) {
+        let user_id = create_user(&pool).await;
+        let pro = plan(&pool, SubscriptionTier::Pro).await;
+        let enterprise = plan(&pool, SubscriptionTier::Enterprise).await;
+        sqlx::query!(
+            r#"
+            INSERT INTO user_subscriptions
+                (user_id, subscription_id, starts_at, is_active, payment_status, stripe_subscription_id)
+            VALUES ($1, $2, NOW(), true, 'paid', 'sub_upgrade')
+            "#,
+            user_id,
+            pro.id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let stripe = StripeStub::start().await;
+        let state = app_state_with_stripe(&pool, Some(&stripe.url)).await;
+
+        let Json(response) = change_subscription(
+            State(state),
+            AuthUser(user_id),
+            Json(ChangeSubscriptionRequest {
+                subscription_id: enterprise.id,
+            }),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.change.subscription.subscription_id, enterprise.id);
+        assert!(response.change.subscription.is_active);
+        assert!(response.change.proration.minor_units() > 0);
+        // Stripe invoices the proration, so nothing is charged here.
+        assert!(response.payment_intent.is_none());
+        assert_eq!(
+            stripe.requests(),
+            vec![
+                "GET /v1/subscriptions/sub_upgrade".to_string(),
+                "POST /v1/subscriptions/sub_upgrade".to_string(),
+            ]
+        );
+        let tier = UserSubscription::tier_for_user(&pool, user_id).await.unwrap();
+        assert_eq!(tier, SubscriptionTier::Enterprise);
+    }
+
+    #[sqlx::test]
+    async fn subscribing_twice_points_at_the_existing_subscription(pool: PgPool) {
+        let user_id = create_user(&pool).await;
+        let existing = subscribe(&pool, user_id, SubscriptionTier::Pro).await;
+        let enterprise = plan(&pool, SubscriptionTier::Enterprise).await;
+        let state = app_state(&pool).await;
+
+        let error = create_subscription(
+            State(state),
+            AuthUser(user_id),
+            Json(CreateSubscriptionRequest {
+                subscription_id: enterprise.id,
+                billing_interval: BillingInterval::Monthly,
+            }),
+        )
+        .await
+        .unwrap_err();
+
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["conflicting_resource"]["id"], existing.id.to_string());
+        assert_eq!(body["conflicting_resource"]["url"], "/api/subscriptions/user");
+    }
+
+    async fn plan_list(state: &AppState, if_none_match: Option<&HeaderValue>) -> Response {
+        let mut headers = HeaderMap::new();
+        if let Some(etag) = if_none_match {
+            headers.insert(header::IF_NONE_MATCH, etag.clone());
+        }
+        list_subscriptions(
+            State(state.clone()),
+            headers,
+            ValidatedQuery(ListSubscriptionsQuery {
+                billing: None,
+                page: None,
+                per_page: None,
+            }),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn the_plan_list_etag_holds_until_a_plan_changes(pool: PgPool) {
+        let state = app_state(&pool).await;
+        let first = plan_list(&state, None).await;
+        assert_eq!(first.status(), StatusCode::OK);
+        let etag = first.headers()[header::ETAG].clone();
+
+        let unchanged = plan_list(&state, Some(&etag)).await;
+        assert_eq!(unchanged.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(unchanged.headers()[header::ETAG], etag);
+
+        let pro = plan(&pool, SubscriptionTier::Pro).await;
+        sqlx::query!(
+            "UPDATE subscriptions SET price_monthly = price_monthly + 100, updated_at = NOW() WHERE id = $1",
+            pro.id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let edited = plan_list(&state, Some(&etag)).await;
+        assert_eq!(edited.status(), StatusCode::OK);
+        assert_ne!(edited.headers()[header::ETAG], etag);
+        let body = axum::body::to_bytes(edited.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let listed = body["subscriptions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|listing| listing["id"] == pro.id.to_string())
+            .unwrap();
+        assert_eq!(listed["price_monthly"], (pro.price_monthly + 100) as f64 / 100.0);
+    }
+
+    async fn cancel(state: &AppState, user_id: Uuid, immediate: bool) -> Option<UserSubscription> {
+        let Json(response) = cancel_subscription(
+            State(state.clone()),
+            AuthUser(user_id),
+            Query(CancelSubscriptionQuery { immediate }),
+        )
+        .await
+        .unwrap();
+        response.subscription
+    }
+
+    async fn latest_subscription(pool: &PgPool, user_id: Uuid) -> UserSubscription {
+        let history = UserSubscription::list_for_user(pool, user_id).await.unwrap();
+        history.into_iter().next().unwrap()
+    }
+
+    #[sqlx::test]
+    async fn an_immediate_cancel_ends_the_subscription_now(pool: PgPool) {
+        let user_id = create_user(&pool).await;
+        subscribe(&pool, user_id, SubscriptionTier::Pro).await;
+        let state = app_state(&pool).await;
+
+        assert!(cancel(&state, user_id, true).await.is_none());
+
+        let ended = latest_subscription(&pool, user_id).await;
+        assert!(!ended.is_active);
+        assert!(!ended.cancel_at_period_end);
+        assert!(ended.ends_at.unwrap() <= Utc::now());
+    }
+
+    #[sqlx::test]
+    async fn cancelling_at_period_end_keeps_access_until_it_lapses(pool: PgPool) {
+        let user_id = create_user(&pool).await;
+        let subscription = subscribe(&pool, user_id, SubscriptionTier::Pro).await;
+        let state = app_state(&pool).await;
+
+        let scheduled = cancel(&state, user_id, false).await.unwrap();
+
+        assert!(scheduled.is_active && scheduled.cancel_at_period_end);
+        let period_end = scheduled.current_period_end.unwrap();
+        assert!(period_end > Utc::now());
+        assert_eq!(latest_subscription(&pool, user_id).await.ends_at, subscription.ends_at);
+        assert_eq!(UserSubscription::expire_lapsed(&pool).await.unwrap(), 0);
+
+        sqlx::query!(
+            "UPDATE user_subscriptions SET current_period_end = NOW() - INTERVAL '1 minute' WHERE id = $1",
+            subscription.id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        assert_eq!(UserSubscription::expire_lapsed(&pool).await.unwrap(), 1);
+        assert!(!latest_subscription(&pool, user_id).await.is_active);
+    }
+
+    #[sqlx::test]
+    async fn a_paused_subscription_loses_access_until_it_resumes(pool: PgPool) {
+        let user_id = create_user(&pool).await;
+        let subscription = subscribe(&pool, user_id, SubscriptionTier::Pro).await;
+        sqlx::query!(
+            r#"
+            UPDATE user_subscriptions
+            SET cancel_at_period_end = true, current_period_end = NOW() + INTERVAL '2 days'
+            WHERE id = $1
+            "#,
+            subscription.id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let state = app_state(&pool).await;
+        let tier = || UserSubscription::tier_for_user(&pool, user_id);
+
+        let Json(paused) = pause_subscription(
+            State(state.clone()),
+            AuthUser(user_id),
+            Json(PauseSubscriptionRequest {
+                resume_at: Utc::now() + chrono::Duration::days(1),
+            }),
+        )
+        .await
+        .unwrap();
+        assert!(paused.subscription.unwrap().is_paused());
+        assert_eq!(tier().await.unwrap(), SubscriptionTier::Free);
+
+        // Three days on: the pause is over and the period it was due to
+        // cancel at has passed while it was paused.
+        sqlx::query!(
+            r#"
+            UPDATE user_subscriptions
+            SET paused_at = paused_at - INTERVAL '3 days',
+                resume_at = NOW() - INTERVAL '1 minute',
+                current_period_end = current_period_end - INTERVAL '3 days'
+            WHERE id = $1
+            "#,
+            subscription.id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        assert_eq!(UserSubscription::expire_lapsed(&pool).await.unwrap(), 0);
+
+        assert_eq!(UserSubscription::resume_due(&pool).await.unwrap(), 1);
+        assert_eq!(UserSubscription::expire_lapsed(&pool).await.unwrap(), 0);
+        let resumed = latest_subscription(&pool, user_id).await;
+        assert!(resumed.is_active && !resumed.is_paused());
+        assert!(resumed.current_period_end.unwrap() > Utc::now());
+        assert_eq!(tier().await.unwrap(), SubscriptionTier::Pro);
+    }
+
+    #[sqlx::test]
+    async fn an_upgrade_notifies_the_user_with_the_new_plan(pool: PgPool) {
+        let user_id = create_user(&pool).await;
+        let pro = plan(&pool, SubscriptionTier::Pro).await;
+        let state = app_state(&pool).await;
+
+        let Json(_) = create_subscription(
+            State(state),
+            AuthUser(user_id),
+            Json(CreateSubscriptionRequest {
+                subscription_id: pro.id,
+                billing_interval: BillingInterval::Monthly,
+            }),
+        )
+        .await
+        .unwrap();
+
+        let notification = sqlx::query_as!(
+            Notification,
+            r#"
+            SELECT id, user_id, kind, message, data, read_at, created_at
+            FROM notifications
+            WHERE user_id = $1
+            "#,
+            user_id
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(notification.kind, "upgraded");
+        assert_eq!(notification.message, format!("You've upgraded to {}", pro.name));
+        assert_eq!(notification.data["plan"], pro.name);
+        assert_eq!(notification.data["subscription_id"], pro.id.to_string());
+    }
+
+    #[sqlx::test]
+    async fn history_lists_a_cancelled_subscription_under_its_replacement(pool: PgPool) {
+        let user_id = create_user(&pool).await;
+        let pro = plan(&pool, SubscriptionTier::Pro).await;
+        let state = app_state(&pool).await;
+        let subscribe_to_pro = || {
+            create_subscription(
+                State(state.clone()),
+                AuthUser(user_id),
+                Json(CreateSubscriptionRequest {
+                    subscription_id: pro.id,
+                    billing_interval: BillingInterval::Monthly,
+                }),
+            )
+        };
+
+        let Json(first) = subscribe_to_pro().await.unwrap();
+        cancel(&state, user_id, true).await;
+        let Json(second) = subscribe_to_pro().await.unwrap();
+
+        let Json(history) = get_subscription_history(State(state), AuthUser(user_id))
+            .await
+            .unwrap();
+        let rows: Vec<_> = history
+            .subscriptions
+            .iter()
+            .map(|s| (s.id, s.is_active, s.ends_at.is_some()))
+            .collect();
+        assert_eq!(rows, [(second.id, true, second.ends_at.is_some()), (first.id, false, true)]);
+        assert!(history.subscriptions.iter().all(|s| s.payment_status.is_some()));
+    }
+
+    #[sqlx::test]
+    async fn cancelling_drops_the_cached_tier_so_the_next_check_downgrades(pool: PgPool) {
+        let behind_the_cache = create_user(&pool).await;
+        let through_the_api = create_user(&pool).await;
+        let mut state = app_state(&pool).await;
+        state.tier_cache = TierCache::with_ttl(std::time::Duration::from_secs(3600));
+        let tier = |user_id| state.tier_cache.tier_for_user(&pool, user_id);
+        for user_id in [behind_the_cache, through_the_api] {
+            subscribe(&pool, user_id, SubscriptionTier::Pro).await;
+            assert_eq!(tier(user_id).await.unwrap(), SubscriptionTier::Pro);
+        }
+
+        // A cancellation that skips invalidation is hidden until expiry...
+        UserSubscription::cancel(&pool, behind_the_cache).await.unwrap();
+        assert_eq!(tier(behind_the_cache).await.unwrap(), SubscriptionTier::Pro);
+        state.tier_cache.invalidate(behind_the_cache);
+        assert_eq!(tier(behind_the_cache).await.unwrap(), SubscriptionTier::Free);
+
+        // ...while cancelling through the API invalidates straight away.
+        cancel(&state, through_the_api, true).await;
+        assert_eq!(tier(through_the_api).await.unwrap(), SubscriptionTier::Free);
+    }
+
+    #[sqlx::test]
+    async fn a_change_preview_surfaces_the_proration_and_changes_nothing(pool: PgPool) {
+        let user_id = create_user(&pool).await;
+        let subscribed = subscribe(&pool, user_id, SubscriptionTier::Pro).await;
+        let pro = plan(&pool, SubscriptionTier::Pro).await;
+        let enterprise = plan(&pool, SubscriptionTier::Enterprise).await;
+        let stripe = StripeStub::start().await;
+        let state = app_state_with_stripe(&pool, Some(&stripe.url)).await;
+
+        let Json(preview) = preview_subscription_change(
+            State(state),
+            AuthUser(user_id),
+            Json(ChangeSubscriptionRequest {
+                subscription_id: enterprise.id,
+            }),
+        )
+        .await
+        .unwrap();
+
+        // Moments into the period, almost all of the price difference is due.
+        let difference = Money::from_minor(
+            enterprise.price_monthly - pro.price_monthly,
+            &enterprise.currency,
+        );
+        assert_eq!(preview.proration, difference.to_major());
+        assert_eq!(preview.amount_due, preview.proration + preview.tax);
+        assert_eq!(preview.currency, enterprise.currency);
+        assert_eq!(preview.next_billing_date, subscribed.starts_at + chrono::Months::new(1));
+
+        assert!(stripe.requests().is_empty());
+        assert_eq!(latest_subscription(&pool, user_id).await.subscription_id, pro.id);
+        let intents = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM payment_intents WHERE user_id = $1"#,
+            user_id
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(intents, 0);
+    }
+
+    #[sqlx::test]
+    async fn a_free_plan_is_active_and_paid_without_a_payment_intent(pool: PgPool) {
+        let user_id = create_user(&pool).await;
+        let free = plan(&pool, SubscriptionTier::Free).await;
+        let stripe = StripeStub::start().await;
+        let state = app_state_with_stripe(&pool, Some(&stripe.url)).await;
+
+        let Json(subscribed) = create_subscription(
+            State(state.clone()),
+            AuthUser(user_id),
+            Json(CreateSubscriptionRequest {
+                subscription_id: free.id,
+                billing_interval: BillingInterval::Monthly,
+            }),
+        )
+        .await
+        .unwrap();
+
+        assert!(subscribed.is_active);
+        assert_eq!(subscribed.payment_status.as_deref(), Some("paid"));
+        let tier = state.tier_cache.tier_for_user(&pool, user_id).await.unwrap();
+        assert_eq!(tier, SubscriptionTier::Free);
+        assert!(stripe.requests().is_empty());
+        let intents = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM payment_intents WHERE user_id = $1"#,
+            user_id
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(intents, 0);
+    }
+}
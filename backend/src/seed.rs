@@ -0,0 +1,221 @@
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::models::{ModelType, SubscriptionTier};
+
+struct SeedPlan {
+    name: &'static str,
+    tier: SubscriptionTier,
+    /// In cents.
+    price_monthly: i64,
+    price_yearly: i64,
+    features: fn() -> serde_json::Value,
+}
+
+const PLANS: &[SeedPlan] = &[
+    SeedPlan {
+        name: "Free Tier",
+        tier: SubscriptionTier::Free,
+        price_monthly: 0,
+        price_yearly: 0,
+        features: || {
+            json!({
+                "model_limit": 5,
+                "requests_per_day": 100,
+                "support": "community",
+                "features": ["Access to public models", "Basic API access", "Community support"]
+            })
+        },
+    },
+    SeedPlan {
+        name: "Pro",
+        tier: SubscriptionTier::Pro,
+        price_monthly: 2999,
+        price_yearly: 29999,
+        features: || {
+            json!({
+                "model_limit": 20,
+                "requests_per_day": 1000,
+                "support": "email",
+                "features": [
+                    "Access to premium models",
+                    "Priority API access",
+                    "Email support",
+                    "Advanced analytics",
+                    "Custom model hosting"
+                ]
+            })
+        },
+    },
+    SeedPlan {
+        name: "Enterprise",
+        tier: SubscriptionTier::Enterprise,
+        price_monthly: 19999,
+        price_yearly: 199999,
+        features: || {
+            json!({
+                "model_limit": -1,
+                "requests_per_day": -1,
+                "support": "dedicated",
+                "features": [
+                    "Unlimited model access",
+                    "Dedicated API endpoints",
+                    "24/7 priority support",
+                    "Custom model training",
+                    "SLA guarantees",
+                    "Team management",
+                    "SSO integration",
+                    "Audit logs"
+                ]
+            })
+        },
+    },
+];
+
+struct SeedModel {
+    name: &'static str,
+    description: &'static str,
+    model_type: ModelType,
+    framework: &'static str,
+    version: &'static str,
+    tags: &'static [&'static str],
+    performance_metrics: fn() -> serde_json::Value,
+}
+
+const MODELS: &[SeedModel] = &[
+    SeedModel {
+        name: "sentiment-small",
+        description: "Binary sentiment classifier for short English text",
+        model_type: ModelType::Classification,
+        framework: "pytorch",
+        version: "1.0.0",
+        tags: &["nlp", "sentiment"],
+        performance_metrics: || json!({ "accuracy": 0.91, "f1": 0.9, "latency_ms": 12.0 }),
+    },
+    SeedModel {
+        name: "house-prices",
+        description: "Gradient-boosted regressor for residential sale prices",
+        model_type: ModelType::Regression,
+        framework: "xgboost",
+        version: "2.1.0",
+        tags: &["tabular", "real-estate"],
+        performance_metrics: || json!({ "latency_ms": 3.5, "throughput": 4000.0 }),
+    },
+    SeedModel {
+        name: "text-embed-base",
+        description: "General-purpose 384-dimension sentence embeddings",
+        model_type: ModelType::Embedding,
+        framework: "onnx",
+        version: "0.3.0",
+        tags: &["nlp", "embeddings", "search"],
+        performance_metrics: || json!({ "latency_ms": 8.0, "throughput": 1200.0 }),
+    },
+    SeedModel {
+        name: "defect-detector",
+        description: "Surface defect detection for manufacturing line images",
+        model_type: ModelType::Vision,
+        framework: "tensorflow",
+        version: "1.4.2",
+        tags: &["vision", "manufacturing"],
+        performance_metrics: || json!({ "accuracy": 0.96, "latency_ms": 40.0 }),
+    },
+];
+
+/// Rows inserted by one run of [`run`].
+#[derive(Debug, Default)]
+pub struct SeedSummary {
+    pub plans: u64,
+    pub models: u64,
+}
+
+/// Insert the three subscription plans and a few public sample models for
+/// local development. Plans are matched by tier and models by name, so
+/// existing rows are left alone and running it again inserts nothing.
+pub async fn run(pool: &PgPool) -> Result<SeedSummary, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let mut summary = SeedSummary::default();
+
+    for plan in PLANS {
+        summary.plans += sqlx::query!(
+            r#"
+            INSERT INTO subscriptions (name, tier, price_monthly, price_yearly, features)
+            SELECT $1, $2, $3, $4, $5
+            WHERE NOT EXISTS (SELECT 1 FROM subscriptions WHERE tier = $2)
+            "#,
+            plan.name,
+            plan.tier as _,
+            plan.price_monthly,
+            plan.price_yearly,
+            (plan.features)()
+        )
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+    }
+
+    for model in MODELS {
+        let tags: Vec<String> = model.tags.iter().map(|tag| tag.to_string()).collect();
+        summary.models += sqlx::query!(
+            r#"
+            INSERT INTO ai_models (
+                name, description, model_type, framework, version,
+                tags, performance_metrics, is_public, status
+            )
+            SELECT $1::varchar, $2, $3, $4, $5, $6, $7, true, 'published'
+            WHERE NOT EXISTS (
+                SELECT 1 FROM ai_models WHERE name = $1::varchar AND deleted_at IS NULL
+            )
+            "#,
+            model.name,
+            model.description,
+            model.model_type as _,
+            model.framework,
+            model.version,
+            &tags,
+            (model.performance_metrics)()
+        )
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+    }
+
+    tx.commit().await?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test]
+    async fn seeding_twice_leaves_one_plan_per_tier(pool: PgPool) {
+        run(&pool).await.unwrap();
+        let again = run(&pool).await.unwrap();
+        assert_eq!((again.plans, again.models), (0, 0));
+
+        let per_tier = sqlx::query!(
+            r#"
+            SELECT tier::text AS "tier!", COUNT(*) AS "count!"
+            FROM subscriptions
+            GROUP BY tier
+            ORDER BY tier
+            "#
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        let per_tier: Vec<_> = per_tier.into_iter().map(|row| (row.tier, row.count)).collect();
+        assert_eq!(
+            per_tier,
+            [("free".to_string(), 1), ("pro".to_string(), 1), ("enterprise".to_string(), 1)]
+        );
+
+        let models = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM ai_models WHERE status = 'published'"#
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(models, MODELS.len() as i64);
+    }
+}
@@ -0,0 +1,495 @@
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::{StatusCode, Version},
+    response::IntoResponse,
+    Router,
+};
+use hyper::body::Incoming;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::{conn::auto::Builder, graceful::GracefulShutdown},
+};
+use std::env;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tower::Service;
+
+/// Pause after an accept error that isn't about a single connection.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Errors that only affect the connection being accepted; the next accept
+/// can go ahead straight away.
+fn is_connection_error(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::ConnectionRefused
+            | std::io::ErrorKind::ConnectionAborted
+            | std::io::ErrorKind::ConnectionReset
+    )
+}
+
+/// Connection-level settings for the HTTP server.
+///
+/// HTTP/1.1 is always served. When `http2` is enabled the server also accepts
+/// cleartext HTTP/2 (h2c with prior knowledge) on the same port, or offers
+/// `h2` over ALPN when serving TLS.
+#[derive(Debug, Clone)]
+pub struct ServerSettings {
+    pub http2: bool,
+    /// Serve TLS with this certificate. Needs the `tls` feature.
+    pub tls: Option<TlsFiles>,
+    pub keep_alive: bool,
+    pub http2_keep_alive_interval: Option<Duration>,
+    pub http2_keep_alive_timeout: Duration,
+    pub http2_max_concurrent_streams: u32,
+    /// How long in-flight requests get to finish after a shutdown signal.
+    pub shutdown_timeout: Duration,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            http2: false,
+            tls: None,
+            keep_alive: true,
+            http2_keep_alive_interval: None,
+            http2_keep_alive_timeout: Duration::from_secs(20),
+            http2_max_concurrent_streams: 200,
+            shutdown_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl ServerSettings {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+
+        Self {
+            http2: env_parse("HTTP2_ENABLED").unwrap_or(defaults.http2),
+            tls: match (env::var("TLS_CERT_PATH"), env::var("TLS_KEY_PATH")) {
+                (Ok(cert_path), Ok(key_path)) => Some(TlsFiles {
+                    cert_path: cert_path.into(),
+                    key_path: key_path.into(),
+                }),
+                _ => None,
+            },
+            keep_alive: env_parse("HTTP_KEEP_ALIVE").unwrap_or(defaults.keep_alive),
+            http2_keep_alive_interval: env_parse("HTTP2_KEEP_ALIVE_INTERVAL_SECS")
+                .map(Duration::from_secs),
+            http2_keep_alive_timeout: env_parse("HTTP2_KEEP_ALIVE_TIMEOUT_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.http2_keep_alive_timeout),
+            http2_max_concurrent_streams: env_parse("HTTP2_MAX_CONCURRENT_STREAMS")
+                .unwrap_or(defaults.http2_max_concurrent_streams),
+            shutdown_timeout: env_parse("SHUTDOWN_TIMEOUT_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.shutdown_timeout),
+        }
+    }
+
+    /// Build the hyper connection builder for these settings.
+    pub fn builder(&self) -> Builder<TokioExecutor> {
+        let mut builder = Builder::new(TokioExecutor::new());
+
+        builder.http1().keep_alive(self.keep_alive);
+
+        if !self.http2 {
+            return builder.http1_only();
+        }
+
+        builder
+            .http2()
+            .timer(TokioTimer::new())
+            .keep_alive_interval(self.http2_keep_alive_interval)
+            .keep_alive_timeout(self.http2_keep_alive_timeout)
+            .max_concurrent_streams(self.http2_max_concurrent_streams);
+
+        builder
+    }
+
+    /// Load the certificate and key, if TLS is configured.
+    #[cfg(feature = "tls")]
+    fn tls_acceptor(&self) -> std::io::Result<Option<TlsAcceptor>> {
+        use rustls_pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+        use tokio_rustls::rustls;
+
+        let Some(files) = &self.tls else {
+            return Ok(None);
+        };
+        let invalid = |e: &dyn std::fmt::Display| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
+        };
+
+        let certs = CertificateDer::pem_file_iter(&files.cert_path)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| invalid(&e))?;
+        let key = PrivateKeyDer::from_pem_file(&files.key_path).map_err(|e| invalid(&e))?;
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| invalid(&e))?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|e| invalid(&e))?;
+        config.alpn_protocols = if self.http2 {
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+        } else {
+            vec![b"http/1.1".to_vec()]
+        };
+
+        Ok(Some(TlsAcceptor::from(Arc::new(config))))
+    }
+
+    #[cfg(not(feature = "tls"))]
+    fn tls_acceptor(&self) -> std::io::Result<Option<TlsAcceptor>> {
+        match &self.tls {
+            Some(files) => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!(
+                    "cannot serve TLS with {} and {}: built without the `tls` feature",
+                    files.cert_path.display(),
+                    files.key_path.display(),
+                ),
+            )),
+            None => Ok(None),
+        }
+    }
+}
+
+/// PEM files for the certificate chain and its private key.
+#[derive(Debug, Clone)]
+pub struct TlsFiles {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+#[cfg(feature = "tls")]
+type TlsAcceptor = tokio_rustls::TlsAcceptor;
+/// Stands in for the acceptor without the `tls` feature; never constructed.
+#[cfg(not(feature = "tls"))]
+#[derive(Clone)]
+enum TlsAcceptor {}
+
+/// How long a client gets to finish the TLS handshake.
+#[cfg(feature = "tls")]
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A plaintext or TLS connection.
+trait Connection: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Connection for T {}
+
+#[cfg(feature = "tls")]
+async fn accept_tls(acceptor: TlsAcceptor, stream: TcpStream) -> std::io::Result<Box<dyn Connection>> {
+    let stream = tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+        .await
+        .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??;
+    Ok(Box::new(stream))
+}
+
+#[cfg(not(feature = "tls"))]
+async fn accept_tls(acceptor: TlsAcceptor, _stream: TcpStream) -> std::io::Result<Box<dyn Connection>> {
+    match acceptor {}
+}
+
+fn env_parse<T: std::str::FromStr>(key: &str) -> Option<T> {
+    env::var(key).ok().and_then(|v| v.parse().ok())
+}
+
+/// Resolves on Ctrl-C or, on Unix, SIGTERM.
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// Decrements the in-flight request count when the request finishes.
+struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    fn start(counter: &Arc<AtomicUsize>) -> Self {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Self(counter.clone())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Accept connections on `listener` and serve `app` on each of them until
+/// `shutdown` resolves, over TLS if `settings.tls` is set. The listener then stops accepting and in-flight
+/// requests get up to `settings.shutdown_timeout` to complete. Failing to
+/// accept a connection is logged and doesn't stop the server.
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    settings: ServerSettings,
+    shutdown: impl Future<Output = ()>,
+) -> std::io::Result<()> {
+    let builder = settings.builder();
+    let tls = settings.tls_acceptor()?;
+    let graceful = GracefulShutdown::new();
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    tokio::pin!(shutdown);
+
+    loop {
+        let (stream, remote_addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    tracing::warn!("failed to accept connection: {}", e);
+                    // Usually out of file descriptors: give open connections
+                    // a moment to close instead of spinning on the error.
+                    if !is_connection_error(&e) {
+                        tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    }
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+        let tower_service = app.clone();
+        let builder = builder.clone();
+        let watcher = graceful.watcher();
+        let in_flight = in_flight.clone();
+        let tls = tls.clone();
+        let http2 = settings.http2;
+
+        tokio::spawn(async move {
+            let stream: Box<dyn Connection> = match tls {
+                Some(acceptor) => match accept_tls(acceptor, stream).await {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        tracing::debug!("TLS handshake with {} failed: {}", remote_addr, e);
+                        return;
+                    }
+                },
+                None => Box::new(stream),
+            };
+            let io = TokioIo::new(stream);
+            let hyper_service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+                // What `into_make_service_with_connect_info` would provide.
+                request.extensions_mut().insert(ConnectInfo(remote_addr));
+                let guard = InFlight::start(&in_flight);
+                // `http1_only` is ignored when serving with upgrades, so a
+                // client with prior knowledge can still open an h2 stream.
+                let response = (http2 || request.version() != Version::HTTP_2)
+                    .then(|| tower_service.clone().call(request));
+                async move {
+                    let response = match response {
+                        Some(response) => response.await,
+                        None => Ok(StatusCode::HTTP_VERSION_NOT_SUPPORTED.into_response()),
+                    };
+                    drop(guard);
+                    response
+                }
+            });
+
+            let connection = builder.serve_connection_with_upgrades(io, hyper_service);
+            if let Err(e) = watcher.watch(connection).await {
+                tracing::debug!("connection from {} closed with error: {}", remote_addr, e);
+            }
+        });
+    }
+
+    drop(listener);
+    let pending = in_flight.load(Ordering::SeqCst);
+    tracing::info!(
+        "shutdown signal received, draining {} in-flight requests (timeout {:?})",
+        pending,
+        settings.shutdown_timeout,
+    );
+
+    match tokio::time::timeout(settings.shutdown_timeout, graceful.shutdown()).await {
+        Ok(()) => tracing::info!("drained {} in-flight requests", pending),
+        Err(_) => {
+            let abandoned = in_flight.load(Ordering::SeqCst);
+            tracing::warn!(
+                "shutdown timed out: drained {} requests, abandoned {}",
+                pending.saturating_sub(abandoned),
+                abandoned,
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Bytes, routing::get};
+    use http_body_util::{BodyExt, Empty};
+    use std::io::{Error, ErrorKind};
+    use std::net::SocketAddr;
+    use tokio::sync::oneshot;
+
+    /// Serve a route that echoes the request's HTTP version.
+    async fn start(settings: ServerSettings) -> (SocketAddr, oneshot::Sender<()>) {
+        let app = Router::new().route("/", get(|request: Request| async move {
+            format!("{:?}", request.version())
+        }));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = oneshot::channel::<()>();
+        tokio::spawn(serve(listener, app, settings, async {
+            stopped.await.ok();
+        }));
+        (addr, stop)
+    }
+
+    /// Send one HTTP/2 request over `io` and return the status and body.
+    async fn h2_get(io: impl Connection) -> hyper::Result<(StatusCode, String)> {
+        let (mut sender, connection) =
+            hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(io)).await?;
+        tokio::spawn(connection);
+        let request = axum::http::Request::get("http://localhost/")
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let response = sender.send_request(request).await?;
+        assert_eq!(response.version(), Version::HTTP_2);
+        let status = response.status();
+        let body = response.into_body().collect().await?.to_bytes();
+        Ok((status, String::from_utf8(body.to_vec()).unwrap()))
+    }
+
+    fn http2() -> ServerSettings {
+        ServerSettings {
+            http2: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn only_resource_errors_back_off() {
+        assert!(is_connection_error(&Error::from(ErrorKind::ConnectionAborted)));
+        assert!(is_connection_error(&Error::from(ErrorKind::ConnectionReset)));
+        // EMFILE
+        assert!(!is_connection_error(&Error::from_raw_os_error(24)));
+    }
+
+    #[tokio::test]
+    async fn serves_h2c_with_prior_knowledge_when_enabled() {
+        let (addr, _stop) = start(http2()).await;
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (status, version) = h2_get(stream).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(version, "HTTP/2.0");
+    }
+
+    #[tokio::test]
+    async fn http2_is_refused_by_default() {
+        let (addr, _stop) = start(ServerSettings::default()).await;
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (status, _) = h2_get(stream).await.unwrap();
+        assert_eq!(status, StatusCode::HTTP_VERSION_NOT_SUPPORTED);
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn negotiates_h2_over_tls_alpn() {
+        use rustls_pki_types::ServerName;
+        use tokio_rustls::{rustls, TlsConnector};
+
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let dir = std::env::temp_dir().join(format!("server-tls-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let files = TlsFiles {
+            cert_path: dir.join("cert.pem"),
+            key_path: dir.join("key.pem"),
+        };
+        std::fs::write(&files.cert_path, certified.cert.pem()).unwrap();
+        std::fs::write(&files.key_path, certified.key_pair.serialize_pem()).unwrap();
+        let (addr, _stop) = start(ServerSettings {
+            tls: Some(files),
+            ..http2()
+        })
+        .await;
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(certified.cert.der().clone()).unwrap();
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let stream = TlsConnector::from(Arc::new(config))
+            .connect(ServerName::try_from("localhost").unwrap(), tcp)
+            .await
+            .unwrap();
+
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+        assert_eq!(h2_get(stream).await.unwrap(), (StatusCode::OK, "HTTP/2.0".into()));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_request_in_flight_at_shutdown_still_completes() {
+        let app = Router::new().route("/slow", get(|| async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            "done"
+        }));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve(listener, app, ServerSettings::default(), async {
+            stopped.await.ok();
+        }));
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (mut sender, connection) =
+            hyper::client::conn::http1::handshake(TokioIo::new(stream)).await.unwrap();
+        tokio::spawn(connection);
+        let request = axum::http::Request::get("/slow")
+            .header(axum::http::header::HOST, "localhost")
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let response = tokio::spawn(sender.send_request(request));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        stop.send(()).unwrap();
+
+        let response = response.await.unwrap().unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"done");
+        server.await.unwrap().unwrap();
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+}
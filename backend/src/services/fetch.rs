@@ -0,0 +1,106 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, thiserror::Error)]
+pub enum FetchError {
+    #[error("invalid URL: {0}")]
+    InvalidUrl(String),
+    #[error("could not fetch {url}: {reason}")]
+    Failed { url: String, reason: String },
+}
+
+/// Only public hosts; internal addresses would let a caller probe our
+/// network through whatever is fetching.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation())
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                // Unique local (fc00::/7) and link-local (fe80::/10).
+                || (segments[0] & 0xfe00) == 0xfc00
+                || (segments[0] & 0xffc0) == 0xfe80
+                || ip.to_ipv4_mapped().is_some_and(|v4| !is_public(IpAddr::V4(v4))))
+        }
+    }
+}
+
+async fn check_url(raw: &str) -> Result<url::Url, FetchError> {
+    let url = url::Url::parse(raw).map_err(|e| FetchError::InvalidUrl(e.to_string()))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(FetchError::InvalidUrl(
+            "only http and https URLs are supported".into(),
+        ));
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| FetchError::InvalidUrl("URL has no host".into()))?;
+    let port = url.port_or_known_default().unwrap_or(443);
+
+    let addrs: Vec<_> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| FetchError::Failed {
+            url: raw.to_string(),
+            reason: format!("could not resolve {}: {}", host, e),
+        })?
+        .collect();
+    if addrs.is_empty() || addrs.iter().any(|addr| !is_public(addr.ip())) {
+        return Err(FetchError::InvalidUrl("URL must point to a public host".into()));
+    }
+    Ok(url)
+}
+
+/// GET a user-supplied URL on a public host and return at most `max_bytes`
+/// of body. Redirects aren't followed, since the target would bypass the
+/// host check. `accept` is sent as the `Accept` header.
+pub async fn fetch_public(raw_url: &str, accept: &str, max_bytes: usize) -> Result<Vec<u8>, FetchError> {
+    let url = check_url(raw_url).await?;
+    fetch(url, accept, max_bytes).await
+}
+
+/// [`fetch_public`] without the host check, for URLs already vetted.
+pub(super) async fn fetch(
+    url: url::Url,
+    accept: &str,
+    max_bytes: usize,
+) -> Result<Vec<u8>, FetchError> {
+    let failed = |reason: String| FetchError::Failed {
+        url: url.to_string(),
+        reason,
+    };
+
+    let client = reqwest::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .map_err(|e| failed(e.to_string()))?;
+
+    let mut response = client
+        .get(url.as_str())
+        .header(reqwest::header::ACCEPT, accept)
+        .send()
+        .await
+        .map_err(|e| failed(e.to_string()))?;
+    if !response.status().is_success() {
+        return Err(failed(format!("server responded with {}", response.status())));
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|e| failed(e.to_string()))? {
+        if body.len() + chunk.len() > max_bytes {
+            return Err(failed(format!("response is larger than {} bytes", max_bytes)));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
@@ -0,0 +1,100 @@
+use crate::models::{ModelManifest, MANIFEST_SCHEMA_VERSION};
+
+use super::fetch::{fetch_public, FetchError};
+
+/// Manifests are small; anything bigger is not a manifest.
+const MAX_MANIFEST_BYTES: usize = 256 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ManifestImportError {
+    #[error("could not load manifest: {0}")]
+    Fetch(#[from] FetchError),
+    #[error("manifest does not match the schema: {0}")]
+    Schema(String),
+}
+
+/// Fetch and parse the manifest at `raw_url`.
+pub async fn fetch_manifest(raw_url: &str) -> Result<ModelManifest, ManifestImportError> {
+    let body = fetch_public(raw_url, "application/json", MAX_MANIFEST_BYTES).await?;
+    parse_manifest(&body)
+}
+
+fn parse_manifest(body: &[u8]) -> Result<ModelManifest, ManifestImportError> {
+    let manifest: ModelManifest =
+        serde_json::from_slice(body).map_err(|e| ManifestImportError::Schema(e.to_string()))?;
+    if manifest.schema_version != MANIFEST_SCHEMA_VERSION {
+        return Err(ManifestImportError::Schema(format!(
+            "unsupported schema_version {}, expected {}",
+            manifest.schema_version, MANIFEST_SCHEMA_VERSION
+        )));
+    }
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ModelType;
+    use crate::services::fetch::fetch;
+    use axum::{routing::get, Json, Router};
+    use serde_json::json;
+
+    /// Serves a valid manifest at `/valid.json` and one with a misspelt
+    /// field at `/invalid.json`.
+    async fn manifest_server() -> String {
+        let valid = json!({
+            "schema_version": 1,
+            "name": "sentiment-small",
+            "description": "Binary sentiment classifier",
+            "model_type": "classification",
+            "framework": "pytorch",
+            "version": "1.2.0",
+            "tags": ["nlp"],
+            "performance_metrics": { "accuracy": 0.93 },
+        });
+        let mut invalid = valid.clone();
+        let framework = invalid.as_object_mut().unwrap().remove("framework").unwrap();
+        invalid["frameworks"] = framework;
+        let app = Router::new()
+            .route("/valid.json", get(move || async move { Json(valid) }))
+            .route("/invalid.json", get(move || async move { Json(invalid) }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        url
+    }
+
+    async fn served(url: &str) -> Result<ModelManifest, ManifestImportError> {
+        let body = fetch(url.parse().unwrap(), "application/json", MAX_MANIFEST_BYTES).await?;
+        parse_manifest(&body)
+    }
+
+    #[tokio::test]
+    async fn a_valid_manifest_maps_to_a_private_draft() {
+        let server = manifest_server().await;
+
+        let model = served(&format!("{}/valid.json", server)).await.unwrap().into_create();
+        assert_eq!((model.name.as_str(), model.version.as_str()), ("sentiment-small", "1.2.0"));
+        assert_eq!(model.model_type, ModelType::Classification);
+        assert_eq!(model.tags, Some(vec!["nlp".to_string()]));
+        assert!(!model.is_public);
+    }
+
+    #[tokio::test]
+    async fn schema_and_fetch_failures_are_told_apart() {
+        let server = manifest_server().await;
+
+        let invalid = served(&format!("{}/invalid.json", server)).await;
+        assert!(
+            matches!(&invalid, Err(ManifestImportError::Schema(e)) if e.contains("frameworks")),
+            "{:?}",
+            invalid
+        );
+        let missing = served(&format!("{}/missing.json", server)).await;
+        assert!(matches!(missing, Err(ManifestImportError::Fetch(FetchError::Failed { .. }))));
+
+        // The real entry point refuses the loopback server outright.
+        let local = fetch_manifest(&format!("{}/valid.json", server)).await;
+        assert!(matches!(local, Err(ManifestImportError::Fetch(FetchError::InvalidUrl(_)))));
+    }
+}
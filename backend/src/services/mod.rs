@@ -0,0 +1,3 @@
+pub mod fetch;
+pub mod manifest_import;
+pub mod stripe;
@@ -1,82 +1,292 @@
 use anyhow::Result;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::future::Future;
+use std::time::Duration;
 use stripe::{
-    Client, CreatePaymentIntent, Currency, Customer, PaymentIntent,
-    PaymentMethod, PaymentMethodCard, Webhook,
+    Charge, Client, CreatePaymentIntent, CreateRefund, Currency, Customer, CustomerId, CustomerSearchParams, Dispute,
+    DisputeStatus, ErrorCode, ErrorType, EventObject, EventType, ListCustomers, PaymentIntent, PaymentMethod,
+    PaymentIntentId, PaymentMethodId, Refund, RequestStrategy, StripeError, SubscriptionId,
+    SubscriptionStatus, Event, UpdateSubscription, UpdateSubscriptionItems,
+};
+// The subscription item module has types of the same names; these are the
+// ones `UpdateSubscription` takes.
+use stripe::generated::billing::subscription::{
+    PlanInterval, SubscriptionItemPriceData, SubscriptionItemPriceDataRecurring,
+    SubscriptionProrationBehavior,
 };
 use uuid::Uuid;
 
 use crate::{
     config::Config,
+    email::{EmailTemplate, Mailer},
+    events::PaymentStatusBus,
+    tier_cache::TierCache,
+    error::{is_unique_violation, AppError},
     models::{
-        payment::{CardDetails, PaymentIntent as DbPaymentIntent},
-        subscription::Subscription,
+        payment::{
+            CardDetails, Invoice, PaymentHistory, PaymentIntent as DbPaymentIntent, PaymentMethod as DbPaymentMethod,
+            StripeCustomer,
+        },
+        subscription::{BillingInterval, Subscription, UserSubscription},
+        Money, PaymentDispute, PendingStripeCleanup,
     },
 };
 
 pub struct StripeService {
     client: Client,
+    pool: PgPool,
     webhook_secret: String,
+    webhook_tolerance: Duration,
+    mailer: Mailer,
+    payment_events: PaymentStatusBus,
+    tier_cache: TierCache,
+}
+
+/// What [`StripeService::reconcile_customers`] did with a user's customers.
+#[derive(Debug, Serialize)]
+pub struct CustomerReconciliation {
+    /// The customer the user's charges go to from now on.
+    pub customer_id: Option<String>,
+    /// Empty duplicates deleted from Stripe.
+    pub deleted: Vec<String>,
+    /// Duplicates left alone because they hold payment methods or
+    /// subscriptions; they need moving over by hand.
+    pub kept: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreatePaymentIntentRequest {
     pub subscription_id: Uuid,
+    /// Defaults to the subscription's currency.
+    pub currency: Option<String>,
+    /// Sent to Stripe so retried requests don't create a second charge.
+    /// Derived from the user and plan when omitted.
+    pub idempotency_key: Option<String>,
+    pub coupon_code: Option<String>,
 }
 
 impl StripeService {
-    pub fn new(config: &Config) -> Self {
+    pub fn new(
+        config: &Config,
+        pool: PgPool,
+        mailer: Mailer,
+        payment_events: PaymentStatusBus,
+        tier_cache: TierCache,
+    ) -> Self {
         Self {
-            client: Client::new(&config.stripe_secret_key),
+            client: Client::new(config.stripe_secret_key.clone()),
+            pool,
             webhook_secret: config.stripe_webhook_secret.clone(),
+            webhook_tolerance: config.stripe_webhook_tolerance,
+            mailer,
+            payment_events,
+            tier_cache,
+        }
+    }
+
+    /// Send API calls to `url` instead of Stripe, e.g. a local stub.
+    #[cfg(test)]
+    pub fn with_api_base(self, url: &str) -> Self {
+        Self {
+            client: Client::from_url(url, "sk_test_fixture"),
+            ..self
         }
     }
 
+    /// Create a payment intent for the plan, or return the user's existing
+    /// pending or still-settling one. The flag is `true` when a new intent
+    /// was created.
     pub async fn create_payment_intent(
         &self,
         user_id: Uuid,
         subscription: &Subscription,
-    ) -> Result<DbPaymentIntent> {
-        // Create or get Stripe customer
-        let customer = self.get_or_create_customer(user_id).await?;
+        total: &Money,
+        tax: &Money,
+        idempotency_key: Option<&str>,
+    ) -> Result<(DbPaymentIntent, bool)> {
+        let latest = DbPaymentIntent::latest_for(&self.pool, user_id, subscription.id).await?;
 
-        // Create payment intent
-        let mut create_intent = CreatePaymentIntent::new(
-            stripe::Amount::from_f64_in_currency(Currency::USD, subscription.price_monthly)?,
-            Currency::USD,
-        );
-        create_intent.customer = Some(&customer.id);
-        create_intent.setup_future_usage = Some(stripe::PaymentIntentSetupFutureUsage::OffSession);
+        if let Some(latest) = &latest {
+            // Charging again while a payment settles could take it twice.
+            if latest.status == "processing" {
+                return Ok((latest.clone(), false));
+            }
+            if latest.status == "pending" {
+                if !latest.is_expired() {
+                    return Ok((latest.clone(), false));
+                }
+                // Clear the lapsed intent out of the way of the new one.
+                self.expire_intent(&latest.stripe_payment_intent_id).await?;
+            }
+        }
+
+        // Concurrent requests see the same latest intent and so send Stripe
+        // the same key; once an intent is created the next key differs.
+        let idempotency_key = match idempotency_key {
+            Some(key) => key.to_string(),
+            None => format!(
+                "pi-{}-{}-{}",
+                user_id,
+                subscription.id,
+                latest.as_ref().map(|i| i.id.to_string()).unwrap_or_else(|| "first".into())
+            ),
+        };
 
-        let payment_intent = PaymentIntent::create(&self.client, create_intent).await?;
+        let payment_intent = self
+            .create_intent_for_user(user_id, total, &idempotency_key)
+            .await?;
 
         // Create payment intent in our database
-        let db_payment_intent = DbPaymentIntent::create(
-            &crate::DB_POOL,
+        let created = DbPaymentIntent::create(
+            &self.pool,
             user_id,
             subscription.id,
             payment_intent.id.to_string(),
-            subscription.price_monthly,
+            total,
+            tax,
             payment_intent.client_secret.unwrap_or_default(),
+            None,
+        )
+        .await;
+
+        match created {
+            Ok(db_payment_intent) => Ok((db_payment_intent, true)),
+            // A concurrent request won the race; hand back its intent.
+            Err(e) if is_unique_violation(&e) => {
+                let existing =
+                    DbPaymentIntent::latest_for(&self.pool, user_id, subscription.id)
+                        .await?
+                        .filter(|intent| intent.status == "pending")
+                        .ok_or_else(|| anyhow::anyhow!("pending payment intent vanished"))?;
+                Ok((existing, false))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Start a fresh intent for the same plan and amount as a failed one.
+    /// `attempt` numbers the retry, so repeated requests for the same retry
+    /// reach Stripe with the same idempotency key.
+    pub async fn retry_payment_intent(
+        &self,
+        failed: &DbPaymentIntent,
+        retry_of: Uuid,
+        attempt: i64,
+    ) -> Result<DbPaymentIntent> {
+        let idempotency_key = format!("pi-retry-{}-{}", retry_of, attempt);
+        let payment_intent = self
+            .create_intent_for_user(failed.user_id, &failed.total(), &idempotency_key)
+            .await?;
+
+        let created = DbPaymentIntent::create(
+            &self.pool,
+            failed.user_id,
+            failed.subscription_id,
+            payment_intent.id.to_string(),
+            &failed.total(),
+            &failed.tax(),
+            payment_intent.client_secret.unwrap_or_default(),
+            Some(retry_of),
         )
         .await?;
 
-        Ok(db_payment_intent)
+        Ok(created)
     }
 
-    pub async fn handle_webhook(&self, payload: &[u8], signature: &str) -> Result<()> {
-        let event = Webhook::construct_event(payload, signature, &self.webhook_secret)?;
+    /// Create the Stripe side of an intent for the user's customer.
+    async fn create_intent_for_user(
+        &self,
+        user_id: Uuid,
+        amount: &Money,
+        idempotency_key: &str,
+    ) -> Result<PaymentIntent> {
+        let stripe_currency: Currency = amount.currency().to_ascii_lowercase().parse()?;
+        let amount_minor = amount.minor_units();
+
+        // Create or get Stripe customer
+        let customer_id = self.get_or_create_customer(user_id).await?;
 
-        match event.type_ {
-            stripe::EventType::PaymentIntentSucceeded => {
-                if let Some(payment_intent) = event.data.object.as_payment_intent() {
-                    self.handle_payment_success(payment_intent).await?;
-                }
+        // The customer may have been deleted in Stripe since we looked it up.
+        // Recreate it and retry once rather than failing the checkout.
+        let payment_intent = match self
+            .create_stripe_intent(customer_id, amount_minor, stripe_currency, idempotency_key)
+            .await
+        {
+            Err(e) if is_missing_customer(&e) => {
+                tracing::warn!("stripe customer for user {} no longer exists, recreating", user_id);
+                let customer_id = self.replace_customer(user_id).await?;
+                self.create_stripe_intent(
+                    customer_id,
+                    amount_minor,
+                    stripe_currency,
+                    &format!("{}-recreated", idempotency_key),
+                )
+                .await?
             }
-            stripe::EventType::PaymentIntentPaymentFailed => {
-                if let Some(payment_intent) = event.data.object.as_payment_intent() {
-                    self.handle_payment_failure(payment_intent).await?;
-                }
+            result => result?,
+        };
+
+        Ok(payment_intent)
+    }
+
+    /// Check a webhook's `Stripe-Signature` and parse the event. Events
+    /// signed longer ago than the configured tolerance are rejected; ones
+    /// close to it are logged so the tolerance can be tuned.
+    pub fn verify_webhook(
+        &self,
+        payload: &str,
+        signature: &str,
+    ) -> std::result::Result<Event, WebhookError> {
+        let age = verify_signature(
+            payload,
+            signature,
+            &self.webhook_secret,
+            self.webhook_tolerance,
+            chrono::Utc::now().timestamp(),
+        )?;
+        if age.as_secs_f64() > self.webhook_tolerance.as_secs_f64() * TOLERANCE_WARNING_FRACTION {
+            tracing::warn!(
+                "accepted webhook signed {}s ago, close to the {}s tolerance",
+                age.as_secs(),
+                self.webhook_tolerance.as_secs()
+            );
+        }
+        serde_json::from_str(payload).map_err(|e| WebhookError::Payload(e.to_string()))
+    }
+
+    pub async fn handle_webhook(&self, event: Event) -> Result<()> {
+        match (event.type_, event.data.object) {
+            (EventType::PaymentIntentSucceeded, EventObject::PaymentIntent(payment_intent)) => {
+                self.handle_payment_success(&payment_intent).await?;
+                crate::metrics::PAYMENT_SUCCEEDED_TOTAL.inc();
+            }
+            (EventType::PaymentIntentProcessing, EventObject::PaymentIntent(payment_intent)) => {
+                self.handle_payment_processing(&payment_intent).await?;
+            }
+            (EventType::PaymentIntentPaymentFailed, EventObject::PaymentIntent(payment_intent)) => {
+                self.handle_payment_failure(&payment_intent).await?;
+                crate::metrics::PAYMENT_FAILED_TOTAL.inc();
+            }
+            (EventType::ChargeRefunded, EventObject::Charge(charge)) => {
+                self.handle_charge_refunded(&charge).await?;
+            }
+            (EventType::ChargeDisputeCreated, EventObject::Dispute(dispute)) => {
+                self.handle_dispute_created(&dispute).await?;
+            }
+            (EventType::ChargeDisputeClosed, EventObject::Dispute(dispute)) => {
+                self.handle_dispute_closed(&dispute).await?;
+            }
+            (EventType::CustomerSubscriptionDeleted, EventObject::Subscription(subscription)) => {
+                self.handle_subscription_status(subscription.id.as_str(), SubscriptionStatus::Canceled)
+                    .await?;
+            }
+            (EventType::CustomerSubscriptionUpdated, EventObject::Subscription(subscription)) => {
+                self.handle_subscription_status(subscription.id.as_str(), subscription.status)
+                    .await?;
             }
             _ => (),
         }
@@ -84,127 +294,1471 @@ impl StripeService {
         Ok(())
     }
 
+    /// Mirror a Stripe Billing subscription's status onto the linked local
+    /// one: canceled ends it, past due flags it. Other statuses are ours
+    /// to manage.
+    async fn handle_subscription_status(
+        &self,
+        stripe_subscription_id: &str,
+        status: SubscriptionStatus,
+    ) -> Result<()> {
+        let changed = match status {
+            SubscriptionStatus::Canceled => {
+                let ended =
+                    UserSubscription::end_by_stripe_id(&self.pool, stripe_subscription_id).await?;
+                if let Some(user_id) = ended {
+                    self.tier_cache.invalidate(user_id);
+                }
+                ended.is_some()
+            }
+            SubscriptionStatus::PastDue => {
+                UserSubscription::flag_past_due_by_stripe_id(&self.pool, stripe_subscription_id)
+                    .await?
+            }
+            _ => return Ok(()),
+        };
+        if changed {
+            tracing::info!(
+                "stripe subscription {} is {}; updated local subscription",
+                stripe_subscription_id,
+                status
+            );
+        }
+        Ok(())
+    }
+
+    /// Marks the intent succeeded, records history, issues the invoice and
+    /// activates the subscription, all in one transaction.
     async fn handle_payment_success(&self, payment_intent: &PaymentIntent) -> Result<()> {
         let payment_intent_id = payment_intent.id.to_string();
-        
-        // Update payment intent status
-        DbPaymentIntent::update_status(
-            &crate::DB_POOL,
-            &payment_intent_id,
+        let mut tx = self.pool.begin().await?;
+
+        let Some(db_payment_intent) =
+            DbPaymentIntent::get_by_stripe_id(&mut tx, &payment_intent_id).await?
+        else {
+            return Ok(());
+        };
+
+        // Expired intents are cancelled in Stripe, so a success here means the
+        // cancellation lost a race. Don't activate; it needs a manual refund.
+        if db_payment_intent.status == "expired" {
+            tracing::error!(
+                "payment intent {} succeeded after it expired; not activating",
+                payment_intent_id
+            );
+            return Ok(());
+        }
+
+        DbPaymentIntent::update_status(&mut tx, &payment_intent_id, "succeeded").await?;
+        PaymentHistory::create(
+            &mut tx,
+            db_payment_intent.user_id,
+            db_payment_intent.subscription_id,
+            db_payment_intent.id,
+            &db_payment_intent.total(),
             "succeeded",
-        ).await?;
-
-        // Get payment intent from our database
-        if let Some(db_payment_intent) = DbPaymentIntent::get_by_stripe_id(
-            &crate::DB_POOL,
-            &payment_intent_id,
-        ).await? {
-            // Create payment history record
-            crate::models::payment::PaymentHistory::create(
-                &crate::DB_POOL,
-                db_payment_intent.user_id,
-                db_payment_intent.subscription_id,
-                db_payment_intent.id,
-                db_payment_intent.amount,
-                "succeeded",
-            ).await?;
-
-            // Activate subscription
-            crate::models::subscription::UserSubscription::activate(
-                &crate::DB_POOL,
-                db_payment_intent.user_id,
-                db_payment_intent.subscription_id,
-            ).await?;
+        )
+        .await?;
+        let invoice = Invoice::issue_for(&mut tx, &db_payment_intent).await?;
+        UserSubscription::activate(
+            &mut tx,
+            db_payment_intent.user_id,
+            db_payment_intent.subscription_id,
+        )
+        .await?;
+
+        tx.commit().await?;
+        self.tier_cache.invalidate(db_payment_intent.user_id);
+        self.payment_events.publish(&payment_intent_id, "succeeded");
+        tracing::info!(
+            "issued invoice {} for payment intent {}",
+            invoice.display_number(),
+            payment_intent_id
+        );
+        self.mailer.send_best_effort(
+            db_payment_intent.user_id,
+            EmailTemplate::PaymentSucceeded {
+                amount: db_payment_intent.total(),
+                invoice_number: invoice.display_number(),
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Payment methods like SEPA debits settle days after confirmation.
+    /// Marks the intent and the subscription as processing; activation
+    /// waits for `payment_intent.succeeded`.
+    async fn handle_payment_processing(&self, payment_intent: &PaymentIntent) -> Result<()> {
+        let payment_intent_id = payment_intent.id.to_string();
+        let mut tx = self.pool.begin().await?;
+
+        let Some(db_payment_intent) =
+            DbPaymentIntent::get_by_stripe_id(&mut tx, &payment_intent_id).await?
+        else {
+            return Ok(());
+        };
+        // Events can arrive out of order; a settled intent stays settled.
+        if !DbPaymentIntent::mark_processing(&mut tx, &payment_intent_id).await? {
+            return Ok(());
         }
+        UserSubscription::flag_payment_processing(
+            &mut tx,
+            db_payment_intent.user_id,
+            db_payment_intent.subscription_id,
+        )
+        .await?;
 
+        tx.commit().await?;
+        self.payment_events.publish(&payment_intent_id, "processing");
         Ok(())
     }
 
+    /// Marks the intent failed and records history in one transaction.
     async fn handle_payment_failure(&self, payment_intent: &PaymentIntent) -> Result<()> {
         let payment_intent_id = payment_intent.id.to_string();
-        
-        // Update payment intent status
-        DbPaymentIntent::update_status(
-            &crate::DB_POOL,
-            &payment_intent_id,
+        let mut tx = self.pool.begin().await?;
+
+        let Some(db_payment_intent) =
+            DbPaymentIntent::get_by_stripe_id(&mut tx, &payment_intent_id).await?
+        else {
+            return Ok(());
+        };
+
+        DbPaymentIntent::update_status(&mut tx, &payment_intent_id, "failed").await?;
+        UserSubscription::fail_processing_payment(
+            &mut tx,
+            db_payment_intent.user_id,
+            db_payment_intent.subscription_id,
+        )
+        .await?;
+        PaymentHistory::create(
+            &mut tx,
+            db_payment_intent.user_id,
+            db_payment_intent.subscription_id,
+            db_payment_intent.id,
+            &db_payment_intent.total(),
             "failed",
-        ).await?;
-
-        // Get payment intent from our database
-        if let Some(db_payment_intent) = DbPaymentIntent::get_by_stripe_id(
-            &crate::DB_POOL,
-            &payment_intent_id,
-        ).await? {
-            // Create payment history record
-            crate::models::payment::PaymentHistory::create(
-                &crate::DB_POOL,
-                db_payment_intent.user_id,
-                db_payment_intent.subscription_id,
-                db_payment_intent.id,
-                db_payment_intent.amount,
-                "failed",
-            ).await?;
+        )
+        .await?;
+
+        tx.commit().await?;
+        self.payment_events.publish(&payment_intent_id, "failed");
+        self.mailer.send_best_effort(
+            db_payment_intent.user_id,
+            EmailTemplate::PaymentFailed {
+                amount: db_payment_intent.total(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Refund a succeeded payment in Stripe and record it. `amount` defaults
+    /// to whatever hasn't been refunded yet; callers check it's in range.
+    pub async fn refund_payment(
+        &self,
+        payment_intent_id: &str,
+        amount: Option<i64>,
+    ) -> Result<DbPaymentIntent> {
+        let intent = DbPaymentIntent::get_by_stripe_id(&self.pool, payment_intent_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("unknown payment intent {}", payment_intent_id))?;
+        let already_refunded = intent.refunded_total(&self.pool).await?;
+        let amount = match amount {
+            Some(amount) => Money::from_minor(amount, &intent.currency),
+            None => Money::from_minor(
+                intent.total().minor_units() - already_refunded.minor_units(),
+                &intent.currency,
+            ),
+        };
+        let refunded_total = Money::from_minor(
+            already_refunded.minor_units() + amount.minor_units(),
+            &intent.currency,
+        );
+
+        // Keyed on the resulting total, so a retried request can't refund twice.
+        let client = self.client.clone().with_strategy(RequestStrategy::Idempotent(format!(
+            "refund-{}-{}",
+            payment_intent_id,
+            refunded_total.minor_units()
+        )));
+        let mut create_refund = CreateRefund::new();
+        create_refund.payment_intent = Some(payment_intent_id.parse::<PaymentIntentId>()?);
+        create_refund.amount = Some(amount.minor_units());
+        Refund::create(&client, create_refund).await?;
+
+        // The charge.refunded webhook reports the same total and is ignored.
+        match DbPaymentIntent::record_refund(&self.pool, payment_intent_id, &refunded_total)
+            .await?
+        {
+            Some((intent, refunded)) => {
+                self.record_refund_history(&intent, &refunded).await?;
+                Ok(intent)
+            }
+            None => Ok(intent),
+        }
+    }
+
+    /// Refunds made in the Stripe dashboard only reach us through this event.
+    async fn handle_charge_refunded(&self, charge: &Charge) -> Result<()> {
+        let Some(payment_intent) = &charge.payment_intent else {
+            return Ok(());
+        };
+        let payment_intent_id = payment_intent.id().to_string();
+        let Some(intent) =
+            DbPaymentIntent::get_by_stripe_id(&self.pool, &payment_intent_id).await?
+        else {
+            tracing::warn!("refund for unknown payment intent {}", payment_intent_id);
+            return Ok(());
+        };
+
+        let refunded_total = Money::from_minor(charge.amount_refunded, &intent.currency);
+        if let Some((intent, refunded)) =
+            DbPaymentIntent::record_refund(&self.pool, &payment_intent_id, &refunded_total)
+                .await?
+        {
+            self.record_refund_history(&intent, &refunded).await?;
         }
 
         Ok(())
     }
 
-    async fn get_or_create_customer(&self, user_id: Uuid) -> Result<Customer> {
-        // Try to find existing customer by user ID metadata
-        let mut customers = Customer::list(
-            &self.client,
-            &stripe::ListCustomers {
-                metadata: Some(vec![("user_id", user_id.to_string())]),
-                ..Default::default()
-            },
+    async fn record_refund_history(&self, intent: &DbPaymentIntent, refunded: &Money) -> Result<()> {
+        self.payment_events
+            .publish(&intent.stripe_payment_intent_id, &intent.status);
+        PaymentHistory::create(
+            &self.pool,
+            intent.user_id,
+            intent.subscription_id,
+            intent.id,
+            refunded,
+            "refunded",
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn handle_dispute_created(&self, dispute: &Dispute) -> Result<()> {
+        // Disputes reference the charge; the intent ties it back to a user.
+        let db_payment_intent = match &dispute.payment_intent {
+            Some(payment_intent) => {
+                DbPaymentIntent::get_by_stripe_id(&self.pool, payment_intent.id().as_str())
+                    .await?
+            }
+            None => None,
+        };
+
+        PaymentDispute::record(
+            &self.pool,
+            dispute.id.as_str(),
+            dispute.charge.id().as_str(),
+            db_payment_intent.as_ref().map(|intent| intent.id),
+            db_payment_intent.as_ref().map(|intent| intent.user_id),
+            dispute.amount,
+            &dispute.currency.to_string().to_ascii_uppercase(),
+            &dispute.reason,
+            dispute.status.as_str(),
         )
         .await?;
 
-        if let Some(customer) = customers.data.pop() {
-            Ok(customer)
-        } else {
-            // Create new customer
+        match db_payment_intent {
+            Some(intent) => {
+                tracing::warn!(
+                    "charge disputed for user {} ({}); flagging subscription",
+                    intent.user_id,
+                    dispute.id
+                );
+                UserSubscription::flag_disputed(
+                    &self.pool,
+                    intent.user_id,
+                    intent.subscription_id,
+                )
+                .await?;
+                self.tier_cache.invalidate(intent.user_id);
+            }
+            None => {
+                tracing::warn!("dispute {} doesn't match a known payment", dispute.id);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_dispute_closed(&self, dispute: &Dispute) -> Result<()> {
+        let Some(recorded) =
+            PaymentDispute::close(&self.pool, dispute.id.as_str(), dispute.status.as_str())
+                .await?
+        else {
+            tracing::warn!("closed dispute {} was never recorded", dispute.id);
+            return Ok(());
+        };
+
+        let Some(payment_intent_id) = recorded.payment_intent_id else {
+            return Ok(());
+        };
+        let Some(intent) = DbPaymentIntent::get_by_id(&self.pool, payment_intent_id).await?
+        else {
+            return Ok(());
+        };
+
+        // Inquiries that close without becoming a chargeback leave the
+        // payment in place, same as a won dispute.
+        let won = matches!(dispute.status, DisputeStatus::Won | DisputeStatus::WarningClosed);
+        tracing::info!(
+            "dispute {} closed as {} for user {}",
+            dispute.id,
+            dispute.status.as_str(),
+            intent.user_id
+        );
+        UserSubscription::resolve_dispute(
+            &self.pool,
+            intent.user_id,
+            intent.subscription_id,
+            won,
+        )
+        .await?;
+        self.tier_cache.invalidate(intent.user_id);
+
+        Ok(())
+    }
+
+    async fn create_stripe_intent(
+        &self,
+        customer_id: CustomerId,
+        amount: i64,
+        currency: Currency,
+        idempotency_key: &str,
+    ) -> Result<PaymentIntent, StripeError> {
+        let client = self
+            .client
+            .clone()
+            .with_strategy(RequestStrategy::Idempotent(idempotency_key.to_string()));
+
+        // Stripe amounts are in the currency's minor unit
+        let mut create_intent = CreatePaymentIntent::new(amount, currency);
+        create_intent.customer = Some(customer_id);
+        create_intent.setup_future_usage =
+            Some(stripe::PaymentIntentSetupFutureUsage::OffSession);
+
+        PaymentIntent::create(&client, create_intent).await
+    }
+
+    /// The user's customer from `stripe_customers`, creating it on first
+    /// use. Users from before that table are found by metadata search once
+    /// and then recorded.
+    async fn get_or_create_customer(&self, user_id: Uuid) -> Result<CustomerId> {
+        if let Some(customer_id) = StripeCustomer::find(&self.pool, user_id).await? {
+            return Ok(customer_id.parse()?);
+        }
+
+        let customer_id = match self.search_customers(user_id).await?.into_iter().next() {
+            Some(customer) => customer.id,
+            // Concurrent first charges send the same key, so Stripe
+            // creates one customer and returns it to all of them.
+            None => self.create_customer(user_id, &format!("customer-{}", user_id)).await?,
+        };
+
+        // Should two different customers still race in, the unique
+        // constraint keeps the first and the other is left for
+        // `reconcile_customers`.
+        let recorded = StripeCustomer::record(&self.pool, user_id, customer_id.as_str()).await?;
+        if recorded != customer_id.as_str() {
+            tracing::warn!(
+                "user {} has duplicate stripe customer {}; using {}",
+                user_id,
+                customer_id,
+                recorded
+            );
+        }
+        Ok(recorded.parse()?)
+    }
+
+    /// Create a new customer for a user whose recorded one no longer
+    /// exists in Stripe.
+    async fn replace_customer(&self, user_id: Uuid) -> Result<CustomerId> {
+        let customer_id = self.create_customer(user_id, &Uuid::new_v4().to_string()).await?;
+        StripeCustomer::replace(&self.pool, user_id, customer_id.as_str()).await?;
+        Ok(customer_id)
+    }
+
+    /// Create a Stripe customer tagged with the user's id. The idempotency
+    /// key makes retries safe: Stripe returns the customer from the first
+    /// attempt instead of creating a second one.
+    async fn create_customer(&self, user_id: Uuid, idempotency_key: &str) -> Result<CustomerId> {
+        let client = self
+            .client
+            .clone()
+            .with_strategy(RequestStrategy::Idempotent(idempotency_key.to_string()));
+
+        let customer = retry("customer creation", || {
             let mut create_customer = stripe::CreateCustomer::new();
-            create_customer.metadata = Some(vec![("user_id", user_id.to_string())].into_iter().collect());
-            
-            Ok(Customer::create(&self.client, create_customer).await?)
+            create_customer.metadata =
+                Some([("user_id".to_string(), user_id.to_string())].into_iter().collect());
+            Customer::create(&client, create_customer)
+        })
+        .await?;
+        Ok(customer.id)
+    }
+
+    /// Every live customer tagged with the user's id, oldest first.
+    async fn search_customers(&self, user_id: Uuid) -> Result<Vec<Customer>> {
+        let query = format!("metadata['user_id']:'{}'", user_id);
+        let customers = retry("customer search", || {
+            Customer::search(
+                &self.client,
+                CustomerSearchParams {
+                    query: query.clone(),
+                    limit: Some(100),
+                    ..Default::default()
+                },
+            )
+        })
+        .await?;
+        let mut customers: Vec<Customer> =
+            customers.data.into_iter().filter(|customer| !customer.deleted).collect();
+        customers.sort_by_key(|customer| customer.created);
+        Ok(customers)
+    }
+
+    /// Settle on one Stripe customer for the user: the recorded one, or
+    /// else the oldest. Other customers tagged with the user are deleted
+    /// if they hold nothing; any with payment methods or subscriptions are
+    /// reported instead, since neither can be moved between customers.
+    pub async fn reconcile_customers(&self, user_id: Uuid) -> Result<CustomerReconciliation> {
+        let customers = self.search_customers(user_id).await?;
+        let recorded = StripeCustomer::find(&self.pool, user_id).await?;
+        let canonical = match recorded {
+            Some(recorded) => Some(recorded),
+            None => match customers.first() {
+                Some(oldest) => Some(
+                    StripeCustomer::record(&self.pool, user_id, oldest.id.as_str()).await?,
+                ),
+                None => None,
+            },
+        };
+
+        let mut deleted = Vec::new();
+        let mut kept = Vec::new();
+        for customer in customers {
+            if Some(customer.id.as_str()) == canonical.as_deref() {
+                continue;
+            }
+            if self.customer_in_use(&customer.id).await? {
+                kept.push(customer.id.to_string());
+            } else {
+                retry("customer deletion", || Customer::delete(&self.client, &customer.id)).await?;
+                deleted.push(customer.id.to_string());
+            }
         }
+
+        if !deleted.is_empty() || !kept.is_empty() {
+            tracing::info!(
+                "reconciled stripe customers for user {}: deleted {:?}, kept {:?}",
+                user_id,
+                deleted,
+                kept
+            );
+        }
+        Ok(CustomerReconciliation {
+            customer_id: canonical,
+            deleted,
+            kept,
+        })
     }
 
+    /// Whether the customer has payment methods or subscriptions.
+    async fn customer_in_use(&self, customer_id: &CustomerId) -> Result<bool> {
+        let mut methods = stripe::ListPaymentMethods::new();
+        methods.customer = Some(customer_id.clone());
+        methods.limit = Some(1);
+        if !retry("payment method list", || PaymentMethod::list(&self.client, &methods))
+            .await?
+            .data
+            .is_empty()
+        {
+            return Ok(true);
+        }
+
+        let mut subscriptions = stripe::ListSubscriptions::new();
+        subscriptions.customer = Some(customer_id.clone());
+        subscriptions.status = Some(stripe::SubscriptionStatusFilter::All);
+        subscriptions.limit = Some(1);
+        let subscriptions =
+            retry("subscription list", || stripe::Subscription::list(&self.client, &subscriptions))
+                .await?;
+        Ok(!subscriptions.data.is_empty())
+    }
+
+    /// Record a payment method the client attached to the user's customer.
+    /// If the user already has the same card under another token, the new
+    /// token is detached and the stored method returned instead; the flag
+    /// is `false` in that case.
     pub async fn attach_payment_method(
         &self,
         user_id: Uuid,
         payment_method_id: &str,
-    ) -> Result<CardDetails> {
-        let payment_method = PaymentMethod::retrieve(&self.client, payment_method_id).await?;
-        
-        if let Some(PaymentMethodCard {
+    ) -> Result<(DbPaymentMethod, bool)> {
+        let id: PaymentMethodId = payment_method_id.parse()?;
+        let payment_method = retry("payment method retrieval", || {
+            PaymentMethod::retrieve(&self.client, &id, &[])
+        })
+        .await?;
+
+        let Some(stripe::CardDetails {
             brand,
             last4,
             exp_month,
             exp_year,
+            fingerprint,
             ..
         }) = payment_method.card
+        else {
+            anyhow::bail!("Invalid payment method type")
+        };
+        let card_details = CardDetails {
+            brand,
+            last4,
+            exp_month: exp_month as i32,
+            exp_year: exp_year as i32,
+            fingerprint,
+        };
+
+        let fingerprint = card_details.fingerprint.clone();
+        if let Some(created) = DbPaymentMethod::create(
+            &self.pool,
+            user_id,
+            payment_method_id.to_string(),
+            Some(card_details),
+        )
+        .await?
         {
-            let card_details = CardDetails {
-                brand: brand.to_string(),
-                last4,
-                exp_month,
-                exp_year,
+            return Ok((created, true));
+        }
+
+        let existing = DbPaymentMethod::find_duplicate(
+            &self.pool,
+            user_id,
+            payment_method_id,
+            fingerprint.as_deref(),
+        )
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Payment method belongs to another user"))?;
+
+        if existing.stripe_payment_method_id != payment_method_id {
+            tracing::info!(
+                "payment method {} duplicates stored card {}; detaching it",
+                payment_method_id,
+                existing.id
+            );
+            if let Err(e) = PaymentMethod::detach(&self.client, &id).await {
+                tracing::warn!("failed to detach duplicate payment method {}: {}", payment_method_id, e);
+            }
+        }
+
+        Ok((existing, false))
+    }
+
+    /// Cancel lapsed pending intents in Stripe and mark them expired locally.
+    /// Returns the number expired.
+    pub async fn expire_lapsed_intents(&self) -> Result<u64> {
+        let mut expired = 0;
+
+        for stripe_id in DbPaymentIntent::list_lapsed(&self.pool).await? {
+            match self.expire_intent(&stripe_id).await {
+                Ok(true) => expired += 1,
+                Ok(false) => {}
+                // Leave it pending so the next pass retries the cancellation.
+                Err(e) => tracing::warn!("failed to expire payment intent {}: {}", stripe_id, e),
+            }
+        }
+
+        Ok(expired)
+    }
+
+    /// Cancel a pending intent in Stripe, then mark it expired locally.
+    async fn expire_intent(&self, stripe_id: &str) -> Result<bool> {
+        let id: stripe::PaymentIntentId = stripe_id.parse()?;
+        let cancel = stripe::CancelPaymentIntent {
+            cancellation_reason: Some(stripe::PaymentIntentCancellationReason::Abandoned),
+        };
+        PaymentIntent::cancel(&self.client, &id, cancel).await?;
+
+        let expired = DbPaymentIntent::mark_expired(&self.pool, stripe_id).await?;
+        if expired {
+            self.payment_events.publish(stripe_id, "expired");
+        }
+        Ok(expired)
+    }
+
+    /// Detach a payment method from the user's Stripe customer and remove it
+    /// locally. Returns `None` if the user doesn't own the payment method.
+    /// Stripe goes first, so a failed detach leaves the card listed rather
+    /// than chargeable but hidden.
+    pub async fn detach_payment_method(
+        &self,
+        user_id: Uuid,
+        payment_method_id: Uuid,
+    ) -> Result<Option<DbPaymentMethod>> {
+        let Some(method) =
+            DbPaymentMethod::get_for_user(&self.pool, user_id, payment_method_id).await?
+        else {
+            return Ok(None);
+        };
+
+        let id: PaymentMethodId = method.stripe_payment_method_id.parse()?;
+        match retry("payment method detach", || PaymentMethod::detach(&self.client, &id)).await {
+            Ok(_) => {}
+            Err(StripeError::Stripe(e)) if e.code == Some(ErrorCode::ResourceMissing) => {
+                tracing::info!("payment method {} already gone from Stripe", method.id);
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        Ok(DbPaymentMethod::detach(&self.pool, user_id, payment_method_id).await?)
+    }
+
+    /// Detach payment methods from their Stripe customer, e.g. those an
+    /// account deletion removed. Methods Stripe no longer knows about are
+    /// skipped. Returns the number detached.
+    pub async fn detach_payment_methods(&self, stripe_ids: &[String]) -> Result<u64> {
+        let mut detached = 0;
+
+        for stripe_id in stripe_ids {
+            let id: PaymentMethodId = stripe_id.parse()?;
+            match retry("payment method detach", || PaymentMethod::detach(&self.client, &id)).await {
+                Ok(_) => detached += 1,
+                Err(StripeError::Stripe(e)) if e.code == Some(ErrorCode::ResourceMissing) => {
+                    tracing::info!("payment method {} already gone from Stripe", stripe_id);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(detached)
+    }
+
+    /// Cancel Stripe subscriptions immediately, skipping any Stripe no
+    /// longer has.
+    pub async fn cancel_stripe_subscriptions(&self, stripe_ids: &[String]) -> Result<u64> {
+        let mut canceled = 0;
+
+        for stripe_id in stripe_ids {
+            let id: stripe::SubscriptionId = stripe_id.parse()?;
+            let cancel = || {
+                stripe::Subscription::cancel(&self.client, &id, stripe::CancelSubscription::new())
+            };
+            match retry("subscription cancel", cancel).await {
+                Ok(_) => canceled += 1,
+                Err(StripeError::Stripe(e)) if e.code == Some(ErrorCode::ResourceMissing) => {
+                    tracing::info!("stripe subscription {} already gone", stripe_id);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(canceled)
+    }
+
+    /// Move a Stripe-billed subscription onto `to`'s price for `interval`,
+    /// invoicing the prorated difference straight away. Plans aren't kept
+    /// as Stripe prices, so the new price is given inline, on the product
+    /// the subscription is already for.
+    pub async fn switch_subscription_plan(
+        &self,
+        stripe_id: &str,
+        to: &Subscription,
+        interval: BillingInterval,
+    ) -> Result<()> {
+        let id: SubscriptionId = stripe_id.parse()?;
+        let subscription = retry("subscription retrieve", || {
+            stripe::Subscription::retrieve(&self.client, &id, &[])
+        })
+        .await?;
+        let item = subscription
+            .items
+            .data
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("Stripe subscription {} has no items", stripe_id))?;
+        let product = item
+            .price
+            .as_ref()
+            .and_then(|price| price.product.as_ref())
+            .map(|product| product.id().to_string())
+            .ok_or_else(|| anyhow::anyhow!("Stripe subscription {} has no product", stripe_id))?;
+
+        let price = to.price(interval);
+        let params = UpdateSubscription {
+            items: Some(vec![UpdateSubscriptionItems {
+                id: Some(item.id.to_string()),
+                price_data: Some(SubscriptionItemPriceData {
+                    currency: price.currency().to_ascii_lowercase().parse()?,
+                    product,
+                    recurring: SubscriptionItemPriceDataRecurring {
+                        interval: match interval {
+                            BillingInterval::Monthly => PlanInterval::Month,
+                            BillingInterval::Yearly => PlanInterval::Year,
+                        },
+                        interval_count: None,
+                    },
+                    tax_behavior: None,
+                    unit_amount: Some(price.minor_units()),
+                    unit_amount_decimal: None,
+                }),
+                ..Default::default()
+            }]),
+            proration_behavior: Some(SubscriptionProrationBehavior::AlwaysInvoice),
+            ..UpdateSubscription::new()
+        };
+        // Not retried: each attempt would invoice the proration again.
+        stripe::Subscription::update(&self.client, &id, params).await?;
+
+        Ok(())
+    }
+
+    /// Cancel and detach what a closed account left behind in Stripe,
+    /// clearing each from the pending list once Stripe confirms. A failure
+    /// is logged and left pending for the next attempt. Returns the number
+    /// of subscriptions canceled and payment methods detached.
+    pub async fn finish_account_cleanup(&self, pending: &PendingStripeCleanup) -> Result<(u64, u64)> {
+        let mut canceled = 0;
+        match self.cancel_stripe_subscriptions(&pending.subscription_ids).await {
+            Ok(count) => {
+                canceled = count;
+                PendingStripeCleanup::resolve(&self.pool, &pending.subscription_ids).await?;
+            }
+            Err(e) => tracing::error!(
+                "failed to cancel Stripe subscriptions {:?} of closed account {}: {}",
+                pending.subscription_ids,
+                pending.user_id,
+                e
+            ),
+        }
+
+        let mut detached = 0;
+        match self.detach_payment_methods(&pending.payment_method_ids).await {
+            Ok(count) => {
+                detached = count;
+                PendingStripeCleanup::resolve(&self.pool, &pending.payment_method_ids).await?;
+            }
+            Err(e) => tracing::error!(
+                "failed to detach Stripe payment methods {:?} of closed account {}: {}",
+                pending.payment_method_ids,
+                pending.user_id,
+                e
+            ),
+        }
+
+        Ok((canceled, detached))
+    }
+
+    /// Retry the Stripe cleanup of every closed account that still has some
+    /// pending. Returns the number of accounts attempted.
+    pub async fn finish_pending_account_cleanups(&self) -> Result<usize> {
+        let pending = PendingStripeCleanup::pending(&self.pool, None).await?;
+        for cleanup in &pending {
+            self.finish_account_cleanup(cleanup).await?;
+        }
+        Ok(pending.len())
+    }
+
+    /// A cheap authenticated call, to check Stripe is reachable and our key
+    /// is accepted.
+    pub async fn ping(&self) -> Result<()> {
+        let params = ListCustomers {
+            limit: Some(1),
+            ..Default::default()
+        };
+        Customer::list(&self.client, &params).await?;
+        Ok(())
+    }
+}
+
+/// Share of the tolerance past which an accepted event's age is logged.
+const TOLERANCE_WARNING_FRACTION: f64 = 0.8;
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebhookError {
+    #[error("malformed Stripe-Signature header")]
+    MalformedHeader,
+    #[error("no matching signature")]
+    BadSignature,
+    #[error("signature timestamp is outside the {0}s tolerance")]
+    OutsideTolerance(u64),
+    #[error("invalid event payload: {0}")]
+    Payload(String),
+}
+
+/// Verify a `Stripe-Signature` header (`t=<unix time>,v1=<hex hmac>,...`)
+/// against `payload`, as Stripe documents it. Returns how long ago the
+/// event was signed. Timestamps in the future count against the tolerance
+/// too, to allow for clock skew either way.
+fn verify_signature(
+    payload: &str,
+    header: &str,
+    secret: &str,
+    tolerance: Duration,
+    now: i64,
+) -> std::result::Result<Duration, WebhookError> {
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+    for part in header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", value)) => timestamp = value.parse::<i64>().ok(),
+            Some(("v1", value)) => signatures.push(value),
+            _ => {}
+        }
+    }
+    let timestamp = timestamp.ok_or(WebhookError::MalformedHeader)?;
+    if signatures.is_empty() {
+        return Err(WebhookError::MalformedHeader);
+    }
+
+    let signed_payload = format!("{}.{}", timestamp, payload);
+    let matches = signatures.iter().any(|signature| {
+        let Ok(expected) = hex::decode(signature) else {
+            return false;
+        };
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(signed_payload.as_bytes());
+        mac.verify_slice(&expected).is_ok()
+    });
+    if !matches {
+        return Err(WebhookError::BadSignature);
+    }
+
+    let age = now.abs_diff(timestamp);
+    if age > tolerance.as_secs() {
+        return Err(WebhookError::OutsideTolerance(tolerance.as_secs()));
+    }
+    Ok(Duration::from_secs(age))
+}
+
+/// Whether Stripe rejected a request because the referenced customer is gone.
+fn is_missing_customer(error: &StripeError) -> bool {
+    match error {
+        StripeError::Stripe(request_error) => {
+            request_error.code == Some(ErrorCode::ResourceMissing)
+                && request_error
+                    .message
+                    .as_deref()
+                    .map(|message| message.contains("No such customer"))
+                    .unwrap_or(false)
+        }
+        _ => false,
+    }
+}
+
+/// A card error from Stripe as a `402` the client can act on. Stripe's
+/// `decline_code` is more specific than its `code` (`insufficient_funds`
+/// rather than `card_declined`), so it wins when present.
+pub fn card_decline(error: &StripeError) -> Option<AppError> {
+    let StripeError::Stripe(request_error) = error else {
+        return None;
+    };
+    if !matches!(request_error.error_type, ErrorType::Card) {
+        return None;
+    }
+
+    let code = match (request_error.decline_code.as_deref(), &request_error.code) {
+        (Some(decline_code), _) => decline_code.to_string(),
+        (None, Some(ErrorCode::ExpiredCard)) => "expired_card".to_string(),
+        (None, _) => "card_declined".to_string(),
+    };
+    let message = match code.as_str() {
+        "insufficient_funds" => "Your card has insufficient funds".to_string(),
+        "expired_card" => "Your card has expired".to_string(),
+        "card_declined" | "generic_decline" => "Your card was declined".to_string(),
+        _ => request_error
+            .message
+            .clone()
+            .unwrap_or_else(|| "Your card was declined".to_string()),
+    };
+    Some(AppError::PaymentFailed { code, message })
+}
+
+const RETRY_ATTEMPTS: u32 = 3;
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(200);
+
+/// Network failures, rate limiting and Stripe-side errors are worth retrying;
+/// anything else (declines, validation errors) will fail the same way again.
+fn is_retryable(error: &StripeError) -> bool {
+    match error {
+        StripeError::Stripe(request_error) => {
+            request_error.http_status == 429 || request_error.http_status >= 500
+        }
+        StripeError::ClientError(_) | StripeError::Timeout => true,
+        _ => false,
+    }
+}
+
+/// Run an idempotent Stripe call, retrying retryable failures with
+/// exponential backoff and jitter.
+async fn retry<T, F, Fut>(operation: &str, mut call: F) -> Result<T, StripeError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, StripeError>>,
+{
+    let mut attempt = 1;
+
+    loop {
+        match call().await {
+            Err(e) if attempt < RETRY_ATTEMPTS && is_retryable(&e) => {
+                let backoff = RETRY_BASE_DELAY * 2u32.pow(attempt - 1);
+                let jitter = rand::thread_rng().gen_range(0..=backoff.as_millis() as u64 / 2);
+                let delay = backoff + Duration::from_millis(jitter);
+
+                tracing::warn!(
+                    "stripe {} failed (attempt {}/{}), retrying in {:?}: {}",
+                    operation,
+                    attempt,
+                    RETRY_ATTEMPTS,
+                    delay,
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::StatusCode, response::IntoResponse};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use stripe::RequestError;
+
+    fn card_error(code: Option<ErrorCode>, decline_code: Option<&str>) -> StripeError {
+        StripeError::Stripe(RequestError {
+            http_status: 402,
+            error_type: ErrorType::Card,
+            message: Some("Declined by the issuer".to_string()),
+            code,
+            decline_code: decline_code.map(str::to_string),
+            ..Default::default()
+        })
+    }
+
+    fn decline_code(error: &StripeError) -> Option<String> {
+        match card_decline(error)? {
+            AppError::PaymentFailed { code, .. } => Some(code),
+            other => panic!("expected PaymentFailed, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decline_code_wins_over_the_error_code() {
+        let error = card_error(Some(ErrorCode::CardDeclined), Some("insufficient_funds"));
+        assert_eq!(decline_code(&error).as_deref(), Some("insufficient_funds"));
+        assert_eq!(
+            decline_code(&card_error(Some(ErrorCode::ExpiredCard), None)).as_deref(),
+            Some("expired_card")
+        );
+        assert_eq!(decline_code(&card_error(None, None)).as_deref(), Some("card_declined"));
+    }
+
+    #[test]
+    fn only_card_errors_are_declines() {
+        let error = StripeError::Stripe(RequestError {
+            http_status: 400,
+            error_type: ErrorType::InvalidRequest,
+            ..Default::default()
+        });
+        assert!(card_decline(&error).is_none());
+        assert!(card_decline(&StripeError::Timeout).is_none());
+    }
+
+    #[tokio::test]
+    async fn a_decline_is_answered_with_402_and_its_code() {
+        let error = card_decline(&card_error(None, Some("insufficient_funds"))).unwrap();
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::PAYMENT_REQUIRED);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "insufficient_funds");
+        assert_eq!(body["error"], "Your card has insufficient funds");
+    }
+
+    #[sqlx::test]
+    async fn a_customer_deleted_in_stripe_is_recreated_and_charged(pool: sqlx::PgPool) {
+        use crate::test_support::{app_state_with_stripe, create_user, StripeStub, DELETED_CUSTOMER};
+
+        let user_id = create_user(&pool).await;
+        StripeCustomer::record(&pool, user_id, DELETED_CUSTOMER).await.unwrap();
+        let stub = StripeStub::start().await;
+        let state = app_state_with_stripe(&pool, Some(&stub.url)).await;
+
+        let intent = state
+            .stripe_service
+            .create_intent_for_user(user_id, &Money::from_minor(1999, "USD"), "pi-test")
+            .await
+            .unwrap();
+
+        assert_eq!(intent.amount, 1999);
+        assert_eq!(
+            stub.requests(),
+            ["POST /v1/payment_intents", "POST /v1/customers", "POST /v1/payment_intents"]
+        );
+        let recreated = StripeCustomer::find(&pool, user_id).await.unwrap().unwrap();
+        assert_ne!(recreated, DELETED_CUSTOMER);
+        let charged = stub.form("POST /v1/payment_intents").unwrap();
+        assert!(charged.contains(&("customer".to_string(), recreated)));
+    }
+
+    fn failing_with(http_status: u16) -> StripeError {
+        StripeError::Stripe(RequestError {
+            http_status,
+            error_type: ErrorType::Api,
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried_until_the_call_succeeds() {
+        let calls = AtomicU32::new(0);
+        let result = retry("test call", || {
+            let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+            async move {
+                match call {
+                    1 => Err(failing_with(503)),
+                    2 => Err(failing_with(429)),
+                    _ => Ok(call),
+                }
+            }
+        })
+        .await;
+
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn a_decline_is_not_retried() {
+        let calls = AtomicU32::new(0);
+        let result = retry("test call", || {
+            calls.fetch_add(1, Ordering::SeqCst);
+            async { Err::<(), _>(card_error(Some(ErrorCode::CardDeclined), None)) }
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(calls.into_inner(), 1);
+    }
+
+    #[sqlx::test]
+    async fn concurrent_requests_share_one_pending_intent(pool: sqlx::PgPool) {
+        use crate::models::SubscriptionTier;
+        use crate::test_support::{app_state_with_stripe, create_user, plan, StripeStub};
+
+        let user_id = create_user(&pool).await;
+        StripeCustomer::record(&pool, user_id, "cus_test").await.unwrap();
+        let pro = plan(&pool, SubscriptionTier::Pro).await;
+        let stub = StripeStub::start().await;
+        let state = app_state_with_stripe(&pool, Some(&stub.url)).await;
+        let total = Money::from_minor(1999, "USD");
+        let tax = Money::from_minor(0, "USD");
+        let create = || state.stripe_service.create_payment_intent(user_id, &pro, &total, &tax, None);
+
+        let (first, second) = tokio::join!(create(), create());
+        let (first, second) = (first.unwrap(), second.unwrap());
+
+        assert_eq!(first.0.id, second.0.id);
+        assert_eq!([first.1, second.1].iter().filter(|created| **created).count(), 1);
+        let pending: i64 = sqlx::query_scalar!(
+            "SELECT COUNT(*) AS \"count!\" FROM payment_intents WHERE user_id = $1 AND status = 'pending'",
+            user_id
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(pending, 1);
+    }
+
+    #[sqlx::test]
+    async fn a_dispute_suspends_the_subscription_until_it_closes(pool: sqlx::PgPool) {
+        use crate::models::SubscriptionTier;
+        use crate::test_support::{app_state, create_user, stripe_dispute, stripe_event, subscribe};
+
+        let state = app_state(&pool).await;
+        let stripe = &state.stripe_service;
+        let mut customers = Vec::new();
+        for outcome in ["won", "lost"] {
+            let user_id = create_user(&pool).await;
+            let subscription = subscribe(&pool, user_id, SubscriptionTier::Pro).await;
+            DbPaymentIntent::create(
+                &pool,
+                user_id,
+                subscription.subscription_id,
+                format!("pi_{}", outcome),
+                &Money::from_minor(1999, "USD"),
+                &Money::from_minor(0, "USD"),
+                String::new(),
+                None,
+            )
+            .await
+            .unwrap();
+            customers.push((outcome, user_id, subscription.id));
+        }
+        let dispute = |outcome: &str, status: &str| {
+            stripe_dispute(&format!("dp_{}", outcome), &format!("pi_{}", outcome), status)
+        };
+        let state_of = |&(_, user_id, id): &(&str, Uuid, Uuid)| {
+            let pool = pool.clone();
+            async move {
+                let subscriptions = UserSubscription::list_for_user(&pool, user_id).await.unwrap();
+                let disputed = subscriptions.into_iter().find(|s| s.id == id).unwrap();
+                let tier = UserSubscription::tier_for_user(&pool, user_id).await.unwrap();
+                (disputed.payment_status.unwrap(), disputed.is_active, tier)
+            }
+        };
+
+        for customer in &customers {
+            let created = dispute(customer.0, "needs_response");
+            stripe.handle_webhook(stripe_event("charge.dispute.created", created)).await.unwrap();
+            assert_eq!(
+                state_of(customer).await,
+                ("disputed".to_string(), true, SubscriptionTier::Free)
+            );
+        }
+
+        for (outcome, _, _) in &customers {
+            let closed = dispute(outcome, outcome);
+            stripe.handle_webhook(stripe_event("charge.dispute.closed", closed)).await.unwrap();
+        }
+        let (won, lost) = (&customers[0], &customers[1]);
+        assert_eq!(state_of(won).await, ("paid".to_string(), true, SubscriptionTier::Pro));
+        assert_eq!(state_of(lost).await, ("chargeback".to_string(), false, SubscriptionTier::Free));
+        let recorded = sqlx::query!(
+            r#"SELECT stripe_dispute_id, status, closed_at IS NOT NULL AS "closed!" FROM payment_disputes ORDER BY stripe_dispute_id"#
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        let recorded: Vec<_> = recorded
+            .into_iter()
+            .map(|row| (row.stripe_dispute_id, row.status, row.closed))
+            .collect();
+        assert_eq!(
+            recorded,
+            [
+                ("dp_lost".to_string(), "lost".to_string(), true),
+                ("dp_won".to_string(), "won".to_string(), true),
+            ]
+        );
+    }
+
+    #[sqlx::test]
+    async fn a_service_on_a_test_pool_stores_attached_cards_there(pool: sqlx::PgPool) {
+        use crate::test_support::{config, create_user, StripeStub};
+
+        let user_id = create_user(&pool).await;
+        let stub = StripeStub::start().await;
+        let service = StripeService::new(
+            &config(),
+            pool.clone(),
+            Mailer::from_env(pool.clone()),
+            Default::default(),
+            Default::default(),
+        )
+        .with_api_base(&stub.url);
+
+        let (method, created) = service.attach_payment_method(user_id, "pm_card").await.unwrap();
+        assert!(created);
+        assert_eq!(method.user_id, user_id);
+        assert_eq!(method.card_last4.as_deref(), Some("4242"));
+        assert_eq!(stub.requests(), ["GET /v1/payment_methods/pm_card"]);
+
+        let (again, created) = service.attach_payment_method(user_id, "pm_card").await.unwrap();
+        assert!(!created);
+        assert_eq!(again.id, method.id);
+        let stored = DbPaymentMethod::list_for_user(&pool, user_id).await.unwrap();
+        assert_eq!(stored.len(), 1);
+    }
+
+    /// A `Stripe-Signature` header for `payload`, signed at `timestamp`.
+    fn stripe_signature(payload: &str, secret: &str, timestamp: i64) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(format!("{}.{}", timestamp, payload).as_bytes());
+        format!("t={},v1={}", timestamp, hex::encode(mac.finalize().into_bytes()))
+    }
+
+    #[test]
+    fn a_widened_tolerance_admits_delayed_events_but_not_stale_ones() {
+        let payload = r#"{"id":"evt_1"}"#;
+        let now = 1_700_000_000;
+        let widened = Duration::from_secs(600);
+        let verify = |signed_at: i64, tolerance: Duration| {
+            let header = stripe_signature(payload, "whsec_test", signed_at);
+            verify_signature(payload, &header, "whsec_test", tolerance, now)
+        };
+
+        assert_eq!(verify(now - 590, widened).unwrap(), Duration::from_secs(590));
+        assert!(matches!(
+            verify(now - 590, Duration::from_secs(300)),
+            Err(WebhookError::OutsideTolerance(300))
+        ));
+        assert!(matches!(verify(now - 3600, widened), Err(WebhookError::OutsideTolerance(600))));
+        // Skew the other way counts too.
+        assert!(matches!(verify(now + 3600, widened), Err(WebhookError::OutsideTolerance(600))));
+
+        let forged = stripe_signature(payload, "whsec_other", now);
+        assert!(matches!(
+            verify_signature(payload, &forged, "whsec_test", widened, now),
+            Err(WebhookError::BadSignature)
+        ));
+    }
+
+    #[sqlx::test]
+    async fn two_tokens_for_the_same_card_are_stored_once(pool: sqlx::PgPool) {
+        use crate::test_support::{app_state_with_stripe, create_user, StripeStub};
+
+        let user_id = create_user(&pool).await;
+        let stub = StripeStub::start().await;
+        let state = app_state_with_stripe(&pool, Some(&stub.url)).await;
+        let attach = |token| state.stripe_service.attach_payment_method(user_id, token);
+
+        let (first, created) = attach("pm_visa_1").await.unwrap();
+        assert!(created);
+        assert_eq!(first.card_fingerprint.as_deref(), Some("fp_visa"));
+
+        let (second, created) = attach("pm_visa_2").await.unwrap();
+        assert!(!created);
+        assert_eq!(second.id, first.id);
+        assert_eq!(second.stripe_payment_method_id, "pm_visa_1");
+        assert!(stub.requests().contains(&"POST /v1/payment_methods/pm_visa_2/detach".into()));
+        assert_eq!(DbPaymentMethod::list_for_user(&pool, user_id).await.unwrap().len(), 1);
+
+        // A different card is its own method.
+        let (other, created) = attach("pm_amex_1").await.unwrap();
+        assert!(created);
+        assert_ne!(other.id, first.id);
+    }
+
+    /// Records every email it's asked to send, then fails to deliver it.
+    #[derive(Default)]
+    struct UndeliverableSender {
+        sent: std::sync::Mutex<Vec<(String, EmailTemplate)>>,
+    }
+
+    #[axum::async_trait]
+    impl crate::email::EmailSender for UndeliverableSender {
+        async fn send(&self, to: &str, template: &EmailTemplate) -> Result<()> {
+            self.sent.lock().unwrap().push((to.to_string(), template.clone()));
+            anyhow::bail!("relay unreachable")
+        }
+    }
+
+    #[sqlx::test]
+    async fn a_payment_emails_success_or_failure_even_when_delivery_fails(pool: sqlx::PgPool) {
+        use crate::models::SubscriptionTier;
+        use crate::test_support::{
+            config, create_user, plan, stripe_event, stripe_payment_intent, subscribe,
+        };
+
+        let sender = std::sync::Arc::new(UndeliverableSender::default());
+        let service = StripeService::new(
+            &config(),
+            pool.clone(),
+            Mailer::new(pool.clone(), sender.clone()),
+            Default::default(),
+            Default::default(),
+        );
+        let pro = plan(&pool, SubscriptionTier::Pro).await;
+        let mut emails = Vec::new();
+        for outcome in ["paid", "declined"] {
+            let user_id = create_user(&pool).await;
+            subscribe(&pool, user_id, SubscriptionTier::Pro).await;
+            DbPaymentIntent::create(
+                &pool,
+                user_id,
+                pro.id,
+                format!("pi_{}", outcome),
+                &Money::from_minor(1999, "USD"),
+                &Money::from_minor(0, "USD"),
+                String::new(),
+                None,
+            )
+            .await
+            .unwrap();
+            let email = sqlx::query_scalar!("SELECT email FROM users WHERE id = $1", user_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            emails.push(email);
+        }
+
+        let paid = stripe_payment_intent("pi_paid", 1999, "usd", "succeeded");
+        let declined = stripe_payment_intent("pi_declined", 1999, "usd", "requires_payment_method");
+        let events = [
+            stripe_event("payment_intent.succeeded", paid),
+            stripe_event("payment_intent.payment_failed", declined),
+        ];
+        for event in events {
+            service.handle_webhook(event).await.unwrap();
+        }
+        for _ in 0..100 {
+            if sender.sent.lock().unwrap().len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let mut sent = sender.sent.lock().unwrap().clone();
+        sent.sort_by_key(|(to, _)| emails.iter().position(|email| email == to));
+        let amount = Money::from_minor(1999, "USD");
+        assert!(matches!(
+            &sent[..],
+            [
+                (_, EmailTemplate::PaymentSucceeded { amount: paid, .. }),
+                (_, EmailTemplate::PaymentFailed { amount: declined }),
+            ] if *paid == amount && *declined == amount
+        ));
+        assert_eq!([&sent[0].0, &sent[1].0], [&emails[0], &emails[1]]);
+        let statuses = sqlx::query_scalar!(
+            "SELECT status FROM payment_intents ORDER BY stripe_payment_intent_id DESC"
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(statuses, ["succeeded", "failed"]);
+    }
+
+    #[sqlx::test]
+    async fn stripe_deleting_a_subscription_ends_ours_and_past_due_flags_it(pool: sqlx::PgPool) {
+        use crate::models::SubscriptionTier;
+        use crate::test_support::{
+            app_state, create_user, stripe_event, stripe_subscription, subscribe,
+        };
+
+        let state = app_state(&pool).await;
+        let mut linked = Vec::new();
+        for stripe_id in ["sub_deleted", "sub_past_due", "sub_renewed"] {
+            let user_id = create_user(&pool).await;
+            let subscription = subscribe(&pool, user_id, SubscriptionTier::Pro).await;
+            sqlx::query!(
+                "UPDATE user_subscriptions SET stripe_subscription_id = $1 WHERE id = $2",
+                stripe_id,
+                subscription.id
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+            linked.push(user_id);
+        }
+
+        let events = [
+            ("customer.subscription.deleted", "sub_deleted", "canceled"),
+            ("customer.subscription.updated", "sub_past_due", "past_due"),
+            ("customer.subscription.updated", "sub_renewed", "active"),
+        ];
+        for (type_, id, status) in events {
+            let event = stripe_event(type_, stripe_subscription(id, status));
+            state.stripe_service.handle_webhook(event).await.unwrap();
+        }
+
+        let mut states = Vec::new();
+        for user_id in linked {
+            let history = UserSubscription::list_for_user(&pool, user_id).await.unwrap();
+            let [subscription] = &history[..] else {
+                panic!("expected one subscription, got {:?}", history);
             };
+            states.push((subscription.is_active, subscription.payment_status.clone()));
+            let tier = UserSubscription::tier_for_user(&pool, user_id).await.unwrap();
+            assert_eq!(tier == SubscriptionTier::Pro, subscription.is_active);
+        }
+        let status = |s: &str| Some(s.to_string());
+        assert_eq!(
+            states,
+            [
+                (false, status("canceled")),
+                (true, status("past_due")),
+                (true, status("paid")),
+            ]
+        );
+    }
+
+    #[sqlx::test]
+    async fn a_processing_payment_activates_only_once_it_settles(pool: sqlx::PgPool) {
+        use crate::models::SubscriptionTier;
+        use crate::test_support::{
+            app_state, create_user, plan, stripe_event, stripe_payment_intent,
+        };
 
-            // Save payment method to our database
-            crate::models::payment::PaymentMethod::create(
-                &crate::DB_POOL,
+        let state = app_state(&pool).await;
+        let stripe = &state.stripe_service;
+        let pro = plan(&pool, SubscriptionTier::Pro).await;
+        let mut checkouts = Vec::new();
+        for outcome in ["settled", "bounced"] {
+            let user_id = create_user(&pool).await;
+            let pending = UserSubscription::create(
+                &pool,
                 user_id,
-                payment_method_id.to_string(),
-                Some(card_details.clone()),
+                pro.id,
+                crate::models::BillingInterval::Monthly,
             )
-            .await?;
+            .await
+            .unwrap();
+            DbPaymentIntent::create(
+                &pool,
+                user_id,
+                pro.id,
+                format!("pi_{}", outcome),
+                &Money::from_minor(1999, "USD"),
+                &Money::from_minor(0, "USD"),
+                String::new(),
+                None,
+            )
+            .await
+            .unwrap();
+            checkouts.push((format!("pi_{}", outcome), user_id, pending.id));
+        }
+        let deliver = |type_: &str, id: &str, status: &str| {
+            let intent = stripe_payment_intent(id, 1999, "usd", status);
+            stripe.handle_webhook(stripe_event(type_, intent))
+        };
+        let state_of = |(id, user_id, subscription_id): &(String, Uuid, Uuid)| {
+            let pool = pool.clone();
+            let (id, user_id, subscription_id) = (id.clone(), *user_id, *subscription_id);
+            async move {
+                let intent = DbPaymentIntent::get_by_stripe_id(&pool, &id).await.unwrap().unwrap();
+                let subscription = UserSubscription::list_for_user(&pool, user_id)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .find(|s| s.id == subscription_id)
+                    .unwrap();
+                let tier = UserSubscription::tier_for_user(&pool, user_id).await.unwrap();
+                (intent.status, subscription.payment_status.unwrap(), tier)
+            }
+        };
+        let expect = |intent: &str, subscription: &str, tier| {
+            (intent.to_string(), subscription.to_string(), tier)
+        };
 
-            Ok(card_details)
-        } else {
-            anyhow::bail!("Invalid payment method type")
+        for (id, _, _) in &checkouts {
+            deliver("payment_intent.processing", id, "processing").await.unwrap();
         }
+        for checkout in &checkouts {
+            assert_eq!(
+                state_of(checkout).await,
+                expect("processing", "processing", SubscriptionTier::Free)
+            );
+        }
+
+        deliver("payment_intent.succeeded", "pi_settled", "succeeded").await.unwrap();
+        deliver("payment_intent.payment_failed", "pi_bounced", "requires_payment_method")
+            .await
+            .unwrap();
+        assert_eq!(
+            state_of(&checkouts[0]).await,
+            expect("succeeded", "paid", SubscriptionTier::Pro)
+        );
+        assert_eq!(
+            state_of(&checkouts[1]).await,
+            expect("failed", "failed", SubscriptionTier::Free)
+        );
     }
-} 
\ No newline at end of file
+}
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::env;
+
+use axum::async_trait;
+
+use crate::models::{BillingLocation, Money};
+
+/// Works out the tax rate for a billing location. [`FlatRateTax`] is the
+/// only implementation for now; a tax service can be plugged in behind the
+/// same trait.
+#[async_trait]
+pub trait TaxProvider: Send + Sync {
+    /// The rate as a fraction, `0.2` for 20%.
+    async fn rate(&self, location: &BillingLocation) -> anyhow::Result<f64>;
+}
+
+/// Tax owed on `amount`, rounded to the currency's minor unit. Users
+/// without a billing location aren't taxed.
+pub async fn tax_on(
+    provider: &dyn TaxProvider,
+    amount: &Money,
+    location: Option<&BillingLocation>,
+) -> anyhow::Result<Money> {
+    let Some(location) = location else {
+        return Ok(Money::zero(amount.currency()));
+    };
+    let rate = provider.rate(location).await?;
+    Ok(amount.scale(rate))
+}
+
+/// Fixed percentages by country, optionally narrowed to a region, read from
+/// `TAX_RATES`, e.g. `DE=19,FR=20,US-CA=7.25`. A `COUNTRY-REGION` entry
+/// wins over its country's; unlisted locations are charged no tax.
+#[derive(Debug, Default)]
+pub struct FlatRateTax {
+    rates: HashMap<String, f64>,
+}
+
+impl FlatRateTax {
+    pub fn from_env() -> Self {
+        let rates = env::var("TAX_RATES").unwrap_or_default();
+        Self::parse(&rates)
+    }
+
+    fn parse(rates: &str) -> Self {
+        let rates = rates
+            .split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .filter_map(|entry| {
+                let parsed = entry.split_once('=').and_then(|(location, percent)| {
+                    let percent: f64 = percent.trim().parse().ok()?;
+                    (0.0..100.0)
+                        .contains(&percent)
+                        .then(|| (location.trim().to_ascii_uppercase(), percent / 100.0))
+                });
+                if parsed.is_none() {
+                    tracing::warn!("ignoring malformed TAX_RATES entry {:?}", entry);
+                }
+                parsed
+            })
+            .collect();
+        Self { rates }
+    }
+}
+
+#[async_trait]
+impl TaxProvider for FlatRateTax {
+    async fn rate(&self, location: &BillingLocation) -> anyhow::Result<f64> {
+        let regional = location
+            .region
+            .as_ref()
+            .and_then(|region| self.rates.get(&format!("{}-{}", location.country, region)));
+        Ok(regional
+            .or_else(|| self.rates.get(&location.country))
+            .copied()
+            .unwrap_or(0.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(country: &str, region: Option<&str>) -> BillingLocation {
+        BillingLocation {
+            country: country.into(),
+            region: region.map(str::to_string),
+        }
+    }
+
+    #[tokio::test]
+    async fn regional_rates_win_over_the_country() {
+        let tax = FlatRateTax::parse("US=5, US-CA=7.25, bogus, DE=19");
+        let amount = Money::from_minor(10000, "USD");
+
+        let california = tax_on(&tax, &amount, Some(&location("US", Some("CA")))).await.unwrap();
+        assert_eq!(california.minor_units(), 725);
+        let texas = tax_on(&tax, &amount, Some(&location("US", Some("TX")))).await.unwrap();
+        assert_eq!(texas.minor_units(), 500);
+        let france = tax_on(&tax, &amount, Some(&location("FR", None))).await.unwrap();
+        assert_eq!(france.minor_units(), 0);
+    }
+
+    #[tokio::test]
+    async fn tax_is_rounded_to_the_minor_unit() {
+        let tax = FlatRateTax::parse("DE=19");
+        let amount = Money::from_minor(2999, "EUR");
+
+        let owed = tax_on(&tax, &amount, Some(&location("DE", None))).await.unwrap();
+        assert_eq!(owed, Money::from_minor(570, "EUR"));
+        let untaxed = tax_on(&tax, &amount, None).await.unwrap();
+        assert_eq!(untaxed, Money::zero("EUR"));
+    }
+}
@@ -0,0 +1,483 @@
+//! Fixtures shared by the database tests.
+
+use axum::{
+    http::{HeaderMap, Method, StatusCode, Uri},
+    response::IntoResponse,
+    Json, Router,
+};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::{
+    auth::{Caller, Claims, Role},
+    config::Config,
+    db::{AIModelRepository, ReviewRepository},
+    email::Mailer,
+    models::{AIModel, CreateAIModel, ModelType, Subscription, SubscriptionTier, UserSubscription},
+    services::stripe::StripeService,
+    storage::StorageSettings,
+    AppState,
+};
+
+/// Insert a user with a unique email and return their id.
+pub async fn create_user(pool: &PgPool) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO users (id, email, name, password_hash) VALUES ($1, $2, 'Test User', 'x')",
+        id,
+        format!("{}@example.com", id)
+    )
+    .execute(pool)
+    .await
+    .expect("insert user");
+    id
+}
+
+/// The seeded plan on `tier`.
+pub async fn plan(pool: &PgPool, tier: SubscriptionTier) -> Subscription {
+    Subscription::get_by_tier(pool, tier)
+        .await
+        .expect("look up plan")
+        .expect("seeded plan")
+}
+
+/// Put the user on the plan for `tier`, paid up.
+pub async fn subscribe(pool: &PgPool, user_id: Uuid, tier: SubscriptionTier) -> UserSubscription {
+    let plan = plan(pool, tier).await;
+    let subscription = UserSubscription::create(pool, user_id, plan.id, Default::default())
+        .await
+        .expect("create subscription");
+    sqlx::query!(
+        "UPDATE user_subscriptions SET payment_status = 'paid' WHERE id = $1",
+        subscription.id
+    )
+    .execute(pool)
+    .await
+    .expect("mark subscription paid");
+    subscription
+}
+
+pub fn user(user_id: Uuid) -> Caller {
+    Caller {
+        user_id,
+        role: Role::User,
+    }
+}
+
+pub fn admin(user_id: Uuid) -> Caller {
+    Caller {
+        user_id,
+        role: Role::Admin,
+    }
+}
+
+/// An `Authorization` header value for `caller`, signed with the test
+/// state's secret.
+pub fn bearer(caller: Caller) -> String {
+    let claims = Claims {
+        sub: caller.user_id,
+        exp: (chrono::Utc::now() + chrono::Duration::hours(1)).timestamp() as usize,
+        role: caller.role,
+    };
+    let token = jsonwebtoken::encode(
+        &jsonwebtoken::Header::default(),
+        &claims,
+        &jsonwebtoken::EncodingKey::from_secret(b"test-secret"),
+    )
+    .expect("sign test token");
+    format!("Bearer {}", token)
+}
+
+/// A valid request for a public classification model called `name`.
+pub fn new_model(name: &str) -> CreateAIModel {
+    CreateAIModel {
+        name: name.to_string(),
+        description: "A test model".to_string(),
+        model_type: ModelType::Classification,
+        framework: "pytorch".to_string(),
+        version: "1.0.0".to_string(),
+        metadata: None,
+        performance_metrics: None,
+        repository_url: None,
+        tags: None,
+        is_public: true,
+        price: None,
+        required_tier: None,
+        license: None,
+    }
+}
+
+/// Create `model` as a draft owned by `owner_id`.
+pub async fn draft(pool: &PgPool, owner_id: Uuid, model: CreateAIModel) -> AIModel {
+    AIModelRepository::new(pool.clone())
+        .create(model, owner_id)
+        .await
+        .expect("create model")
+}
+
+/// Create `model` owned by `owner_id` and publish it.
+pub async fn published(pool: &PgPool, owner_id: Uuid, model: CreateAIModel) -> AIModel {
+    let model = draft(pool, owner_id, model).await;
+    sqlx::query!("UPDATE ai_models SET status = 'published' WHERE id = $1", model.id)
+        .execute(pool)
+        .await
+        .expect("publish model");
+    AIModelRepository::new(pool.clone())
+        .get(model.id)
+        .await
+        .expect("reload model")
+        .expect("published model")
+}
+
+/// Settings pointing nowhere real, with the JWT secret `bearer` signs with.
+pub fn config() -> Config {
+    Config {
+        database_url: String::new(),
+        addr: ([127, 0, 0, 1], 0).into(),
+        jwt_secret: "test-secret".into(),
+        stripe_secret_key: "sk_test_fixture".into(),
+        stripe_webhook_secret: "whsec_fixture".into(),
+        stripe_webhook_tolerance: Duration::from_secs(300),
+        pool: Default::default(),
+        connect_retry: Default::default(),
+    }
+}
+
+/// Application state over `pool` with in-process defaults everywhere.
+/// Stripe calls go to `stripe_url` when given, and fail otherwise.
+pub async fn app_state_with_stripe(pool: &PgPool, stripe_url: Option<&str>) -> AppState {
+    let mailer = Mailer::from_env(pool.clone());
+    let payment_events = crate::events::PaymentStatusBus::default();
+    let tier_cache = crate::tier_cache::TierCache::default();
+    let mut stripe_service = StripeService::new(
+        &config(),
+        pool.clone(),
+        mailer.clone(),
+        payment_events.clone(),
+        tier_cache.clone(),
+    );
+    stripe_service = stripe_service.with_api_base(stripe_url.unwrap_or("http://127.0.0.1:9"));
+
+    AppState {
+        pool: pool.clone(),
+        repo: AIModelRepository::new(pool.clone()),
+        reviews: ReviewRepository::new(pool.clone()),
+        jwt_secret: "test-secret".into(),
+        stripe_service: Arc::new(stripe_service),
+        plan_cache: Default::default(),
+        tier_cache,
+        storage: None,
+        feature_flags: Default::default(),
+        events: Default::default(),
+        payment_events,
+        review_limiter: crate::rate_limit::RateLimiter::new(100, Duration::from_secs(3600)),
+        route_limiter: crate::rate_limit::RouteLimiter::from_env().await,
+        concurrency_limits: crate::concurrency::ConcurrencyLimits::from_env(),
+        tax: Arc::new(crate::tax::FlatRateTax::default()),
+        mailer,
+        receipt_signer: None,
+        graphql: crate::graphql::schema(),
+    }
+}
+
+pub async fn app_state(pool: &PgPool) -> AppState {
+    app_state_with_stripe(pool, None).await
+}
+
+/// A customer the stub treats as deleted in Stripe: charging it fails with
+/// "No such customer".
+pub const DELETED_CUSTOMER: &str = "cus_deleted";
+
+/// A stand-in for the Stripe API on a local port. Subscription, payment
+/// method and intent cancellation calls get back a minimal object with the
+/// requested id, new customers get a fresh id, and new payment intents
+/// echo the amount and currency they were created with unless charged to
+/// [`DELETED_CUSTOMER`]; anything else is answered as a missing resource.
+/// Payment methods are Visa cards fingerprinted by the second part of
+/// their id, so `pm_visa_1` and `pm_visa_2` are the same card. Each request
+/// is recorded as `"METHOD /path"` along with its form body.
+pub struct StripeStub {
+    pub url: String,
+    requests: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+impl StripeStub {
+    pub async fn start() -> Self {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let app = Router::new().fallback(move |method: Method, uri: Uri, body: String| {
+            let recorded = recorded.clone();
+            async move {
+                let response = stripe_response(&method, uri.path(), &body);
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push((format!("{} {}", method, uri.path()), body));
+                response
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind Stripe stub");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        Self { url, requests }
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        let requests = self.requests.lock().unwrap();
+        requests.iter().map(|(request, _)| request.clone()).collect()
+    }
+
+    /// The form fields of the last `request` (`"METHOD /path"`) received.
+    pub fn form(&self, request: &str) -> Option<Vec<(String, String)>> {
+        let requests = self.requests.lock().unwrap();
+        let (_, body) = requests.iter().rev().find(|(sent, _)| sent == request)?;
+        Some(url::form_urlencoded::parse(body.as_bytes()).into_owned().collect())
+    }
+}
+
+fn form_field(body: &str, name: &str) -> Option<String> {
+    url::form_urlencoded::parse(body.as_bytes())
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+fn stripe_response(method: &Method, path: &str, body: &str) -> axum::response::Response {
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    match segments.as_slice() {
+        ["v1", "subscriptions", id] => {
+            let status = if method == Method::DELETE { "canceled" } else { "active" };
+            Json(stripe_subscription(id, status)).into_response()
+        }
+        ["v1", "payment_intents"] if form_field(body, "customer").as_deref() == Some(DELETED_CUSTOMER) => {
+            stripe_error(&format!("No such customer: '{}'", DELETED_CUSTOMER))
+        }
+        ["v1", "payment_intents"] if method == Method::POST => {
+            let amount: i64 = form_field(body, "amount").and_then(|a| a.parse().ok()).unwrap_or(0);
+            let currency = form_field(body, "currency").unwrap_or_else(|| "usd".into());
+            Json(stripe_payment_intent(
+                &format!("pi_{}", Uuid::new_v4().simple()),
+                amount,
+                &currency,
+                "requires_payment_method",
+            ))
+            .into_response()
+        }
+        ["v1", "payment_intents", id, "cancel"] => {
+            Json(stripe_payment_intent(id, 0, "usd", "canceled")).into_response()
+        }
+        ["v1", "customers"] if method == Method::POST => Json(json!({
+            "id": format!("cus_{}", Uuid::new_v4().simple()),
+            "object": "customer",
+            "created": 1_700_000_000,
+            "livemode": false,
+            "metadata": {},
+        }))
+        .into_response(),
+        ["v1", "payment_methods", id] if method == Method::GET => Json(json!({
+            "id": id,
+            "object": "payment_method",
+            "billing_details": {},
+            "card": {
+                "brand": "visa",
+                "exp_month": 12,
+                "exp_year": 2030,
+                "fingerprint": format!("fp_{}", id.split('_').nth(1).unwrap_or(id)),
+                "funding": "credit",
+                "last4": "4242",
+            },
+            "created": 1_700_000_000,
+            "livemode": false,
+            "type": "card",
+        }))
+        .into_response(),
+        ["v1", "payment_methods", id, "detach"] => Json(json!({
+            "id": id,
+            "object": "payment_method",
+            "billing_details": {},
+            "created": 1_700_000_000,
+            "livemode": false,
+            "type": "card",
+        }))
+        .into_response(),
+        _ => stripe_error(&format!("No such resource: {}", path)),
+    }
+}
+
+fn stripe_error(message: &str) -> axum::response::Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({
+            "error": {
+                "type": "invalid_request_error",
+                "code": "resource_missing",
+                "message": message,
+            }
+        })),
+    )
+        .into_response()
+}
+
+/// A Stripe PaymentIntent object as the API returns it.
+pub fn stripe_payment_intent(id: &str, amount: i64, currency: &str, status: &str) -> Value {
+    json!({
+        "id": id,
+        "object": "payment_intent",
+        "amount": amount,
+        "amount_capturable": 0,
+        "amount_received": 0,
+        "capture_method": "automatic",
+        "client_secret": format!("{}_secret_test", id),
+        "confirmation_method": "automatic",
+        "created": 1_700_000_000,
+        "currency": currency,
+        "livemode": false,
+        "metadata": {},
+        "payment_method_types": ["card"],
+        "status": status,
+    })
+}
+
+/// A Stripe Dispute object against the charge for `payment_intent`.
+pub fn stripe_dispute(id: &str, payment_intent: &str, status: &str) -> Value {
+    json!({
+        "id": id,
+        "object": "dispute",
+        "amount": 1999,
+        "balance_transactions": [],
+        "charge": format!("ch_for_{}", payment_intent),
+        "created": 1_700_000_000,
+        "currency": "usd",
+        "evidence": {},
+        "evidence_details": { "has_evidence": false, "past_due": false, "submission_count": 0 },
+        "is_charge_refundable": false,
+        "livemode": false,
+        "metadata": {},
+        "payment_intent": payment_intent,
+        "reason": "fraudulent",
+        "status": status,
+    })
+}
+
+/// A Stripe Billing subscription for the test customer in `status`.
+pub fn stripe_subscription(id: &str, status: &str) -> Value {
+    json!({
+        "id": id,
+        "object": "subscription",
+        "automatic_tax": { "enabled": false },
+        "billing_cycle_anchor": 1_700_000_000,
+        "cancel_at_period_end": false,
+        "created": 1_700_000_000,
+        "currency": "usd",
+        "current_period_end": 1_702_592_000,
+        "current_period_start": 1_700_000_000,
+        "customer": "cus_test",
+        "items": {
+            "object": "list",
+            "data": [stripe_subscription_item("si_test")],
+            "has_more": false,
+            "url": format!("/v1/subscription_items?subscription={}", id),
+        },
+        "livemode": false,
+        "metadata": {},
+        "start_date": 1_700_000_000,
+        "status": status,
+    })
+}
+
+/// A webhook event of `type_` (e.g. `"payment_intent.succeeded"`) about
+/// `object`, as Stripe would deliver it.
+pub fn stripe_event(type_: &str, object: Value) -> stripe::Event {
+    serde_json::from_value(json!({
+        "id": format!("evt_{}", Uuid::new_v4().simple()),
+        "object": "event",
+        "type": type_,
+        "created": 1_700_000_000,
+        "livemode": false,
+        "pending_webhooks": 0,
+        "data": { "object": object },
+    }))
+    .expect("valid Stripe event")
+}
+
+fn stripe_subscription_item(id: &str) -> Value {
+    json!({
+        "id": id,
+        "object": "subscription_item",
+        "created": 1_700_000_000,
+        "metadata": {},
+        "price": {
+            "id": "price_test",
+            "object": "price",
+            "product": "prod_test",
+        },
+        "quantity": 1,
+        "subscription": "sub_test",
+    })
+}
+
+/// A stand-in for an S3 bucket named `bucket` on a local port, holding
+/// objects by path. Server-side copies and deletes work on them, and a
+/// presigned `GET` succeeds only while its object exists. Signatures
+/// aren't checked.
+pub struct BucketStub {
+    pub settings: StorageSettings,
+    objects: Arc<Mutex<HashSet<String>>>,
+}
+
+impl BucketStub {
+    pub async fn start() -> Self {
+        let objects = Arc::new(Mutex::new(HashSet::new()));
+        let held = objects.clone();
+        let app = Router::new().fallback(move |method: Method, uri: Uri, headers: HeaderMap| {
+            let held = held.clone();
+            async move {
+                let mut objects = held.lock().unwrap();
+                let path = uri.path().to_string();
+                let copy_source = headers.get("x-amz-copy-source").and_then(|v| v.to_str().ok());
+                match (method, copy_source) {
+                    (Method::PUT, Some(source)) if objects.contains(source) => {
+                        objects.insert(path);
+                        StatusCode::OK
+                    }
+                    (Method::DELETE, None) => {
+                        objects.remove(&path);
+                        StatusCode::NO_CONTENT
+                    }
+                    (Method::GET, None) if objects.contains(&path) => StatusCode::OK,
+                    _ => StatusCode::NOT_FOUND,
+                }
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind bucket stub");
+        let endpoint = format!("http://{}/bucket", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let settings = StorageSettings {
+            endpoint: url::Url::parse(&endpoint).unwrap(),
+            region: "us-east-1".into(),
+            access_key_id: "AKIDTEST".into(),
+            secret_access_key: "secret".into(),
+            url_expiry: Duration::from_secs(900),
+        };
+        Self { settings, objects }
+    }
+
+    pub fn put(&self, key: &str) {
+        self.objects.lock().unwrap().insert(format!("/bucket/{}", key));
+    }
+
+    /// Fetch `url` the way a client following a download link would.
+    pub async fn get(&self, url: &str) -> StatusCode {
+        let status = reqwest::get(url).await.expect("reach bucket stub").status();
+        StatusCode::from_u16(status.as_u16()).unwrap()
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    features::FeatureFlags,
+    models::{SubscriptionTier, UserSubscription},
+};
+
+/// Entries kept before expired ones are swept out.
+const MAX_ENTRIES: usize = 10_000;
+
+/// Recently resolved subscription tiers, so tier-gated requests don't read
+/// `user_subscriptions` every time. Turned on with the `tier_cache` feature
+/// flag; entries live for `TIER_CACHE_TTL_SECS` (default 60). Changes we
+/// make invalidate the user's entry; ones made by background jobs show up
+/// once it expires. Deployments that need every check to see the latest
+/// state leave the flag off.
+#[derive(Clone, Default)]
+pub struct TierCache {
+    /// `None` when caching is off.
+    ttl: Option<Duration>,
+    entries: Arc<Mutex<HashMap<Uuid, (SubscriptionTier, Instant)>>>,
+}
+
+impl TierCache {
+    pub fn from_env(flags: &FeatureFlags) -> Self {
+        if !flags.is_enabled("tier_cache") {
+            return Self::default();
+        }
+        let secs = std::env::var("TIER_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|secs| *secs > 0)
+            .unwrap_or(60);
+        Self {
+            ttl: Some(Duration::from_secs(secs)),
+            ..Self::default()
+        }
+    }
+
+    /// A cache that's on with the given time to live, whatever the flags say.
+    #[cfg(test)]
+    pub fn with_ttl(ttl: Duration) -> Self {
+        Self {
+            ttl: Some(ttl),
+            ..Self::default()
+        }
+    }
+
+    /// The user's tier, from the cache while it's fresh and otherwise via
+    /// [`UserSubscription::tier_for_user`].
+    pub async fn tier_for_user(
+        &self,
+        pool: &PgPool,
+        user_id: Uuid,
+    ) -> Result<SubscriptionTier, sqlx::Error> {
+        let Some(ttl) = self.ttl else {
+            return UserSubscription::tier_for_user(pool, user_id).await;
+        };
+
+        if let Some((tier, expires_at)) = self.entries.lock().unwrap().get(&user_id) {
+            if *expires_at > Instant::now() {
+                return Ok(*tier);
+            }
+        }
+
+        let tier = UserSubscription::tier_for_user(pool, user_id).await?;
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, (_, expires_at)| *expires_at > now);
+        }
+        if entries.len() < MAX_ENTRIES {
+            entries.insert(user_id, (tier, now + ttl));
+        }
+        Ok(tier)
+    }
+
+    /// Forget the user's tier after their subscription changes.
+    pub fn invalidate(&self, user_id: Uuid) {
+        self.entries.lock().unwrap().remove(&user_id);
+    }
+}